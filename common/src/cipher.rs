@@ -0,0 +1,100 @@
+//! Secret encryption
+//!
+//! Secrets are encrypted with XChaCha20-Poly1305 under 32 byte key, which is passed around base64 encoded.
+//! Encrypted secret is random nonce followed by ciphertext.
+//! Associated data binds ciphertext to its owner and name, so encrypted values can't be swapped between secrets.
+
+use base64::engine::{general_purpose::STANDARD as BASE64, Engine};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+
+const NONCE_LEN: usize = 24;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CipherError(&'static str);
+
+impl std::fmt::Display for CipherError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for CipherError {}
+
+pub struct Cipher {
+    inner: XChaCha20Poly1305,
+}
+
+impl std::fmt::Debug for Cipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Cipher {{}}")
+    }
+}
+
+impl Cipher {
+    /// Create cipher from base64 encoded key
+    pub fn new(key: &str) -> Result<Self, CipherError> {
+        let key = BASE64
+            .decode(key.trim())
+            .map_err(|_| CipherError("secrets key should be base64 encoded"))?;
+        let inner = XChaCha20Poly1305::new_from_slice(&key)
+            .map_err(|_| CipherError("secrets key should be 32 bytes long"))?;
+        Ok(Self { inner })
+    }
+
+    /// Generate new base64 encoded key
+    pub fn generate_key() -> String {
+        BASE64.encode(XChaCha20Poly1305::generate_key(&mut OsRng))
+    }
+
+    pub fn encrypt(&self, aad: &str, value: &str) -> Result<Vec<u8>, CipherError> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: value.as_bytes(),
+            aad: aad.as_bytes(),
+        };
+        let ciphertext = self
+            .inner
+            .encrypt(&nonce, payload)
+            .map_err(|_| CipherError("failed to encrypt secret"))?;
+        Ok([nonce.as_slice(), ciphertext.as_slice()].concat())
+    }
+
+    pub fn decrypt(&self, aad: &str, encrypted: &[u8]) -> Result<String, CipherError> {
+        if encrypted.len() < NONCE_LEN {
+            return Err(CipherError("encrypted secret is too short"));
+        }
+        let (nonce, ciphertext) = encrypted.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: aad.as_bytes(),
+        };
+        let value = self
+            .inner
+            .decrypt(XNonce::from_slice(nonce), payload)
+            .map_err(|_| CipherError("failed to decrypt secret, key or data is invalid"))?;
+        String::from_utf8(value).map_err(|_| CipherError("secret should be utf-8 string"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encrypt_decrypt() {
+        let cipher = Cipher::new(&Cipher::generate_key()).unwrap();
+        let encrypted = cipher.encrypt("client/pg_password", "hunter2").unwrap();
+        assert_eq!(
+            cipher.decrypt("client/pg_password", &encrypted).unwrap(),
+            "hunter2"
+        );
+        assert!(cipher.decrypt("client/other", &encrypted).is_err());
+
+        let other = Cipher::new(&Cipher::generate_key()).unwrap();
+        assert!(other.decrypt("client/pg_password", &encrypted).is_err());
+        assert!(Cipher::new("c2hvcnQ=").is_err());
+    }
+}
@@ -1,9 +1,13 @@
-use pipe::{
+pub mod cipher;
+
+use pipe_config::{
     config::{Config as DynamicPipeConfig, Value as DynamicPipeValue},
-    types::SectionError,
+    config_schema::SectionSchema,
+    SectionError,
 };
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use std::collections::HashMap;
 
 /// Top level configuration object
 // todo: should this be only on the client?
@@ -11,11 +15,16 @@ use sqlx::FromRow;
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ClientConfig {
     pub node: Node,
-    pub server: Server,
+    /// client without server runs only locally defined pipes
+    #[serde(default)]
+    pub server: Option<Server>,
     #[serde(default)]
     pub sources: Vec<Source>,
     #[serde(default)]
     pub destinations: Vec<Destination>,
+    /// locally defined pipes, never sent to server
+    #[serde(default, skip_serializing)]
+    pub pipes: Vec<LocalPipe>,
 }
 
 /// Client-side server config
@@ -31,6 +40,43 @@ pub struct Node {
     pub display_name: String,
     pub unique_id: String,
     pub storage_path: String,
+    /// path to local encrypted secrets file
+    #[serde(default)]
+    pub secrets_path: Option<String>,
+    /// path to directory with pipe toml files, each file defines one local pipe
+    #[serde(default)]
+    pub pipes_dir: Option<String>,
+    /// path to unix socket of local control api
+    #[serde(default)]
+    pub control_socket: Option<String>,
+}
+
+/// Locally defined pipe
+///
+/// ```toml
+/// [[pipes]]
+/// name = "hello"
+///
+/// [[pipes.section]]
+/// name = "hello_world_source"
+///
+/// [[pipes.section]]
+/// name = "hello_world_destination"
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LocalPipe {
+    /// unique name of pipe, local pipe id is derived from it
+    pub name: String,
+    pub section: serde_json::Value,
+}
+
+impl TryInto<DynamicPipeConfig> for LocalPipe {
+    type Error = SectionError;
+
+    fn try_into(self) -> Result<DynamicPipeConfig, Self::Error> {
+        let value: DynamicPipeValue = self.section.try_into()?;
+        DynamicPipeConfig::try_from(value)
+    }
 }
 
 /// Internally-tagged type of a source needs to match the variant name
@@ -143,6 +189,11 @@ pub struct PostgresConnectorDestinationConfig {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ProvisionClientRequest {
     pub client_config: ClientConfig,
+    /// config schemas of sections, which client can run
+    #[serde(default)]
+    pub sections: Vec<SectionSchema>,
+    /// credential, generated by client, first provisioning binds client id to credential
+    pub credential: String,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -153,6 +204,8 @@ pub struct ProvisionClientResponse {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct IssueTokenRequest {
     pub client_id: String,
+    /// credential, which client was provisioned with
+    pub credential: String,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -161,12 +214,40 @@ pub struct IssueTokenResponse {
     pub client_id: String,
 }
 
+/// Pipe config revisions, which client is running
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RunningPipes {
+    pub pipes: Vec<RunningPipe>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RunningPipe {
+    pub id: u64,
+    pub revision: u64,
+}
+
+/// Secrets of client, delivered only to client itself
+#[derive(Serialize, Deserialize, Default)]
+pub struct ClientSecrets {
+    pub secrets: HashMap<String, String>,
+}
+
+impl std::fmt::Debug for ClientSecrets {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut names = self.secrets.keys().collect::<Vec<_>>();
+        names.sort();
+        f.debug_struct("ClientSecrets")
+            .field("names", &names)
+            .finish()
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, FromRow)]
 pub struct PipeConfigs {
     pub configs: Vec<PipeConfig>,
 }
 
-#[derive(Serialize, Deserialize, Debug, FromRow)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, FromRow)]
 pub struct PipeConfig {
     /// Scheduler needs to maintain pipe processes:
     /// - start new pipes
@@ -203,6 +284,17 @@ pub struct PipeConfig {
     #[sqlx(try_from = "i64")]
     #[serde(default = "default_id")]
     pub workspace_id: u64,
+
+    /// Revision of pipe config, server bumps revision on every config change
+    #[serde(default)]
+    #[sqlx(try_from = "i64")]
+    pub revision: u64,
+
+    /// Paused pipe is stopped on clients, but its config and state are kept
+    ///
+    /// Pausing is not a config change and doesn't bump revision.
+    #[serde(default)]
+    pub paused: bool,
 }
 
 fn default_id() -> u64 {
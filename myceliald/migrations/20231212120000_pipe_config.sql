@@ -0,0 +1,5 @@
+-- Last known pipe configs, started on boot before server is reachable
+CREATE TABLE pipe_config (
+    id INTEGER PRIMARY KEY,
+    config TEXT
+);
@@ -0,0 +1,5 @@
+-- Credential of client, generated once and presented to server to get client token
+CREATE TABLE client_credential (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    credential TEXT NOT NULL
+);
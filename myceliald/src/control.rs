@@ -0,0 +1,260 @@
+//! Local control api
+//!
+//! Daemon listens on unix socket, configured as `node.control_socket`, control api is available only on unix.
+//! Each connection carries single json request line, which is answered with single json response line.
+//! `myceliald pipes` subcommands are clients of this api.
+
+use std::fs::{DirBuilder, Permissions};
+use std::io::ErrorKind;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::Path;
+
+use pipe::{
+    scheduler::{LogEntry, PipeStatus, SchedulerHandle},
+    storage::Storage,
+    types::SectionError,
+};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::task::JoinHandle;
+
+use crate::{
+    local_pipes::is_local_id,
+    storage::{SqliteState, SqliteStorageHandle},
+    Error, Result,
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "request", rename_all = "snake_case")]
+pub enum Request {
+    /// List pipes with status and config
+    ListPipes,
+
+    /// Stored state of pipe sections
+    State {
+        id: u64,
+    },
+
+    Pause {
+        id: u64,
+    },
+
+    Resume {
+        id: u64,
+    },
+
+    Restart {
+        id: u64,
+    },
+
+    /// Recent section logs, see [`SchedulerHandle::logs`]
+    Logs {
+        id: Option<u64>,
+        since: u64,
+        limit: usize,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "response", rename_all = "snake_case")]
+pub enum Response {
+    Pipes { pipes: Vec<Pipe> },
+    State { state: serde_json::Value },
+    Logs { logs: Vec<LogEntry> },
+    Ok,
+    Error { message: String },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Pipe {
+    pub id: u64,
+    /// pipe is defined locally, not by server
+    pub local: bool,
+    pub status: PipeStatus,
+    pub config: serde_json::Value,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct Control {
+    scheduler_handle: SchedulerHandle,
+    storage_handle: SqliteStorageHandle,
+}
+
+impl Control {
+    async fn handle(&self, request: Request) -> std::result::Result<Response, SectionError> {
+        let response = match request {
+            Request::ListPipes => {
+                let pipes = self
+                    .scheduler_handle
+                    .list_pipes()
+                    .await?
+                    .into_iter()
+                    .map(|pipe| {
+                        Ok(Pipe {
+                            id: pipe.id,
+                            local: is_local_id(pipe.id),
+                            status: pipe.status,
+                            config: serde_json::to_value(&pipe.config)?,
+                            last_error: pipe.last_error,
+                        })
+                    })
+                    .collect::<std::result::Result<_, SectionError>>()?;
+                Response::Pipes { pipes }
+            }
+            Request::State { id } => {
+                let state = self
+                    .storage_handle
+                    .retrieve_state(id)
+                    .await?
+                    .map(SqliteState::into_map)
+                    .unwrap_or_default();
+                Response::State {
+                    state: serde_json::Value::Object(state),
+                }
+            }
+            Request::Pause { id } => {
+                // scheduler accepts pause of pipe, which is not added yet
+                if !self.scheduler_handle.list_ids().await?.contains(&id) {
+                    Err(format!("pipe with id {id} doesn't exist"))?
+                }
+                self.scheduler_handle.pause_pipe(id).await?;
+                Response::Ok
+            }
+            Request::Resume { id } => {
+                self.scheduler_handle.resume_pipe(id).await?;
+                Response::Ok
+            }
+            Request::Restart { id } => {
+                self.scheduler_handle.restart_pipe(id).await?;
+                Response::Ok
+            }
+            Request::Logs { id, since, limit } => Response::Logs {
+                logs: self.scheduler_handle.logs(id, since, limit).await?,
+            },
+        };
+        Ok(response)
+    }
+
+    async fn serve(&self, stream: UnixStream) -> Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut line = String::new();
+        BufReader::new(reader).read_line(&mut line).await?;
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => self
+                .handle(request)
+                .await
+                .unwrap_or_else(|e| Response::Error {
+                    message: e.to_string(),
+                }),
+            Err(e) => Response::Error {
+                message: format!("bad request: {e}"),
+            },
+        };
+        let mut response = serde_json::to_vec(&response)?;
+        response.push(b'\n');
+        writer.write_all(&response).await?;
+        Ok(())
+    }
+
+    async fn enter_loop(self, listener: UnixListener) {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    log::error!("failed to accept control connection: {}", e);
+                    continue;
+                }
+            };
+            let control = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = control.serve(stream).await {
+                    log::error!("control connection failed: {}", e);
+                }
+            });
+        }
+    }
+}
+
+/// Start control api on unix socket, socket is accessible only by owner of daemon
+pub fn new(
+    path: &str,
+    scheduler_handle: SchedulerHandle,
+    storage_handle: SqliteStorageHandle,
+) -> Result<JoinHandle<()>> {
+    // socket left from previous run
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e)?,
+        _ => (),
+    };
+    let listener = bind(Path::new(path))?;
+    let control = Control {
+        scheduler_handle,
+        storage_handle,
+    };
+    Ok(tokio::spawn(control.enter_loop(listener)))
+}
+
+/// Bind socket inside private directory and move it to `path` once permissions are restricted
+///
+/// Socket is never reachable by other users, even while its permissions are set.
+fn bind(path: &Path) -> Result<UnixListener> {
+    let file_name = path.file_name().ok_or(Error::Control(
+        "control_socket should be a file path".into(),
+    ))?;
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut private_dir_name = file_name.to_owned();
+    private_dir_name.push(format!(".{}", std::process::id()));
+    let private_dir = dir.join(private_dir_name);
+    DirBuilder::new().mode(0o700).create(&private_dir)?;
+    let tmp_path = private_dir.join("socket");
+    let result = (|| -> Result<UnixListener> {
+        let listener = UnixListener::bind(&tmp_path)?;
+        std::fs::set_permissions(&tmp_path, Permissions::from_mode(0o600))?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(listener)
+    })();
+    std::fs::remove_dir_all(&private_dir).ok();
+    result
+}
+
+/// Send request to control api of running daemon
+pub async fn request(path: &str, request: &Request) -> Result<Response> {
+    let stream = UnixStream::connect(path).await?;
+    let (reader, mut writer) = stream.into_split();
+    let mut data = serde_json::to_vec(request)?;
+    data.push(b'\n');
+    writer.write_all(&data).await?;
+    let mut line = String::new();
+    BufReader::new(reader).read_line(&mut line).await?;
+    match serde_json::from_str(&line)? {
+        Response::Error { message } => Err(Error::Control(message)),
+        response => Ok(response),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_bind_socket() {
+        let dir = std::env::temp_dir().join(format!("myceliald-control-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("control.sock");
+
+        let listener = bind(&path).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        // only socket is left, private directory is removed
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        let (stream, accepted) = tokio::join!(UnixStream::connect(&path), listener.accept());
+        assert!(stream.is_ok() && accepted.is_ok());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,31 +1,64 @@
 //! http client
 //!
-//! Poll mycelial server configuration endpoint
+//! Poll mycelial server configuration endpoint and report revisions of scheduled pipe configs
+//!
+//! Secrets of client are fetched from server on every poll and merged with local secrets before pipes are scheduled.
+//!
+//! Pipe configs received from server are persisted in client storage. On boot last known pipe
+//! configs are started right away and reconciled with server once it becomes reachable.
+//!
+//! Pipes are paused and resumed when their paused flag changes on server, so pipes paused or
+//! resumed through local control api keep their state until server flag changes.
 
-use std::{collections::HashSet, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
 
 use base64::engine::{general_purpose::STANDARD as BASE64, Engine};
 use common::{
-    ClientConfig, IssueTokenRequest, IssueTokenResponse, PipeConfig, PipeConfigs,
-    ProvisionClientRequest, ProvisionClientResponse,
+    ClientConfig, ClientSecrets, IssueTokenRequest, IssueTokenResponse, PipeConfig, PipeConfigs,
+    ProvisionClientRequest, ProvisionClientResponse, RunningPipe, RunningPipes, Server,
 };
 use pipe::{
     config::{Config, Value},
     scheduler::SchedulerHandle,
+    secrets::Secrets,
+    sections,
     types::SectionError,
 };
 use tokio::task::JoinHandle;
 
+use crate::{local_pipes::is_local_id, secrets::LocalSecrets, storage::SqliteStorageHandle};
+
 /// Http Client
 #[derive(Debug)]
 struct Client {
     config: ClientConfig,
 
+    /// Server config
+    server: Server,
+
     /// Client token
     client_token: String,
 
     /// SchedulerHandle
     scheduler_handle: SchedulerHandle,
+
+    /// Local secrets file
+    local_secrets: Option<LocalSecrets>,
+
+    /// Secrets last fetched from server
+    server_secrets: Secrets,
+
+    /// Storage for last known pipe configs
+    storage_handle: SqliteStorageHandle,
+
+    /// Pipe configs, which were last persisted in storage
+    persisted_configs: Vec<PipeConfig>,
+
+    /// Last known paused flags of server pipes
+    server_paused: HashMap<u64, bool>,
 }
 
 fn is_for_client(config: &Config, name: &str) -> bool {
@@ -35,39 +68,57 @@ fn is_for_client(config: &Config, name: &str) -> bool {
 }
 
 impl Client {
-    fn new(config: ClientConfig, scheduler_handle: SchedulerHandle) -> Self {
-        let client_token = config.server.token.clone();
+    fn new(
+        config: ClientConfig,
+        server: Server,
+        scheduler_handle: SchedulerHandle,
+        storage_handle: SqliteStorageHandle,
+        local_secrets: Option<LocalSecrets>,
+    ) -> Self {
+        let client_token = server.token.clone();
 
         Self {
             config,
+            server,
             client_token,
             scheduler_handle,
+            local_secrets,
+            server_secrets: Secrets::new(),
+            storage_handle,
+            persisted_configs: vec![],
+            server_paused: HashMap::new(),
         }
     }
 
     async fn register(&mut self) -> Result<(), SectionError> {
         let client = reqwest::Client::new();
-        let url = format!("{}/api/client", self.config.server.endpoint.as_str());
+        let credential = self.storage_handle.client_credential().await?;
+        let url = format!("{}/api/client", self.server.endpoint.as_str());
         let _x: ProvisionClientResponse = client
             .post(url)
             .header("Authorization", self.basic_auth())
             .json(&ProvisionClientRequest {
                 client_config: self.config.clone(),
+                sections: sections::schemas()?,
+                credential: credential.clone(),
             })
             .send()
             .await?
+            .error_for_status()?
             .json()
             .await?;
 
-        let url = format!("{}/api/tokens", self.config.server.endpoint.as_str());
+        let url = format!("{}/api/tokens", self.server.endpoint.as_str());
         let token: IssueTokenResponse = client
             .post(url)
             .header("Authorization", self.basic_auth())
             .json(&IssueTokenRequest {
                 client_id: self.config.node.unique_id.clone(),
+                credential,
             })
             .send()
             .await?
+            .error_for_status()?
             .json()
             .await?;
 
@@ -77,7 +128,7 @@ impl Client {
 
     async fn get_configs(&self) -> Result<Vec<PipeConfig>, SectionError> {
         let client = reqwest::Client::new();
-        let url = format!("{}/api/pipe", self.config.server.endpoint.as_str());
+        let url = format!("{}/api/pipe", self.server.endpoint.as_str());
         let configs: PipeConfigs = client
             .get(url)
             .header("Authorization", self.basic_auth())
@@ -89,11 +140,78 @@ impl Client {
         Ok(configs.configs)
     }
 
+    async fn get_secrets(&self) -> Result<HashMap<String, String>, SectionError> {
+        let client = reqwest::Client::new();
+        let url = format!("{}/api/client/secrets", self.server.endpoint.as_str());
+        let secrets: ClientSecrets = client
+            .get(url)
+            .header("Authorization", self.basic_auth())
+            .header("X-Authorization", self.client_auth())
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(secrets.secrets)
+    }
+
+    /// Refresh secrets of scheduler, local secrets take precedence over server secrets
+    ///
+    /// Previously fetched secrets are kept if server or local secrets file can't be read.
+    /// Changed server secrets are persisted along with local secrets, if local secrets file is configured.
+    async fn update_secrets(&mut self) -> Result<(), SectionError> {
+        match self.get_secrets().await {
+            Ok(values) => {
+                let secrets = Secrets::from(values.clone());
+                if secrets != self.server_secrets {
+                    if let Some(local_secrets) = self.local_secrets.as_ref() {
+                        if let Err(e) = local_secrets.store_server(&values) {
+                            log::error!("failed to persist server secrets: {}", e);
+                        }
+                    }
+                    self.server_secrets = secrets;
+                }
+            }
+            Err(e) => log::error!("failed to fetch secrets: {:?}", e),
+        }
+        self.apply_secrets().await
+    }
+
+    /// Merge last fetched server secrets with local secrets and pass them to scheduler
+    async fn apply_secrets(&self) -> Result<(), SectionError> {
+        let mut secrets = self.server_secrets.clone();
+        if let Some(local_secrets) = self.local_secrets.as_ref() {
+            match local_secrets.load() {
+                Ok(local_secrets) => secrets.extend(local_secrets),
+                Err(e) => {
+                    log::error!("failed to load local secrets: {}", e);
+                    return Ok(());
+                }
+            }
+        }
+        self.scheduler_handle.set_secrets(secrets).await
+    }
+
+    async fn report_running_pipes(&self, pipes: Vec<RunningPipe>) -> Result<(), SectionError> {
+        let client = reqwest::Client::new();
+        let url = format!(
+            "{}/api/clients/{}/pipes",
+            self.server.endpoint.as_str(),
+            self.config.node.unique_id
+        );
+        client
+            .put(url)
+            .header("Authorization", self.basic_auth())
+            .header("X-Authorization", self.client_auth())
+            .json(&RunningPipes { pipes })
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
     fn basic_auth(&self) -> String {
-        format!(
-            "Basic {}",
-            BASE64.encode(format!("{}:", self.config.server.token))
-        )
+        format!("Basic {}", BASE64.encode(format!("{}:", self.server.token)))
     }
 
     fn client_auth(&self) -> String {
@@ -105,12 +223,115 @@ impl Client {
         tokio::spawn(async move { self.enter_loop().await })
     }
 
-    async fn enter_loop(&mut self) -> Result<(), SectionError> {
-        while let Err(e) = self.register().await {
-            log::error!("failed to register client: {:?}", e);
-            tokio::time::sleep(Duration::from_secs(3)).await;
+    /// Start pipes from last known configs and server secrets, without waiting for server
+    ///
+    /// Server secrets are persisted only if local secrets file is configured. Without it, pipes referencing
+    /// server secrets fail to build until server is reachable, scheduler rebuilds them once secrets are fetched.
+    async fn start_persisted_pipes(&mut self) -> Result<(), SectionError> {
+        let pipe_configs = match self.storage_handle.retrieve_pipe_configs().await {
+            Ok(pipe_configs) => pipe_configs,
+            Err(e) => {
+                log::error!("failed to retrieve persisted pipe configs: {:?}", e);
+                return Ok(());
+            }
+        };
+        if let Some(local_secrets) = self.local_secrets.as_ref() {
+            match local_secrets.load_server() {
+                Ok(secrets) => self.server_secrets = secrets,
+                Err(e) => log::error!("failed to load persisted server secrets: {}", e),
+            }
         }
+        log::info!("starting {} persisted pipes", pipe_configs.len());
+        self.apply_secrets().await?;
+        self.persisted_configs = pipe_configs.clone();
+        self.reconcile(pipe_configs).await?;
+        Ok(())
+    }
+
+    /// Schedule pipe configs for this client, remove pipes which are not in the list
+    ///
+    /// Returns scheduled pipes and pipe configs, which belong to this client
+    async fn reconcile(
+        &mut self,
+        pipe_configs: Vec<PipeConfig>,
+    ) -> Result<(Vec<RunningPipe>, Vec<PipeConfig>), SectionError> {
+        log::debug!("pipe configs: {:#?}", pipe_configs);
+        // local pipes are managed by local pipes watcher
+        let mut ids: HashSet<u64> = self
+            .scheduler_handle
+            .list_ids()
+            .await?
+            .into_iter()
+            .filter(|id| !is_local_id(*id))
+            .collect();
+        let mut running = vec![];
+        let mut client_configs = vec![];
+        for pipe_config in pipe_configs.into_iter() {
+            let id = pipe_config.id;
+            let revision = pipe_config.revision;
+            let config: Config = match pipe_config.clone().try_into() {
+                Ok(c) => c,
+                Err(e) => {
+                    log::error!("bad pipe config: {:?}", e);
+                    continue;
+                }
+            };
+            if is_for_client(&config, &self.config.node.unique_id) {
+                let paused = pipe_config.paused;
+                let was_paused = self.server_paused.insert(id, paused);
+                // pipe is paused before it's added, so paused pipe is never started
+                if paused && was_paused != Some(true) {
+                    self.scheduler_handle.pause_pipe(id).await?;
+                }
+                match self.scheduler_handle.add_pipe(id, config).await {
+                    // paused pipes are stopped, so they are not reported as running
+                    Ok(_) if !paused => running.push(RunningPipe { id, revision }),
+                    Ok(_) => (),
+                    Err(e) => log::error!("failed to schedule pipe: {:?}", e),
+                }
+                if !paused && was_paused == Some(true) {
+                    if let Err(e) = self.scheduler_handle.resume_pipe(id).await {
+                        log::error!("failed to resume pipe: {:?}", e);
+                    }
+                }
+                client_configs.push(pipe_config);
+                ids.remove(&id);
+            }
+        }
+        for id in ids.into_iter() {
+            self.server_paused.remove(&id);
+            self.scheduler_handle.remove_pipe(id).await?;
+        }
+        Ok((running, client_configs))
+    }
+
+    /// Persist pipe configs if they differ from previously persisted
+    async fn persist_configs(&mut self, pipe_configs: Vec<PipeConfig>) {
+        if self.persisted_configs == pipe_configs {
+            return;
+        }
+        match self
+            .storage_handle
+            .store_pipe_configs(pipe_configs.clone())
+            .await
+        {
+            Ok(_) => self.persisted_configs = pipe_configs,
+            Err(e) => log::error!("failed to persist pipe configs: {:?}", e),
+        }
+    }
+
+    async fn enter_loop(&mut self) -> Result<(), SectionError> {
+        self.start_persisted_pipes().await?;
+        let mut registered = false;
         loop {
+            if !registered {
+                if let Err(e) = self.register().await {
+                    log::error!("failed to register client: {:?}", e);
+                    tokio::time::sleep(Duration::from_secs(3)).await;
+                    continue;
+                }
+                registered = true;
+            }
             let pipe_configs = match self.get_configs().await {
                 Ok(pipe_configs) => pipe_configs,
                 Err(e) => {
@@ -120,27 +341,11 @@ impl Client {
                 }
             };
 
-            log::debug!("pipe configs: {:#?}", pipe_configs);
-            let mut ids: HashSet<u64> =
-                HashSet::from_iter(self.scheduler_handle.list_ids().await?.into_iter());
-            for pipe_config in pipe_configs.into_iter() {
-                let id = pipe_config.id;
-                let config: Config = match pipe_config.try_into() {
-                    Ok(c) => c,
-                    Err(e) => {
-                        log::error!("bad pipe config: {:?}", e);
-                        continue;
-                    }
-                };
-                if is_for_client(&config, &self.config.node.unique_id) {
-                    if let Err(e) = self.scheduler_handle.add_pipe(id, config).await {
-                        log::error!("failed to schedule pipe: {:?}", e);
-                    }
-                    ids.remove(&id);
-                }
-            }
-            for id in ids.into_iter() {
-                self.scheduler_handle.remove_pipe(id).await?;
+            self.update_secrets().await?;
+            let (running, client_configs) = self.reconcile(pipe_configs).await?;
+            self.persist_configs(client_configs).await;
+            if let Err(e) = self.report_running_pipes(running).await {
+                log::error!("failed to report running pipes: {:?}", e);
             }
 
             tokio::time::sleep(Duration::from_secs(5)).await
@@ -150,7 +355,17 @@ impl Client {
 
 pub fn new(
     config: ClientConfig,
+    server: Server,
     scheduler_handle: SchedulerHandle,
+    storage_handle: SqliteStorageHandle,
+    local_secrets: Option<LocalSecrets>,
 ) -> JoinHandle<Result<(), SectionError>> {
-    Client::new(config, scheduler_handle).spawn()
+    Client::new(
+        config,
+        server,
+        scheduler_handle,
+        storage_handle,
+        local_secrets,
+    )
+    .spawn()
 }
@@ -0,0 +1,184 @@
+//! Locally defined pipes
+//!
+//! Pipes are declared in client config as `[[pipes]]` tables, or as toml files with `[[section]]`
+//! tables in `node.pipes_dir`, where name of pipe is the file stem.
+//! Config file and pipes directory are re-read periodically, changed pipes are rescheduled and
+//! removed pipes are stopped.
+//!
+//! Ids of local pipes are derived from pipe names, so pipe state survives restarts.
+//! Local ids have highest bit set, which never clashes with ids of server pipes.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+
+use common::ClientConfig;
+use pipe::{config::Config, scheduler::SchedulerHandle, types::SectionError};
+use tokio::task::JoinHandle;
+
+use crate::{read_config, Error, Result};
+
+const LOCAL_ID_BIT: u64 = 1 << 63;
+
+/// Check if pipe id belongs to local pipe
+pub fn is_local_id(id: u64) -> bool {
+    id & LOCAL_ID_BIT != 0
+}
+
+/// Stable id of local pipe, FNV-1a hash of pipe name with highest bit set
+pub fn local_id(name: &str) -> u64 {
+    let hash = name.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    hash | LOCAL_ID_BIT
+}
+
+/// Read local pipes from client config and pipes directory
+pub fn read_pipes(config: &ClientConfig) -> Result<BTreeMap<u64, Config>> {
+    let mut names = BTreeMap::<u64, String>::new();
+    let mut pipes = BTreeMap::new();
+    let mut add = |name: String, config: std::result::Result<Config, SectionError>| {
+        let config = config.map_err(|e| Error::LocalPipes(format!("pipe '{name}': {e}")))?;
+        let id = local_id(&name);
+        if let Some(other) = names.insert(id, name.clone()) {
+            let message = if other == name {
+                format!("pipe '{name}' is defined more than once")
+            } else {
+                format!("pipes '{other}' and '{name}' have clashing ids")
+            };
+            Err(Error::LocalPipes(message))?
+        }
+        pipes.insert(id, config);
+        Ok::<_, Error>(())
+    };
+    for pipe in config.pipes.iter().cloned() {
+        add(pipe.name.clone(), pipe.try_into())?;
+    }
+    if let Some(pipes_dir) = config.node.pipes_dir.as_deref() {
+        let mut paths = std::fs::read_dir(pipes_dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        paths.retain(|path| path.extension().map(|ext| ext == "toml").unwrap_or(false));
+        paths.sort();
+        for path in paths {
+            let name = pipe_name(&path)?;
+            let content = std::fs::read_to_string(&path)?;
+            add(name, Config::try_from_toml(&content))?;
+        }
+    }
+    Ok(pipes)
+}
+
+fn pipe_name(path: &Path) -> Result<String> {
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .map(Into::into)
+        .ok_or_else(|| Error::LocalPipes(format!("bad pipe file name: {}", path.display())))
+}
+
+/// Local pipes watcher
+#[derive(Debug)]
+struct LocalPipes {
+    /// path to client config
+    config_path: String,
+
+    scheduler_handle: SchedulerHandle,
+
+    /// currently scheduled local pipes
+    pipes: BTreeMap<u64, Config>,
+}
+
+impl LocalPipes {
+    fn new(config_path: String, scheduler_handle: SchedulerHandle) -> Self {
+        Self {
+            config_path,
+            scheduler_handle,
+            pipes: BTreeMap::new(),
+        }
+    }
+
+    fn read(&self) -> Result<BTreeMap<u64, Config>> {
+        read_pipes(&read_config(&self.config_path)?)
+    }
+
+    /// Schedule new and changed pipes, remove pipes which are no longer defined
+    async fn reconcile(&mut self, pipes: BTreeMap<u64, Config>) -> Result<()> {
+        for id in self.pipes.keys() {
+            if !pipes.contains_key(id) {
+                self.scheduler_handle.remove_pipe(*id).await?;
+            }
+        }
+        for (id, config) in pipes.iter() {
+            if self.pipes.get(id) == Some(config) {
+                continue;
+            }
+            if let Err(e) = self.scheduler_handle.add_pipe(*id, config.clone()).await {
+                log::error!("failed to schedule local pipe with id {id}: {:?}", e);
+            }
+        }
+        self.pipes = pipes;
+        Ok(())
+    }
+
+    // spawns local pipes watcher
+    pub fn spawn(mut self) -> JoinHandle<Result<()>> {
+        tokio::spawn(async move { self.enter_loop().await })
+    }
+
+    async fn enter_loop(&mut self) -> Result<()> {
+        loop {
+            // broken config keeps previous local pipes running
+            match self.read() {
+                Ok(pipes) => self.reconcile(pipes).await?,
+                Err(e) => log::error!("failed to read local pipes: {}", e),
+            }
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+    }
+}
+
+pub fn new(config_path: String, scheduler_handle: SchedulerHandle) -> JoinHandle<Result<()>> {
+    LocalPipes::new(config_path, scheduler_handle).spawn()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_pipes() {
+        let config: ClientConfig = toml::from_str(
+            r#"
+            [node]
+            display_name = "node"
+            unique_id = "node"
+            storage_path = "node.sqlite"
+
+            [[pipes]]
+            name = "hello"
+
+            [[pipes.section]]
+            name = "hello_world_source"
+            message = "hello"
+
+            [[pipes.section]]
+            name = "hello_world_destination"
+        "#,
+        )
+        .unwrap();
+        assert!(config.server.is_none());
+
+        let pipes = read_pipes(&config).unwrap();
+        let id = local_id("hello");
+        assert_eq!(pipes.keys().copied().collect::<Vec<_>>(), vec![id]);
+        assert_eq!(pipes[&id].get_sections().len(), 2);
+        assert!(is_local_id(id));
+        assert!(!is_local_id(1));
+        assert_eq!(id, local_id("hello"));
+        assert_ne!(id, local_id("hello2"));
+
+        let mut config = config;
+        config.pipes.push(config.pipes[0].clone());
+        assert!(read_pipes(&config).is_err());
+    }
+}
@@ -2,13 +2,23 @@
 //! - communicates with server, grabs and persists pipe configs:
 //!     - dumb server endpoint polling
 //!     - server dumbly returns all existing pipes
+//! - starts last known pipe configs on boot, without waiting for server
 //! - schedules and runs pipes
+//! - runs locally defined pipes, with or without server
+//! - serves local control api for inspecting and controlling pipes
+//! - resolves secrets, referenced by pipe configs, from local secrets file and server
+#[cfg(unix)]
+mod control;
 mod http_client;
+mod local_pipes;
 mod runtime;
+mod secrets;
 mod storage;
 
-use clap::Parser;
-use common::ClientConfig;
+use clap::{Parser, Subcommand};
+use common::{cipher::CipherError, ClientConfig};
+#[cfg(unix)]
+use control::{Request, Response};
 use pipe::types::SectionError;
 use std::fs::File;
 use std::io::Read;
@@ -30,6 +40,25 @@ pub enum Error {
 
     #[error(transparent)]
     TokioTask(#[from] tokio::task::JoinError),
+
+    #[error(transparent)]
+    Cipher(#[from] CipherError),
+
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    Base64(#[from] base64::DecodeError),
+
+    #[error("{0}")]
+    Secrets(&'static str),
+
+    #[error("{0}")]
+    LocalPipes(String),
+
+    #[cfg(unix)]
+    #[error("{0}")]
+    Control(String),
 }
 
 pub type Result<T> = result::Result<T, Error>;
@@ -39,6 +68,77 @@ struct Cli {
     /// Path to the TOML config file
     #[clap(short, long, env = "CONFIG_PATH")]
     config: String,
+
+    /// Base64 encoded 32 byte key of local secrets file, can be generated with `openssl rand -base64 32`
+    #[clap(long, env = "SECRETS_KEY", hide_env_values = true)]
+    secrets_key: Option<String>,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Manage local secrets file
+    Secrets {
+        #[clap(subcommand)]
+        command: SecretsCommand,
+    },
+
+    /// Inspect and control pipes of running daemon through local control api
+    #[cfg(unix)]
+    Pipes {
+        #[clap(subcommand)]
+        command: PipesCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum SecretsCommand {
+    /// Set secret, value is read from stdin
+    Set { name: String },
+
+    /// Remove secret
+    Remove { name: String },
+
+    /// List names of secrets
+    List,
+}
+
+#[cfg(unix)]
+#[derive(Subcommand)]
+enum PipesCommand {
+    /// List pipes with status
+    List,
+
+    /// Show pipe status, last error and config
+    Show { id: u64 },
+
+    /// Show stored state of pipe sections, including offsets
+    State { id: u64 },
+
+    /// Stop pipe, config is kept until pipe is resumed
+    Pause { id: u64 },
+
+    /// Start paused pipe
+    Resume { id: u64 },
+
+    /// Stop and start pipe
+    Restart { id: u64 },
+
+    /// Show recent section logs
+    Logs {
+        /// Show logs of single pipe
+        id: Option<u64>,
+
+        /// Number of recent log lines
+        #[clap(short = 'n', long, default_value_t = 20)]
+        lines: usize,
+
+        /// Keep printing new log lines
+        #[clap(short, long)]
+        follow: bool,
+    },
 }
 
 fn read_config(path: &str) -> Result<ClientConfig> {
@@ -49,15 +149,164 @@ fn read_config(path: &str) -> Result<ClientConfig> {
     Ok(toml::from_str(&config)?)
 }
 
+fn local_secrets(
+    config: &ClientConfig,
+    secrets_key: Option<&str>,
+) -> Result<Option<secrets::LocalSecrets>> {
+    let path = match config.node.secrets_path.as_deref() {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let key = secrets_key.ok_or(Error::Secrets(
+        "secrets key is required to use secrets file",
+    ))?;
+    Ok(Some(secrets::LocalSecrets::new(path, key)?))
+}
+
+fn secrets_command(
+    local_secrets: Option<secrets::LocalSecrets>,
+    command: SecretsCommand,
+) -> Result<()> {
+    let local_secrets =
+        local_secrets.ok_or(Error::Secrets("secrets_path is not set in node config"))?;
+    match command {
+        SecretsCommand::Set { name } => {
+            let mut value = String::new();
+            io::stdin().read_line(&mut value)?;
+            let value = value.trim_end_matches(['\r', '\n']);
+            local_secrets.set(&name, value)?;
+        }
+        SecretsCommand::Remove { name } => {
+            if !local_secrets.remove(&name)? {
+                Err(Error::Secrets("secret doesn't exist"))?
+            }
+        }
+        SecretsCommand::List => {
+            for name in local_secrets.names()? {
+                println!("{name}");
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn pipes_command(config: &ClientConfig, command: PipesCommand) -> Result<()> {
+    let path = config.node.control_socket.as_deref().ok_or(Error::Control(
+        "control_socket is not set in node config".into(),
+    ))?;
+    let pipes = || async {
+        match control::request(path, &Request::ListPipes).await? {
+            Response::Pipes { pipes } => Ok::<_, Error>(pipes),
+            _ => Err(Error::Control("unexpected response".into())),
+        }
+    };
+    let ok = |response: Response| match response {
+        Response::Ok => Ok(()),
+        _ => Err(Error::Control("unexpected response".into())),
+    };
+    match command {
+        PipesCommand::List => {
+            println!("{:<20}  {:<6}  {:<7}  SECTIONS", "ID", "KIND", "STATUS");
+            for pipe in pipes().await? {
+                let sections = pipe
+                    .config
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|section| section.get("name")?.as_str())
+                    .collect::<Vec<_>>()
+                    .join(" -> ");
+                let kind = if pipe.local { "local" } else { "server" };
+                println!(
+                    "{:<20}  {:<6}  {:<7}  {}",
+                    pipe.id, kind, pipe.status, sections
+                );
+            }
+        }
+        PipesCommand::Show { id } => {
+            let pipe = pipes()
+                .await?
+                .into_iter()
+                .find(|pipe| pipe.id == id)
+                .ok_or(Error::Control(format!("pipe with id {id} doesn't exist")))?;
+            println!("{}", serde_json::to_string_pretty(&pipe)?);
+        }
+        PipesCommand::State { id } => match control::request(path, &Request::State { id }).await? {
+            Response::State { state } => println!("{}", serde_json::to_string_pretty(&state)?),
+            _ => Err(Error::Control("unexpected response".into()))?,
+        },
+        PipesCommand::Pause { id } => ok(control::request(path, &Request::Pause { id }).await?)?,
+        PipesCommand::Resume { id } => ok(control::request(path, &Request::Resume { id }).await?)?,
+        PipesCommand::Restart { id } => {
+            ok(control::request(path, &Request::Restart { id }).await?)?
+        }
+        PipesCommand::Logs { id, lines, follow } => {
+            let (mut since, mut limit) = (0, lines);
+            loop {
+                let logs = match control::request(path, &Request::Logs { id, since, limit }).await?
+                {
+                    Response::Logs { logs } => logs,
+                    _ => Err(Error::Control("unexpected response".into()))?,
+                };
+                for entry in logs {
+                    println!("{} pipe<{}>: {}", entry.time, entry.pipe_id, entry.message);
+                    since = entry.seq + 1;
+                }
+                if !follow {
+                    break;
+                }
+                limit = usize::MAX;
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            }
+        }
+    }
+    Ok(())
+}
+
 async fn run() -> Result<()> {
     pretty_env_logger::init_timed();
     let cli = Cli::try_parse()?;
     let config = read_config(&cli.config)?;
+    #[cfg(unix)]
+    if let Some(Command::Pipes { command }) = cli.command {
+        return pipes_command(&config, command).await;
+    }
+    let local_secrets = local_secrets(&config, cli.secrets_key.as_deref())?;
+    if let Some(Command::Secrets { command }) = cli.command {
+        return secrets_command(local_secrets, command);
+    }
 
     let storage_handle = storage::new(config.node.storage_path.clone()).await?;
-    let runtime_handle = runtime::new(storage_handle);
-    let client_handle = http_client::new(config, runtime_handle);
-    client_handle.await??;
+    let runtime_handle = runtime::new(storage_handle.clone());
+    if let Some(local_secrets) = local_secrets.as_ref() {
+        runtime_handle.set_secrets(local_secrets.load()?).await?;
+    }
+    #[cfg(unix)]
+    if let Some(path) = config.node.control_socket.as_deref() {
+        control::new(path, runtime_handle.clone(), storage_handle.clone())?;
+    }
+    #[cfg(not(unix))]
+    if config.node.control_socket.is_some() {
+        log::warn!("control api is available only on unix, control_socket is ignored");
+    }
+    let local_pipes_handle = local_pipes::new(cli.config.clone(), runtime_handle.clone());
+    match config.server.clone() {
+        Some(server) => {
+            let client_handle = http_client::new(
+                config,
+                server,
+                runtime_handle,
+                storage_handle,
+                local_secrets,
+            );
+            tokio::select! {
+                result = client_handle => result??,
+                result = local_pipes_handle => result??,
+            }
+        }
+        None => local_pipes_handle.await??,
+    }
     Ok(())
 }
 
@@ -0,0 +1,163 @@
+//! Local secrets
+//!
+//! Secrets, referenced in pipe configs as `${secret:<name>}`, are kept in json file next to client config.
+//! Secret values are encrypted with secrets key, key is never stored in the file.
+//! Local secrets take precedence over secrets delivered by server.
+//!
+//! Last known secrets delivered by server are kept encrypted with the same key in `<secrets file>.server`,
+//! so pipes, which reference them, can be started before server is reachable.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use base64::engine::{general_purpose::STANDARD as BASE64, Engine};
+use common::cipher::Cipher;
+use pipe::secrets::Secrets;
+use serde::{Deserialize, Serialize};
+
+use crate::Result;
+
+#[derive(Serialize, Deserialize, Default)]
+struct SecretsFile {
+    /// base64 encoded encrypted values by secret name
+    secrets: BTreeMap<String, String>,
+}
+
+#[derive(Debug)]
+pub struct LocalSecrets {
+    path: PathBuf,
+    cipher: Cipher,
+}
+
+impl LocalSecrets {
+    pub fn new(path: impl Into<PathBuf>, key: &str) -> Result<Self> {
+        Ok(Self {
+            path: path.into(),
+            cipher: Cipher::new(key)?,
+        })
+    }
+
+    /// Decrypt all secrets
+    pub fn load(&self) -> Result<Secrets> {
+        let mut secrets = Secrets::new();
+        for (name, value) in read(&self.path)?.secrets {
+            let value = self.cipher.decrypt(&name, &BASE64.decode(value)?)?;
+            secrets.insert(name, value);
+        }
+        Ok(secrets)
+    }
+
+    pub fn names(&self) -> Result<Vec<String>> {
+        Ok(read(&self.path)?.secrets.into_keys().collect())
+    }
+
+    pub fn set(&self, name: &str, value: &str) -> Result<()> {
+        let mut file = read(&self.path)?;
+        let value = BASE64.encode(self.cipher.encrypt(name, value)?);
+        file.secrets.insert(name.into(), value);
+        write(&self.path, &file)
+    }
+
+    /// Remove secret, returns false if secret doesn't exist
+    pub fn remove(&self, name: &str) -> Result<bool> {
+        let mut file = read(&self.path)?;
+        if file.secrets.remove(name).is_none() {
+            return Ok(false);
+        }
+        write(&self.path, &file)?;
+        Ok(true)
+    }
+
+    /// Decrypt last known server secrets
+    pub fn load_server(&self) -> Result<Secrets> {
+        let mut secrets = Secrets::new();
+        for (name, value) in read(&self.server_path())?.secrets {
+            let value = self
+                .cipher
+                .decrypt(&server_aad(&name), &BASE64.decode(value)?)?;
+            secrets.insert(name, value);
+        }
+        Ok(secrets)
+    }
+
+    /// Replace last known server secrets
+    pub fn store_server(&self, secrets: &HashMap<String, String>) -> Result<()> {
+        let mut file = SecretsFile::default();
+        for (name, value) in secrets {
+            let value = BASE64.encode(self.cipher.encrypt(&server_aad(name), value)?);
+            file.secrets.insert(name.clone(), value);
+        }
+        write(&self.server_path(), &file)
+    }
+
+    fn server_path(&self) -> PathBuf {
+        with_suffix(&self.path, ".server")
+    }
+}
+
+/// Associated data of server secrets, so server and local secrets can't be swapped
+fn server_aad(name: &str) -> String {
+    format!("server:{name}")
+}
+
+/// Read secrets file, missing file has no secrets
+fn read(path: &Path) -> Result<SecretsFile> {
+    match std::fs::read(path) {
+        Ok(data) => Ok(serde_json::from_slice(&data)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(SecretsFile::default()),
+        Err(e) => Err(e)?,
+    }
+}
+
+/// Write secrets file through temporary file, so file is never left half-written
+fn write(path: &Path, file: &SecretsFile) -> Result<()> {
+    let tmp_path = with_suffix(path, ".tmp");
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut tmp = options.open(&tmp_path)?;
+    tmp.write_all(&serde_json::to_vec_pretty(file)?)?;
+    tmp.sync_all()?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    path.into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_server_secrets() {
+        let dir = std::env::temp_dir().join(format!("myceliald-secrets-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let secrets = LocalSecrets::new(dir.join("secrets.json"), &Cipher::generate_key()).unwrap();
+        assert!(secrets.load_server().unwrap().is_empty());
+
+        secrets.set("local", "local value").unwrap();
+        secrets
+            .store_server(&HashMap::from([("server".into(), "server value".into())]))
+            .unwrap();
+        assert_eq!(secrets.load().unwrap().get("server"), None);
+        assert_eq!(
+            secrets.load_server().unwrap().get("server"),
+            Some("server value")
+        );
+        assert_eq!(secrets.load_server().unwrap().get("local"), None);
+
+        // stored server secrets are replaced, not merged
+        secrets.store_server(&HashMap::new()).unwrap();
+        assert!(secrets.load_server().unwrap().is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
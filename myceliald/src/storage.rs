@@ -1,9 +1,13 @@
 //! storage backend for client
+//!
+//! Stores state of pipes and last known pipe configs, so pipes can be started without server.
+//! Also keeps credential, which binds client to its id on server.
 
+use common::{cipher::Cipher, PipeConfig};
 use pipe::storage::Storage;
 use section::State;
 use serde::{Deserialize, Serialize};
-use sqlx::{sqlite::SqliteConnectOptions, ConnectOptions, Row, SqliteConnection};
+use sqlx::{sqlite::SqliteConnectOptions, ConnectOptions, Connection, Row, SqliteConnection};
 use std::any::{type_name, Any, TypeId};
 use std::future::Future;
 use std::{pin::Pin, str::FromStr};
@@ -38,6 +42,12 @@ impl std::fmt::Display for SqliteStateError {
 
 impl std::error::Error for SqliteStateError {}
 
+impl SqliteState {
+    pub fn into_map(self) -> serde_json::Map<String, serde_json::Value> {
+        self.map
+    }
+}
+
 impl State for SqliteState {
     type Error = SqliteStateError;
 
@@ -173,10 +183,61 @@ impl SqliteStorage {
                         .map_err(|e| e.into());
                     reply_to.send(result).ok();
                 }
+
+                Message::StorePipeConfigs { configs, reply_to } => {
+                    reply_to.send(self.store_pipe_configs(configs).await).ok();
+                }
+
+                Message::RetrievePipeConfigs { reply_to } => {
+                    reply_to.send(self.retrieve_pipe_configs().await).ok();
+                }
+
+                Message::ClientCredential { reply_to } => {
+                    reply_to.send(self.client_credential().await).ok();
+                }
             }
         }
         Ok(())
     }
+
+    /// Replace stored pipe configs
+    async fn store_pipe_configs(&mut self, configs: Vec<PipeConfig>) -> Result<(), StdError> {
+        let mut transaction = self.connection.begin().await?;
+        sqlx::query("DELETE FROM pipe_config")
+            .execute(&mut *transaction)
+            .await?;
+        for config in configs {
+            sqlx::query("INSERT INTO pipe_config VALUES(?, ?)")
+                .bind(config.id as i64)
+                .bind(serde_json::to_string(&config)?)
+                .execute(&mut *transaction)
+                .await?;
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn retrieve_pipe_configs(&mut self) -> Result<Vec<PipeConfig>, StdError> {
+        let rows = sqlx::query("SELECT config FROM pipe_config ORDER BY id")
+            .fetch_all(&mut self.connection)
+            .await?;
+        rows.into_iter()
+            .map(|row| Ok(serde_json::from_str(&row.get::<String, _>("config"))?))
+            .collect()
+    }
+
+    /// Credential of client, generated on first call
+    async fn client_credential(&mut self) -> Result<String, StdError> {
+        sqlx::query("INSERT INTO client_credential VALUES(0, ?) ON CONFLICT (id) DO NOTHING")
+            .bind(Cipher::generate_key())
+            .execute(&mut self.connection)
+            .await?;
+        Ok(
+            sqlx::query_scalar("SELECT credential FROM client_credential WHERE id = 0")
+                .fetch_one(&mut self.connection)
+                .await?,
+        )
+    }
 }
 
 #[derive(Debug)]
@@ -190,6 +251,16 @@ pub enum Message {
         pipe_id: u64,
         reply_to: OneshotSender<Result<Option<SqliteState>, StdError>>,
     },
+    StorePipeConfigs {
+        configs: Vec<PipeConfig>,
+        reply_to: OneshotSender<Result<(), StdError>>,
+    },
+    RetrievePipeConfigs {
+        reply_to: OneshotSender<Result<Vec<PipeConfig>, StdError>>,
+    },
+    ClientCredential {
+        reply_to: OneshotSender<Result<String, StdError>>,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -201,6 +272,28 @@ impl SqliteStorageHandle {
     async fn send(&self, message: Message) -> Result<(), StdError> {
         Ok(self.tx.send(message).await?)
     }
+
+    /// Replace last known pipe configs
+    pub async fn store_pipe_configs(&self, configs: Vec<PipeConfig>) -> Result<(), StdError> {
+        let (reply_to, rx) = oneshot_channel();
+        self.send(Message::StorePipeConfigs { configs, reply_to })
+            .await?;
+        rx.await?
+    }
+
+    /// Retrieve last known pipe configs
+    pub async fn retrieve_pipe_configs(&self) -> Result<Vec<PipeConfig>, StdError> {
+        let (reply_to, rx) = oneshot_channel();
+        self.send(Message::RetrievePipeConfigs { reply_to }).await?;
+        rx.await?
+    }
+
+    /// Credential of client, which is generated once and kept for the lifetime of storage
+    pub async fn client_credential(&self) -> Result<String, StdError> {
+        let (reply_to, rx) = oneshot_channel();
+        self.send(Message::ClientCredential { reply_to }).await?;
+        rx.await?
+    }
 }
 
 impl Storage<SqliteState> for SqliteStorageHandle {
@@ -263,4 +356,41 @@ mod test {
         assert_eq!(64, state.get::<i64>("key").unwrap().unwrap());
         assert_eq!(None, state.get::<String>("key").unwrap());
     }
+
+    #[tokio::test]
+    async fn test_pipe_configs() {
+        let handle = new("sqlite::memory:".into()).await.unwrap();
+        assert!(handle.retrieve_pipe_configs().await.unwrap().is_empty());
+
+        let config = |id, revision| PipeConfig {
+            id,
+            pipe: serde_json::json!({"section": [{"name": "hello_world_source"}]}),
+            workspace_id: 1,
+            revision,
+            paused: false,
+        };
+        handle
+            .store_pipe_configs(vec![config(1, 1), config(2, 1)])
+            .await
+            .unwrap();
+        assert_eq!(
+            vec![config(1, 1), config(2, 1)],
+            handle.retrieve_pipe_configs().await.unwrap()
+        );
+
+        // stored configs are replaced, not merged
+        handle.store_pipe_configs(vec![config(2, 3)]).await.unwrap();
+        assert_eq!(
+            vec![config(2, 3)],
+            handle.retrieve_pipe_configs().await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_client_credential() {
+        let handle = new("sqlite::memory:".into()).await.unwrap();
+        let credential = handle.client_credential().await.unwrap();
+        assert!(!credential.is_empty());
+        assert_eq!(credential, handle.client_credential().await.unwrap());
+    }
 }
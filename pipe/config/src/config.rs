@@ -1,7 +1,15 @@
 //! Dynamic section configuration
+//!
+//! Sections declare typed config structs, which are deserialized from section [`Map`], see [`SectionConfig`].
+mod de;
+
 use std::collections::HashMap;
 
-use super::types::SectionError;
+use serde::{de::DeserializeOwned, Serialize, Serializer};
+
+use crate::SectionError;
+
+pub use de::Error as DeError;
 
 pub type Map = HashMap<String, Value>;
 
@@ -25,6 +33,8 @@ pub enum Value {
     String(String),
     Bool(bool),
     Int(i64),
+    Float(f64),
+    Null,
 }
 
 impl Value {
@@ -69,6 +79,40 @@ impl Value {
             _ => None,
         }
     }
+
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Self::Float(f) => Some(*f),
+            Self::Int(i) => Some(*i as f64),
+            _ => None,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Self::Null)
+    }
+}
+
+/// Value is serialized as plain json/toml value, without enum tags
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Map(m) => m.serialize(serializer),
+            Self::Array(v) => v.serialize(serializer),
+            Self::String(s) => serializer.serialize_str(s),
+            Self::Bool(b) => serializer.serialize_bool(*b),
+            Self::Int(i) => serializer.serialize_i64(*i),
+            Self::Float(f) => serializer.serialize_f64(*f),
+            Self::Null => serializer.serialize_unit(),
+        }
+    }
+}
+
+/// Config is serialized as array of section maps
+impl Serialize for Config {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.sections.serialize(serializer)
+    }
 }
 
 impl TryFrom<toml::Value> for Value {
@@ -78,6 +122,7 @@ impl TryFrom<toml::Value> for Value {
         let value = match value {
             toml::Value::String(s) => Value::String(s),
             toml::Value::Integer(i) => Value::Int(i),
+            toml::Value::Float(f) => Value::Float(f),
             toml::Value::Boolean(b) => Value::Bool(b),
             toml::Value::Array(v) => Value::Array(
                 v.into_iter()
@@ -105,6 +150,11 @@ impl TryFrom<serde_json::Value> for Value {
         let value = match value {
             serde_json::Value::String(s) => Value::String(s),
             serde_json::Value::Number(i) if i.is_i64() => Value::Int(i.as_i64().unwrap()),
+            serde_json::Value::Number(f) => match f.as_f64() {
+                Some(f) => Value::Float(f),
+                None => return Err(format!("unsupported number {f}").into()),
+            },
+            serde_json::Value::Null => Value::Null,
             serde_json::Value::Bool(b) => Value::Bool(b),
             serde_json::Value::Array(v) => Value::Array(
                 v.into_iter()
@@ -119,12 +169,31 @@ impl TryFrom<serde_json::Value> for Value {
                     })
                     .collect::<Result<HashMap<_, _>, _>>()?,
             ),
-            _ => return Err(format!("unsupported value type {value:?}").into()),
         };
         Ok(value)
     }
 }
 
+/// Typed section config
+///
+/// Config is deserialized from section map, keys with null values are treated as absent.
+/// Keys which are not fields of config struct are ignored.
+pub trait SectionConfig: DeserializeOwned + Serialize {
+    /// descriptions of config fields, exported in section config schema
+    const DESCRIPTIONS: &'static [(&'static str, &'static str)] = &[];
+
+    /// checks which can't be expressed by field types
+    fn validate(&self) -> Result<(), SectionError> {
+        Ok(())
+    }
+
+    fn from_map(map: &Map) -> Result<Self, SectionError> {
+        let config = Self::deserialize(de::MapDeserializer::new(map))?;
+        config.validate()?;
+        Ok(config)
+    }
+}
+
 impl TryFrom<Value> for Config {
     type Error = SectionError;
 
@@ -201,5 +270,8 @@ mod test {
         let json_config = Config::try_from_json(json).unwrap();
         let toml_config = Config::try_from_toml(toml).unwrap();
         assert_eq!(toml_config, json_config);
+
+        let serialized = serde_json::to_string(&json_config).unwrap();
+        assert_eq!(Config::try_from_json(&serialized).unwrap(), json_config);
     }
 }
@@ -0,0 +1,252 @@
+//! serde deserializer over config [`Value`]
+
+use serde::de::{self, DeserializeSeed, IntoDeserializer, Visitor};
+use serde::forward_to_deserialize_any;
+
+use super::{Map, Value};
+
+/// Config deserialization error
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error(String);
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// String values can hold resolved secrets, so they are never included in errors
+fn redact(unexp: de::Unexpected) -> de::Unexpected {
+    match unexp {
+        de::Unexpected::Str(_) | de::Unexpected::Char(_) => de::Unexpected::Other("string"),
+        de::Unexpected::Bytes(_) => de::Unexpected::Other("bytes"),
+        unexp => unexp,
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        Self(msg.to_string())
+    }
+
+    fn invalid_type(unexp: de::Unexpected, exp: &dyn de::Expected) -> Self {
+        Self(format!("invalid type: {}, expected {exp}", redact(unexp)))
+    }
+
+    fn invalid_value(unexp: de::Unexpected, exp: &dyn de::Expected) -> Self {
+        Self(format!("invalid value: {}, expected {exp}", redact(unexp)))
+    }
+
+    fn unknown_variant(_variant: &str, expected: &'static [&'static str]) -> Self {
+        let expected = expected
+            .iter()
+            .map(|variant| format!("`{variant}`"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        Self(format!("unknown variant, expected one of {expected}"))
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for &'de Value {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        self
+    }
+}
+
+impl<'de> de::Deserializer<'de> for &'de Value {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self {
+            Value::Map(map) => visitor.visit_map(MapDeserializer::new(map)),
+            Value::Array(values) => {
+                visitor.visit_seq(de::value::SeqDeserializer::new(values.iter()))
+            }
+            Value::String(s) => visitor.visit_borrowed_str(s),
+            Value::Bool(b) => visitor.visit_bool(*b),
+            Value::Int(i) => visitor.visit_i64(*i),
+            Value::Float(f) => visitor.visit_f64(*f),
+            Value::Null => visitor.visit_unit(),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self {
+            Value::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        match self {
+            Value::String(s) => visitor.visit_enum(s.as_str().into_deserializer()),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+/// Deserializer of section map
+///
+/// Keys with null values are skipped, errors of values are prefixed with key.
+pub struct MapDeserializer<'de> {
+    iter: std::collections::hash_map::Iter<'de, String, Value>,
+    /// key and value of next entry
+    entry: Option<(&'de str, &'de Value)>,
+}
+
+impl<'de> MapDeserializer<'de> {
+    pub fn new(map: &'de Map) -> Self {
+        Self {
+            iter: map.iter(),
+            entry: None,
+        }
+    }
+}
+
+impl<'de> de::MapAccess<'de> for MapDeserializer<'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        match self.iter.by_ref().find(|(_, value)| !value.is_null()) {
+            Some((key, value)) => {
+                self.entry = Some((key.as_str(), value));
+                seed.deserialize(key.as_str().into_deserializer()).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, Self::Error> {
+        let (key, value) = self
+            .entry
+            .take()
+            .ok_or_else(|| Error("value requested before key".into()))?;
+        seed.deserialize(value)
+            .map_err(|e| Error(format!("'{key}': {e}")))
+    }
+}
+
+impl<'de> de::Deserializer<'de> for MapDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_map(self)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::{Config, SectionConfig};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Deserialize, Serialize)]
+    #[serde(rename_all = "lowercase")]
+    enum Mode {
+        Append,
+        Merge,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize, Serialize)]
+    struct TestConfig {
+        path: String,
+        #[serde(default)]
+        once: bool,
+        ratio: f64,
+        limit: Option<u64>,
+        mode: Mode,
+    }
+
+    impl SectionConfig for TestConfig {}
+
+    fn section(json: &str) -> crate::config::Map {
+        Config::try_from_json(json).unwrap().get_sections()[0].clone()
+    }
+
+    #[test]
+    fn deserialize_config() {
+        let config = section(
+            r#"[{"name": "test", "path": "/tmp/path", "ratio": 0.5, "limit": null, "mode": "merge"}]"#,
+        );
+        assert_eq!(
+            TestConfig::from_map(&config).unwrap(),
+            TestConfig {
+                path: "/tmp/path".into(),
+                once: false,
+                ratio: 0.5,
+                limit: None,
+                mode: Mode::Merge,
+            }
+        );
+    }
+
+    #[test]
+    fn deserialize_config_errors() {
+        let config = section(r#"[{"path": "/tmp/path", "ratio": 1, "mode": "append"}]"#);
+        assert!(TestConfig::from_map(&config).is_ok());
+
+        let config = section(r#"[{"ratio": 1, "mode": "append"}]"#);
+        let err = TestConfig::from_map(&config).unwrap_err();
+        assert_eq!(err.to_string(), "missing field `path`");
+
+        let config = section(r#"[{"path": 1, "ratio": 1, "mode": "append"}]"#);
+        let err = TestConfig::from_map(&config).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "'path': invalid type: integer `1`, expected a string"
+        );
+
+        let config = section(r#"[{"path": "/tmp", "ratio": 1, "mode": "replace"}]"#);
+        let err = TestConfig::from_map(&config).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "'mode': unknown variant, expected one of `append`, `merge`"
+        );
+    }
+
+    #[test]
+    fn deserialize_config_errors_redact_values() {
+        #[derive(Debug, Deserialize, Serialize)]
+        struct PortConfig {
+            port: u16,
+        }
+        impl SectionConfig for PortConfig {}
+
+        let config = section(r#"[{"port": "hunter2"}]"#);
+        let err = PortConfig::from_map(&config).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "'port': invalid type: string, expected u16"
+        );
+
+        let config = section(r#"[{"path": "/tmp", "ratio": 1, "mode": "hunter2"}]"#);
+        let err = TestConfig::from_map(&config).unwrap_err();
+        assert!(!err.to_string().contains("hunter2"));
+    }
+}
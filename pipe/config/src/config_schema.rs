@@ -0,0 +1,554 @@
+//! Section config schema
+//!
+//! Schema is derived from typed section config (see [`SectionConfig`]): keys, types, whether key is required,
+//! defaults and descriptions.
+//! Schemas allow to validate pipe configs before they reach clients and to render section forms.
+//! Schemas are serializable, so clients can register schemas of sections they run with server. Schema, which was
+//! deserialized, validates only field types, checks of typed config are left to client.
+//! Keys which are not declared in schema are ignored, pipe configs carry presentation keys (labels, client ids).
+
+use serde::de::{self, DeserializeSeed, IntoDeserializer, Visitor};
+use serde::{forward_to_deserialize_any, Deserialize, Serialize};
+
+use crate::config::{Config, DeError, Map, SectionConfig, Value};
+use crate::SectionError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    String,
+    Int,
+    Float,
+    Bool,
+    Map,
+    Array,
+}
+
+impl FieldType {
+    /// Check if config value can be used as value of field type
+    fn accepts(self, value: &Value) -> bool {
+        matches!(
+            (self, value),
+            (Self::String, Value::String(_))
+                | (Self::Int, Value::Int(_))
+                | (Self::Float, Value::Float(_) | Value::Int(_))
+                | (Self::Bool, Value::Bool(_))
+                | (Self::Map, Value::Map(_))
+                | (Self::Array, Value::Array(_))
+        )
+    }
+
+    fn name_of(value: &Value) -> &'static str {
+        match value {
+            Value::String(_) => "string",
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::Bool(_) => "bool",
+            Value::Map(_) => "map",
+            Value::Array(_) => "array",
+            Value::Null => "null",
+        }
+    }
+}
+
+impl std::fmt::Display for FieldType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::String => "string",
+            Self::Int => "int",
+            Self::Float => "float",
+            Self::Bool => "bool",
+            Self::Map => "map",
+            Self::Array => "array",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Field {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: FieldType,
+    pub required: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<serde_json::Value>,
+    /// allowed values of string field, any value is allowed if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub one_of: Vec<String>,
+    #[serde(default)]
+    pub description: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SectionSchema {
+    /// name of section in registry
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub fields: Vec<Field>,
+
+    /// builds typed config, catches errors which are not expressed by field types
+    ///
+    /// Not set if schema was deserialized.
+    #[serde(skip)]
+    check: Option<fn(&Map) -> Result<(), SectionError>>,
+}
+
+/// Validation error
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldError {
+    /// index of section in pipe
+    pub section: usize,
+    /// field name, not set if error is not specific to a single field
+    pub field: Option<String>,
+    pub message: String,
+}
+
+impl FieldError {
+    fn new(section: usize, field: Option<&str>, message: impl Into<String>) -> Self {
+        Self {
+            section,
+            field: field.map(Into::into),
+            message: message.into(),
+        }
+    }
+}
+
+fn check<T: SectionConfig>(map: &Map) -> Result<(), SectionError> {
+    T::from_map(map).map(|_| ())
+}
+
+impl SectionSchema {
+    /// Derive schema from typed section config
+    pub fn of<T: SectionConfig>(
+        name: &'static str,
+        description: &'static str,
+    ) -> Result<Self, SectionError> {
+        let fields = trace::<T>().map_err(|e| format!("failed to trace config of {name}: {e}"))?;
+        Ok(Self {
+            name: name.into(),
+            description: description.into(),
+            fields,
+            check: Some(check::<T>),
+        })
+    }
+
+    /// Validate config of section at `section` index of pipe
+    pub fn validate(&self, section: usize, config: &Map) -> Vec<FieldError> {
+        let mut errors = vec![];
+        for field in self.fields.iter() {
+            let value = match config.get(&field.name).filter(|value| !value.is_null()) {
+                Some(value) => value,
+                None if field.required => {
+                    errors.push(FieldError::new(
+                        section,
+                        Some(field.name.as_str()),
+                        "field is required",
+                    ));
+                    continue;
+                }
+                None => continue,
+            };
+            if !field.ty.accepts(value) {
+                let message = format!("expected {}, got {}", field.ty, FieldType::name_of(value));
+                errors.push(FieldError::new(section, Some(field.name.as_str()), message));
+                continue;
+            }
+            match value.as_str() {
+                Some(value)
+                    if !field.one_of.is_empty() && !field.one_of.iter().any(|v| v == value) =>
+                {
+                    let message = format!("expected one of: {}", field.one_of.join(", "));
+                    errors.push(FieldError::new(section, Some(field.name.as_str()), message));
+                }
+                _ => (),
+            }
+        }
+        if let (true, Some(check)) = (errors.is_empty(), self.check) {
+            if let Err(e) = check(config) {
+                errors.push(FieldError::new(section, None, e.to_string()));
+            }
+        }
+        errors
+    }
+}
+
+/// Validate each section of pipe config against schema of section with the same name
+pub fn validate(config: &Config, schemas: &[SectionSchema]) -> Vec<FieldError> {
+    config
+        .get_sections()
+        .iter()
+        .enumerate()
+        .flat_map(|(index, section)| {
+            let name = match section.get("name").map(Value::as_str) {
+                Some(Some(name)) => name,
+                Some(None) => return vec![FieldError::new(index, Some("name"), "expected string")],
+                None => return vec![FieldError::new(index, Some("name"), "field is required")],
+            };
+            match schemas.iter().find(|schema| schema.name == name) {
+                Some(schema) => schema.validate(index, section),
+                None => vec![FieldError::new(
+                    index,
+                    Some("name"),
+                    format!("unknown section '{name}'"),
+                )],
+            }
+        })
+        .collect()
+}
+
+/// Derive fields of typed config
+///
+/// Config is deserialized with tracing deserializer, which feeds placeholder values and records requested types.
+/// Field is optional if config can be built without it, defaults are read from config built without optional fields.
+/// Configs, which can't be traced (nested structs, untagged or flattened fields), are rejected.
+fn trace<T: SectionConfig>() -> Result<Vec<Field>, DeError> {
+    let mut fields = vec![];
+    T::deserialize(Tracer::new(&mut fields, &[]))?;
+    for field in fields.iter_mut() {
+        field.required = T::deserialize(Tracer::new(&mut vec![], &[field.name.as_str()])).is_err();
+        field.description = T::DESCRIPTIONS
+            .iter()
+            .find(|(name, _)| *name == field.name)
+            .map(|(_, description)| description.to_string())
+            .unwrap_or_default();
+    }
+    let optional = fields
+        .iter()
+        .filter(|field| !field.required)
+        .map(|field| field.name.as_str())
+        .collect::<Vec<_>>();
+    let defaults = T::deserialize(Tracer::new(&mut vec![], &optional))
+        .ok()
+        .and_then(|config| serde_json::to_value(config).ok());
+    for field in fields.iter_mut().filter(|field| !field.required) {
+        field.default = defaults
+            .as_ref()
+            .and_then(|defaults| defaults.get(&field.name))
+            .filter(|value| !value.is_null())
+            .cloned();
+    }
+    Ok(fields)
+}
+
+/// Deserializer of config struct, which records fields instead of reading values
+struct Tracer<'a> {
+    fields: &'a mut Vec<Field>,
+    /// fields, which are omitted from traced struct
+    omit: &'a [&'a str],
+}
+
+impl<'a> Tracer<'a> {
+    fn new(fields: &'a mut Vec<Field>, omit: &'a [&'a str]) -> Self {
+        Self { fields, omit }
+    }
+}
+
+impl<'de, 'a> de::Deserializer<'de> for Tracer<'a> {
+    type Error = DeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("section config should be a struct"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        let names = fields
+            .iter()
+            .copied()
+            .filter(|name| !self.omit.contains(name))
+            .collect::<Vec<_>>();
+        visitor.visit_map(TraceFields {
+            fields: self.fields,
+            names: names.into_iter(),
+            current: None,
+        })
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
+
+struct TraceFields<'a> {
+    fields: &'a mut Vec<Field>,
+    names: std::vec::IntoIter<&'static str>,
+    current: Option<&'static str>,
+}
+
+impl<'de, 'a> de::MapAccess<'de> for TraceFields<'a> {
+    type Error = DeError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        match self.names.next() {
+            Some(name) => {
+                self.current = Some(name);
+                seed.deserialize(name.into_deserializer()).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, Self::Error> {
+        let name = self.current.take().unwrap_or_default();
+        let mut field = Field {
+            name: name.into(),
+            ty: FieldType::String,
+            required: false,
+            default: None,
+            one_of: vec![],
+            description: String::new(),
+        };
+        let value = seed.deserialize(TraceValue { field: &mut field })?;
+        self.fields.push(field);
+        Ok(value)
+    }
+}
+
+/// Deserializer of config field, which records field type and feeds placeholder value
+struct TraceValue<'a> {
+    field: &'a mut Field,
+}
+
+impl<'de, 'a> de::Deserializer<'de> for TraceValue<'a> {
+    type Error = DeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom(format!(
+            "unsupported type of config field '{}'",
+            self.field.name
+        )))
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.field.ty = FieldType::Bool;
+        visitor.visit_bool(false)
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.field.ty = FieldType::Int;
+        visitor.visit_i64(0)
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.field.ty = FieldType::Int;
+        visitor.visit_u64(0)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.field.ty = FieldType::Float;
+        visitor.visit_f64(0.0)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.field.ty = FieldType::String;
+        visitor.visit_str("")
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.field.ty = FieldType::Array;
+        visitor.visit_seq(de::value::SeqDeserializer::new(std::iter::empty::<String>()))
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.field.ty = FieldType::Map;
+        visitor.visit_map(de::value::MapDeserializer::new(std::iter::empty::<(
+            String,
+            String,
+        )>()))
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.field.ty = FieldType::String;
+        self.field.one_of = variants.iter().map(|variant| variant.to_string()).collect();
+        let variant = variants.first().copied().unwrap_or_default();
+        visitor.visit_enum(variant.into_deserializer())
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_u64(visitor)
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_u64(visitor)
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_u64(visitor)
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_f64(visitor)
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_string(visitor)
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 char bytes byte_buf unit unit_struct newtype_struct tuple
+        tuple_struct struct identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, Serialize)]
+    #[serde(rename_all = "lowercase")]
+    enum Format {
+        Json,
+        Csv,
+    }
+
+    fn default_format() -> Format {
+        Format::Json
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    struct TestConfig {
+        path: String,
+        #[serde(default)]
+        once: bool,
+        #[serde(default = "default_format")]
+        format: Format,
+        limit: Option<u64>,
+    }
+
+    impl SectionConfig for TestConfig {
+        const DESCRIPTIONS: &'static [(&'static str, &'static str)] = &[("path", "path to file")];
+
+        fn validate(&self) -> Result<(), SectionError> {
+            match (&self.format, self.limit) {
+                (Format::Csv, Some(_)) => Err("limit is not supported for csv")?,
+                _ => Ok(()),
+            }
+        }
+    }
+
+    #[test]
+    fn derive_schema() {
+        let schema = SectionSchema::of::<TestConfig>("source", "test source").unwrap();
+        assert_eq!(
+            serde_json::to_value(&schema).unwrap(),
+            serde_json::json!({
+                "name": "source",
+                "description": "test source",
+                "fields": [
+                    {"name": "path", "type": "string", "required": true, "description": "path to file"},
+                    {"name": "once", "type": "bool", "required": false, "default": false, "description": ""},
+                    {
+                        "name": "format", "type": "string", "required": false, "default": "json",
+                        "one_of": ["json", "csv"], "description": ""
+                    },
+                    {"name": "limit", "type": "int", "required": false, "description": ""},
+                ]
+            })
+        );
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    struct Nested {
+        path: String,
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    struct NestedConfig {
+        nested: Nested,
+    }
+
+    impl SectionConfig for NestedConfig {}
+
+    #[test]
+    fn derive_schema_of_nested_config() {
+        let err = SectionSchema::of::<NestedConfig>("source", "test source").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "failed to trace config of source: unsupported type of config field 'nested'"
+        );
+    }
+
+    #[test]
+    fn validate_sections() {
+        let schemas = [SectionSchema::of::<TestConfig>("source", "test source").unwrap()];
+        let config = Config::try_from_json(
+            r#"[
+                {"name": "source", "path": "/tmp/path", "format": "csv", "label": "ignored"},
+                {"name": "source", "once": "false", "format": "xml"},
+                {"name": "source", "path": "/tmp/path", "format": "csv", "limit": 10},
+                {"name": "destination"},
+                {"path": "/tmp/path"}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            validate(&config, &schemas),
+            vec![
+                FieldError::new(1, Some("path"), "field is required"),
+                FieldError::new(1, Some("once"), "expected bool, got string"),
+                FieldError::new(1, Some("format"), "expected one of: json, csv"),
+                FieldError::new(2, None, "limit is not supported for csv"),
+                FieldError::new(3, Some("name"), "unknown section 'destination'"),
+                FieldError::new(4, Some("name"), "field is required"),
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_deserialized_schema() {
+        let schema = SectionSchema::of::<TestConfig>("source", "test source").unwrap();
+        let schemas: Vec<SectionSchema> =
+            serde_json::from_value(serde_json::to_value([schema]).unwrap()).unwrap();
+        let config = Config::try_from_json(
+            r#"[
+                {"name": "source", "format": "xml"},
+                {"name": "source", "path": "/tmp/path", "format": "csv", "limit": 10}
+            ]"#,
+        )
+        .unwrap();
+        // typed config checks are not available for deserialized schema
+        assert_eq!(
+            validate(&config, &schemas),
+            vec![
+                FieldError::new(0, Some("path"), "field is required"),
+                FieldError::new(0, Some("format"), "expected one of: json, csv"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,8 @@
+//! Pipe configuration
+//!
+//! Dynamic pipe configs, typed section configs and section config schemas.
+//! Kept apart from pipe runtime, so server can validate pipe configs without depending on section implementations.
+pub mod config;
+pub mod config_schema;
+
+pub type SectionError = Box<dyn std::error::Error + Send + Sync + 'static>;
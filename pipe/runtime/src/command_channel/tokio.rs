@@ -188,6 +188,13 @@ impl<S: StateTrait> SectionChannelTrait for SectionChannel<S> {
             .map_err(|_| ChanError::Closed)
     }
 
+    // request to runtime
+    async fn resync(&mut self) -> Result<(), Self::Error> {
+        self.root_tx
+            .send(SectionRequest::Resync { id: self.id })
+            .map_err(|_| ChanError::Closed)
+    }
+
     // request from runtime or from own weak ref?
     async fn recv(&mut self) -> Result<Command, Self::Error> {
         match self.rx.recv().await {
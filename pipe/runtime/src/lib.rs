@@ -1,12 +1,13 @@
 pub mod channel;
 pub mod command_channel;
-pub mod config;
 pub mod message;
 pub mod pipe;
 pub mod registry;
 pub mod scheduler;
+pub mod secrets;
 pub mod sections;
 pub mod storage;
 pub mod types;
 
 pub use pipe::Pipe;
+pub use pipe_config::{config, config_schema};
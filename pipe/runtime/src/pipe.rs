@@ -1,9 +1,18 @@
 //! Pipe
 
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::task::AtomicWaker;
 use futures::{FutureExt, Sink, SinkExt, Stream};
+use tokio::sync::mpsc::Sender;
 use tokio::task::JoinHandle;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::PollSender;
 
-use crate::channel::channel;
 use crate::types::{DynSection, DynSink, DynStream, SectionError, SectionFuture};
 use section::{Command, RootChannel, SectionChannel};
 use section::{ReplyTo as _, Section, SectionRequest, State};
@@ -12,6 +21,10 @@ use stub::Stub;
 use super::config::Config;
 use super::message::Message;
 use super::registry::Registry;
+use super::secrets::Secrets;
+
+/// Interval of checking if channel to section, which should be stopped next, is drained
+const DRAIN_INTERVAL: Duration = Duration::from_millis(10);
 
 #[allow(dead_code)]
 pub struct Pipe<R: RootChannel + Send + 'static> {
@@ -44,13 +57,14 @@ impl<R: RootChannel + Send + 'static> Pipe<R> {
     }
 }
 
-impl<R: RootChannel + Send + 'static> TryFrom<(&'_ Config, &'_ Registry<R::SectionChannel>)>
-    for Pipe<R>
+/// Build pipe out of config, section configs are passed to constructors with resolved secret references
+impl<R: RootChannel + Send + 'static>
+    TryFrom<(&'_ Config, &'_ Registry<R::SectionChannel>, &'_ Secrets)> for Pipe<R>
 {
     type Error = SectionError;
 
     fn try_from(
-        (config, registry): (&Config, &Registry<R::SectionChannel>),
+        (config, registry, secrets): (&Config, &Registry<R::SectionChannel>, &Secrets),
     ) -> Result<Self, Self::Error> {
         let sections = config
             .get_sections()
@@ -65,7 +79,10 @@ impl<R: RootChannel + Send + 'static> TryFrom<(&'_ Config, &'_ Registry<R::Secti
                     let constructor = registry
                         .get_constructor(name)
                         .ok_or(format!("no constructor for '{name}' available"))?;
-                    constructor(section_cfg)
+                    let section_cfg = secrets
+                        .resolve(section_cfg)
+                        .map_err(|e| format!("section '{name}': {e}"))?;
+                    constructor(&section_cfg)
                 },
             )
             .collect::<Result<Vec<Box<dyn DynSection<R::SectionChannel>>>, _>>()?;
@@ -93,6 +110,9 @@ where
         let input: DynStream = Box::pin(input);
         let output: DynSink = Box::pin(output);
         let mut root_channel = <RootChan as RootChannel>::new();
+        let gate = Gate::default();
+        // senders of channels between sections, kept to check if channel is drained
+        let mut senders: Vec<Sender<Message>> = vec![];
         let (_, _, _, handles) = self.sections.take().unwrap().into_iter().enumerate().fold(
             (None, Some(input), Some(output), vec![]),
             |(prev, mut pipe_input, mut pipe_output, mut acc), (pos, section)| {
@@ -106,10 +126,17 @@ where
                     let output = pipe_output.take().unwrap();
                     (next_input, output)
                 } else {
-                    let (tx, rx) = channel::<Message>(1);
-                    let tx = tx.sink_map_err(|_| -> SectionError { "send error".into() });
+                    let (tx, rx) = tokio::sync::mpsc::channel::<Message>(1);
+                    senders.push(tx.clone());
+                    let tx = PollSender::new(tx)
+                        .sink_map_err(|_| -> SectionError { "send error".into() });
+                    let rx = ReceiverStream::new(rx);
                     (Box::pin(rx), Box::pin(tx))
                 };
+                let output: DynSink = match pos {
+                    0 => Box::pin(GatedSink::new(output, gate.clone())),
+                    _ => output,
+                };
                 let section_channel = root_channel.add_section(pos as u64).unwrap();
                 let handle = tokio::spawn(section.dyn_start(input, output, section_channel));
                 acc.push(HandleWrap::new(handle));
@@ -122,7 +149,17 @@ where
                 <<RootChan as RootChannel>::SectionChannel as SectionChannel>::State::new(),
             );
             let mut handles = handles;
+            // pipe was asked to stop and waits for sections to finish
+            let mut stopping = false;
+            // index of section, which is asked to stop next
+            let mut next_stop = 0;
             loop {
+                let drain = async move {
+                    match stopping {
+                        true => tokio::time::sleep(DRAIN_INTERVAL).await,
+                        false => futures::future::pending().await,
+                    }
+                };
                 futures::select! {
                     msg = root_channel.recv().fuse() => {
                         match msg? {
@@ -138,6 +175,29 @@ where
                             SectionRequest::Log { id, message } => {
                                 section_chan.log(format!("section_id<id: {id}>: {message}")).await?;
                             }
+                            SectionRequest::Resync { id } => {
+                                // sections preceding requester start over, pipe is restarted by scheduler
+                                for prev in 0..id {
+                                    state.set(
+                                        &format!("{prev}"),
+                                        <<RootChan as RootChannel>::SectionChannel as SectionChannel>::State::new(),
+                                    )?;
+                                }
+                                section_chan.store_state(state.clone()).await?;
+                                Err(format!("section_id<id: {id}>: resync requested"))?
+                            }
+                            SectionRequest::Stopped { id } if stopping => {
+                                if let Some(handle) = handles[id as usize].handle.take() {
+                                    handle.await??;
+                                }
+                                if handles.iter().all(|handle| handle.handle.is_none()) {
+                                    return Ok(())
+                                }
+                                while can_stop(&handles, &senders, next_stop) {
+                                    stop_section(&mut root_channel, &gate, handles.len(), next_stop).await;
+                                    next_stop += 1;
+                                }
+                            }
                             SectionRequest::Stopped { id } => {
                                 return match handles[id as usize].handle.take() {
                                     Some(handle) => {
@@ -152,8 +212,19 @@ where
                         }
                     },
                     cmd = section_chan.recv().fuse() => {
-                        if let Command::Stop = cmd? {
-                            return Ok(())
+                        if let (Command::Stop, false) = (cmd?, stopping) {
+                            // graceful stop: source is held back from producing, sections following source are
+                            // asked to stop one by one once drained, source is stopped last, so acks of
+                            // delivered messages reach it, requests of sections are served until all of them finish
+                            gate.set(Gate::HOLD);
+                            next_stop = 1;
+                            stopping = true;
+                        }
+                    }
+                    _ = drain.fuse() => {
+                        while can_stop(&handles, &senders, next_stop) {
+                            stop_section(&mut root_channel, &gate, handles.len(), next_stop).await;
+                            next_stop += 1;
                         }
                     }
                 }
@@ -163,6 +234,117 @@ where
     }
 }
 
+/// Section, which is `next` in stop order, can be stopped
+///
+/// Sections following source are stopped in order: previous section finished or is source, which is held back by
+/// gate, and all messages it sent were taken from channel, so messages in flight reach destination before it stops.
+/// Source is stopped last, once all following sections finished.
+fn can_stop(handles: &[HandleWrap], senders: &[Sender<Message>], next: usize) -> bool {
+    if next == 0 || next > handles.len() {
+        return false;
+    }
+    if next == handles.len() {
+        return handles[1..].iter().all(|handle| handle.handle.is_none());
+    }
+    let sender = &senders[next - 1];
+    (next == 1 || handles[next - 1].handle.is_none()) && sender.capacity() == sender.max_capacity()
+}
+
+/// Ask section, which is `next` in stop order, to stop
+///
+/// Source is stopped last, messages it's still trying to send are discarded, so it can receive stop command.
+async fn stop_section<R: RootChannel>(root_channel: &mut R, gate: &Gate, len: usize, next: usize) {
+    let id = match next == len {
+        true => {
+            gate.set(Gate::DISCARD);
+            0
+        }
+        false => next,
+    };
+    root_channel.send(id as u64, Command::Stop).await.ok();
+}
+
+/// Gate on output of source section
+#[derive(Debug, Clone, Default)]
+struct Gate {
+    inner: Arc<GateInner>,
+}
+
+#[derive(Debug, Default)]
+struct GateInner {
+    state: AtomicU8,
+    waker: AtomicWaker,
+}
+
+impl Gate {
+    /// Messages are passed through
+    const OPEN: u8 = 0;
+
+    /// Messages are held back, source waits until message is sent
+    const HOLD: u8 = 1;
+
+    /// Messages are dropped, dropped messages are not acked, so source can deliver them again after restart
+    const DISCARD: u8 = 2;
+
+    fn set(&self, state: u8) {
+        self.inner.state.store(state, Ordering::SeqCst);
+        self.inner.waker.wake();
+    }
+
+    fn get(&self) -> u8 {
+        self.inner.state.load(Ordering::SeqCst)
+    }
+}
+
+/// Sink, which passes messages to inner sink only while gate is open
+struct GatedSink {
+    inner: DynSink,
+    gate: Gate,
+    /// inner sink was polled for readiness, so next message is passed to it regardless of gate
+    forwarding: bool,
+}
+
+impl GatedSink {
+    fn new(inner: DynSink, gate: Gate) -> Self {
+        Self {
+            inner,
+            gate,
+            forwarding: false,
+        }
+    }
+}
+
+impl Sink<Message> for GatedSink {
+    type Error = SectionError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if !self.forwarding {
+            self.gate.inner.waker.register(cx.waker());
+            match self.gate.get() {
+                Gate::OPEN => self.forwarding = true,
+                Gate::HOLD => return Poll::Pending,
+                _ => return Poll::Ready(Ok(())),
+            }
+        }
+        self.inner.as_mut().poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Message) -> Result<(), Self::Error> {
+        match std::mem::take(&mut self.forwarding) {
+            true => self.inner.as_mut().start_send(item),
+            false => Ok(()),
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.as_mut().poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.as_mut().poll_close(cx)
+    }
+}
+
 // Wrapper around tokio handle
 //
 // Implements custom Drop to abort spawned tasks
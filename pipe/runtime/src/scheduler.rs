@@ -1,10 +1,18 @@
 //! Pipe scheduler
+//!
+//! Scheduler keeps pipe configs, starts pipes and restarts failed ones.
+//! Paused pipes keep their configs, but are not started until resumed.
+//! Pausing pipe stops it gracefully: pipe is asked to stop and aborted only if it doesn't stop in time.
+//! Pipe, resumed while it's still stopping, is started once it stops.
+//! Recent section logs are kept in memory for inspection.
 
+use crate::secrets::{references_secrets, Secrets};
 use crate::storage::Storage;
 use crate::{config::Config, pipe::Pipe, registry::Registry, types::SectionError};
 
 use section::{Command, ReplyTo, RootChannel, Section, SectionChannel, SectionRequest};
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::time::Duration;
 use stub::Stub;
 use tokio::sync::mpsc::WeakSender;
@@ -21,6 +29,75 @@ pub struct Scheduler<T: Storage<<R::SectionChannel as SectionChannel>::State>, R
     pipe_configs: HashMap<u64, Config>,
     pipes: HashMap<u64, Option<JoinHandle<Result<(), SectionError>>>>,
     root_chan: R,
+    /// secrets, referenced by pipe configs
+    secrets: Secrets,
+    /// ids of paused pipes
+    paused: HashSet<u64>,
+    /// pipes, which were asked to stop, and sequence number of their stop timeout
+    stopping: HashMap<u64, u64>,
+    /// sequence number of last stop timeout
+    stop_seq: u64,
+    /// time given to paused pipe to stop, before it's aborted
+    stop_timeout: Duration,
+    /// last error of pipe
+    errors: HashMap<u64, String>,
+    /// recent section logs
+    logs: VecDeque<LogEntry>,
+    /// sequence number of last log entry
+    log_seq: u64,
+}
+
+/// Max amount of log entries, kept by scheduler
+const LOGS_CAPACITY: usize = 1000;
+
+/// Time given to paused pipe to stop, before it's aborted
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Delay of checking stopped pipe again, if its task wasn't finished yet when pipe reported it stopped
+const STOPPED_CHECK_DELAY: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PipeStatus {
+    /// Pipe is running
+    Running,
+
+    /// Pipe was paused
+    Paused,
+
+    /// Pipe failed to start or stopped, and waits to be restarted
+    Stopped,
+}
+
+impl std::fmt::Display for PipeStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = match self {
+            Self::Running => "running",
+            Self::Paused => "paused",
+            Self::Stopped => "stopped",
+        };
+        write!(f, "{status}")
+    }
+}
+
+/// Scheduled pipe
+#[derive(Debug, Clone, Serialize)]
+pub struct PipeInfo {
+    pub id: u64,
+    pub status: PipeStatus,
+    pub config: Config,
+    pub last_error: Option<String>,
+}
+
+/// Log message of pipe section
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogEntry {
+    /// sequence number, increasing
+    pub seq: u64,
+    pub pipe_id: u64,
+    /// rfc3339 timestamp
+    pub time: String,
+    pub message: String,
 }
 
 #[derive(Debug)]
@@ -38,6 +115,12 @@ pub enum Message {
         reply_to: OneshotSender<Result<(), SectionError>>,
     },
 
+    /// Replace secrets, pipes which reference secrets are restarted if secrets changed
+    SetSecrets {
+        secrets: Secrets,
+        reply_to: OneshotSender<Result<(), SectionError>>,
+    },
+
     /// Shutdown scheduler and all pipes
     Shutdown {
         reply_to: OneshotSender<Result<(), SectionError>>,
@@ -48,8 +131,44 @@ pub enum Message {
         reply_to: OneshotSender<Result<Vec<u64>, SectionError>>,
     },
 
+    /// List pipes with status and config
+    ListPipes {
+        reply_to: OneshotSender<Result<Vec<PipeInfo>, SectionError>>,
+    },
+
+    /// Stop pipe gracefully and keep its config
+    ///
+    /// Pipe, which is not added yet, will be added in paused state
+    PausePipe {
+        id: u64,
+        reply_to: OneshotSender<Result<(), SectionError>>,
+    },
+
+    /// Start paused pipe
+    ResumePipe {
+        id: u64,
+        reply_to: OneshotSender<Result<(), SectionError>>,
+    },
+
+    /// Stop and start pipe
+    RestartPipe {
+        id: u64,
+        reply_to: OneshotSender<Result<(), SectionError>>,
+    },
+
+    /// Recent logs with sequence number not less than `since`, at most `limit` latest entries
+    Logs {
+        pipe_id: Option<u64>,
+        since: u64,
+        limit: usize,
+        reply_to: OneshotSender<Result<Vec<LogEntry>, SectionError>>,
+    },
+
     /// Reschedule pipe
     Reschedule { id: u64 },
+
+    /// Abort pipe, if it didn't stop in time
+    StopTimeout { id: u64, seq: u64 },
 }
 
 #[derive(Debug)]
@@ -80,6 +199,14 @@ where
             pipe_configs: HashMap::new(),
             pipes: HashMap::new(),
             root_chan: RootChannel::new(),
+            secrets: Secrets::new(),
+            paused: HashSet::new(),
+            stopping: HashMap::new(),
+            stop_seq: 0,
+            stop_timeout: STOP_TIMEOUT,
+            errors: HashMap::new(),
+            logs: VecDeque::new(),
+            log_seq: 0,
         }
     }
 
@@ -118,11 +245,46 @@ where
                             self.remove_pipe(id).await;
                             reply_to.send(Ok(())).ok();
                         }
+                        Message::SetSecrets { secrets, reply_to } => {
+                            self.set_secrets(secrets).await;
+                            reply_to.send(Ok(())).ok();
+                        }
                         Message::ListIds { reply_to } => {
                             reply_to
                                 .send(Ok(self.pipe_configs.keys().copied().collect()))
                                 .ok();
                         }
+                        Message::ListPipes { reply_to } => {
+                            reply_to.send(Ok(self.list_pipes())).ok();
+                        }
+                        Message::PausePipe { id, reply_to } => {
+                            reply_to.send(self.pause_pipe(id, weak_tx.clone()).await).ok();
+                        }
+                        Message::ResumePipe { id, reply_to } => {
+                            reply_to.send(self.resume_pipe(id).await).ok();
+                        }
+                        Message::RestartPipe { id, reply_to } => {
+                            reply_to.send(self.restart_pipe(id).await).ok();
+                        }
+                        Message::Logs { pipe_id, since, limit, reply_to } => {
+                            reply_to.send(Ok(self.get_logs(pipe_id, since, limit))).ok();
+                        }
+                        Message::StopTimeout{ id, seq } => {
+                            // timeout of earlier stop is ignored, pipe could be resumed and paused again since
+                            if self.stopping.get(&id) == Some(&seq) {
+                                match self.status_of_handle(id) {
+                                    PipeStatus::Running => {
+                                        log::warn!("pipe with id: {id} didn't stop in time, aborting");
+                                    }
+                                    _ => self.retrieve_pipe_error(id).await,
+                                }
+                                self.unschedule(id).await;
+                                // pipe, resumed while stopping, is started again
+                                if let Err(e) = self.schedule(id) {
+                                    log::error!("failed to schedule pipe with id: {id}: {:?}", e);
+                                }
+                            }
+                        }
                         Message::Reschedule{ id } => {
                             if !self.pipes.contains_key(&id) {
                                 self.schedule(id).ok();
@@ -142,18 +304,33 @@ where
                         SectionRequest::Log { id, message } => {
                             // FIXME: use proper logger
                             log::info!("pipe<{id}>: {message}");
+                            self.push_log(id, message);
                         },
                         SectionRequest::Stopped{ id } => {
                             let finished = match self.pipes.get(&id) {
                                 Some(Some(handle)) => handle.is_finished(),
                                 _ => true,
                             };
-                            if finished {
-                                if let Err(err) = self.retrieve_pipe_error(id).await {
-                                    log::error!("pipe with id: {id} stopped: {:?}", err);
-                                };
-                                self.unschedule(id).await;
-                                self.reschedule(id, weak_tx.clone());
+                            match (finished, self.stopping.get(&id).copied()) {
+                                (true, stopping) => {
+                                    self.retrieve_pipe_error(id).await;
+                                    self.unschedule(id).await;
+                                    match (self.paused.contains(&id), stopping) {
+                                        (true, _) => (),
+                                        // pipe was resumed while stopping
+                                        (false, Some(_)) => {
+                                            if let Err(e) = self.schedule(id) {
+                                                log::error!("failed to schedule pipe with id: {id}: {:?}", e);
+                                            }
+                                        }
+                                        (false, None) => self.reschedule(id, weak_tx.clone()),
+                                    }
+                                }
+                                // task of stopping pipe is about to finish
+                                (false, Some(seq)) => {
+                                    self.stop_timeout(id, seq, STOPPED_CHECK_DELAY, weak_tx.clone());
+                                }
+                                (false, None) => (),
                             }
                         },
                         _ => {},
@@ -167,7 +344,8 @@ where
         let schedule_result = match self.pipe_configs.get(&id) {
             Some(c) if c == &config => return Ok(ScheduleResult::Noop),
             Some(_) => {
-                self.remove_pipe(id).await;
+                self.unschedule(id).await;
+                self.errors.remove(&id);
                 ScheduleResult::Updated
             }
             None => ScheduleResult::New,
@@ -178,12 +356,138 @@ where
 
     async fn remove_pipe(&mut self, id: u64) {
         self.pipe_configs.remove(&id);
+        self.paused.remove(&id);
+        self.errors.remove(&id);
+        self.unschedule(id).await;
+    }
+
+    fn list_pipes(&self) -> Vec<PipeInfo> {
+        let mut pipes = self
+            .pipe_configs
+            .iter()
+            .map(|(&id, config)| PipeInfo {
+                id,
+                status: self.status(id),
+                config: config.clone(),
+                last_error: self.errors.get(&id).cloned(),
+            })
+            .collect::<Vec<_>>();
+        pipes.sort_by_key(|pipe| pipe.id);
+        pipes
+    }
+
+    fn status(&self, id: u64) -> PipeStatus {
+        if self.paused.contains(&id) {
+            return PipeStatus::Paused;
+        }
+        self.status_of_handle(id)
+    }
+
+    /// Status of pipe task, regardless of pause
+    fn status_of_handle(&self, id: u64) -> PipeStatus {
+        match self.pipes.get(&id) {
+            Some(Some(handle)) if !handle.is_finished() => PipeStatus::Running,
+            _ => PipeStatus::Stopped,
+        }
+    }
+
+    /// Ask pipe to stop, pipe is aborted if it's still running after stop timeout
+    async fn pause_pipe(
+        &mut self,
+        id: u64,
+        weak_tx: WeakSender<Message>,
+    ) -> Result<(), SectionError> {
+        if !self.paused.insert(id) || !self.pipes.contains_key(&id) {
+            return Ok(());
+        }
+        self.root_chan.send(id, Command::Stop).await.ok();
+        self.stop_seq += 1;
+        self.stopping.insert(id, self.stop_seq);
+        self.stop_timeout(id, self.stop_seq, self.stop_timeout, weak_tx);
+        Ok(())
+    }
+
+    /// Start paused pipe, pipe which is still stopping is started once it stops
+    async fn resume_pipe(&mut self, id: u64) -> Result<(), SectionError> {
+        if !self.pipe_configs.contains_key(&id) {
+            Err(format!("pipe with id {id} doesn't exist"))?
+        }
+        if self.paused.remove(&id) && !self.stopping.contains_key(&id) {
+            self.schedule(id)?;
+        }
+        Ok(())
+    }
+
+    async fn restart_pipe(&mut self, id: u64) -> Result<(), SectionError> {
+        if !self.pipe_configs.contains_key(&id) {
+            Err(format!("pipe with id {id} doesn't exist"))?
+        }
+        if self.paused.contains(&id) {
+            Err(format!("pipe with id {id} is paused"))?
+        }
         self.unschedule(id).await;
+        self.schedule(id)
+    }
+
+    fn push_log(&mut self, pipe_id: u64, message: String) {
+        if self.logs.len() == LOGS_CAPACITY {
+            self.logs.pop_front();
+        }
+        self.log_seq += 1;
+        self.logs.push_back(LogEntry {
+            seq: self.log_seq,
+            pipe_id,
+            time: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            message,
+        });
+    }
+
+    fn get_logs(&self, pipe_id: Option<u64>, since: u64, limit: usize) -> Vec<LogEntry> {
+        let mut logs = self
+            .logs
+            .iter()
+            .rev()
+            .take_while(|entry| entry.seq >= since)
+            .filter(|entry| pipe_id.is_none() || Some(entry.pipe_id) == pipe_id)
+            .take(limit)
+            .cloned()
+            .collect::<Vec<_>>();
+        logs.reverse();
+        logs
     }
 
+    async fn set_secrets(&mut self, secrets: Secrets) {
+        if self.secrets == secrets {
+            return;
+        }
+        self.secrets = secrets;
+        let ids = self
+            .pipe_configs
+            .iter()
+            .filter(|(_, config)| references_secrets(config))
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for id in ids {
+            self.unschedule(id).await;
+            if let Err(e) = self.schedule(id) {
+                log::error!("failed to schedule pipe with id: {id}: {:?}", e);
+            }
+        }
+    }
+
+    /// Start pipe, paused pipes are not started
     fn schedule(&mut self, id: u64) -> Result<(), SectionError> {
+        if self.paused.contains(&id) {
+            return Ok(());
+        }
         if let Some(config) = self.pipe_configs.get(&id).cloned() {
-            let pipe = Pipe::<R>::try_from((&config, &self.registry))?;
+            let pipe = match Pipe::<R>::try_from((&config, &self.registry, &self.secrets)) {
+                Ok(pipe) => pipe,
+                Err(e) => {
+                    self.errors.insert(id, e.to_string());
+                    return Err(e);
+                }
+            };
             let section_chan = self.root_chan.add_section(id)?;
             let pipe = pipe.start(
                 Stub::<_, SectionError>::new(),
@@ -198,6 +502,7 @@ where
 
     /// Stop pipe by removing it from pipes list
     async fn unschedule(&mut self, pipe_id: u64) {
+        self.stopping.remove(&pipe_id);
         self.root_chan.send(pipe_id, Command::Stop).await.ok();
         if let Some(Some(handle)) = self.pipes.remove(&pipe_id) {
             handle.abort();
@@ -205,6 +510,16 @@ where
         self.root_chan.remove_section(pipe_id).ok();
     }
 
+    /// Send stop timeout of pipe after delay, see [`Message::StopTimeout`]
+    fn stop_timeout(&self, id: u64, seq: u64, delay: Duration, weak_tx: WeakSender<Message>) {
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            if let Some(tx) = weak_tx.upgrade() {
+                tx.send(Message::StopTimeout { id, seq }).await.ok();
+            }
+        });
+    }
+
     /// reschedule failed pipe
     fn reschedule(&self, id: u64, weak_tx: WeakSender<Message>) {
         tokio::spawn(async move {
@@ -215,18 +530,22 @@ where
         });
     }
 
-    /// retrieve pipe error, if any
-    async fn retrieve_pipe_error(&mut self, pipe_id: u64) -> Result<(), SectionError> {
-        match self.pipes.get_mut(&pipe_id) {
-            Some(handle) if handle.is_some() => {
-                if handle.as_mut().map(|handle| handle.is_finished()).unwrap() {
-                    let handle = handle.take().unwrap();
-                    handle.await?
-                } else {
-                    Ok(())
-                }
+    /// Retrieve error of finished pipe, if any, and keep it as last error of pipe
+    async fn retrieve_pipe_error(&mut self, pipe_id: u64) {
+        let handle = match self.pipes.get_mut(&pipe_id) {
+            Some(handle) if handle.as_ref().is_some_and(|handle| handle.is_finished()) => {
+                handle.take().unwrap()
             }
-            _ => Ok(()),
+            _ => return,
+        };
+        let result = match handle.await {
+            Ok(result) => result,
+            Err(e) => Err(e.into()),
+        };
+        if let Err(err) = result {
+            log::error!("pipe with id: {pipe_id} stopped: {:?}", err);
+            self.push_log(pipe_id, format!("pipe stopped: {err}"));
+            self.errors.insert(pipe_id, err.to_string());
         }
     }
 }
@@ -281,11 +600,56 @@ impl SchedulerHandle {
         call!(self, Message::RemovePipe { id })
     }
 
+    /// Replace secrets, referenced by pipe configs
+    pub async fn set_secrets(&self, secrets: Secrets) -> Result<(), SectionError> {
+        call!(self, Message::SetSecrets { secrets })
+    }
+
     /// List pipes ids
     pub async fn list_ids(&self) -> Result<Vec<u64>, SectionError> {
         call!(self, Message::ListIds {})
     }
 
+    /// List pipes with status and config
+    pub async fn list_pipes(&self) -> Result<Vec<PipeInfo>, SectionError> {
+        call!(self, Message::ListPipes {})
+    }
+
+    /// Pause pipe
+    ///
+    /// Paused pipe is stopped gracefully, but its config is kept and re-adding pipe with new config
+    /// doesn't start it. Pipe, which is not added yet, will be added in paused state.
+    pub async fn pause_pipe(&self, id: u64) -> Result<(), SectionError> {
+        call!(self, Message::PausePipe { id })
+    }
+
+    /// Resume paused pipe
+    pub async fn resume_pipe(&self, id: u64) -> Result<(), SectionError> {
+        call!(self, Message::ResumePipe { id })
+    }
+
+    /// Restart running pipe
+    pub async fn restart_pipe(&self, id: u64) -> Result<(), SectionError> {
+        call!(self, Message::RestartPipe { id })
+    }
+
+    /// Recent section logs, optionally filtered by pipe id
+    pub async fn logs(
+        &self,
+        pipe_id: Option<u64>,
+        since: u64,
+        limit: usize,
+    ) -> Result<Vec<LogEntry>, SectionError> {
+        call!(
+            self,
+            Message::Logs {
+                pipe_id,
+                since,
+                limit
+            }
+        )
+    }
+
     /// Shutdown scheduler
     pub async fn shutdown(self) -> Result<(), SectionError> {
         call!(self, Message::Shutdown {})
@@ -295,3 +659,393 @@ impl SchedulerHandle {
         Ok(self.tx.send(message).await?)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::command_channel::RootChannel as PipeRootChannel;
+    use crate::config::Map;
+    use crate::message::Message as PipeMessage;
+    use crate::types::{DynSection, DynSink, DynStream, SectionFuture};
+    use futures::{SinkExt, StreamExt};
+    use section::dummy::DummyState;
+    use section::{State, WeakSectionChannel};
+    use std::any::Any;
+    use std::convert::Infallible;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    type TestSectionChannel = <PipeRootChannel<DummyState> as RootChannel>::SectionChannel;
+
+    const TIMEOUT: Duration = Duration::from_secs(1);
+
+    #[derive(Debug, Clone)]
+    struct NoStorage;
+
+    impl Storage<DummyState> for NoStorage {
+        fn store_state(
+            &self,
+            _id: u64,
+            _state: DummyState,
+        ) -> Pin<Box<dyn Future<Output = Result<(), SectionError>> + Send + 'static>> {
+            Box::pin(async { Ok(()) })
+        }
+
+        fn retrieve_state(
+            &self,
+            _id: u64,
+        ) -> Pin<Box<dyn Future<Output = Result<Option<DummyState>, SectionError>> + Send + 'static>>
+        {
+            Box::pin(async { Ok(None) })
+        }
+    }
+
+    /// Counters of section instances
+    struct Counters {
+        running: AtomicUsize,
+        /// instances, stopped on request
+        stopped: AtomicUsize,
+    }
+
+    impl Counters {
+        const fn new() -> Self {
+            Self {
+                running: AtomicUsize::new(0),
+                stopped: AtomicUsize::new(0),
+            }
+        }
+
+        fn get(&self) -> (usize, usize) {
+            (
+                self.running.load(Ordering::SeqCst),
+                self.stopped.load(Ordering::SeqCst),
+            )
+        }
+    }
+
+    /// Decrements running instances, when section is finished or aborted
+    struct Running(&'static Counters);
+
+    impl Drop for Running {
+        fn drop(&mut self) {
+            self.0.running.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Section, which runs until it's asked to stop, or forever if it ignores stop
+    struct Counted {
+        counters: &'static Counters,
+        ignore_stop: bool,
+    }
+
+    impl<S: SectionChannel + Send + 'static> Section<DynStream, DynSink, S> for Counted {
+        type Error = SectionError;
+        type Future = SectionFuture;
+
+        fn start(self, _input: DynStream, _output: DynSink, mut section_chan: S) -> Self::Future {
+            Box::pin(async move {
+                self.counters.running.fetch_add(1, Ordering::SeqCst);
+                let _running = Running(self.counters);
+                loop {
+                    if let (Command::Stop, false) = (section_chan.recv().await?, self.ignore_stop) {
+                        self.counters.stopped.fetch_add(1, Ordering::SeqCst);
+                        return Ok(());
+                    }
+                }
+            })
+        }
+    }
+
+    static GRACEFUL: Counters = Counters::new();
+    static STUBBORN: Counters = Counters::new();
+
+    fn graceful(_: &Map) -> Result<Box<dyn DynSection<TestSectionChannel>>, SectionError> {
+        Ok(Box::new(Counted {
+            counters: &GRACEFUL,
+            ignore_stop: false,
+        }))
+    }
+
+    fn stubborn(_: &Map) -> Result<Box<dyn DynSection<TestSectionChannel>>, SectionError> {
+        Ok(Box::new(Counted {
+            counters: &STUBBORN,
+            ignore_stop: true,
+        }))
+    }
+
+    fn scheduler(stop_timeout: Duration) -> SchedulerHandle {
+        let mut registry = Registry::new();
+        registry.register_section("graceful", graceful);
+        registry.register_section("stubborn", stubborn);
+        let mut scheduler = Scheduler::<_, PipeRootChannel<DummyState>>::new(registry, NoStorage);
+        scheduler.stop_timeout = stop_timeout;
+        scheduler.spawn()
+    }
+
+    /// Wait until counters of section instances are equal to expected
+    async fn wait_for(counters: &Counters, expected: (usize, usize)) {
+        let result = tokio::time::timeout(TIMEOUT, async {
+            while counters.get() != expected {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert!(
+            result.is_ok(),
+            "expected {:?}, got {:?}",
+            expected,
+            counters.get()
+        );
+    }
+
+    async fn status(handle: &SchedulerHandle, id: u64) -> PipeStatus {
+        let pipes = handle.list_pipes().await.unwrap();
+        pipes.iter().find(|pipe| pipe.id == id).unwrap().status
+    }
+
+    #[tokio::test]
+    async fn test_pause_resume() {
+        let handle = scheduler(STOP_TIMEOUT);
+        let config = Config::try_from_json(r#"[{"name": "graceful"}]"#).unwrap();
+        handle.add_pipe(1, config).await.unwrap();
+        wait_for(&GRACEFUL, (1, 0)).await;
+        assert_eq!(status(&handle, 1).await, PipeStatus::Running);
+
+        handle.pause_pipe(1).await.unwrap();
+        wait_for(&GRACEFUL, (0, 1)).await;
+        assert_eq!(status(&handle, 1).await, PipeStatus::Paused);
+
+        handle.resume_pipe(1).await.unwrap();
+        wait_for(&GRACEFUL, (1, 1)).await;
+        assert_eq!(status(&handle, 1).await, PipeStatus::Running);
+
+        // pipe, resumed while stopping, is stopped gracefully and started again
+        handle.pause_pipe(1).await.unwrap();
+        handle.resume_pipe(1).await.unwrap();
+        wait_for(&GRACEFUL, (1, 2)).await;
+        assert_eq!(status(&handle, 1).await, PipeStatus::Running);
+        handle.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_pause_stop_timeout() {
+        let handle = scheduler(Duration::from_millis(100));
+        let config = Config::try_from_json(r#"[{"name": "stubborn"}]"#).unwrap();
+        handle.add_pipe(1, config).await.unwrap();
+        wait_for(&STUBBORN, (1, 0)).await;
+
+        // pipe, which doesn't stop in time, is aborted
+        handle.pause_pipe(1).await.unwrap();
+        assert_eq!(STUBBORN.get(), (1, 0));
+        wait_for(&STUBBORN, (0, 0)).await;
+        assert_eq!(status(&handle, 1).await, PipeStatus::Paused);
+
+        handle.resume_pipe(1).await.unwrap();
+        wait_for(&STUBBORN, (1, 0)).await;
+        handle.shutdown().await.unwrap();
+    }
+
+    /// State, which keeps values of any type
+    #[derive(Clone, Default)]
+    struct AnyState(HashMap<String, Arc<dyn Any + Send + Sync>>);
+
+    impl std::fmt::Debug for AnyState {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_set().entries(self.0.keys()).finish()
+        }
+    }
+
+    impl State for AnyState {
+        type Error = Infallible;
+
+        fn new() -> Self {
+            Self::default()
+        }
+
+        fn get<T: Clone + Send + Sync + 'static>(
+            &self,
+            key: &str,
+        ) -> Result<Option<T>, Infallible> {
+            Ok(self
+                .0
+                .get(key)
+                .and_then(|value| value.downcast_ref::<T>())
+                .cloned())
+        }
+
+        fn set<T: Clone + Send + Sync + 'static>(
+            &mut self,
+            key: &str,
+            value: T,
+        ) -> Result<(), Infallible> {
+            self.0.insert(key.to_string(), Arc::new(value));
+            Ok(())
+        }
+    }
+
+    /// Storage, which keeps pipe states in memory
+    #[derive(Debug, Clone, Default)]
+    struct MemoryStorage(Arc<Mutex<HashMap<u64, AnyState>>>);
+
+    impl Storage<AnyState> for MemoryStorage {
+        fn store_state(
+            &self,
+            id: u64,
+            state: AnyState,
+        ) -> Pin<Box<dyn Future<Output = Result<(), SectionError>> + Send + 'static>> {
+            self.0.lock().unwrap().insert(id, state);
+            Box::pin(async { Ok(()) })
+        }
+
+        fn retrieve_state(
+            &self,
+            id: u64,
+        ) -> Pin<Box<dyn Future<Output = Result<Option<AnyState>, SectionError>> + Send + 'static>>
+        {
+            let state = self.0.lock().unwrap().get(&id).cloned();
+            Box::pin(async { Ok(state) })
+        }
+    }
+
+    type AnySectionChannel = <PipeRootChannel<AnyState> as RootChannel>::SectionChannel;
+
+    static ACKING: Counters = Counters::new();
+
+    /// Messages, received by acking destination
+    static RECEIVED: AtomicU64 = AtomicU64::new(0);
+
+    /// Source, which sends messages until it's asked to stop, and keeps sequence number of last acked message
+    /// in state
+    struct AckingSource;
+
+    impl<S: SectionChannel + Send + 'static> Section<DynStream, DynSink, S> for AckingSource {
+        type Error = SectionError;
+        type Future = SectionFuture;
+
+        fn start(
+            self,
+            _input: DynStream,
+            mut output: DynSink,
+            mut section_chan: S,
+        ) -> Self::Future {
+            Box::pin(async move {
+                ACKING.running.fetch_add(1, Ordering::SeqCst);
+                let _running = Running(&ACKING);
+                let mut state = section_chan
+                    .retrieve_state()
+                    .await?
+                    .unwrap_or(<S::State as State>::new());
+                let mut seq = 0_u64;
+                loop {
+                    let next = seq + 1;
+                    let weak_chan = section_chan.weak_chan();
+                    let batch = arrow::record_batch::RecordBatch::new_empty(Arc::new(
+                        arrow::datatypes::Schema::empty(),
+                    ));
+                    let message = PipeMessage::new(
+                        "test",
+                        batch,
+                        Some(Box::pin(async move { weak_chan.ack(Box::new(next)).await })),
+                    );
+                    tokio::select! {
+                        res = output.send(message) => {
+                            res?;
+                            seq = next;
+                        }
+                        cmd = section_chan.recv() => {
+                            match cmd? {
+                                Command::Ack(ack) => {
+                                    let acked = *ack.downcast::<u64>().map_err(|_| "unexpected ack")?;
+                                    state.set("acked", acked)?;
+                                    section_chan.store_state(state.clone()).await?;
+                                }
+                                Command::Stop => {
+                                    ACKING.stopped.fetch_add(1, Ordering::SeqCst);
+                                    return Ok(());
+                                }
+                                _ => (),
+                            }
+                        }
+                    }
+                }
+            })
+        }
+    }
+
+    /// Destination, which counts and acks received messages
+    struct AckingDestination;
+
+    impl<S: SectionChannel + Send + 'static> Section<DynStream, DynSink, S> for AckingDestination {
+        type Error = SectionError;
+        type Future = SectionFuture;
+
+        fn start(
+            self,
+            mut input: DynStream,
+            _output: DynSink,
+            mut section_chan: S,
+        ) -> Self::Future {
+            Box::pin(async move {
+                ACKING.running.fetch_add(1, Ordering::SeqCst);
+                let _running = Running(&ACKING);
+                loop {
+                    tokio::select! {
+                        Some(mut message) = input.next() => {
+                            RECEIVED.fetch_add(1, Ordering::SeqCst);
+                            message.ack().await;
+                        }
+                        cmd = section_chan.recv() => {
+                            if let Command::Stop = cmd? {
+                                ACKING.stopped.fetch_add(1, Ordering::SeqCst);
+                                return Ok(());
+                            }
+                        }
+                    }
+                }
+            })
+        }
+    }
+
+    fn acking_source(_: &Map) -> Result<Box<dyn DynSection<AnySectionChannel>>, SectionError> {
+        Ok(Box::new(AckingSource))
+    }
+
+    fn acking_destination(_: &Map) -> Result<Box<dyn DynSection<AnySectionChannel>>, SectionError> {
+        Ok(Box::new(AckingDestination))
+    }
+
+    #[tokio::test]
+    async fn test_pause_delivers_acks() {
+        let mut registry = Registry::new();
+        registry.register_section("acking_source", acking_source);
+        registry.register_section("acking_destination", acking_destination);
+        let storage = MemoryStorage::default();
+        let handle =
+            Scheduler::<_, PipeRootChannel<AnyState>>::new(registry, storage.clone()).spawn();
+        let config =
+            Config::try_from_json(r#"[{"name": "acking_source"}, {"name": "acking_destination"}]"#)
+                .unwrap();
+        handle.add_pipe(1, config).await.unwrap();
+        wait_for(&ACKING, (2, 0)).await;
+        let result = tokio::time::timeout(TIMEOUT, async {
+            while RECEIVED.load(Ordering::SeqCst) < 100 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await;
+        assert!(result.is_ok());
+
+        // source is stopped after destination, so it keeps acks of all messages received by destination
+        handle.pause_pipe(1).await.unwrap();
+        wait_for(&ACKING, (0, 2)).await;
+        let state = storage.0.lock().unwrap().get(&1).cloned().unwrap();
+        let source_state = state.get::<AnyState>("0").unwrap().unwrap();
+        assert_eq!(
+            source_state.get::<u64>("acked").unwrap(),
+            Some(RECEIVED.load(Ordering::SeqCst))
+        );
+        handle.shutdown().await.unwrap();
+    }
+}
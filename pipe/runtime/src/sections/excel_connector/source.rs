@@ -3,10 +3,12 @@ use excel_connector::source::Excel;
 use futures::SinkExt;
 use section::Section;
 use section::SectionChannel;
+use serde::{Deserialize, Serialize};
 
 use crate::types::SectionFuture;
 use crate::{
-    config::Map,
+    config::{Map, SectionConfig},
+    config_schema::SectionSchema,
     types::{DynSection, DynSink, DynStream, SectionError},
 };
 
@@ -30,7 +32,8 @@ impl<SectionChan: SectionChannel + Send + 'static> Section<DynStream, DynSink, S
         Box::pin(async move {
             let output = output.with(|message: excel_connector::Message| async {
                 let payload: RecordBatch = message.payload.try_into()?;
-                let message = Message::new(message.origin, payload, message.ack);
+                let message =
+                    Message::new(message.origin, payload, message.ack).with_offset(message.offset);
                 Ok(message)
             });
             self.inner.start(input, output, section_channel).await
@@ -38,27 +41,37 @@ impl<SectionChan: SectionChannel + Send + 'static> Section<DynStream, DynSink, S
     }
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SourceConfig {
+    pub path: String,
+    // FIXME: Use correct input config values for excel
+    pub sheets: String,
+}
+
+impl SectionConfig for SourceConfig {
+    const DESCRIPTIONS: &'static [(&'static str, &'static str)] = &[
+        ("path", "path to excel workbook"),
+        ("sheets", "comma-separated list of sheets"),
+    ];
+}
+
 /// constructor for Excel
 pub fn constructor<S: SectionChannel>(
     config: &Map,
 ) -> Result<Box<dyn DynSection<S>>, SectionError> {
-    // FIXME: Use correct input config values for excel
+    let config = SourceConfig::from_map(config)?;
     let sheets = config
-        .get("sheets")
-        .ok_or("excel section requires 'sheets'")?
-        .as_str()
-        .ok_or("'sheets' should be string")?;
-    let path = config
-        .get("path")
-        .ok_or("excel section requires 'path'")?
-        .as_str()
-        .ok_or("path should be string")?;
-    let sheets = sheets
+        .sheets
         .split(',')
         .map(|x| x.trim())
         .filter(|x| !x.is_empty())
         .collect::<Vec<&str>>();
     Ok(Box::new(ExcelAdapter {
-        inner: Excel::new(path, sheets.as_slice()),
+        inner: Excel::new(config.path.as_str(), sheets.as_slice()),
     }))
 }
+
+/// config schema of excel_connector_source section
+pub fn schema() -> Result<SectionSchema, SectionError> {
+    SectionSchema::of::<SourceConfig>("excel_connector_source", "Reads sheets of excel workbook")
+}
@@ -4,12 +4,14 @@
 //! then forwards the message on to the next section.
 use futures::{FutureExt, Sink, SinkExt, Stream, StreamExt};
 use section::{Command, Section, SectionChannel};
+use serde::{Deserialize, Serialize};
 use std::future::Future;
 
 use std::pin::{pin, Pin};
 
 use crate::{
-    config::Map,
+    config::{Map, SectionConfig},
+    config_schema::SectionSchema,
     message::Message,
     types::{DynSection, SectionError},
 };
@@ -79,8 +81,20 @@ where
     }
 }
 
+/// hello world destination has no configuration
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DestinationConfig {}
+
+impl SectionConfig for DestinationConfig {}
+
 pub fn constructor<S: SectionChannel>(
-    _config: &Map,
+    config: &Map,
 ) -> Result<Box<dyn DynSection<S>>, SectionError> {
+    DestinationConfig::from_map(config)?;
     Ok(Box::new(HelloWorld::new()))
 }
+
+/// config schema of hello_world_destination section
+pub fn schema() -> Result<SectionSchema, SectionError> {
+    SectionSchema::of::<DestinationConfig>("hello_world_destination", "Logs incoming messages")
+}
@@ -4,12 +4,14 @@
 //! Since it is a source, this section ignores the input stream, and writes its message to the output stream.
 use super::HelloWorldPayload;
 use crate::{
-    config::Map,
+    config::{Map, SectionConfig},
+    config_schema::SectionSchema,
     message::{Message, RecordBatch},
     types::{DynSection, SectionError, SectionFuture},
 };
 use futures::{FutureExt, Sink, SinkExt, Stream};
 use section::{Command, Section, SectionChannel};
+use serde::{Deserialize, Serialize};
 use tokio::time;
 
 use std::pin::pin;
@@ -88,20 +90,35 @@ where
     }
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SourceConfig {
+    pub message: String,
+    pub interval_milis: i64,
+}
+
+impl SectionConfig for SourceConfig {
+    const DESCRIPTIONS: &'static [(&'static str, &'static str)] = &[
+        ("message", "message prefix"),
+        (
+            "interval_milis",
+            "interval between messages in milliseconds",
+        ),
+    ];
+}
+
 pub fn constructor<S: SectionChannel>(
     config: &Map,
 ) -> Result<Box<dyn DynSection<S>>, SectionError> {
-    let message = config
-        .get("message")
-        .ok_or("hello world section requires 'message'")?
-        .as_str()
-        .ok_or("'message' should be a string")?;
-    let interval_milis = config
-        .get("interval_milis")
-        .ok_or("hello world section requires 'interval_milis'")?
-        .as_int()
-        .ok_or("'interval_milis' should be an int")?;
-    Ok(Box::new(HelloWorld::new(message, interval_milis)))
+    let config = SourceConfig::from_map(config)?;
+    Ok(Box::new(HelloWorld::new(
+        config.message,
+        config.interval_milis,
+    )))
+}
+
+/// config schema of hello_world_source section
+pub fn schema() -> Result<SectionSchema, SectionError> {
+    SectionSchema::of::<SourceConfig>("hello_world_source", "Emits numbered message on interval")
 }
 
 #[cfg(test)]
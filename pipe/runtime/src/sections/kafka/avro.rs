@@ -0,0 +1,190 @@
+//! Avro encoding with Confluent compatible schema registry
+//!
+//! Each row is encoded as avro datum, prefixed with magic byte and schema id:
+//! `| 0u8 | schema id, 4 bytes big endian | avro datum |`
+use std::collections::HashMap;
+
+use crate::types::SectionError;
+use apache_avro::{to_avro_datum, types::Value, Schema as AvroSchema};
+use arrow::{
+    array::{Array, AsArray},
+    datatypes::{
+        DataType, Float32Type, Float64Type, Int16Type, Int32Type, Int64Type, Int8Type, Schema,
+        UInt16Type, UInt32Type, UInt8Type,
+    },
+    record_batch::RecordBatch,
+    util::display::array_value_to_string,
+};
+use serde_json::json;
+
+/// Generate avro record schema for arrow schema
+///
+/// All fields are nullable.
+pub fn schema(name: &str, schema: &Schema) -> String {
+    let fields = schema
+        .fields()
+        .iter()
+        .map(|field| {
+            json!({
+                "name": avro_name(field.name()),
+                "type": ["null", avro_type(field.data_type())],
+                "default": null,
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({
+        "type": "record",
+        "name": avro_name(name),
+        "fields": fields,
+    })
+    .to_string()
+}
+
+/// Encode each row of record batch as avro datum
+pub fn encode(schema: &str, batch: &RecordBatch) -> Result<Vec<Vec<u8>>, SectionError> {
+    let schema = AvroSchema::parse_str(schema)?;
+    let names = batch
+        .schema()
+        .fields()
+        .iter()
+        .map(|field| avro_name(field.name()))
+        .collect::<Vec<_>>();
+    (0..batch.num_rows())
+        .map(|row| {
+            let fields = names
+                .iter()
+                .zip(batch.columns())
+                .map(|(name, column)| Ok((name.clone(), avro_value(column.as_ref(), row)?)))
+                .collect::<Result<Vec<_>, SectionError>>()?;
+            Ok(to_avro_datum(&schema, Value::Record(fields))?)
+        })
+        .collect()
+}
+
+/// Prefix avro datum with magic byte and schema id
+pub fn wire_format(id: u32, datum: Vec<u8>) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(datum.len() + 5);
+    buf.push(0);
+    buf.extend_from_slice(&id.to_be_bytes());
+    buf.extend(datum);
+    buf
+}
+
+/// Avro names should match `[A-Za-z_][A-Za-z0-9_]*`
+fn avro_name(name: &str) -> String {
+    let mut name = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        name.insert(0, '_');
+    }
+    name
+}
+
+fn avro_type(data_type: &DataType) -> &'static str {
+    match data_type {
+        DataType::Boolean => "boolean",
+        DataType::Int8
+        | DataType::Int16
+        | DataType::Int32
+        | DataType::Int64
+        | DataType::UInt8
+        | DataType::UInt16
+        | DataType::UInt32 => "long",
+        DataType::Float32 | DataType::Float64 => "double",
+        DataType::Binary | DataType::LargeBinary => "bytes",
+        // everything else is encoded as string representation
+        _ => "string",
+    }
+}
+
+/// Convert arrow value into nullable avro value
+fn avro_value(column: &dyn Array, row: usize) -> Result<Value, SectionError> {
+    if column.is_null(row) {
+        return Ok(Value::Union(0, Box::new(Value::Null)));
+    }
+    let value = match column.data_type() {
+        DataType::Boolean => Value::Boolean(column.as_boolean().value(row)),
+        DataType::Int8 => Value::Long(column.as_primitive::<Int8Type>().value(row) as i64),
+        DataType::Int16 => Value::Long(column.as_primitive::<Int16Type>().value(row) as i64),
+        DataType::Int32 => Value::Long(column.as_primitive::<Int32Type>().value(row) as i64),
+        DataType::Int64 => Value::Long(column.as_primitive::<Int64Type>().value(row)),
+        DataType::UInt8 => Value::Long(column.as_primitive::<UInt8Type>().value(row) as i64),
+        DataType::UInt16 => Value::Long(column.as_primitive::<UInt16Type>().value(row) as i64),
+        DataType::UInt32 => Value::Long(column.as_primitive::<UInt32Type>().value(row) as i64),
+        DataType::Float32 => Value::Double(column.as_primitive::<Float32Type>().value(row) as f64),
+        DataType::Float64 => Value::Double(column.as_primitive::<Float64Type>().value(row)),
+        DataType::Binary => Value::Bytes(column.as_binary::<i32>().value(row).to_vec()),
+        DataType::LargeBinary => Value::Bytes(column.as_binary::<i64>().value(row).to_vec()),
+        DataType::Utf8 => Value::String(column.as_string::<i32>().value(row).into()),
+        DataType::LargeUtf8 => Value::String(column.as_string::<i64>().value(row).into()),
+        _ => Value::String(array_value_to_string(column, row)?),
+    };
+    Ok(Value::Union(1, Box::new(value)))
+}
+
+/// Confluent compatible schema registry client
+#[derive(Debug)]
+pub struct SchemaRegistry {
+    url: String,
+    client: reqwest::Client,
+
+    /// cache of schema ids, keyed by subject and schema
+    ids: HashMap<(String, String), u32>,
+}
+
+impl SchemaRegistry {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into().trim_end_matches('/').into(),
+            client: reqwest::Client::new(),
+            ids: HashMap::new(),
+        }
+    }
+
+    /// Look up schema id under given subject, register schema if it's not known to registry
+    pub async fn schema_id(&mut self, subject: &str, schema: &str) -> Result<u32, SectionError> {
+        let key = (subject.to_string(), schema.to_string());
+        if let Some(id) = self.ids.get(&key) {
+            return Ok(*id);
+        }
+        let id = match self
+            .request(format!("{}/subjects/{subject}", self.url), schema)
+            .await?
+        {
+            Some(id) => id,
+            None => self
+                .request(format!("{}/subjects/{subject}/versions", self.url), schema)
+                .await?
+                .ok_or(format!("failed to register schema for subject '{subject}'"))?,
+        };
+        self.ids.insert(key, id);
+        Ok(id)
+    }
+
+    /// Post schema to registry endpoint, returns `None` if subject or schema was not found
+    async fn request(&self, url: String, schema: &str) -> Result<Option<u32>, SectionError> {
+        let response = self
+            .client
+            .post(url)
+            .header("Content-Type", "application/vnd.schemaregistry.v1+json")
+            .body(json!({ "schema": schema }).to_string())
+            .send()
+            .await?;
+        match response.status().as_u16() {
+            200 => (),
+            404 => return Ok(None),
+            status => Err(format!(
+                "schema registry request failed with status {status}: {}",
+                response.text().await?
+            ))?,
+        };
+        let body: serde_json::Value = serde_json::from_slice(&response.bytes().await?)?;
+        let id = body
+            .get("id")
+            .and_then(|id| id.as_u64())
+            .ok_or("schema registry response doesn't contain schema id")?;
+        Ok(Some(id as u32))
+    }
+}
@@ -1,26 +1,30 @@
 //! Kafka Destination section implementation
 //! CAUTION: ALPHA QUALITY CODE :) Use with caution.
 //!
-//! Receives a message, converts the arrow record batch to json, and outputs it to the configured kafka topic
+//! Receives a message, encodes the arrow record batch with configured format, and outputs it to the configured kafka topic
 use crate::message::Message;
 use futures::StreamExt;
 use kafka::destination::Kafka;
 use section::Section;
+use std::pin::pin;
 use stub::Stub;
+use tokio_stream::wrappers::ReceiverStream;
 
 use crate::types::SectionFuture;
 use crate::{
-    config::Map,
+    config::{Map, SectionConfig},
+    config_schema::SectionSchema,
     types::{DynSection, DynSink, DynStream, SectionError},
 };
 
 use section::SectionChannel;
+use serde::{Deserialize, Serialize};
 
-use super::OwnedMessageNewType;
+use super::{Encoder, Format};
 
-#[allow(dead_code)]
 pub struct KafkaAdapter {
     inner: Kafka,
+    encoder: Encoder,
 }
 
 impl<SectionChan: SectionChannel + Send + 'static> Section<DynStream, DynSink, SectionChan>
@@ -31,36 +35,153 @@ impl<SectionChan: SectionChannel + Send + 'static> Section<DynStream, DynSink, S
 
     fn start(
         self,
-        input: DynStream,
+        mut input: DynStream,
         _output: DynSink,
         section_channel: SectionChan,
     ) -> Self::Future {
         Box::pin(async move {
-            let input = input.map(|message: Message| {
-                let kafka_payload: OwnedMessageNewType = (&message.payload).into();
-                kafka::Message::new(message.origin, kafka_payload.0, message.ack)
-            });
+            let KafkaAdapter { inner, mut encoder } = self;
+            // encoding is async (schema registry lookups) and fallible, so it's done outside of input stream
+            let (tx, rx) = tokio::sync::mpsc::channel(1);
+            let encode = async move {
+                while let Some(message) = input.next().await {
+                    let records = encoder.encode(&message.origin, &message.payload).await?;
+                    let message = kafka::Message::new(message.origin, records, message.ack);
+                    if tx.send(message).await.is_err() {
+                        // kafka section is stopped
+                        break;
+                    }
+                }
+                Ok::<(), SectionError>(())
+            };
             let output = Stub::<kafka::Message, SectionError>::new();
-            self.inner.start(input, output, section_channel).await
+            let mut section = inner.start(ReceiverStream::new(rx), output, section_channel);
+            let mut encode = pin!(encode);
+            tokio::select! {
+                // sender is dropped with encode future, kafka section delivers in-flight message and exits
+                res = &mut encode => {
+                    res?;
+                    section.await
+                },
+                res = &mut section => res,
+            }
         })
     }
 }
 
+/// name of record format, see [`Format`]
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FormatName {
+    Json,
+    #[default]
+    JsonLines,
+    ArrowIpc,
+    Csv,
+    Avro,
+}
+
+impl FormatName {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::JsonLines => "json_lines",
+            Self::ArrowIpc => "arrow_ipc",
+            Self::Csv => "csv",
+            Self::Avro => "avro",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DestinationConfig {
+    pub brokers: String,
+    pub topic: String,
+    #[serde(default)]
+    pub format: FormatName,
+    pub schema_registry_url: Option<String>,
+    pub key: Option<String>,
+    pub partition_column: Option<String>,
+    pub partitioner: Option<String>,
+}
+
+impl SectionConfig for DestinationConfig {
+    const DESCRIPTIONS: &'static [(&'static str, &'static str)] = &[
+        ("brokers", "comma-separated list of brokers"),
+        (
+            "topic",
+            "topic template with {origin} and {<column name>} placeholders",
+        ),
+        ("format", "record format"),
+        (
+            "schema_registry_url",
+            "schema registry url, required for avro format",
+        ),
+        ("key", "comma-separated list of key columns"),
+        ("partition_column", "column with explicit partition number"),
+        ("partitioner", "librdkafka partitioner"),
+    ];
+
+    fn validate(&self) -> Result<(), SectionError> {
+        if self.format == FormatName::Avro && self.schema_registry_url.is_none() {
+            Err("avro format requires 'schema_registry_url'")?
+        }
+        Ok(())
+    }
+}
+
 /// constructor for kafka destination
+///
+/// # Config example:
+/// ```toml
+/// [[section]]
+/// name = "kafka_destination"
+/// brokers = "localhost:9092"
+/// # topic template, `{origin}` and `{<column name>}` are replaced with message origin and row values
+/// topic = "{origin}.events"
+/// # optional, one of json, json_lines, arrow_ipc, csv, avro, defaults to json_lines
+/// # json and avro produce record per row, other formats - record per group of rows with the same topic, key and partition
+/// format = "avro"
+/// # required for avro format
+/// schema_registry_url = "http://localhost:8081"
+/// # optional, comma-separated list of columns used as record key, message origin is used by default
+/// key = "id"
+/// # optional, column with explicit partition number
+/// partition_column = "partition"
+/// # optional, librdkafka partitioner used for records without explicit partition
+/// partitioner = "murmur2_random"
+/// ```
 pub fn constructor<S: SectionChannel>(
     config: &Map,
 ) -> Result<Box<dyn DynSection<S>>, SectionError> {
-    let brokers = config
-        .get("brokers")
-        .ok_or("kafka destination section requires 'brokers'")?
-        .as_str()
-        .ok_or("brokers should be string")?;
-    let topic = config
-        .get("topic")
-        .ok_or("kafka destination section requires 'topic'")?
-        .as_str()
-        .ok_or("topic should be string")?;
+    let config = DestinationConfig::from_map(config)?;
+    let key_columns: Vec<&str> = config
+        .key
+        .as_deref()
+        .unwrap_or("")
+        .split(',')
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .collect();
     Ok(Box::new(KafkaAdapter {
-        inner: Kafka::new(brokers, topic)?,
+        inner: Kafka::new(
+            &config.brokers,
+            &config.topic,
+            config.partitioner.as_deref(),
+        )?,
+        encoder: Encoder::new(
+            &config.topic,
+            Format::new(
+                config.format.as_str(),
+                config.schema_registry_url.as_deref(),
+            )?,
+            key_columns.as_slice(),
+            config.partition_column.as_deref(),
+        ),
     }))
 }
+
+/// config schema of kafka_destination section
+pub fn schema() -> Result<SectionSchema, SectionError> {
+    SectionSchema::of::<DestinationConfig>("kafka_destination", "Produces messages to kafka topic")
+}
@@ -1,27 +1,412 @@
 // Kafka section implementation details
 // CAUTION: ALPHA QUALITY CODE :) Use with caution.
+pub mod avro;
 pub mod destination;
 
-use crate::message::RecordBatch;
-use rdkafka::message::{OwnedMessage, Timestamp};
+use std::collections::HashMap;
+use std::sync::Arc;
 
+use crate::types::SectionError;
+use arrow::{
+    array::{Array, ArrayRef, AsArray, StringArray, UInt32Array},
+    compute::take,
+    datatypes::{DataType, Field, Schema},
+    ipc::writer::StreamWriter,
+    record_batch::RecordBatch,
+    util::display::array_value_to_string,
+};
+use base64::engine::{general_purpose::STANDARD as BASE64, Engine};
+use kafka::Record;
+use serde_json::json;
+
+use self::avro::SchemaRegistry;
+
+/// Record serialization format
+#[derive(Debug)]
+pub enum Format {
+    /// json object per row
+    Json,
+
+    /// json lines, record per batch
+    JsonLines,
+
+    /// arrow ipc stream, record per batch
+    ArrowIpc,
+
+    /// csv with header, record per batch
+    Csv,
+
+    /// avro datum per row, prefixed with schema id from confluent compatible schema registry
+    Avro(SchemaRegistry),
+}
+
+impl Format {
+    pub fn new(format: &str, schema_registry_url: Option<&str>) -> Result<Self, SectionError> {
+        let format = match format {
+            "json" => Format::Json,
+            "json_lines" => Format::JsonLines,
+            "arrow_ipc" => Format::ArrowIpc,
+            "csv" => Format::Csv,
+            "avro" => {
+                let url =
+                    schema_registry_url.ok_or("avro format requires 'schema_registry_url'")?;
+                Format::Avro(SchemaRegistry::new(url))
+            }
+            format => Err(format!("unsupported format: {format}"))?,
+        };
+        Ok(format)
+    }
+}
+
+/// Format name, used in record headers
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Format::Json => "json",
+            Format::JsonLines => "json_lines",
+            Format::ArrowIpc => "arrow_ipc",
+            Format::Csv => "csv",
+            Format::Avro(_) => "avro",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Where row should be delivered to
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Route {
+    topic: String,
+    key: Option<Vec<u8>>,
+    partition: Option<i32>,
+}
+
+/// Encodes record batches into kafka records
+///
+/// Each row is routed by topic template, key columns and partition column.
+/// Row formats (json, avro) produce record per row, batch formats produce record per group of rows with
+/// the same route.
 #[derive(Debug)]
-#[repr(transparent)]
-pub struct OwnedMessageNewType(OwnedMessage);
-
-impl From<&RecordBatch> for OwnedMessageNewType {
-    fn from(arrow_rb: &RecordBatch) -> Self {
-        println!("arrow_rb: {:?}", arrow_rb);
-        let buf = Vec::new();
-        let mut writer = arrow::json::LineDelimitedWriter::new(buf);
-        // todo:  this couldn't handle a "binary" column type
-        // `JsonError("data type Binary not supported in nested map for json writer")`
-        writer.write(arrow_rb).unwrap();
-        writer.finish().unwrap();
-        let buf = writer.into_inner();
-
-        // TODO: Basic message works for now, but need to figure ou tif we need to think about the values in any field other than `payload`
-        let payload = OwnedMessage::new(Some(buf), None, "".into(), Timestamp::now(), 0, 0, None);
-        OwnedMessageNewType(payload)
+pub struct Encoder {
+    /// topic template, `{origin}` and `{<column name>}` placeholders are replaced with message origin
+    /// and row values
+    topic: String,
+    format: Format,
+
+    /// columns, which values are used as record key, message origin is used as a key if empty
+    key_columns: Vec<String>,
+
+    /// column with explicit partition number
+    partition_column: Option<String>,
+}
+
+impl Encoder {
+    pub fn new(
+        topic: impl Into<String>,
+        format: Format,
+        key_columns: &[&str],
+        partition_column: Option<&str>,
+    ) -> Self {
+        Self {
+            topic: topic.into(),
+            format,
+            key_columns: key_columns.iter().map(|&x| x.into()).collect(),
+            partition_column: partition_column.map(Into::into),
+        }
+    }
+
+    pub async fn encode(
+        &mut self,
+        origin: &str,
+        batch: &RecordBatch,
+    ) -> Result<Vec<Record>, SectionError> {
+        let routes = (0..batch.num_rows())
+            .map(|row| self.route(origin, batch, row))
+            .collect::<Result<Vec<_>, _>>()?;
+        let headers = vec![
+            ("origin".to_string(), origin.as_bytes().to_vec()),
+            ("format".to_string(), self.format.to_string().into_bytes()),
+            ("schema".to_string(), schema_header(batch.schema().as_ref())),
+        ];
+        let record = |route: Route, headers: Vec<(String, Vec<u8>)>, payload: Vec<u8>| Record {
+            topic: Some(route.topic),
+            key: route.key,
+            partition: route.partition,
+            headers,
+            payload,
+        };
+        let records = match &mut self.format {
+            Format::Json => {
+                // json lines writer escapes new lines in values, so each line is exactly one row
+                let payload = to_json_lines(batch)?;
+                payload
+                    .split(|&b| b == b'\n')
+                    .filter(|line| !line.is_empty())
+                    .zip(routes)
+                    .map(|(line, route)| record(route, headers.clone(), line.to_vec()))
+                    .collect()
+            }
+            Format::Avro(registry) => {
+                let schema = avro::schema(origin, batch.schema().as_ref());
+                let payloads = avro::encode(&schema, batch)?;
+                let mut records = Vec::with_capacity(payloads.len());
+                for (payload, route) in payloads.into_iter().zip(routes) {
+                    // subject follows topic name strategy
+                    let subject = format!("{}-value", route.topic);
+                    let id = registry.schema_id(&subject, &schema).await?;
+                    let mut headers = headers.clone();
+                    headers.push(("schema_id".to_string(), id.to_string().into_bytes()));
+                    records.push(record(route, headers, avro::wire_format(id, payload)));
+                }
+                records
+            }
+            format => {
+                let mut records = vec![];
+                for (route, indices) in group(routes) {
+                    let batch = match indices.len() == batch.num_rows() {
+                        true => batch.clone(),
+                        false => take_rows(batch, indices)?,
+                    };
+                    let payload = match format {
+                        Format::JsonLines => to_json_lines(&batch)?,
+                        Format::ArrowIpc => {
+                            let mut writer =
+                                StreamWriter::try_new(vec![], batch.schema().as_ref())?;
+                            writer.write(&batch)?;
+                            writer.finish()?;
+                            writer.into_inner()?
+                        }
+                        Format::Csv => {
+                            let mut writer = arrow::csv::Writer::new(vec![]);
+                            writer.write(&encode_binary_columns(&batch)?)?;
+                            writer.into_inner()
+                        }
+                        Format::Json | Format::Avro(_) => unreachable!(),
+                    };
+                    records.push(record(route, headers.clone(), payload));
+                }
+                records
+            }
+        };
+        Ok(records)
+    }
+
+    fn route(&self, origin: &str, batch: &RecordBatch, row: usize) -> Result<Route, SectionError> {
+        let mut topic = String::new();
+        let mut template = self.topic.as_str();
+        while let Some(start) = template.find('{') {
+            topic.push_str(&template[..start]);
+            let end = template[start..]
+                .find('}')
+                .ok_or("unclosed '{' in topic template")?
+                + start;
+            match &template[start + 1..end] {
+                "origin" => topic.push_str(origin),
+                column => topic.push_str(&column_value(batch, column, row)?),
+            };
+            template = &template[end + 1..];
+        }
+        topic.push_str(template);
+
+        let key = match self.key_columns.is_empty() {
+            true => None,
+            false => Some(
+                self.key_columns
+                    .iter()
+                    .map(|column| column_value(batch, column, row))
+                    .collect::<Result<Vec<_>, _>>()?
+                    .join(":")
+                    .into_bytes(),
+            ),
+        };
+        let partition = match self.partition_column.as_deref() {
+            Some(column) => Some(
+                column_value(batch, column, row)?
+                    .parse::<i32>()
+                    .map_err(|_| format!("partition column '{column}' should contain integers"))?,
+            ),
+            None => None,
+        };
+        Ok(Route {
+            topic,
+            key,
+            partition,
+        })
+    }
+}
+
+/// Group row indices by route, preserving order of first occurrence
+fn group(routes: Vec<Route>) -> Vec<(Route, Vec<u32>)> {
+    let mut positions: HashMap<Route, usize> = HashMap::new();
+    let mut groups: Vec<(Route, Vec<u32>)> = vec![];
+    for (row, route) in routes.into_iter().enumerate() {
+        match positions.get(&route) {
+            Some(&pos) => groups[pos].1.push(row as u32),
+            None => {
+                positions.insert(route.clone(), groups.len());
+                groups.push((route, vec![row as u32]));
+            }
+        }
+    }
+    groups
+}
+
+fn take_rows(batch: &RecordBatch, indices: Vec<u32>) -> Result<RecordBatch, SectionError> {
+    let indices = UInt32Array::from(indices);
+    let columns = batch
+        .columns()
+        .iter()
+        .map(|column| take(column.as_ref(), &indices, None))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(RecordBatch::try_new(batch.schema(), columns)?)
+}
+
+/// String representation of row value
+fn column_value(batch: &RecordBatch, column: &str, row: usize) -> Result<String, SectionError> {
+    let index = batch
+        .schema()
+        .index_of(column)
+        .map_err(|_| format!("column '{column}' not found"))?;
+    let array = batch.column(index);
+    if array.is_null(row) {
+        Err(format!("column '{column}' contains NULL"))?
+    }
+    Ok(array_value_to_string(array, row)?)
+}
+
+/// Arrow schema description: list of column names, types and nullability
+fn schema_header(schema: &Schema) -> Vec<u8> {
+    let fields = schema
+        .fields()
+        .iter()
+        .map(|field| {
+            json!({
+                "name": field.name(),
+                "type": field.data_type().to_string(),
+                "nullable": field.is_nullable(),
+            })
+        })
+        .collect::<Vec<_>>();
+    serde_json::Value::Array(fields).to_string().into_bytes()
+}
+
+fn to_json_lines(batch: &RecordBatch) -> Result<Vec<u8>, SectionError> {
+    let mut writer = arrow::json::LineDelimitedWriter::new(vec![]);
+    writer.write(&encode_binary_columns(batch)?)?;
+    writer.finish()?;
+    Ok(writer.into_inner())
+}
+
+/// Replace binary columns with base64 encoded strings
+///
+/// Json and csv writers don't support binary data types.
+fn encode_binary_columns(batch: &RecordBatch) -> Result<RecordBatch, SectionError> {
+    let schema = batch.schema();
+    let (fields, columns): (Vec<Field>, Vec<ArrayRef>) = schema
+        .fields()
+        .iter()
+        .zip(batch.columns())
+        .map(|(field, column)| {
+            let encoded: Option<StringArray> = match field.data_type() {
+                DataType::Binary => Some(
+                    column
+                        .as_binary::<i32>()
+                        .iter()
+                        .map(|value| value.map(|value| BASE64.encode(value)))
+                        .collect(),
+                ),
+                DataType::LargeBinary => Some(
+                    column
+                        .as_binary::<i64>()
+                        .iter()
+                        .map(|value| value.map(|value| BASE64.encode(value)))
+                        .collect(),
+                ),
+                _ => None,
+            };
+            match encoded {
+                Some(array) => (
+                    Field::new(field.name(), DataType::Utf8, field.is_nullable()),
+                    Arc::new(array) as ArrayRef,
+                ),
+                None => (field.as_ref().clone(), Arc::clone(column)),
+            }
+        })
+        .unzip();
+    Ok(RecordBatch::try_new(
+        Arc::new(Schema::new(fields)),
+        columns,
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::{BinaryArray, Int64Array};
+
+    type StdError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+    #[tokio::test]
+    async fn test_json_encoding_with_binary_column() -> Result<(), StdError> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("data", DataType::Binary, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(Int64Array::from(vec![1, 2])),
+                Arc::new(BinaryArray::from(vec![Some(b"hi".as_slice()), None])),
+            ],
+        )?;
+        let mut encoder =
+            Encoder::new("{origin}.events", Format::new("json", None)?, &["id"], None);
+        let records = encoder.encode("origin", &batch).await?;
+        assert_eq!(
+            records
+                .iter()
+                .map(|r| (r.topic.as_deref(), r.key.as_deref(), r.payload.as_slice()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    Some("origin.events"),
+                    Some(b"1".as_slice()),
+                    br#"{"id":1,"data":"aGk="}"#.as_slice()
+                ),
+                (
+                    Some("origin.events"),
+                    Some(b"2".as_slice()),
+                    br#"{"id":2}"#.as_slice()
+                ),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_batch_format_groups_rows_by_route() -> Result<(), StdError> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("region", DataType::Utf8, false),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(Int64Array::from(vec![1, 2, 3])),
+                Arc::new(StringArray::from(vec!["eu", "us", "eu"])),
+            ],
+        )?;
+        let mut encoder = Encoder::new("{region}", Format::new("csv", None)?, &[], None);
+        let records = encoder.encode("origin", &batch).await?;
+        assert_eq!(
+            records
+                .iter()
+                .map(|r| (r.topic.as_deref(), r.payload.as_slice()))
+                .collect::<Vec<_>>(),
+            vec![
+                (Some("eu"), b"id,region\n1,eu\n3,eu\n".as_slice()),
+                (Some("us"), b"id,region\n2,us\n".as_slice()),
+            ]
+        );
+        Ok(())
     }
 }
@@ -6,3 +6,44 @@ pub mod postgres_connector;
 pub mod snowflake;
 pub mod sqlite_connector;
 pub mod sqlite_physical_replication;
+
+use crate::config_schema::SectionSchema;
+use crate::types::SectionError;
+
+/// Config schemas of all sections
+pub fn schemas() -> Result<Vec<SectionSchema>, SectionError> {
+    [
+        sqlite_connector::source::schema(),
+        sqlite_connector::destination::schema(),
+        sqlite_physical_replication::source::schema(),
+        sqlite_physical_replication::destination::schema(),
+        mycelial_server::source::schema(),
+        mycelial_server::destination::schema(),
+        hello_world::source::schema(),
+        hello_world::destination::schema(),
+        excel_connector::source::schema(),
+        kafka::destination::schema(),
+        snowflake::source::schema(),
+        snowflake::destination::schema(),
+        postgres_connector::destination::schema(),
+    ]
+    .into_iter()
+    .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn trace_section_schemas() {
+        let schemas = schemas().unwrap();
+        let mut names = schemas
+            .iter()
+            .map(|schema| schema.name.as_str())
+            .collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), schemas.len());
+    }
+}
@@ -1,10 +1,23 @@
 //! Mycelial Net
 //!
 //! network section, dumps incoming messages to provided http endpoint
-use arrow::ipc::writer::StreamWriter;
+//!
+//! Messages are sent as arrow ipc streams, optionally with LZ4 or ZSTD compressed ipc buffers.
+//!
+//! Each message carries producer message id, which stays the same across retries, so server
+//! can deduplicate messages which were delivered more than once within its deduplication window.
+//! Producer id is generated on first start and kept in section state, configured producer id is used as its prefix.
+//! If source assigns offset to message, producer message id consists of producer id, message origin and offset,
+//! so message redelivered by source after restart gets the same id.
+//! Otherwise producer message id consists of producer id and sequence number kept in section state: sequence
+//! number is stored before upstream ack is persisted, so message redelivered after restart gets new id and can be
+//! ingested twice.
+use arrow::ipc::writer::{IpcWriteOptions, StreamWriter};
+use arrow::ipc::CompressionType;
 use bytes::Bytes;
 use futures::{FutureExt, Sink, Stream, StreamExt};
-use section::{Command, Section, SectionChannel};
+use section::{Command, Section, SectionChannel, State};
+use serde::{Deserialize, Serialize};
 use std::future::Future;
 
 use base64::engine::{general_purpose::STANDARD as BASE64, Engine};
@@ -12,7 +25,8 @@ use std::pin::{pin, Pin};
 use std::time::Duration;
 
 use crate::{
-    config::Map,
+    config::{Map, SectionConfig},
+    config_schema::SectionSchema,
     message::Message,
     types::{DynSection, SectionError},
 };
@@ -22,6 +36,8 @@ pub struct Mycelial {
     endpoint: String,
     token: String,
     topic: String,
+    compression: Option<CompressionType>,
+    producer_id: Option<String>,
 }
 
 impl Mycelial {
@@ -29,11 +45,15 @@ impl Mycelial {
         endpoint: impl Into<String>,
         token: impl Into<String>,
         topic: impl Into<String>,
+        compression: Option<CompressionType>,
+        producer_id: Option<impl Into<String>>,
     ) -> Self {
         Self {
             endpoint: endpoint.into(),
             token: token.into(),
             topic: topic.into(),
+            compression,
+            producer_id: producer_id.map(Into::into),
         }
     }
 
@@ -50,6 +70,29 @@ impl Mycelial {
     {
         let mut input = pin!(input.fuse());
         let client = &mut reqwest::Client::new();
+        let mut state = section_chan.retrieve_state().await?.unwrap_or(State::new());
+        // producer id is always generated and kept in state, so two sections configured with the same producer id
+        // don't produce colliding message ids, configured producer id is used only as prefix
+        let producer_id = match (
+            self.producer_id.as_deref(),
+            state.get::<String>("producer_id")?,
+        ) {
+            (None, Some(producer_id)) => producer_id,
+            (Some(prefix), Some(producer_id)) if producer_id.starts_with(&format!("{prefix}-")) => {
+                producer_id
+            }
+            (prefix, _) => {
+                let producer_id = match prefix {
+                    Some(prefix) => format!("{prefix}-{}", uuid::Uuid::new_v4()),
+                    None => uuid::Uuid::new_v4().to_string(),
+                };
+                state.set("producer_id", producer_id.clone())?;
+                section_chan.store_state(state.clone()).await?;
+                producer_id
+            }
+        };
+        // sequence number of last delivered message without offset
+        let mut seq = state.get::<u64>("seq")?.unwrap_or(0);
         loop {
             futures::select! {
                 msg = input.next() => {
@@ -57,15 +100,11 @@ impl Mycelial {
                         Some(msg) => msg,
                         None => Err("input stream closed")?
                     };
-                    // FIXME: error / unwrap
-                    let mut stream_writer: StreamWriter<_> =
-                        StreamWriter::try_new(vec![], msg.payload.0.schema().as_ref()).unwrap();
-
-                    // FIXME: unwrap
-                    stream_writer.write(&msg.payload).unwrap();
-                    stream_writer.finish().unwrap();
-
-                    let bytes: Bytes = stream_writer.into_inner().unwrap().into();
+                    let bytes = self.encode(&msg)?;
+                    let message_id = match msg.offset.as_deref() {
+                        Some(offset) => format!("{producer_id}:{}:{offset}", msg.origin),
+                        None => format!("{producer_id}:{}", seq + 1),
+                    };
                     loop {
                         match client
                             .post(format!(
@@ -75,6 +114,7 @@ impl Mycelial {
                             ))
                             .header("Authorization", self.basic_auth())
                             .header("x-message-origin", &msg.origin)
+                            .header("x-producer-message-id", &message_id)
                             .body(bytes.clone())
                             .send()
                             .await
@@ -83,10 +123,29 @@ impl Mycelial {
                                 section_chan.log(format!("failed to push message: {:?}", e)).await?;
                                 tokio::time::sleep(Duration::from_secs(3)).await;
                             }
-                            Ok(res) if res.status() == 200 => break,
-                            Ok(res) => Err(format!("unexpected status code: {}", res.status()))?,
+                            Ok(res) if res.status() == 200 => {
+                                let duplicate = res
+                                    .headers()
+                                    .get("x-message-duplicate")
+                                    .map(|v| v == "true")
+                                    .unwrap_or(false);
+                                if duplicate {
+                                    section_chan.log(format!("message {message_id} was already delivered")).await?;
+                                }
+                                break
+                            },
+                            Ok(res) => {
+                                let status = res.status();
+                                let reason = res.text().await.unwrap_or_default();
+                                Err(format!("unexpected status code: {status}, {reason}"))?
+                            },
                         }
                     }
+                    if msg.offset.is_none() {
+                        seq += 1;
+                        state.set("seq", seq)?;
+                        section_chan.store_state(state.clone()).await?;
+                    }
                     msg.ack().await;
                 },
                 cmd = section_chan.recv().fuse() => {
@@ -99,6 +158,16 @@ impl Mycelial {
         }
     }
 
+    /// Encode message payload into arrow ipc stream
+    fn encode(&self, msg: &Message) -> Result<Bytes, SectionError> {
+        let options = IpcWriteOptions::default().try_with_compression(self.compression)?;
+        let mut stream_writer: StreamWriter<_> =
+            StreamWriter::try_new_with_options(vec![], msg.payload.0.schema().as_ref(), options)?;
+        stream_writer.write(&msg.payload)?;
+        stream_writer.finish()?;
+        Ok(stream_writer.into_inner()?.into())
+    }
+
     fn basic_auth(&self) -> String {
         format!("Basic {}", BASE64.encode(format!("{}:", self.token)))
     }
@@ -119,6 +188,39 @@ where
     }
 }
 
+/// compression of ipc buffers
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Lz4,
+    Zstd,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DestinationConfig {
+    pub endpoint: String,
+    pub token: String,
+    pub topic: String,
+    #[serde(default)]
+    pub compression: Compression,
+    pub producer_id: Option<String>,
+}
+
+impl SectionConfig for DestinationConfig {
+    const DESCRIPTIONS: &'static [(&'static str, &'static str)] = &[
+        ("endpoint", "ingestion endpoint url"),
+        ("token", "server token"),
+        ("topic", "topic name"),
+        ("compression", "compression of ipc buffers"),
+        (
+            "producer_id",
+            "prefix of producer id used in producer message ids",
+        ),
+    ];
+}
+
 /// constructor for mycelial net section
 ///
 /// # Config example:
@@ -127,24 +229,33 @@ where
 /// name = "mycelial_net"
 /// endpoint = "http://localhost:7777/ingestion"
 /// token = "token"
+/// # optional, compression of ipc buffers: "none", "lz4" or "zstd", defaults to "none"
+/// compression = "zstd"
+/// # optional, prefix of producer id used in producer message ids, producer id is generated and kept in section state
+/// producer_id = "producer"
 /// ```
 pub fn constructor<S: SectionChannel>(
     config: &Map,
 ) -> Result<Box<dyn DynSection<S>>, SectionError> {
-    let endpoint = config
-        .get("endpoint")
-        .ok_or("mycelial net section requires 'endpoint' url")?
-        .as_str()
-        .ok_or("endpoint should be string")?;
-    let token = config
-        .get("token")
-        .ok_or("mycelial net section requires 'token'")?
-        .as_str()
-        .ok_or("token should be string")?;
-    let topic = config
-        .get("topic")
-        .ok_or("mycelial net section requires 'topic'")?
-        .as_str()
-        .ok_or("topic should be string")?;
-    Ok(Box::new(Mycelial::new(endpoint, token, topic)))
+    let config = DestinationConfig::from_map(config)?;
+    let compression = match config.compression {
+        Compression::None => None,
+        Compression::Lz4 => Some(CompressionType::LZ4_FRAME),
+        Compression::Zstd => Some(CompressionType::ZSTD),
+    };
+    Ok(Box::new(Mycelial::new(
+        config.endpoint,
+        config.token,
+        config.topic,
+        compression,
+        config.producer_id,
+    )))
+}
+
+/// config schema of mycelial_server_destination section
+pub fn schema() -> Result<SectionSchema, SectionError> {
+    SectionSchema::of::<DestinationConfig>(
+        "mycelial_server_destination",
+        "Publishes messages to mycelial server topic",
+    )
 }
@@ -1,20 +1,33 @@
 //! Mycelial Net
+//!
+//! If consumer id is configured - acked offsets are committed to server, and offset committed to server is used
+//! when section has no local state.
+//! Commits are coalesced: latest acked offset is committed once per [`COMMIT_INTERVAL`] and on stop.
+//!
+//! Server reports schema version of fetched records, versions are tracked per origin of topic. Schema changes are
+//! logged and, depending on configuration, either passed downstream or stop the section.
 
 use crate::{
-    config::Map,
+    config::{Map, SectionConfig},
+    config_schema::SectionSchema,
     message::{Message, RecordBatch},
     types::{DynSection, SectionError, SectionFuture},
 };
 use arrow::ipc::reader::StreamReader;
-use futures::{stream::FusedStream, FutureExt};
-use futures::{Sink, SinkExt, Stream, StreamExt};
+use futures::FutureExt;
+use futures::{Sink, SinkExt, Stream};
 use reqwest::Client;
 use section::{Command, Section, SectionChannel, State, WeakSectionChannel};
-use tokio::time::{Instant, Interval};
+use serde::{Deserialize, Serialize};
 
 use base64::engine::{general_purpose::STANDARD as BASE64, Engine};
-use std::pin::{pin, Pin};
+use std::collections::HashMap;
+use std::pin::pin;
 use std::time::Duration;
+use tokio::time::Instant;
+
+/// Interval between commits of acked offsets to server
+const COMMIT_INTERVAL: Duration = Duration::from_secs(5);
 
 #[derive(Debug)]
 pub struct Mycelial {
@@ -26,42 +39,28 @@ pub struct Mycelial {
 
     /// topic
     topic: String,
-}
 
-struct IntervalStream {
-    delay: Duration,
-    interval: Interval,
-}
+    /// max number of records fetched per request
+    batch_size: u64,
 
-impl IntervalStream {
-    /// Create a new `IntervalStream`.
-    pub fn new(delay: Duration) -> Self {
-        Self {
-            delay,
-            interval: tokio::time::interval(delay),
-        }
-    }
+    /// max size of records fetched per request in bytes
+    max_bytes: u64,
 
-    pub fn reset(&mut self) {
-        self.interval = tokio::time::interval(self.delay)
-    }
-}
+    /// time server waits for new records before returning empty response
+    wait: Duration,
 
-impl FusedStream for IntervalStream {
-    fn is_terminated(&self) -> bool {
-        false
-    }
-}
+    /// consumer id, under which offsets are committed to server
+    consumer_id: Option<String>,
 
-impl Stream for IntervalStream {
-    type Item = Instant;
+    /// stop section if topic schema changes
+    fail_on_schema_change: bool,
+}
 
-    fn poll_next(
-        mut self: Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-    ) -> std::task::Poll<Option<Self::Item>> {
-        self.interval.poll_tick(cx).map(Some)
-    }
+/// Records fetched from server
+struct Batch {
+    origin: String,
+    schema_version: Option<u64>,
+    records: Vec<(u64, RecordBatch)>,
 }
 
 impl Mycelial {
@@ -69,11 +68,21 @@ impl Mycelial {
         endpoint: impl Into<String>,
         token: impl Into<String>,
         topic: impl Into<String>,
+        batch_size: u64,
+        max_bytes: u64,
+        wait: Duration,
+        consumer_id: Option<impl Into<String>>,
+        fail_on_schema_change: bool,
     ) -> Self {
         Self {
             endpoint: endpoint.into(),
             token: token.into(),
             topic: topic.into(),
+            batch_size,
+            max_bytes,
+            wait,
+            consumer_id: consumer_id.map(Into::into),
+            fail_on_schema_change,
         }
     }
 
@@ -89,20 +98,73 @@ impl Mycelial {
         SectionChan: SectionChannel + Send + 'static,
     {
         let mut output = pin!(output);
-        let mut client = reqwest::Client::new();
-        let mut interval = pin!(IntervalStream::new(Duration::from_secs(3)));
+        let client = reqwest::Client::new();
+        // delay before next request
+        let mut delay: Option<Duration> = None;
         let mut state = section_chan.retrieve_state().await?.unwrap_or(State::new());
-        let mut offset = state.get::<u64>(&self.topic)?.unwrap_or(0);
+        // schema version of last fetched records per origin
+        let mut schema_versions: HashMap<String, u64> = HashMap::new();
+        let mut offset = match state.get::<u64>(&self.topic)? {
+            Some(offset) => offset,
+            None => self.get_committed_offset(&client).await?.unwrap_or(0),
+        };
+        // acked offset, which is not committed to server yet, and time of next commit
+        let mut uncommitted: Option<u64> = None;
+        let mut commit_at: Option<Instant> = None;
         loop {
+            // request is dropped if command arrives while server is long polling
+            let (this, client, cur_delay, cur_offset) = (&self, &client, delay, offset);
+            let mut fetch = pin!(async move {
+                if let Some(delay) = cur_delay {
+                    tokio::time::sleep(delay).await;
+                }
+                this.get_next_batch(client, cur_offset).await
+            }
+            .fuse());
+            let mut commit_timer = pin!(async move {
+                match commit_at {
+                    Some(commit_at) => tokio::time::sleep_until(commit_at).await,
+                    None => futures::future::pending().await,
+                }
+            }
+            .fuse());
             futures::select! {
-                _ = interval.next() => {
-                    match self.get_next_batch(&mut client, &section_chan, &mut offset).await {
-                        Ok(Some(msg)) => {
-                            output.send(msg).await.ok();
-                            interval.reset();
+                res = fetch => {
+                    match res {
+                        Ok(Some(batch)) => {
+                            if let Some(new) = batch.schema_version {
+                                match schema_versions.insert(batch.origin.clone(), new) {
+                                    Some(prev) if prev != new => {
+                                        section_chan.log(format!(
+                                            "schema of topic '{}' origin '{}' changed from version {prev} to {new}",
+                                            self.topic, batch.origin
+                                        )).await?;
+                                        if self.fail_on_schema_change {
+                                            Err(format!("schema of topic '{}' changed", self.topic))?
+                                        }
+                                    },
+                                    _ => (),
+                                }
+                            }
+                            for (id, record_batch) in batch.records {
+                                let weak_chan = section_chan.weak_chan();
+                                let message = Message::new(
+                                    batch.origin.as_str(),
+                                    record_batch,
+                                    Some(Box::pin(async move { weak_chan.ack(Box::new(id)).await })),
+                                ).with_offset(Some(id.to_string()));
+                                output.send(message).await?;
+                                offset = id;
+                            }
+                            delay = None;
+                        },
+                        // server already waited for new records, no need to delay next request
+                        Ok(None) if !self.wait.is_zero() => delay = None,
+                        Ok(None) => delay = Some(Duration::from_secs(3)),
+                        Err(e) => {
+                            section_chan.log(format!("failed to retrieve next batch: {:?}", e)).await?;
+                            delay = Some(Duration::from_secs(3));
                         },
-                        Ok(None) => (),
-                        Err(e) => section_chan.log(format!("failed to retrieve next batch: {:?}", e)).await?,
                     }
                 },
                 cmd = section_chan.recv().fuse() => {
@@ -112,27 +174,46 @@ impl Mycelial {
                                 Ok(offset) => {
                                     state.set(&self.topic, *offset)?;
                                     section_chan.store_state(state.clone()).await?;
+                                    if self.consumer_id.is_some() {
+                                        uncommitted = Some(*offset);
+                                        commit_at = commit_at.or_else(|| Some(Instant::now() + COMMIT_INTERVAL));
+                                    }
                                 },
                                 Err(_) =>
                                     break Err("Failed to downcast incoming Ack message to SqliteRecordBatch".into()),
                             };
                         },
                         Command::Stop => {
+                            if let Some(offset) = uncommitted {
+                                if let Err(e) = self.commit_offset(&client, offset).await {
+                                    section_chan.log(format!("failed to commit offset: {:?}", e)).await?;
+                                }
+                            }
                             return Ok(())
                         },
                         _ => (),
                     }
-                }
+                },
+                _ = commit_timer => {
+                    commit_at = None;
+                    // local state is source of truth, failed commit is retried on next tick
+                    if let Some(offset) = uncommitted.take() {
+                        if let Err(e) = self.commit_offset(&client, offset).await {
+                            section_chan.log(format!("failed to commit offset: {:?}", e)).await?;
+                            uncommitted = Some(offset);
+                            commit_at = Some(Instant::now() + COMMIT_INTERVAL);
+                        }
+                    }
+                },
             }
         }
     }
 
-    async fn get_next_batch<SectionChan: SectionChannel>(
+    async fn get_next_batch(
         &self,
-        client: &mut Client,
-        section_chan: &SectionChan,
-        offset: &mut u64,
-    ) -> Result<Option<Message>, SectionError> {
+        client: &Client,
+        offset: u64,
+    ) -> Result<Option<Batch>, SectionError> {
         let res = client
             .get(format!(
                 "{}/{}/{}",
@@ -140,47 +221,116 @@ impl Mycelial {
                 self.topic,
                 offset
             ))
+            .query(&[
+                ("limit", self.batch_size),
+                ("max_bytes", self.max_bytes),
+                ("wait", self.wait.as_secs()),
+            ])
             .header("Authorization", self.basic_auth())
             .send()
             .await?;
 
         let origin = match res.headers().get("x-message-origin") {
             None => Err("response needs to have x-message-origin header")?,
-            Some(v) => v.to_str().unwrap().to_string(),
+            Some(v) => v.to_str()?.to_string(),
         };
 
-        let maybe_new_offset = match res.headers().get("x-message-id") {
+        let maybe_new_offset: u64 = match res.headers().get("x-message-id") {
             None => Err("response needs to have x-message-id header")?,
-            // FIXME: unwrap
-            Some(v) => v.to_str().unwrap().parse().unwrap(),
+            Some(v) => v.to_str()?.parse()?,
         };
 
-        if maybe_new_offset == *offset {
+        if maybe_new_offset == offset {
             return Ok(None);
         }
-        *offset = maybe_new_offset;
+
+        // older servers don't track schema versions
+        let schema_version = match res.headers().get("x-message-schema-version") {
+            Some(v) if !v.is_empty() => Some(v.to_str()?.parse::<u64>()?),
+            _ => None,
+        };
+
+        // older servers return single record without list of ids
+        let ids = match res.headers().get("x-message-ids") {
+            None => vec![maybe_new_offset],
+            Some(v) => v
+                .to_str()?
+                .split(',')
+                .map(|id| id.parse::<u64>())
+                .collect::<Result<Vec<_>, _>>()?,
+        };
 
         // FIXME: it's not possible to stream to arrow's StreamReader directl
         // StreamReader expects sync std::io::Read implementation
-
         let body = res.bytes().await?.to_vec();
-        // FIXME: unwrap
-        let reader = StreamReader::try_new(body.as_slice(), None).unwrap();
-        let weak_chan = section_chan.weak_chan();
-        let vec = reader.collect::<Result<Vec<_>, _>>()?;
-        match vec {
-            mut vec if vec.len() == 1 => {
-                let batch = RecordBatch(vec.pop().unwrap());
-                let o = *offset;
-                let message = Message::new(
-                    origin,
-                    batch,
-                    Some(Box::pin(async move { weak_chan.ack(Box::new(o)).await })),
-                );
-                Ok(Some(message))
-            }
-            _ => Err("multiple batches are not supported")?,
+        let reader = StreamReader::try_new(body.as_slice(), None)?;
+        let batches = reader.collect::<Result<Vec<_>, _>>()?;
+        if batches.len() != ids.len() {
+            Err(format!(
+                "response contains {} batches, but {} record ids",
+                batches.len(),
+                ids.len()
+            ))?
         }
+        Ok(Some(Batch {
+            origin,
+            schema_version,
+            records: ids
+                .into_iter()
+                .zip(batches.into_iter().map(RecordBatch))
+                .collect(),
+        }))
+    }
+
+    fn consumer_url(&self, consumer_id: &str) -> String {
+        format!(
+            "{}/{}/consumers/{}",
+            self.endpoint.as_str().trim_end_matches('/'),
+            self.topic,
+            consumer_id
+        )
+    }
+
+    /// Get offset committed to server by consumer
+    async fn get_committed_offset(&self, client: &Client) -> Result<Option<u64>, SectionError> {
+        let consumer_id = match self.consumer_id.as_deref() {
+            Some(consumer_id) => consumer_id,
+            None => return Ok(None),
+        };
+        let res = client
+            .get(self.consumer_url(consumer_id))
+            .header("Authorization", self.basic_auth())
+            .send()
+            .await?;
+        match res.status().as_u16() {
+            200 => (),
+            404 => return Ok(None),
+            status => Err(format!("unexpected status code: {status}"))?,
+        };
+        let body: serde_json::Value = serde_json::from_slice(&res.bytes().await?)?;
+        let offset = body["offset"]
+            .as_u64()
+            .ok_or("committed offset should be unsigned int")?;
+        Ok(Some(offset))
+    }
+
+    /// Commit acked offset to server
+    async fn commit_offset(&self, client: &Client, offset: u64) -> Result<(), SectionError> {
+        let consumer_id = match self.consumer_id.as_deref() {
+            Some(consumer_id) => consumer_id,
+            None => return Ok(()),
+        };
+        let res = client
+            .put(self.consumer_url(consumer_id))
+            .header("Authorization", self.basic_auth())
+            .header("Content-Type", "application/json")
+            .body(serde_json::json!({ "offset": offset }).to_string())
+            .send()
+            .await?;
+        if res.status() != 200 {
+            Err(format!("unexpected status code: {}", res.status()))?
+        }
+        Ok(())
     }
 
     fn basic_auth(&self) -> String {
@@ -203,6 +353,74 @@ where
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnSchemaChange {
+    /// pass records downstream
+    Pass,
+    /// stop section
+    Fail,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SourceConfig {
+    pub endpoint: String,
+    pub token: String,
+    // FIXME: validate topic is not empty
+    pub topic: String,
+    #[serde(default = "default_batch_size")]
+    pub batch_size: u64,
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: u64,
+    #[serde(default = "default_wait")]
+    pub wait: u64,
+    pub consumer_id: Option<String>,
+    #[serde(default = "default_on_schema_change")]
+    pub on_schema_change: OnSchemaChange,
+}
+
+fn default_batch_size() -> u64 {
+    100
+}
+
+fn default_max_bytes() -> u64 {
+    8 * 1024 * 1024
+}
+
+fn default_wait() -> u64 {
+    30
+}
+
+fn default_on_schema_change() -> OnSchemaChange {
+    OnSchemaChange::Pass
+}
+
+impl SectionConfig for SourceConfig {
+    const DESCRIPTIONS: &'static [(&'static str, &'static str)] = &[
+        ("endpoint", "ingestion endpoint url"),
+        ("token", "server token"),
+        ("topic", "topic name"),
+        ("batch_size", "max number of records fetched per request"),
+        (
+            "max_bytes",
+            "max size of records fetched per request in bytes",
+        ),
+        ("wait", "seconds server waits for new records"),
+        (
+            "consumer_id",
+            "consumer id, acked offsets are committed to server",
+        ),
+        ("on_schema_change", "action on topic schema change"),
+    ];
+
+    fn validate(&self) -> Result<(), SectionError> {
+        if self.batch_size == 0 || self.max_bytes == 0 {
+            Err("batch_size and max_bytes should be positive")?
+        }
+        Ok(())
+    }
+}
+
 /// constructor for mycelial net section
 ///
 /// # Config example:
@@ -212,25 +430,40 @@ where
 /// endpoint = "http://localhost:7777/ingestion"
 /// token = "token"
 /// topic = "some_topic"
+/// # optional, max number of records fetched per request, defaults to 100
+/// batch_size = 100
+/// # optional, max size of records fetched per request in bytes, defaults to 8MiB
+/// max_bytes = 8388608
+/// # optional, seconds server waits for new records (long polling), defaults to 30
+/// wait = 30
+/// # optional, consumer id, acked offsets are committed to server and used if section has no local state
+/// consumer_id = "edge-node-1"
+/// # optional, action on topic schema change: "pass" records downstream or "fail", defaults to "pass"
+/// on_schema_change = "pass"
 /// ```
 pub fn constructor<S: SectionChannel>(
     config: &Map,
 ) -> Result<Box<dyn DynSection<S>>, SectionError> {
-    let endpoint = config
-        .get("endpoint")
-        .ok_or("mycelial net section requires 'endpoint' url")?
-        .as_str()
-        .ok_or("endpoint should be string")?;
-    let token = config
-        .get("token")
-        .ok_or("mycelian net section requires 'token'")?
-        .as_str()
-        .ok_or("token should be string")?;
-    // FIXME: validate topic is not empty
-    let topic = config
-        .get("topic")
-        .ok_or("mycelian net section requires 'topic'")?
-        .as_str()
-        .ok_or("token should be string")?;
-    Ok(Box::new(Mycelial::new(endpoint, token, topic)))
+    let config = SourceConfig::from_map(config)?;
+    let consumer_id = config
+        .consumer_id
+        .filter(|consumer_id| !consumer_id.is_empty());
+    Ok(Box::new(Mycelial::new(
+        config.endpoint,
+        config.token,
+        config.topic,
+        config.batch_size,
+        config.max_bytes,
+        Duration::from_secs(config.wait),
+        consumer_id,
+        config.on_schema_change == OnSchemaChange::Fail,
+    )))
+}
+
+/// config schema of mycelial_server_source section
+pub fn schema() -> Result<SectionSchema, SectionError> {
+    SectionSchema::of::<SourceConfig>(
+        "mycelial_server_source",
+        "Consumes records of mycelial server topic",
+    )
 }
@@ -1,16 +1,24 @@
-use arrow::datatypes::{DataType, SchemaRef};
+use arrow::array::{ArrayRef, Int64Array};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
 use futures::{FutureExt, Sink, Stream, StreamExt};
 use parquet::arrow::AsyncArrowWriter;
 use parquet::errors::ParquetError;
 use section::{Command, Section, SectionChannel};
+use serde::{Deserialize, Serialize};
 use snowflake_api::{SnowflakeApi, SnowflakeApiError};
 use std::pin::pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tempfile::tempdir;
 use thiserror::Error;
 use tokio::fs::File;
 
 use crate::{
-    config::Map,
+    config::{Map, SectionConfig},
+    config_schema::SectionSchema,
     message::Message,
     types::{DynSection, SectionError, SectionFuture},
 };
@@ -26,6 +34,22 @@ pub enum SnowflakeDestinationError {
 
     #[error(transparent)]
     IoError(#[from] std::io::Error),
+
+    #[error(transparent)]
+    ArrowError(#[from] ArrowError),
+}
+
+/// Column with load sequence of staged rows, used to pick latest row per merge key
+const LOAD_SEQ_COLUMN: &str = "MYCELIAL_LOAD_SEQ";
+
+/// Load mode of snowflake destination
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadMode {
+    /// Append loaded rows to destination table
+    Append,
+
+    /// Merge loaded rows into destination table by key columns
+    Merge { keys: Vec<String> },
 }
 
 pub struct SnowflakeDestination {
@@ -38,6 +62,11 @@ pub struct SnowflakeDestination {
     schema: String,
     // destination
     table: String,
+    mode: LoadMode,
+    // max amount of messages, staged in single file
+    batch_size: usize,
+    // max time message can wait in buffer before load
+    flush_interval: Duration,
 }
 
 impl SnowflakeDestination {
@@ -51,6 +80,9 @@ impl SnowflakeDestination {
         database: impl Into<String>,
         schema: impl Into<String>,
         table: impl Into<String>,
+        mode: LoadMode,
+        batch_size: usize,
+        flush_interval: Duration,
     ) -> Self {
         Self {
             username: username.into(),
@@ -61,6 +93,9 @@ impl SnowflakeDestination {
             database: database.into(),
             schema: schema.into(),
             table: table.into(),
+            mode,
+            batch_size: batch_size.max(1),
+            flush_interval,
         }
     }
 
@@ -86,6 +121,9 @@ impl SnowflakeDestination {
             &self.password,
         )?;
 
+        // messages are acked only after buffer was loaded into snowflake
+        let mut buffer: Vec<Message> = Vec::with_capacity(self.batch_size);
+        let mut flush_interval = pin!(tokio::time::interval(self.flush_interval));
         loop {
             futures::select! {
                 cmd = section_chan.recv().fuse() => {
@@ -93,63 +131,132 @@ impl SnowflakeDestination {
                         return Ok(())
                     }
                 },
+                _ = flush_interval.tick().fuse() => {
+                    self.flush(&mut api, &mut buffer).await?;
+                },
                 msg = input.next() => {
-                    if msg.is_none() {
-                        return Ok(())
+                    let msg = match msg {
+                        Some(msg) => msg,
+                        None => return Ok(()),
+                    };
+                    // batches with different schemas can't be staged in the same file
+                    if matches!(buffer.first(), Some(first) if first.payload.schema() != msg.payload.schema()) {
+                        self.flush(&mut api, &mut buffer).await?;
+                    }
+                    buffer.push(msg);
+                    if buffer.len() >= self.batch_size {
+                        self.flush(&mut api, &mut buffer).await?;
+                        flush_interval.reset();
                     }
-                    let mut msg = msg.unwrap();
-                    self.destructive_load_batch(&mut api, &msg.payload).await?;
-                    msg.ack().await;
                 }
             }
         }
     }
 
-    async fn destructive_load_batch(
+    /// Load buffered messages and ack them
+    async fn flush(
+        &self,
+        api: &mut SnowflakeApi,
+        buffer: &mut Vec<Message>,
+    ) -> Result<(), SnowflakeDestinationError> {
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        let batches = buffer.iter().map(|msg| &msg.payload.0).collect::<Vec<_>>();
+        self.load_batches(api, batches.as_slice()).await?;
+        for mut msg in buffer.drain(..) {
+            msg.ack().await;
+        }
+        Ok(())
+    }
+
+    async fn load_batches(
         &self,
         api: &mut SnowflakeApi,
-        batch: &arrow::record_batch::RecordBatch,
+        batches: &[&RecordBatch],
     ) -> Result<(), SnowflakeDestinationError> {
-        // fixme: race condition on multiple batches in succession, disambiguate file names?
+        let schema = batches[0].schema();
         let tmp_dir = tempdir()?;
-        let file_path = &tmp_dir.path().join("mycelial.parquet");
+        let file_name = staged_file_name()?;
+        let file_path = &tmp_dir.path().join(&file_name);
         log::info!(
-            "Dumping batch to parquet file: {}",
-            file_path.to_str().unwrap()
+            "Dumping {} batches to parquet file: {}",
+            batches.len(),
+            file_path.display()
         );
         let mut tmp_file = File::create(file_path).await?;
 
-        let mut writer = AsyncArrowWriter::try_new(&mut tmp_file, batch.schema(), 0, None)?;
-        writer.write(batch).await?;
+        // merged rows carry load sequence, so latest row wins if merge key is staged more than once
+        let staged = match &self.mode {
+            LoadMode::Append => batches.iter().map(|&batch| batch.clone()).collect(),
+            LoadMode::Merge { .. } => with_load_seq(batches)?,
+        };
+        let mut writer = AsyncArrowWriter::try_new(&mut tmp_file, staged[0].schema(), 0, None)?;
+        for batch in staged.iter() {
+            writer.write(batch).await?;
+        }
         writer.close().await?;
 
         // todo: use load and select into custom stage
         let table_name = self.table.as_str();
-        let schema = self.arrow_schema_to_snowflake_schema(batch.schema());
+        let columns = self.arrow_schema_to_snowflake_schema(schema.clone());
         api.exec(&format!(
             "CREATE TABLE IF NOT EXISTS {}({});",
-            table_name, schema
+            table_name, columns
         ))
         .await?;
 
-        // fixme: unwrap
-        api.exec(&format!(
-            "PUT file://{} @%{};",
-            &file_path.to_str().unwrap(),
-            table_name
-        ))
-        .await?;
+        let file_path = file_path.to_str().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "staged file path is not valid utf-8: {}",
+                    file_path.display()
+                ),
+            )
+        })?;
+        api.exec(&format!("PUT file://{} @%{};", file_path, table_name))
+            .await?;
 
         api.exec(
             "CREATE OR REPLACE TEMPORARY FILE FORMAT CUSTOM_PARQUET_FORMAT TYPE = PARQUET COMPRESSION = NONE TRIM_SPACE = TRUE REPLACE_INVALID_CHARACTERS = TRUE BINARY_AS_TEXT = FALSE;"
         ).await?;
 
-        api.exec(&format!("TRUNCATE TABLE {};", table_name)).await?;
-
-        api.exec(
-            &format!("COPY INTO {} FILE_FORMAT = CUSTOM_PARQUET_FORMAT PURGE = TRUE MATCH_BY_COLUMN_NAME = CASE_INSENSITIVE;", table_name)
-        ).await?;
-
+        // staged file can be compressed by PUT, so it's matched by pattern instead of exact name
+        let copy_into = |target: &str| {
+            format!(
+                "COPY INTO {} FROM @%{} PATTERN = '.*{}.*' FILE_FORMAT = CUSTOM_PARQUET_FORMAT PURGE = TRUE MATCH_BY_COLUMN_NAME = CASE_INSENSITIVE;",
+                target, table_name, file_name
+            )
+        };
+        match &self.mode {
+            LoadMode::Append => {
+                api.exec(&copy_into(table_name)).await?;
+            }
+            LoadMode::Merge { keys } => {
+                let staging_table = format!("{}_MYCELIAL_STAGING", table_name);
+                api.exec(&format!(
+                    "CREATE OR REPLACE TEMPORARY TABLE {} LIKE {};",
+                    staging_table, table_name
+                ))
+                .await?;
+                api.exec(&format!(
+                    "ALTER TABLE {} ADD COLUMN {} NUMBER;",
+                    staging_table, LOAD_SEQ_COLUMN
+                ))
+                .await?;
+                api.exec(&copy_into(&staging_table)).await?;
+                let columns = schema
+                    .fields
+                    .iter()
+                    .map(|f| f.name().clone())
+                    .collect::<Vec<_>>();
+                api.exec(&generate_merge(table_name, &staging_table, &columns, keys))
+                    .await?;
+                api.exec(&format!("DROP TABLE IF EXISTS {};", staging_table))
+                    .await?;
+            }
+        }
         Ok(())
     }
 
@@ -187,6 +294,84 @@ impl SnowflakeDestination {
     }
 }
 
+/// Generate unique name for staged file
+///
+/// Staged files from previous loads can still be present in table stage, so each load needs its own
+/// file name
+fn staged_file_name() -> Result<String, std::io::Error> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+        .as_nanos();
+    Ok(format!(
+        "mycelial_{}_{}_{}.parquet",
+        std::process::id(),
+        timestamp,
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+/// Append load sequence column to batches
+///
+/// Sequence grows with row position across all batches, so rows of later messages get larger
+/// sequence
+fn with_load_seq(batches: &[&RecordBatch]) -> Result<Vec<RecordBatch>, ArrowError> {
+    let mut seq = 0;
+    batches
+        .iter()
+        .map(|batch| {
+            let schema = batch.schema();
+            let mut fields = schema.fields.iter().cloned().collect::<Vec<_>>();
+            fields.push(Arc::new(Field::new(
+                LOAD_SEQ_COLUMN,
+                DataType::Int64,
+                false,
+            )));
+            let schema = Schema::new_with_metadata(fields, schema.metadata().clone());
+            let mut columns = batch.columns().to_vec();
+            columns.push(Arc::new(Int64Array::from_iter_values(
+                seq..seq + batch.num_rows() as i64,
+            )) as ArrayRef);
+            seq += batch.num_rows() as i64;
+            RecordBatch::try_new(Arc::new(schema), columns)
+        })
+        .collect()
+}
+
+/// Generate merge statement from staging table into destination table
+///
+/// Staging table can contain the same merge key more than once, only the row with the latest
+/// load sequence is merged
+fn generate_merge(table: &str, staging_table: &str, columns: &[String], keys: &[String]) -> String {
+    let on = keys
+        .iter()
+        .map(|key| format!("dst.{key} = src.{key}"))
+        .collect::<Vec<_>>()
+        .join(" AND ");
+    let updates = columns
+        .iter()
+        .filter(|column| !keys.contains(column))
+        .map(|column| format!("dst.{column} = src.{column}"))
+        .collect::<Vec<_>>();
+    let insert_columns = columns.join(", ");
+    let insert_values = columns
+        .iter()
+        .map(|column| format!("src.{column}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let when_matched = match updates.is_empty() {
+        true => String::new(),
+        false => format!(" WHEN MATCHED THEN UPDATE SET {}", updates.join(", ")),
+    };
+    let partition = keys.join(", ");
+    format!(
+        "MERGE INTO {table} AS dst USING (SELECT * FROM {staging_table} \
+         QUALIFY ROW_NUMBER() OVER (PARTITION BY {partition} ORDER BY {LOAD_SEQ_COLUMN} DESC) = 1) AS src \
+         ON {on}{when_matched} WHEN NOT MATCHED THEN INSERT ({insert_columns}) VALUES ({insert_values});"
+    )
+}
+
 impl<Input, Output, SectionChan> Section<Input, Output, SectionChan> for SnowflakeDestination
 where
     Input: Stream<Item = Message> + Send + 'static,
@@ -201,57 +386,181 @@ where
     }
 }
 
+/// Configured load mode, see [`LoadMode`]
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    #[default]
+    Append,
+    Merge,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DestinationConfig {
+    pub username: String,
+    pub password: String,
+    pub role: String,
+    pub account_identifier: String,
+    pub warehouse: String,
+    pub database: String,
+    pub schema: String,
+    pub table: String,
+    #[serde(default)]
+    pub mode: Mode,
+    pub merge_keys: Option<String>,
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    #[serde(default = "default_flush_interval")]
+    pub flush_interval: u64,
+}
+
+fn default_batch_size() -> usize {
+    8
+}
+
+fn default_flush_interval() -> u64 {
+    5
+}
+
+impl DestinationConfig {
+    fn merge_keys(&self) -> Vec<String> {
+        self.merge_keys
+            .as_deref()
+            .unwrap_or("")
+            .split(',')
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+            .map(Into::into)
+            .collect()
+    }
+}
+
+impl SectionConfig for DestinationConfig {
+    const DESCRIPTIONS: &'static [(&'static str, &'static str)] = &[
+        ("username", "snowflake username"),
+        ("password", "snowflake password"),
+        ("role", "snowflake role"),
+        ("account_identifier", "snowflake account identifier"),
+        ("warehouse", "snowflake warehouse"),
+        ("database", "snowflake database"),
+        ("schema", "snowflake schema"),
+        ("table", "destination table"),
+        ("mode", "load mode"),
+        (
+            "merge_keys",
+            "comma-separated list of merge key columns, required for merge mode",
+        ),
+        ("batch_size", "max number of messages loaded at once"),
+        ("flush_interval", "max seconds between loads"),
+    ];
+
+    fn validate(&self) -> Result<(), SectionError> {
+        match self.mode {
+            Mode::Merge if self.merge_keys.is_none() => {
+                Err("'merge_keys' required for merge mode")?
+            }
+            Mode::Merge if self.merge_keys().is_empty() => {
+                Err("'merge_keys' should contain at least one column")?
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
 pub fn constructor<S: SectionChannel>(
     config: &Map,
 ) -> Result<Box<dyn DynSection<S>>, SectionError> {
-    let username = config
-        .get("username")
-        .ok_or("username required")?
-        .as_str()
-        .ok_or("'username' should be a string")?;
-    let password = config
-        .get("password")
-        .ok_or("password required")?
-        .as_str()
-        .ok_or("'password' should be a string")?;
-    let role = config
-        .get("role")
-        .ok_or("role required")?
-        .as_str()
-        .ok_or("'role' should be a string")?;
-    let account_identifier = config
-        .get("account_identifier")
-        .ok_or("account_identifier required")?
-        .as_str()
-        .ok_or("'account_identifier' should be a string")?;
-    let warehouse = config
-        .get("warehouse")
-        .ok_or("warehouse required")?
-        .as_str()
-        .ok_or("'warehouse' should be a string")?;
-    let database = config
-        .get("database")
-        .ok_or("database required")?
-        .as_str()
-        .ok_or("'database' should be a string")?;
-    let schema = config
-        .get("schema")
-        .ok_or("schema required")?
-        .as_str()
-        .ok_or("'schema' should be a string")?;
-    let table = config
-        .get("table")
-        .ok_or("table required")?
-        .as_str()
-        .ok_or("'table' should be a string")?;
+    let config = DestinationConfig::from_map(config)?;
+    let mode = match config.mode {
+        Mode::Append => LoadMode::Append,
+        Mode::Merge => LoadMode::Merge {
+            keys: config.merge_keys(),
+        },
+    };
     Ok(Box::new(SnowflakeDestination::new(
-        username,
-        password,
-        role,
-        account_identifier,
-        warehouse,
-        database,
-        schema,
-        table,
+        config.username,
+        config.password,
+        config.role,
+        config.account_identifier,
+        config.warehouse,
+        config.database,
+        config.schema,
+        config.table,
+        mode,
+        config.batch_size.max(1),
+        Duration::from_secs(config.flush_interval.max(1)),
     )))
 }
+
+/// config schema of snowflake_destination section
+pub fn schema() -> Result<SectionSchema, SectionError> {
+    SectionSchema::of::<DestinationConfig>(
+        "snowflake_destination",
+        "Loads messages into snowflake table",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_merge() {
+        let columns = vec!["id".to_string(), "name".to_string()];
+        assert_eq!(
+            generate_merge("t", "t_staging", &columns, &["id".to_string()]),
+            "MERGE INTO t AS dst USING (SELECT * FROM t_staging \
+             QUALIFY ROW_NUMBER() OVER (PARTITION BY id ORDER BY MYCELIAL_LOAD_SEQ DESC) = 1) AS src \
+             ON dst.id = src.id \
+             WHEN MATCHED THEN UPDATE SET dst.name = src.name \
+             WHEN NOT MATCHED THEN INSERT (id, name) VALUES (src.id, src.name);"
+        );
+        assert_eq!(
+            generate_merge("t", "t_staging", &columns, &columns),
+            "MERGE INTO t AS dst USING (SELECT * FROM t_staging \
+             QUALIFY ROW_NUMBER() OVER (PARTITION BY id, name ORDER BY MYCELIAL_LOAD_SEQ DESC) = 1) AS src \
+             ON dst.id = src.id AND dst.name = src.name \
+             WHEN NOT MATCHED THEN INSERT (id, name) VALUES (src.id, src.name);"
+        );
+    }
+
+    #[test]
+    fn test_load_seq_of_shared_merge_key() {
+        use arrow::array::{Array, StringArray};
+
+        // two buffered messages with the same merge key
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("name", DataType::Utf8, false),
+        ]));
+        let batch = |name: &str| {
+            RecordBatch::try_new(
+                Arc::clone(&schema),
+                vec![
+                    Arc::new(Int64Array::from(vec![1])),
+                    Arc::new(StringArray::from(vec![name])),
+                ],
+            )
+            .unwrap()
+        };
+        let (first, second) = (batch("first"), batch("second"));
+        let staged = with_load_seq(&[&first, &second]).unwrap();
+        assert_eq!(staged.len(), 2);
+
+        let seq = |batch: &RecordBatch| {
+            let column = batch.column_by_name(LOAD_SEQ_COLUMN).unwrap();
+            let column = column.as_any().downcast_ref::<Int64Array>().unwrap();
+            assert_eq!(column.len(), 1);
+            column.value(0)
+        };
+        let name = |batch: &RecordBatch| {
+            let column = batch.column_by_name("name").unwrap();
+            let column = column.as_any().downcast_ref::<StringArray>().unwrap();
+            column.value(0).to_string()
+        };
+        // latest message has larger sequence, so its row is picked by merge
+        let latest = staged.iter().max_by_key(|batch| seq(batch)).unwrap();
+        assert_eq!(name(latest), "second");
+        assert!(seq(&staged[0]) < seq(&staged[1]));
+    }
+}
@@ -1,14 +1,99 @@
+//! Snowflake source
+//!
+//! Periodically executes configured query and emits results as arrow record batches.
+//! Results are fetched in pages of `chunk_size` rows, until query returns less rows than requested,
+//! so whole query result is never loaded into memory at once.
+//!
+//! # Watermark mode
+//! If cursor column is configured - results are filtered by position of last delivered row and ordered
+//! by cursor column (and tie breaker column, if configured).
+//! Query may contain `{watermark}` placeholder, which is replaced with cursor value of last delivered row
+//! (or initial watermark on first run). Filter on placeholder needs to be inclusive, i.e.
+//! `WHERE cursor >= {watermark}`, since rows with the same cursor value may be split between pages.
+//! Position is persisted in section state once message is acked.
+//!
+//! Without tie breaker column, rows with cursor value equal to watermark are de-duplicated by count of
+//! already delivered rows with that value. With tie breaker column (unique within cursor value),
+//! rows are filtered by `(cursor, tie breaker)` pair.
+//!
+//! # Known issues
+//! 1. Without cursor column pages are ordered by all columns, changes to queried data between pages of
+//!    single poll can lead to skipped or duplicated rows.
+//! 2. Without tie breaker column, rows inserted with cursor value equal to watermark after it was
+//!    delivered can be skipped.
 use crate::{
-    config::Map,
+    config::{Map, SectionConfig},
+    config_schema::SectionSchema,
     message::Message,
     types::{DynSection, SectionError, SectionFuture},
 };
+use arrow::record_batch::RecordBatch;
+use arrow::util::display::array_value_to_string;
 use futures::{FutureExt, Sink, SinkExt, Stream, StreamExt};
-use section::{Command, Section, SectionChannel};
+use section::{Command, Section, SectionChannel, State, WeakSectionChannel};
+use serde::{Deserialize, Serialize};
 use snowflake_api::{QueryResult, SnowflakeApi};
 use std::pin::pin;
 use std::time::Duration;
 
+/// Watermark configuration
+#[derive(Debug, Clone)]
+pub struct Watermark {
+    /// cursor column name
+    pub column: String,
+
+    /// watermark value, used if section state has no stored watermark yet
+    pub initial: String,
+
+    /// column, which values are unique among rows with the same cursor value
+    pub tie_breaker: Option<String>,
+}
+
+/// Position of last delivered row in watermark mode
+#[derive(Debug, Clone, PartialEq)]
+struct Position {
+    /// cursor value of last delivered row
+    watermark: String,
+
+    /// tie breaker value of last delivered row
+    tie_breaker: Option<String>,
+
+    /// number of delivered rows with cursor value equal to watermark
+    boundary_rows: u64,
+}
+
+impl Position {
+    fn new(watermark: String) -> Self {
+        Self {
+            watermark,
+            tie_breaker: None,
+            boundary_rows: 0,
+        }
+    }
+
+    /// Position after delivery of given chunk
+    fn advance(&self, chunk: &RecordBatch, watermark: &Watermark) -> Result<Self, SectionError> {
+        let values = column_values(chunk, &watermark.column)?;
+        let last = match values.last() {
+            Some(last) => last.clone(),
+            None => Err("can't take watermark from empty batch")?,
+        };
+        let mut boundary_rows = values.iter().rev().take_while(|v| **v == last).count() as u64;
+        if last == self.watermark {
+            boundary_rows += self.boundary_rows;
+        }
+        let tie_breaker = match watermark.tie_breaker.as_deref() {
+            Some(column) => column_values(chunk, column)?.pop(),
+            None => None,
+        };
+        Ok(Self {
+            watermark: last,
+            tie_breaker,
+            boundary_rows,
+        })
+    }
+}
+
 pub struct SnowflakeSource {
     username: String,
     password: String,
@@ -19,6 +104,9 @@ pub struct SnowflakeSource {
     schema: String,
     query: String,
     delay: Duration,
+    origin: String,
+    watermark: Option<Watermark>,
+    chunk_size: usize,
 }
 
 impl SnowflakeSource {
@@ -33,6 +121,9 @@ impl SnowflakeSource {
         schema: impl Into<String>,
         query: impl Into<String>,
         delay: Duration,
+        origin: impl Into<String>,
+        watermark: Option<Watermark>,
+        chunk_size: usize,
     ) -> Self {
         Self {
             username: username.into(),
@@ -44,6 +135,9 @@ impl SnowflakeSource {
             schema: schema.into(),
             query: query.into(),
             delay,
+            origin: origin.into(),
+            watermark,
+            chunk_size: chunk_size.max(1),
         }
     }
 
@@ -69,34 +163,165 @@ impl SnowflakeSource {
             &self.password,
         )?;
 
+        let mut state = section_chan
+            .retrieve_state()
+            .await?
+            .unwrap_or(<SectionChan as SectionChannel>::State::new());
+        // position of last delivered row
+        let mut position = match self.watermark.as_ref() {
+            Some(w) => Some(match state.get::<String>("watermark")? {
+                Some(watermark) => Position {
+                    watermark,
+                    tie_breaker: state.get::<String>("tie_breaker")?,
+                    boundary_rows: state.get::<u64>("boundary_rows")?.unwrap_or(0),
+                },
+                None => Position::new(w.initial.clone()),
+            }),
+            None => None,
+        };
+
         let mut _input = pin!(input.fuse());
         let mut output = pin!(output);
         let mut tick = pin!(tokio::time::interval(self.delay));
         loop {
             futures::select! {
                 cmd = section_chan.recv().fuse() => {
-                    if let Command::Stop = cmd? {
-                        return Ok(())
+                    match cmd? {
+                        Command::Ack(ack) => {
+                            match ack.downcast::<Position>() {
+                                Ok(position) => {
+                                    state.set("watermark", position.watermark)?;
+                                    if let Some(tie_breaker) = position.tie_breaker {
+                                        state.set("tie_breaker", tie_breaker)?;
+                                    }
+                                    state.set("boundary_rows", position.boundary_rows)?;
+                                    section_chan.store_state(state.clone()).await?;
+                                },
+                                Err(_) => Err("failed to downcast ack to Position")?,
+                            }
+                        },
+                        Command::Stop => return Ok(()),
+                        _ => (),
                     }
                 },
                 _ = tick.tick().fuse() => {
-                    let query_result = api.exec(&self.query).await?;
-                    match query_result {
-                        QueryResult::Arrow(batches) => {
-                            for batch in batches {
-                                let message = Message::new("snowflake_src", batch, None);
+                    // offset of page, used without watermark
+                    let mut offset = 0;
+                    loop {
+                        let query = self.build_query(position.as_ref(), offset);
+                        let batches = match api.exec(&query).await? {
+                            QueryResult::Arrow(batches) => batches,
+                            QueryResult::Json(_) => {
+                                Err("unexpected payload, expected arrow, got json")?
+                            }
+                            QueryResult::Empty => vec![],
+                        };
+                        let rows: usize = batches.iter().map(|batch| batch.num_rows()).sum();
+                        offset += rows;
+                        for batch in batches {
+                            for chunk in self.split(batch) {
+                                if chunk.num_rows() == 0 {
+                                    continue;
+                                }
+                                let message = match (self.watermark.as_ref(), position.as_mut()) {
+                                    (Some(w), Some(position)) => {
+                                        *position = position.advance(&chunk, w)?;
+                                        let ack = position.clone();
+                                        let weak_chan = section_chan.weak_chan();
+                                        Message::new(
+                                            self.origin.as_str(),
+                                            chunk,
+                                            Some(Box::pin(async move { weak_chan.ack(Box::new(ack)).await })),
+                                        )
+                                    },
+                                    _ => Message::new(self.origin.as_str(), chunk, None),
+                                };
                                 output.send(message).await?;
                             }
                         }
-                        QueryResult::Json(_) => {
-                            Err("unexpected payload, expected arrow, got json")?
+                        if rows < self.chunk_size {
+                            break
                         }
-                        QueryResult::Empty => {}
                     }
                 }
             }
         }
     }
+
+    /// Build query for next page of results
+    ///
+    /// In watermark mode page starts right after last delivered row, otherwise page starts at given offset.
+    fn build_query(&self, position: Option<&Position>, offset: usize) -> String {
+        let query = self.query.trim().trim_end_matches(';');
+        let limit = self.chunk_size;
+        let (w, position) = match (self.watermark.as_ref(), position) {
+            (Some(w), Some(position)) => (w, position),
+            _ => {
+                return format!(
+                    "SELECT * FROM ({query}) ORDER BY ALL LIMIT {limit} OFFSET {offset}"
+                )
+            }
+        };
+        let column = w.column.as_str();
+        let watermark = quote_literal(&position.watermark);
+        let query = query.replace("{watermark}", &watermark);
+        match (w.tie_breaker.as_deref(), position.tie_breaker.as_deref()) {
+            (Some(tie_breaker), Some(value)) => format!(
+                "SELECT * FROM ({query}) \
+                 WHERE {column} > {watermark} OR ({column} = {watermark} AND {tie_breaker} > {}) \
+                 ORDER BY {column}, {tie_breaker} LIMIT {limit}",
+                quote_literal(value)
+            ),
+            (Some(tie_breaker), None) => format!(
+                "SELECT * FROM ({query}) WHERE {column} >= {watermark} \
+                 ORDER BY {column}, {tie_breaker} LIMIT {limit}"
+            ),
+            (None, _) => format!(
+                "SELECT * FROM ({query}) WHERE {column} >= {watermark} \
+                 ORDER BY {column} LIMIT {limit} OFFSET {}",
+                position.boundary_rows
+            ),
+        }
+    }
+
+    /// Split record batch into chunks of at most `chunk_size` rows
+    fn split(&self, batch: RecordBatch) -> Vec<RecordBatch> {
+        if batch.num_rows() <= self.chunk_size {
+            return vec![batch];
+        }
+        (0..batch.num_rows())
+            .step_by(self.chunk_size)
+            .map(|offset| {
+                let len = self.chunk_size.min(batch.num_rows() - offset);
+                batch.slice(offset, len)
+            })
+            .collect()
+    }
+}
+
+/// Quote value as sql string literal
+fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Values of column as strings
+fn column_values(batch: &RecordBatch, column: &str) -> Result<Vec<String>, SectionError> {
+    let schema = batch.schema();
+    // snowflake returns upper-cased column names for unquoted identifiers
+    let index = schema
+        .fields()
+        .iter()
+        .position(|field| field.name().eq_ignore_ascii_case(column))
+        .ok_or(format!("column '{column}' not found in query result"))?;
+    let array = batch.column(index);
+    (0..batch.num_rows())
+        .map(|row| -> Result<String, SectionError> {
+            if array.is_null(row) {
+                Err(format!("column '{column}' contains NULL"))?
+            }
+            Ok(array_value_to_string(array, row)?)
+        })
+        .collect()
 }
 
 impl<Input, Output, SectionChan> Section<Input, Output, SectionChan> for SnowflakeSource
@@ -113,63 +338,240 @@ where
     }
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SourceConfig {
+    pub username: String,
+    pub password: String,
+    pub role: String,
+    pub account_identifier: String,
+    pub warehouse: String,
+    pub database: String,
+    pub schema: String,
+    pub query: String,
+    pub delay: u64,
+    #[serde(default = "default_origin")]
+    pub origin: String,
+    pub cursor_column: Option<String>,
+    pub initial_watermark: Option<String>,
+    pub tie_breaker_column: Option<String>,
+    #[serde(default = "default_chunk_size")]
+    pub chunk_size: usize,
+}
+
+fn default_origin() -> String {
+    "snowflake_src".into()
+}
+
+fn default_chunk_size() -> usize {
+    10000
+}
+
+impl SectionConfig for SourceConfig {
+    const DESCRIPTIONS: &'static [(&'static str, &'static str)] = &[
+        ("username", "snowflake username"),
+        ("password", "snowflake password"),
+        ("role", "snowflake role"),
+        ("account_identifier", "snowflake account identifier"),
+        ("warehouse", "snowflake warehouse"),
+        ("database", "snowflake database"),
+        ("schema", "snowflake schema"),
+        (
+            "query",
+            "query, may contain {watermark} placeholder in inclusive filter",
+        ),
+        ("delay", "delay between queries in seconds"),
+        ("origin", "origin of messages"),
+        (
+            "cursor_column",
+            "column used as watermark for incremental queries",
+        ),
+        (
+            "initial_watermark",
+            "initial watermark, required with cursor_column",
+        ),
+        (
+            "tie_breaker_column",
+            "column unique among rows with the same cursor value",
+        ),
+        ("chunk_size", "max number of rows in single message"),
+    ];
+
+    fn validate(&self) -> Result<(), SectionError> {
+        if self.cursor_column.is_some() {
+            if self.initial_watermark.is_none() {
+                Err("'initial_watermark' required when 'cursor_column' is set")?
+            }
+        } else if self.tie_breaker_column.is_some() {
+            Err("'tie_breaker_column' requires 'cursor_column'")?
+        }
+        Ok(())
+    }
+}
+
 pub fn constructor<S: SectionChannel>(
     config: &Map,
 ) -> Result<Box<dyn DynSection<S>>, SectionError> {
-    let username = config
-        .get("username")
-        .ok_or("username required")?
-        .as_str()
-        .ok_or("'username' should be a string")?;
-    let password = config
-        .get("password")
-        .ok_or("password required")?
-        .as_str()
-        .ok_or("'password' should be a string")?;
-    let role = config
-        .get("role")
-        .ok_or("role required")?
-        .as_str()
-        .ok_or("'role' should be a string")?;
-    let account_identifier = config
-        .get("account_identifier")
-        .ok_or("account_identifier required")?
-        .as_str()
-        .ok_or("'account_identifier' should be a string")?;
-    let warehouse = config
-        .get("warehouse")
-        .ok_or("warehouse required")?
-        .as_str()
-        .ok_or("'warehouse' should be a string")?;
-    let database = config
-        .get("database")
-        .ok_or("database required")?
-        .as_str()
-        .ok_or("'database' should be a string")?;
-    let schema = config
-        .get("schema")
-        .ok_or("schema required")?
-        .as_str()
-        .ok_or("'schema' should be a string")?;
-    let query = config
-        .get("query")
-        .ok_or("query required")?
-        .as_str()
-        .ok_or("'query' should be a string")?;
-    let delay = config
-        .get("delay")
-        .ok_or("snowflake source requires 'delay'")?
-        .as_int()
-        .ok_or("'delay' should be an int")?;
+    let config = SourceConfig::from_map(config)?;
+    let tie_breaker = config.tie_breaker_column;
+    let watermark = config
+        .cursor_column
+        .zip(config.initial_watermark)
+        .map(|(column, initial)| Watermark {
+            column,
+            initial,
+            tie_breaker,
+        });
     Ok(Box::new(SnowflakeSource::new(
-        username,
-        password,
-        role,
-        account_identifier,
-        warehouse,
-        database,
-        schema,
-        query,
-        Duration::from_secs(delay as u64),
+        config.username,
+        config.password,
+        config.role,
+        config.account_identifier,
+        config.warehouse,
+        config.database,
+        config.schema,
+        config.query,
+        Duration::from_secs(config.delay),
+        config.origin,
+        watermark,
+        config.chunk_size.max(1),
     )))
 }
+
+/// config schema of snowflake_source section
+pub fn schema() -> Result<SectionSchema, SectionError> {
+    SectionSchema::of::<SourceConfig>("snowflake_source", "Polls snowflake query")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::{Int64Array, StringArray};
+    use arrow::datatypes::{DataType, Field, Schema};
+    use std::sync::Arc;
+
+    fn source(watermark: Option<Watermark>) -> SnowflakeSource {
+        SnowflakeSource::new(
+            "user",
+            "password",
+            "role",
+            "account",
+            "warehouse",
+            "database",
+            "schema",
+            "SELECT * FROM t WHERE ts >= {watermark};",
+            Duration::from_secs(1),
+            "origin",
+            watermark,
+            3,
+        )
+    }
+
+    fn watermark(tie_breaker: Option<&str>) -> Watermark {
+        Watermark {
+            column: "ts".into(),
+            initial: "0".into(),
+            tie_breaker: tie_breaker.map(Into::into),
+        }
+    }
+
+    fn batch(ts: &[i64], ids: &[&str]) -> RecordBatch {
+        let schema = Schema::new(vec![
+            Field::new("TS", DataType::Int64, false),
+            Field::new("ID", DataType::Utf8, false),
+        ]);
+        RecordBatch::try_new(
+            Arc::new(schema),
+            vec![
+                Arc::new(Int64Array::from(ts.to_vec())),
+                Arc::new(StringArray::from(ids.to_vec())),
+            ],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_build_query() {
+        let source = source(None);
+        assert_eq!(
+            source.build_query(None, 6),
+            "SELECT * FROM (SELECT * FROM t WHERE ts >= {watermark}) ORDER BY ALL LIMIT 3 OFFSET 6"
+        );
+
+        let source = self::source(Some(watermark(None)));
+        let position = Position {
+            watermark: "it's".into(),
+            tie_breaker: None,
+            boundary_rows: 2,
+        };
+        assert_eq!(
+            source.build_query(Some(&position), 0),
+            "SELECT * FROM (SELECT * FROM t WHERE ts >= 'it''s') WHERE ts >= 'it''s' \
+             ORDER BY ts LIMIT 3 OFFSET 2"
+        );
+
+        let source = self::source(Some(watermark(Some("id"))));
+        assert_eq!(
+            source.build_query(Some(&Position::new("1".into())), 0),
+            "SELECT * FROM (SELECT * FROM t WHERE ts >= '1') WHERE ts >= '1' \
+             ORDER BY ts, id LIMIT 3"
+        );
+        let position = Position {
+            watermark: "1".into(),
+            tie_breaker: Some("b".into()),
+            boundary_rows: 1,
+        };
+        assert_eq!(
+            source.build_query(Some(&position), 0),
+            "SELECT * FROM (SELECT * FROM t WHERE ts >= '1') \
+             WHERE ts > '1' OR (ts = '1' AND id > 'b') ORDER BY ts, id LIMIT 3"
+        );
+    }
+
+    #[test]
+    fn test_position_advance() {
+        let w = watermark(None);
+        let position = Position::new("0".into());
+
+        // rows with the same cursor value split between chunks
+        let position = position
+            .advance(&batch(&[1, 2, 2], &["a", "b", "c"]), &w)
+            .unwrap();
+        assert_eq!(position.watermark, "2");
+        assert_eq!(position.boundary_rows, 2);
+
+        let position = position
+            .advance(&batch(&[2, 2, 2], &["d", "e", "f"]), &w)
+            .unwrap();
+        assert_eq!(position.watermark, "2");
+        assert_eq!(position.boundary_rows, 5);
+
+        let position = position.advance(&batch(&[2, 3], &["g", "h"]), &w).unwrap();
+        assert_eq!(position.watermark, "3");
+        assert_eq!(position.boundary_rows, 1);
+
+        let w = watermark(Some("id"));
+        let position = Position::new("0".into())
+            .advance(&batch(&[1, 1], &["a", "b"]), &w)
+            .unwrap();
+        assert_eq!(position.watermark, "1");
+        assert_eq!(position.tie_breaker.as_deref(), Some("b"));
+
+        let w = watermark(Some("missing"));
+        assert!(Position::new("0".into())
+            .advance(&batch(&[1], &["a"]), &w)
+            .is_err());
+        assert!(Position::new("0".into())
+            .advance(&batch(&[], &[]), &watermark(None))
+            .is_err());
+    }
+
+    #[test]
+    fn test_split() {
+        let source = source(None);
+        let chunks = source.split(batch(&[1, 2, 3, 4, 5, 6, 7], &["a"; 7]));
+        assert_eq!(
+            chunks.iter().map(|c| c.num_rows()).collect::<Vec<_>>(),
+            vec![3, 3, 1]
+        );
+    }
+}
@@ -6,11 +6,13 @@ use stub::Stub;
 
 use crate::types::SectionFuture;
 use crate::{
-    config::Map,
+    config::{Map, SectionConfig},
+    config_schema::SectionSchema,
     types::{DynSection, DynSink, DynStream, SectionError},
 };
 
 use section::SectionChannel;
+use serde::{Deserialize, Serialize};
 
 use super::SqlitePayloadNewType;
 
@@ -42,6 +44,16 @@ impl<SectionChan: SectionChannel + Send + 'static> Section<DynStream, DynSink, S
     }
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DestinationConfig {
+    pub path: String,
+}
+
+impl SectionConfig for DestinationConfig {
+    const DESCRIPTIONS: &'static [(&'static str, &'static str)] =
+        &[("path", "path to sqlite database")];
+}
+
 /// constructor for sqlite destination
 ///
 /// # Config example:
@@ -53,12 +65,16 @@ impl<SectionChan: SectionChannel + Send + 'static> Section<DynStream, DynSink, S
 pub fn constructor<S: SectionChannel>(
     config: &Map,
 ) -> Result<Box<dyn DynSection<S>>, SectionError> {
-    let path = config
-        .get("path")
-        .ok_or("sqlite section requires 'path'")?
-        .as_str()
-        .ok_or("path should be string")?;
+    let config = DestinationConfig::from_map(config)?;
     Ok(Box::new(SqliteAdapter {
-        inner: Sqlite::new(path),
+        inner: Sqlite::new(config.path.as_str()),
     }))
 }
+
+/// config schema of sqlite_connector_destination section
+pub fn schema() -> Result<SectionSchema, SectionError> {
+    SectionSchema::of::<DestinationConfig>(
+        "sqlite_connector_destination",
+        "Writes messages into sqlite database",
+    )
+}
@@ -2,11 +2,13 @@ use crate::message::{Message, RecordBatch};
 use futures::SinkExt;
 use section::Section;
 use section::SectionChannel;
+use serde::{Deserialize, Serialize};
 use sqlite_connector::source::Sqlite;
 
 use crate::types::SectionFuture;
 use crate::{
-    config::Map,
+    config::{Map, SectionConfig},
+    config_schema::SectionSchema,
     types::{DynSection, DynSink, DynStream, SectionError},
 };
 
@@ -30,7 +32,8 @@ impl<SectionChan: SectionChannel + Send + 'static> Section<DynStream, DynSink, S
         Box::pin(async move {
             let output = output.with(|message: sqlite_connector::Message| async {
                 let payload: RecordBatch = message.payload.try_into()?;
-                let message = Message::new(message.origin, payload, message.ack);
+                let message =
+                    Message::new(message.origin, payload, message.ack).with_offset(message.offset);
                 Ok(message)
             });
             self.inner.start(input, output, section_channel).await
@@ -38,6 +41,22 @@ impl<SectionChan: SectionChannel + Send + 'static> Section<DynStream, DynSink, S
     }
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SourceConfig {
+    pub path: String,
+    pub tables: String,
+    #[serde(default)]
+    pub once: bool,
+}
+
+impl SectionConfig for SourceConfig {
+    const DESCRIPTIONS: &'static [(&'static str, &'static str)] = &[
+        ("path", "path to sqlite database"),
+        ("tables", "comma-separated list of tables"),
+        ("once", "read tables once and stop"),
+    ];
+}
+
 /// constructor for sqlite
 ///
 /// # Config example:
@@ -51,26 +70,19 @@ impl<SectionChan: SectionChannel + Send + 'static> Section<DynStream, DynSink, S
 pub fn constructor<S: SectionChannel>(
     config: &Map,
 ) -> Result<Box<dyn DynSection<S>>, SectionError> {
+    let config = SourceConfig::from_map(config)?;
     let tables = config
-        .get("tables")
-        .ok_or("sqlite section requires 'tables'")?
-        .as_str()
-        .ok_or("'tables' should be string")?;
-    let path = config
-        .get("path")
-        .ok_or("sqlite section requires 'path'")?
-        .as_str()
-        .ok_or("path should be string")?;
-    let tables = tables
+        .tables
         .split(',')
         .map(|x| x.trim())
         .filter(|x| !x.is_empty())
         .collect::<Vec<&str>>();
-    let once = match config.get("once") {
-        Some(val) => val.as_bool().ok_or("once should be bool")?,
-        None => false,
-    };
     Ok(Box::new(SqliteAdapter {
-        inner: Sqlite::new(path, tables.as_slice(), once),
+        inner: Sqlite::new(config.path.as_str(), tables.as_slice(), config.once),
     }))
 }
+
+/// config schema of sqlite_connector_source section
+pub fn schema() -> Result<SectionSchema, SectionError> {
+    SectionSchema::of::<SourceConfig>("sqlite_connector_source", "Reads tables of sqlite database")
+}
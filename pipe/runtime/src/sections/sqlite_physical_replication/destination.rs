@@ -1,11 +1,13 @@
 use crate::{
-    config::Map,
+    config::{Map, SectionConfig},
+    config_schema::SectionSchema,
     message,
     types::{DynSection, DynSink, DynStream, SectionError, SectionFuture},
 };
 use futures::{SinkExt, StreamExt};
 use section::{Section, SectionChannel};
-use sqlite_physical_replication::destination::Destination;
+use serde::{Deserialize, Serialize};
+use sqlite_physical_replication::destination::{Destination, RestorePoint};
 
 pub struct DestinationAdapter {
     inner: Destination,
@@ -30,6 +32,57 @@ impl<S: SectionChannel> Section<DynStream, DynSink, S> for DestinationAdapter {
     }
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DestinationConfig {
+    pub journal_path: String,
+    pub database_path: Option<String>,
+    pub restore_path: Option<String>,
+    pub restore_snapshot_id: Option<u64>,
+    pub restore_timestamp: Option<i64>,
+    #[serde(default)]
+    pub integrity_check: bool,
+}
+
+impl DestinationConfig {
+    fn restore_point(&self) -> Option<RestorePoint> {
+        match (self.restore_snapshot_id, self.restore_timestamp) {
+            (Some(id), _) => Some(RestorePoint::SnapshotId(id)),
+            (None, Some(timestamp)) => Some(RestorePoint::Timestamp(timestamp)),
+            (None, None) => None,
+        }
+    }
+}
+
+impl SectionConfig for DestinationConfig {
+    const DESCRIPTIONS: &'static [(&'static str, &'static str)] = &[
+        ("journal_path", "path to journal"),
+        ("database_path", "path to replicated database"),
+        ("restore_path", "path to materialized database"),
+        (
+            "restore_snapshot_id",
+            "materialize database as of snapshot id",
+        ),
+        ("restore_timestamp", "materialize database as of timestamp"),
+        (
+            "integrity_check",
+            "run integrity check on materialized databases",
+        ),
+    ];
+
+    fn validate(&self) -> Result<(), SectionError> {
+        if self.restore_snapshot_id.is_some() && self.restore_timestamp.is_some() {
+            Err("only one of restore_snapshot_id and restore_timestamp can be set")?
+        }
+        match (self.restore_point(), self.restore_path.as_ref()) {
+            (Some(_), None) => Err("restore point requires restore_path")?,
+            (None, Some(_)) => {
+                Err("restore_path requires restore_snapshot_id or restore_timestamp")?
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
 /// constructor for sqlite_physical_replication journal destination
 ///
 /// # Config example:
@@ -38,30 +91,36 @@ impl<S: SectionChannel> Section<DynStream, DynSink, S> for DestinationAdapter {
 /// name = "sqlite_physical_replication_destination"
 /// journal_path = "/tmp/path_to_journal"
 /// database_path = "/tmp/path_to_database"
+/// # optional, materialize database as of given snapshot id or timestamp into separate file
+/// restore_path = "/tmp/path_to_restored_database"
+/// restore_snapshot_id = 42
+/// # or
+/// # restore_timestamp = 1697000000000000
+/// # optional, run `PRAGMA integrity_check` on materialized databases, defaults to false
+/// integrity_check = true
 /// ```
 pub fn constructor<S: SectionChannel>(
     config: &Map,
 ) -> Result<Box<dyn DynSection<S>>, SectionError> {
-    let path = config
-        .get("journal_path")
-        .ok_or("sqlite_physical_replication journal path is required")?
-        .as_str()
-        .ok_or("path should be string")?;
-    let database_path = config.get("database_path").map_or(
-        Result::<Option<String>, SectionError>::Ok(None),
-        |v| {
-            v.as_str()
-                .ok_or("database path should be string".into())
-                .map(|s| {
-                    if s.is_empty() {
-                        None
-                    } else {
-                        Some(s.to_string())
-                    }
-                })
-        },
-    )?;
+    let config = DestinationConfig::from_map(config)?;
+    let restore = config.restore_point().zip(config.restore_path.clone());
+    let database_path = config
+        .database_path
+        .filter(|database_path| !database_path.is_empty());
     Ok(Box::new(DestinationAdapter {
-        inner: Destination::new(path, database_path),
+        inner: Destination::new(
+            config.journal_path.as_str(),
+            database_path,
+            restore,
+            config.integrity_check,
+        ),
     }))
 }
+
+/// config schema of sqlite_physical_replication_destination section
+pub fn schema() -> Result<SectionSchema, SectionError> {
+    SectionSchema::of::<DestinationConfig>(
+        "sqlite_physical_replication_destination",
+        "Writes sqlite physical replication journal and database",
+    )
+}
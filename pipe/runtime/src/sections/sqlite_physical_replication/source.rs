@@ -1,10 +1,12 @@
 use crate::{
-    config::Map,
+    config::{Map, SectionConfig},
+    config_schema::SectionSchema,
     message,
     types::{DynSection, DynSink, DynStream, SectionError, SectionFuture},
 };
 use futures::{SinkExt, StreamExt};
 use section::{Section, SectionChannel};
+use serde::{Deserialize, Serialize};
 use sqlite_physical_replication::source::Source;
 
 pub struct SourceAdapter {
@@ -21,13 +23,41 @@ impl<S: SectionChannel> Section<DynStream, DynSink, S> for SourceAdapter {
                 sqlite_physical_replication::Message::new(msg.origin, msg.payload.0, msg.ack)
             });
             let output = output.with(|msg: sqlite_physical_replication::Message| async move {
-                Ok(message::Message::new(msg.origin, msg.payload, msg.ack))
+                Ok(message::Message::new(msg.origin, msg.payload, msg.ack).with_offset(msg.offset))
             });
             self.inner.start(input, output, command_channel).await
         })
     }
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SourceConfig {
+    pub journal_path: String,
+    #[serde(default = "default_max_chunk_size")]
+    pub max_chunk_size: usize,
+}
+
+fn default_max_chunk_size() -> usize {
+    4 * 1024 * 1024
+}
+
+impl SectionConfig for SourceConfig {
+    const DESCRIPTIONS: &'static [(&'static str, &'static str)] = &[
+        ("journal_path", "path to journal"),
+        (
+            "max_chunk_size",
+            "max size of blobs in single message in bytes",
+        ),
+    ];
+
+    fn validate(&self) -> Result<(), SectionError> {
+        if self.max_chunk_size == 0 {
+            Err("max_chunk_size should be positive")?
+        }
+        Ok(())
+    }
+}
+
 /// constructor for sqlite_physical_replication
 ///
 /// # Config example:
@@ -35,16 +65,22 @@ impl<S: SectionChannel> Section<DynStream, DynSink, S> for SourceAdapter {
 /// [[section]]
 /// name = "sqlite_physical_replication_source"
 /// journal_path = "/tmp/path_to_journal"
+/// # optional, max size of blobs in single message in bytes, defaults to 4MiB
+/// max_chunk_size = 4194304
 /// ```
 pub fn constructor<S: SectionChannel>(
     config: &Map,
 ) -> Result<Box<dyn DynSection<S>>, SectionError> {
-    let path = config
-        .get("journal_path")
-        .ok_or("sqlite_physical_replication journal path is required")?
-        .as_str()
-        .ok_or("path should be string")?;
+    let config = SourceConfig::from_map(config)?;
     Ok(Box::new(SourceAdapter {
-        inner: Source::new(path),
+        inner: Source::new(config.journal_path.as_str(), config.max_chunk_size),
     }))
 }
+
+/// config schema of sqlite_physical_replication_source section
+pub fn schema() -> Result<SectionSchema, SectionError> {
+    SectionSchema::of::<SourceConfig>(
+        "sqlite_physical_replication_source",
+        "Reads sqlite physical replication journal",
+    )
+}
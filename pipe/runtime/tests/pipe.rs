@@ -0,0 +1,190 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use runtime::command_channel::{RootChannel, SectionRequest};
+use runtime::config::Config;
+use runtime::message::{Message, RecordBatch};
+use runtime::types::{DynSection, DynSink, DynStream, SectionError, SectionFuture};
+use runtime::Pipe;
+use section::dummy::DummyState;
+use section::{Command, RootChannel as _, Section, SectionChannel, State};
+use stub::Stub;
+use tokio::time::timeout;
+
+const TIMEOUT: Duration = Duration::from_millis(100);
+
+type PipeSectionChannel = runtime::command_channel::SectionChannel<DummyState>;
+
+/// Section, which stores state when asked to stop
+struct StoreOnStop;
+
+impl<S: SectionChannel + Send + 'static> Section<DynStream, DynSink, S> for StoreOnStop {
+    type Error = SectionError;
+    type Future = SectionFuture;
+
+    fn start(self, _input: DynStream, _output: DynSink, mut section_chan: S) -> Self::Future {
+        Box::pin(async move {
+            loop {
+                if let Command::Stop = section_chan.recv().await? {
+                    section_chan.store_state(<S::State as State>::new()).await?;
+                    return Ok(());
+                }
+            }
+        })
+    }
+}
+
+/// Section, which requests resync on start
+struct ResyncOnStart;
+
+impl<S: SectionChannel + Send + 'static> Section<DynStream, DynSink, S> for ResyncOnStart {
+    type Error = SectionError;
+    type Future = SectionFuture;
+
+    fn start(self, _input: DynStream, _output: DynSink, mut section_chan: S) -> Self::Future {
+        Box::pin(async move {
+            section_chan.resync().await?;
+            loop {
+                section_chan.recv().await?;
+            }
+        })
+    }
+}
+
+/// Section, which sends message on start and runs until it's asked to stop
+struct SendOnStart;
+
+impl<S: SectionChannel + Send + 'static> Section<DynStream, DynSink, S> for SendOnStart {
+    type Error = SectionError;
+    type Future = SectionFuture;
+
+    fn start(self, _input: DynStream, mut output: DynSink, mut section_chan: S) -> Self::Future {
+        Box::pin(async move {
+            let batch = arrow::record_batch::RecordBatch::new_empty(Arc::new(
+                arrow::datatypes::Schema::empty(),
+            ));
+            output
+                .send(Message::new("test", RecordBatch::from(batch), None))
+                .await?;
+            loop {
+                if let Command::Stop = section_chan.recv().await? {
+                    return Ok(());
+                }
+            }
+        })
+    }
+}
+
+/// Section, which stores state for each received message
+struct StoreOnMessage;
+
+impl<S: SectionChannel + Send + 'static> Section<DynStream, DynSink, S> for StoreOnMessage {
+    type Error = SectionError;
+    type Future = SectionFuture;
+
+    fn start(self, mut input: DynStream, _output: DynSink, mut section_chan: S) -> Self::Future {
+        Box::pin(async move {
+            loop {
+                tokio::select! {
+                    Some(_) = input.next() => {
+                        section_chan.store_state(<S::State as State>::new()).await?;
+                    }
+                    cmd = section_chan.recv() => {
+                        if let Command::Stop = cmd? {
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+        })
+    }
+}
+
+#[tokio::test]
+async fn test_pipe_graceful_stop() -> Result<(), Box<dyn std::error::Error>> {
+    let mut root_chan = RootChannel::<DummyState>::new();
+    let section_chan = root_chan.add_section(0)?;
+    let sections: Vec<Box<dyn DynSection<PipeSectionChannel>>> = vec![Box::new(StoreOnStop)];
+    let pipe = Pipe::<RootChannel<DummyState>>::new(Config::try_from_json("[]")?, sections);
+    let handle = tokio::spawn(pipe.start(
+        Stub::<Message, SectionError>::new(),
+        Stub::<Message, SectionError>::new(),
+        section_chan,
+    ));
+
+    // pipe retrieves state on start
+    let request = timeout(TIMEOUT, root_chan.recv()).await??;
+    assert!(matches!(request, SectionRequest::RetrieveState { .. }));
+    assert!(request.reply_retrieve_state(None).await.is_ok());
+
+    root_chan.send(0, Command::Stop).await?;
+
+    // state, stored by section on stop, reaches runtime before pipe finishes
+    let request = timeout(TIMEOUT, root_chan.recv()).await??;
+    assert!(matches!(request, SectionRequest::StoreState { .. }));
+    assert!(request.reply_store_state().await.is_ok());
+
+    let pipe_result = timeout(TIMEOUT, handle).await??;
+    assert!(pipe_result.is_ok(), "{:?}", pipe_result);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_pipe_resync() -> Result<(), Box<dyn std::error::Error>> {
+    let mut root_chan = RootChannel::<DummyState>::new();
+    let section_chan = root_chan.add_section(0)?;
+    let sections: Vec<Box<dyn DynSection<PipeSectionChannel>>> =
+        vec![Box::new(StoreOnStop), Box::new(ResyncOnStart)];
+    let pipe = Pipe::<RootChannel<DummyState>>::new(Config::try_from_json("[]")?, sections);
+    let handle = tokio::spawn(pipe.start(
+        Stub::<Message, SectionError>::new(),
+        Stub::<Message, SectionError>::new(),
+        section_chan,
+    ));
+
+    let request = timeout(TIMEOUT, root_chan.recv()).await??;
+    assert!(matches!(request, SectionRequest::RetrieveState { .. }));
+    assert!(request.reply_retrieve_state(None).await.is_ok());
+
+    // state with reset state of preceding sections is stored before pipe fails
+    let request = timeout(TIMEOUT, root_chan.recv()).await??;
+    assert!(matches!(request, SectionRequest::StoreState { .. }));
+    assert!(request.reply_store_state().await.is_ok());
+
+    let pipe_result = timeout(TIMEOUT, handle).await??;
+    assert_eq!(
+        pipe_result.unwrap_err().to_string(),
+        "section_id<id: 1>: resync requested"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_pipe_stop_drains_sections() -> Result<(), Box<dyn std::error::Error>> {
+    let mut root_chan = RootChannel::<DummyState>::new();
+    let section_chan = root_chan.add_section(0)?;
+    let sections: Vec<Box<dyn DynSection<PipeSectionChannel>>> =
+        vec![Box::new(SendOnStart), Box::new(StoreOnMessage)];
+    let pipe = Pipe::<RootChannel<DummyState>>::new(Config::try_from_json("[]")?, sections);
+    let handle = tokio::spawn(pipe.start(
+        Stub::<Message, SectionError>::new(),
+        Stub::<Message, SectionError>::new(),
+        section_chan,
+    ));
+
+    let request = timeout(TIMEOUT, root_chan.recv()).await??;
+    assert!(matches!(request, SectionRequest::RetrieveState { .. }));
+    assert!(request.reply_retrieve_state(None).await.is_ok());
+
+    root_chan.send(0, Command::Stop).await?;
+
+    // message, sent by source, is processed by destination before destination is stopped
+    let request = timeout(TIMEOUT, root_chan.recv()).await??;
+    assert!(matches!(request, SectionRequest::StoreState { .. }));
+    assert!(request.reply_store_state().await.is_ok());
+
+    let pipe_result = timeout(TIMEOUT, handle).await??;
+    assert!(pipe_result.is_ok(), "{:?}", pipe_result);
+    Ok(())
+}
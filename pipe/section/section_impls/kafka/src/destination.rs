@@ -2,11 +2,11 @@
 // CAUTION: ALPHA QUALITY CODE :) Use with caution.
 
 use crate::{Message, StdError};
+use futures::future::try_join_all;
 use futures::{FutureExt, Sink, Stream, StreamExt};
-use rdkafka::message::OwnedMessage;
+use rdkafka::message::{Header, OwnedHeaders};
 use rdkafka::producer::FutureRecord;
 use rdkafka::util::Timeout;
-use rdkafka::Message as KafkaMessage;
 use rdkafka::{error::KafkaError, producer::FutureProducer, ClientConfig};
 use section::{Command, Section, SectionChannel};
 use std::pin::{pin, Pin};
@@ -19,11 +19,19 @@ pub struct Kafka {
 }
 
 impl Kafka {
-    pub fn new(brokers: &str, topic: &str) -> Result<Self, KafkaError> {
-        let producer = ClientConfig::new()
+    /// Create new kafka destination
+    ///
+    /// `partitioner` is a librdkafka partitioner name (e.g. `murmur2_random` for java client compatible hashing),
+    /// which is used to pick partition from record key, if record has no explicit partition.
+    pub fn new(brokers: &str, topic: &str, partitioner: Option<&str>) -> Result<Self, KafkaError> {
+        let mut config = ClientConfig::new();
+        config
             .set("bootstrap.servers", brokers)
-            .set("message.timeout.ms", "5000")
-            .create()?;
+            .set("message.timeout.ms", "5000");
+        if let Some(partitioner) = partitioner {
+            config.set("partitioner", partitioner);
+        }
+        let producer = config.create()?;
         Ok(Self {
             producer,
             topic: topic.into(),
@@ -51,23 +59,7 @@ impl Kafka {
                 message = input.next() => {
                     match message {
                         Some(mut msg) => {
-                            let payload: &OwnedMessage = &msg.payload;
-                            let origin = &msg.origin;
-
-                             let p = payload.payload();
-                             let p = match p {
-                                    Some(p) => p,
-                                    None => Err("payload is none")?,
-                             };
-
-                            let record = FutureRecord::to(self.topic.as_str())
-                                .payload(p)
-                                .key(origin.as_bytes());
-
-                            if self.producer.send(record, Timeout::Never).await.is_err() {
-                                return Ok(())
-                            }
-
+                            self.send(&msg).await?;
                             msg.ack().await;
                         },
                         None => Err("input closed")?,
@@ -76,6 +68,35 @@ impl Kafka {
             }
         }
     }
+
+    /// Send all records of the message, resolves once every record is delivered
+    async fn send(&self, msg: &Message) -> Result<(), StdError> {
+        let deliveries = msg.payload.iter().map(|record| {
+            let headers =
+                record
+                    .headers
+                    .iter()
+                    .fold(OwnedHeaders::new(), |headers, (key, value)| {
+                        headers.insert(Header {
+                            key: key.as_str(),
+                            value: Some(value.as_slice()),
+                        })
+                    });
+            let mut future_record =
+                FutureRecord::to(record.topic.as_deref().unwrap_or(&self.topic))
+                    .payload(record.payload.as_slice())
+                    .key(record.key.as_deref().unwrap_or(msg.origin.as_bytes()))
+                    .headers(headers);
+            if let Some(partition) = record.partition {
+                future_record = future_record.partition(partition);
+            }
+            self.producer.send(future_record, Timeout::Never)
+        });
+        try_join_all(deliveries)
+            .await
+            .map_err(|(err, _)| format!("failed to deliver record: {err}"))?;
+        Ok(())
+    }
 }
 
 impl<Input, Output, SectionChan> Section<Input, Output, SectionChan> for Kafka
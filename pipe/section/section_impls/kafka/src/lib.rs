@@ -3,6 +3,35 @@ use section::Message as _Message;
 pub mod destination;
 
 type StdError = Box<dyn std::error::Error + Send + Sync + 'static>;
-pub type Message = _Message<OwnedMessage>;
+pub type Message = _Message<Vec<Record>>;
 
-use rdkafka::message::OwnedMessage;
+/// Kafka record
+///
+/// Single message payload can be encoded into several records (e.g. record per row)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Record {
+    /// destination topic, section topic is used if not set
+    pub topic: Option<String>,
+
+    /// record key, message origin is used if not set
+    pub key: Option<Vec<u8>>,
+
+    /// destination partition, producer partitioner is used if not set
+    pub partition: Option<i32>,
+
+    /// record headers
+    pub headers: Vec<(String, Vec<u8>)>,
+
+    /// encoded payload
+    pub payload: Vec<u8>,
+}
+
+impl Record {
+    pub fn new(key: Option<Vec<u8>>, payload: Vec<u8>) -> Self {
+        Self {
+            key,
+            payload,
+            ..Default::default()
+        }
+    }
+}
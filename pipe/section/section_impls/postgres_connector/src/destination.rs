@@ -1,21 +1,49 @@
 use futures::{FutureExt, Sink, Stream, StreamExt};
 use section::{Command, Section, SectionChannel};
+use std::collections::{HashMap, HashSet};
 use std::pin::{pin, Pin};
 
-use crate::{escape_table_name, generate_schema, Message, StdError, Value};
-use sqlx::Connection;
+use crate::{
+    generate_insert, generate_schema, qualified_table_name, quote_identifier, Message,
+    PostgresPayload, StdError, Value,
+};
 use sqlx::{postgres::PgConnectOptions, ConnectOptions};
+use sqlx::{Connection, PgConnection, Row};
 use std::future::Future;
 use std::str::FromStr;
 
 #[derive(Debug)]
 pub struct Postgres {
     url: String,
+
+    /// target schema, connection default (usually `public`) is used if not set
+    schema: Option<String>,
+
+    /// mapping between message origin and destination table name
+    tables: HashMap<String, String>,
+
+    /// primary key columns, if provided - rows are upserted
+    primary_keys: Vec<String>,
+
+    /// add missing columns to destination table
+    evolve_schema: bool,
 }
 
 impl Postgres {
-    pub fn new(url: impl Into<String>) -> Self {
-        Self { url: url.into() }
+    pub fn new(
+        url: impl Into<String>,
+        schema: Option<&str>,
+        tables: HashMap<String, String>,
+        primary_keys: &[&str],
+        evolve_schema: bool,
+    ) -> Self {
+        Self {
+            url: url.into(),
+            schema: schema.map(Into::into),
+            tables,
+            primary_keys: primary_keys.iter().map(|&x| x.into()).collect(),
+            evolve_schema,
+        }
     }
 
     async fn enter_loop<Input, Output, SectionChan>(
@@ -36,6 +64,14 @@ impl Postgres {
             .connect()
             .await?;
 
+        if let Some(schema) = self.schema.as_deref() {
+            let query = format!("CREATE SCHEMA IF NOT EXISTS {}", quote_identifier(schema));
+            sqlx::query(&query).execute(&mut *connection).await?;
+        }
+
+        // known columns per destination table
+        let mut tables: HashMap<String, HashSet<String>> = HashMap::new();
+
         loop {
             futures::select! {
                 cmd = section_chan.recv().fuse() => {
@@ -47,14 +83,18 @@ impl Postgres {
                         Some(message) => message,
                     };
                     let payload = &message.payload;
-                    let name = escape_table_name(&message.origin);
-                    let schema = generate_schema(&message);
-                    sqlx::query(&schema).execute(&mut *connection).await?;
-                    let values_placeholder = (0..payload.values.len()).map(|x| {
-                        let x = x + 1;
-                        format!("${x}")
-                    }).collect::<Vec<_>>().join(",");
-                    let query = format!("INSERT INTO \"{name}\" VALUES({values_placeholder})");
+                    let table = self.table_name(&message.origin);
+                    let name = qualified_table_name(self.schema.as_deref(), table);
+                    if !tables.contains_key(&name) {
+                        let schema = generate_schema(&name, payload, &self.primary_keys);
+                        sqlx::query(&schema).execute(&mut *connection).await?;
+                        let columns = self.get_columns(connection, table).await?;
+                        tables.insert(name.clone(), columns);
+                    }
+                    let columns = tables.get_mut(&name).unwrap();
+                    self.add_missing_columns(connection, &name, columns, payload).await?;
+
+                    let query = generate_insert(&name, &payload.columns, &self.primary_keys);
                     let mut transaction = connection.begin().await?;
                     for row in 0..payload.values[0].len() {
                         let mut q = sqlx::query(&query);
@@ -77,6 +117,68 @@ impl Postgres {
             }
         }
     }
+
+    /// Destination table name for given message origin
+    fn table_name<'a>(&'a self, origin: &'a str) -> &'a str {
+        self.tables
+            .get(origin)
+            .map(String::as_str)
+            .unwrap_or(origin)
+    }
+
+    /// Retrieve column names of existing table
+    async fn get_columns(
+        &self,
+        connection: &mut PgConnection,
+        table: &str,
+    ) -> Result<HashSet<String>, StdError> {
+        let columns = sqlx::query(
+            "SELECT column_name::text FROM information_schema.columns \
+             WHERE table_schema::text = COALESCE($1, current_schema()::text) AND table_name::text = $2",
+        )
+        .bind(self.schema.as_deref())
+        .bind(table)
+        .fetch_all(&mut *connection)
+        .await?
+        .into_iter()
+        .map(|row| row.try_get::<String, _>(0))
+        .collect::<Result<HashSet<_>, _>>()?;
+        Ok(columns)
+    }
+
+    /// Add columns, which are present in payload but missing in destination table
+    async fn add_missing_columns(
+        &self,
+        connection: &mut PgConnection,
+        table: &str,
+        columns: &mut HashSet<String>,
+        payload: &PostgresPayload,
+    ) -> Result<(), StdError> {
+        let missing = payload
+            .columns
+            .iter()
+            .zip(payload.column_types.iter())
+            .filter(|(name, _)| !columns.contains(name.as_str()))
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            return Ok(());
+        }
+        if !self.evolve_schema {
+            Err(format!(
+                "table {table} is missing columns: {:?}",
+                missing.iter().map(|(name, _)| name).collect::<Vec<_>>()
+            ))?
+        }
+        for (name, ty) in missing {
+            let query = format!(
+                "ALTER TABLE {table} ADD COLUMN IF NOT EXISTS {} {ty}",
+                quote_identifier(name)
+            );
+            sqlx::query(&query).execute(&mut *connection).await?;
+            columns.insert(name.clone());
+        }
+        Ok(())
+    }
 }
 
 impl<Input, Output, SectionChan> Section<Input, Output, SectionChan> for Postgres
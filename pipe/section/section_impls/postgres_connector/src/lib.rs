@@ -46,10 +46,10 @@ pub enum ColumnType {
 impl Display for ColumnType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let ty = match self {
-            ColumnType::Int => "INTEGER",
+            ColumnType::Int => "BIGINT",
             ColumnType::Text => "TEXT",
-            ColumnType::Blob => "BLOB",
-            ColumnType::Real => "DOUBLE",
+            ColumnType::Blob => "BYTEA",
+            ColumnType::Real => "DOUBLE PRECISION",
             ColumnType::Numeric => "NUMERIC",
             ColumnType::Bool => "BOOLEAN",
         };
@@ -57,30 +57,128 @@ impl Display for ColumnType {
     }
 }
 
-/// Escape table name
-pub fn escape_table_name(name: impl AsRef<str>) -> String {
-    name.as_ref()
-        .chars()
-        .flat_map(|char| {
-            let maybe_char = match char {
-                '"' => Some('\\'),
-                _ => None,
-            };
-            maybe_char.into_iter().chain([char])
-        })
-        .collect::<String>()
+/// Quote identifier
+///
+/// Identifier is wrapped into double quotes, embedded double quotes are doubled.
+pub fn quote_identifier(name: impl AsRef<str>) -> String {
+    format!("\"{}\"", name.as_ref().replace('"', "\"\""))
+}
+
+/// Build quoted table name, qualified with schema if provided
+pub fn qualified_table_name(schema: Option<&str>, table: &str) -> String {
+    match schema {
+        Some(schema) => format!("{}.{}", quote_identifier(schema), quote_identifier(table)),
+        None => quote_identifier(table),
+    }
 }
 
-/// generate create table command for provided record batch
-pub fn generate_schema(message: &Message) -> String {
-    let name = escape_table_name(message.origin.as_str());
-    let payload = &message.payload;
-    let columns = payload
+/// generate create table command for provided payload
+///
+/// `table` is expected to be already quoted, see [`qualified_table_name`].
+pub fn generate_schema(table: &str, payload: &PostgresPayload, primary_keys: &[String]) -> String {
+    let mut columns = payload
         .columns
         .iter()
         .zip(payload.column_types.iter())
-        .map(|(name, ty)| format!("{name} {ty}"))
+        .map(|(name, ty)| format!("{} {ty}", quote_identifier(name)))
+        .collect::<Vec<_>>();
+    if !primary_keys.is_empty() {
+        let keys = primary_keys
+            .iter()
+            .map(quote_identifier)
+            .collect::<Vec<_>>()
+            .join(",");
+        columns.push(format!("PRIMARY KEY ({keys})"));
+    }
+    format!("CREATE TABLE IF NOT EXISTS {table} ({})", columns.join(","))
+}
+
+/// generate insert statement for provided columns
+///
+/// If primary keys are provided - statement will update conflicting rows instead of failing.
+pub fn generate_insert(table: &str, columns: &[String], primary_keys: &[String]) -> String {
+    let names = columns
+        .iter()
+        .map(quote_identifier)
+        .collect::<Vec<_>>()
+        .join(",");
+    let placeholders = (1..=columns.len())
+        .map(|x| format!("${x}"))
+        .collect::<Vec<_>>()
+        .join(",");
+    let query = format!("INSERT INTO {table} ({names}) VALUES({placeholders})");
+    if primary_keys.is_empty() {
+        return query;
+    }
+    let keys = primary_keys
+        .iter()
+        .map(quote_identifier)
         .collect::<Vec<_>>()
         .join(",");
-    format!("CREATE TABLE IF NOT EXISTS \"{name}\" ({columns})",)
+    let updates = columns
+        .iter()
+        .filter(|column| !primary_keys.contains(column))
+        .map(|column| {
+            let column = quote_identifier(column);
+            format!("{column} = EXCLUDED.{column}")
+        })
+        .collect::<Vec<_>>();
+    match updates.is_empty() {
+        true => format!("{query} ON CONFLICT ({keys}) DO NOTHING"),
+        false => format!(
+            "{query} ON CONFLICT ({keys}) DO UPDATE SET {}",
+            updates.join(",")
+        ),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_quote_identifier() {
+        assert_eq!(quote_identifier("table"), r#""table""#);
+        assert_eq!(quote_identifier(r#"ta"ble"#), r#""ta""ble""#);
+        assert_eq!(
+            qualified_table_name(Some("edge"), "table"),
+            r#""edge"."table""#
+        );
+        assert_eq!(qualified_table_name(None, "table"), r#""table""#);
+    }
+
+    #[test]
+    fn test_generate_schema() {
+        let payload = PostgresPayload {
+            columns: Arc::from(vec!["id".to_string(), "text".to_string()]),
+            column_types: Arc::from(vec![ColumnType::Int, ColumnType::Text]),
+            values: vec![vec![], vec![]],
+            offset: 0,
+        };
+        assert_eq!(
+            generate_schema(r#""test""#, &payload, &[]),
+            r#"CREATE TABLE IF NOT EXISTS "test" ("id" BIGINT,"text" TEXT)"#
+        );
+        assert_eq!(
+            generate_schema(r#""test""#, &payload, &["id".to_string()]),
+            r#"CREATE TABLE IF NOT EXISTS "test" ("id" BIGINT,"text" TEXT,PRIMARY KEY ("id"))"#
+        );
+    }
+
+    #[test]
+    fn test_generate_insert() {
+        let columns = vec!["id".to_string(), "text".to_string()];
+        assert_eq!(
+            generate_insert(r#""test""#, &columns, &[]),
+            r#"INSERT INTO "test" ("id","text") VALUES($1,$2)"#
+        );
+        assert_eq!(
+            generate_insert(r#""test""#, &columns, &["id".to_string()]),
+            r#"INSERT INTO "test" ("id","text") VALUES($1,$2) ON CONFLICT ("id") DO UPDATE SET "text" = EXCLUDED."text""#
+        );
+        assert_eq!(
+            generate_insert(r#""test""#, &columns, &columns),
+            r#"INSERT INTO "test" ("id","text") VALUES($1,$2) ON CONFLICT ("id","text") DO NOTHING"#
+        );
+    }
 }
@@ -190,7 +190,7 @@ impl Sqlite {
                         });
                         let message = Message::new(table.name.to_string(), sqlite_payload, Some(Box::pin(async move {
                             weak_chan.ack(ack_message).await;
-                        })));
+                        }))).with_offset(Some(table.offset.to_string()));
                         output.send(message).await.map_err(|_| "failed to send data to sink")?;
                     }
                     // if empty count is less than table count - we didn't reach ends of table on
@@ -1,42 +1,82 @@
 //! Destination journal destination
+//!
+//! Received snapshots are appended to the journal and, if database path is configured, pages of each snapshot are
+//! applied to the database.
+//! Pages are written in place while exclusive lock on database is held through SQLite, so readers never observe
+//! partially applied snapshot. Original content of overwritten pages is saved to rollback file next to the
+//! database before pages are written, so incomplete snapshot is undone on error or on next start.
+//! Database is expected to be in rollback journal mode, readers of WAL database don't take database file locks.
+//! Last applied snapshot id is stored in section state, on start only snapshots past it are applied.
+//!
+//! Optionally database state as of given snapshot id or timestamp can be materialized into a separate file.
+//!
+//! Blobs of incoming snapshot are spooled next to the journal, snapshot is appended to the journal and applied to
+//! the database only once it is complete and verified.
+//! Blob sizes and checksums, snapshot checksums and snapshot sequence are verified before snapshot is committed
+//! to the journal. Optionally `PRAGMA integrity_check` is executed on database once snapshot is applied, failed
+//! check rolls snapshot back.
+//! Snapshot gap triggers resync: preceding sections of pipe replay snapshots from the start, snapshots which are
+//! already in the journal are skipped.
 
-use arrow::array::{BinaryArray, Int64Array, UInt32Array, UInt64Array};
-use arrow::datatypes::{DataType, Field, Schema};
+use arrow::array::{Array, BinaryArray, BooleanArray, Int64Array, UInt32Array, UInt64Array};
+use arrow::datatypes::Schema;
 use futures::{FutureExt, Sink, Stream, StreamExt};
 use std::collections::HashSet;
 use std::future::Future;
-use std::io::SeekFrom;
+use std::io::{ErrorKind, SeekFrom};
 use std::path::Path;
 use std::pin::{pin, Pin};
 
+/// Point in time to restore database at
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RestorePoint {
+    /// include snapshots up to and including given snapshot id
+    SnapshotId(u64),
+
+    /// include snapshots with timestamp less or equal to given timestamp
+    Timestamp(i64),
+}
+
+impl RestorePoint {
+    fn includes(&self, snapshot_header: &SnapshotHeader) -> bool {
+        match *self {
+            RestorePoint::SnapshotId(id) => snapshot_header.id <= id,
+            RestorePoint::Timestamp(timestamp) => snapshot_header.timestamp <= timestamp,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Destination {
     journal_path: String,
     database_path: Option<String>,
+    restore: Option<(RestorePoint, String)>,
+    integrity_check: bool,
     schema: Schema,
 }
 
 use journal::{AsyncJournal, BlobHeader, SnapshotHeader};
-use section::{Command, Section, SectionChannel};
-use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use section::{Command, Section, SectionChannel, State};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode};
+use sqlx::{ConnectOptions, Connection, SqliteConnection};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader, BufWriter};
 
 use crate::{Message, StdError};
 
 impl Destination {
-    pub fn new(journal_path: impl Into<String>, database_path: Option<impl Into<String>>) -> Self {
-        let schema = Schema::new(vec![
-            Field::new("snapshot_id", DataType::UInt64, false),
-            Field::new("timestamp", DataType::Int64, false),
-            Field::new("page_size", DataType::UInt32, true),
-            Field::new("offset", DataType::UInt64, false),
-            Field::new("blob_num", DataType::UInt32, false),
-            Field::new("blob_size", DataType::UInt32, false),
-            Field::new("blob", DataType::Binary, false),
-        ]);
+    pub fn new(
+        journal_path: impl Into<String>,
+        database_path: Option<impl Into<String>>,
+        restore: Option<(RestorePoint, impl Into<String>)>,
+        integrity_check: bool,
+    ) -> Self {
         Self {
             journal_path: journal_path.into(),
             database_path: database_path.map(Into::into),
-            schema,
+            restore: restore.map(|(point, path)| (point, path.into())),
+            integrity_check,
+            schema: crate::schema(),
         }
     }
 
@@ -51,17 +91,6 @@ impl Destination {
         Output: Sink<Message, Error = StdError> + Send,
         SectionChan: SectionChannel + Send + Sync + 'static,
     {
-        let mut db_fd = match self.database_path.as_ref() {
-            Some(path) => Some(
-                tokio::fs::OpenOptions::new()
-                    .create(true)
-                    .write(true)
-                    .open(Path::new(path))
-                    .await?,
-            ),
-            None => None,
-        };
-
         // section doesn't produce any output
         let _output = output;
         let mut input = pin!(input.fuse());
@@ -75,6 +104,52 @@ impl Destination {
             Err(e) => Err(e),
         }?;
 
+        let mut state = section_chan
+            .retrieve_state()
+            .await?
+            .unwrap_or(<SectionChan as SectionChannel>::State::new());
+
+        // bring database in sync with journal, after that only new snapshots are applied
+        if let Some(path) = self.database_path.as_deref() {
+            rollback(path).await?;
+            journal.update_header().await?;
+            let last_snapshot = journal.get_header().snapshot_counter.checked_sub(1);
+            let applied = match (
+                state.get::<u64>("snapshot_id")?,
+                tokio::fs::try_exists(path).await?,
+            ) {
+                (Some(applied), true) => {
+                    catch_up(pin!(journal.stream()), path, applied, self.integrity_check).await?;
+                    Some(applied)
+                }
+                _ => {
+                    materialize(pin!(journal.stream()), path, None, self.integrity_check).await?;
+                    None
+                }
+            };
+            if let Some(last_snapshot) = last_snapshot.filter(|id| Some(*id) != applied) {
+                state.set("snapshot_id", last_snapshot)?;
+                section_chan.store_state(state.clone()).await?;
+            }
+        }
+        let mut restored = match self.restore.as_ref() {
+            Some((point, path)) => {
+                materialize(
+                    pin!(journal.stream()),
+                    path,
+                    Some(*point),
+                    self.integrity_check,
+                )
+                .await?
+            }
+            None => true,
+        };
+
+        // snapshot, which chunks are being received, and spool of its blobs
+        let mut pending_snapshot: Option<(SnapshotHeader, Spool)> = None;
+        // checksum of received blobs of pending snapshot
+        let mut snapshot_hasher = crc32fast::Hasher::new();
+
         loop {
             futures::select! {
                 cmd = section_chan.recv().fuse() => {
@@ -101,45 +176,431 @@ impl Destination {
                     let blob_num: UInt32Array = payload["blob_num"].to_data().into();
                     let blob_size: UInt32Array = payload["blob_size"].to_data().into();
                     let blob: BinaryArray = payload["blob"].to_data().into();
+                    let last_chunk: BooleanArray = payload["last_chunk"].to_data().into();
+                    let blob_checksum: UInt32Array = payload["blob_checksum"].to_data().into();
+                    let snapshot_checksum: UInt32Array = payload["snapshot_checksum"].to_data().into();
 
                     let snapshot_id = HashSet::<u64>::from_iter(snapshot_id.iter().map(|x| x.unwrap()));
                     if snapshot_id.len() != 1 {
                         Err("arrow message with multiple snapshots are not supported yet")?
                     };
                     let snapshot_id = snapshot_id.into_iter().next().unwrap();
+                    let last_chunk = len > 0 && last_chunk.value(0);
+
+                    if snapshot_id > header.snapshot_counter {
+                        let expected = header.snapshot_counter;
+                        section_chan.log(format!(
+                            "snapshot gap detected, requesting resync from snapshot {expected}"
+                        )).await?;
+                        // preceding sections replay snapshots from the start, existing snapshots are skipped
+                        section_chan.resync().await?;
+                        Err(format!("snapshot gap: expected snapshot {expected}, got snapshot {snapshot_id}"))?
+                    }
 
                     // if snapshot id already exists - skip
                     if snapshot_id + 1 > header.snapshot_counter {
-                        let timestamp = timestamp.value(0);
-                        let page_size = page_size.value(0);
-                        let snapshot_header = SnapshotHeader::new(snapshot_id, timestamp, Some(page_size));
-
-                        journal.add_snapshot(&snapshot_header).await?;
+                        match pending_snapshot.as_ref() {
+                            None => {
+                                let timestamp = timestamp.value(0);
+                                let page_size = page_size.value(0);
+                                let snapshot_header = SnapshotHeader::new(snapshot_id, timestamp, Some(page_size));
+                                let spool = Spool::create(&spool_path(&self.journal_path)).await?;
+                                pending_snapshot = Some((snapshot_header, spool));
+                                snapshot_hasher = crc32fast::Hasher::new();
+                            },
+                            Some((s, _)) if s.id == snapshot_id => (),
+                            Some((s, _)) => Err(format!(
+                                "snapshot {} is incomplete, got chunk of snapshot {snapshot_id}", s.id
+                            ))?,
+                        };
+                        let (_, spool) = pending_snapshot.as_mut().unwrap();
                         for pos in 0..len {
                             let blob_h = BlobHeader::new(offset.value(pos), blob_num.value(pos), blob_size.value(pos));
                             let blob = blob.value(pos);
-                            journal.add_blob(&blob_h, blob).await?;
+                            if blob.len() != blob_h.blob_size as usize {
+                                Err(format!(
+                                    "truncated blob {} in snapshot {snapshot_id}: expected {} bytes, got {}",
+                                    blob_h.blob_num, blob_h.blob_size, blob.len()
+                                ))?
+                            }
+                            if crc32fast::hash(blob) != blob_checksum.value(pos) {
+                                Err(format!("checksum mismatch for blob {} in snapshot {snapshot_id}", blob_h.blob_num))?
+                            }
+                            snapshot_hasher.update(blob);
+                            spool.push(&blob_h, blob).await?;
                         }
-                        journal.commit().await?;
-                    }
-                    msg.ack().await;
-
-                    // FIXME: db recovered from scratch
-                    let mut journal_stream = pin!(journal.stream());
-                    if let Some(ref mut fd) = db_fd {
-                        while let Some(data) = journal_stream.next().await {
-                            let (_, blob_h, blob) = data?;
-                            fd.seek(SeekFrom::Start(blob_h.offset)).await?;
-                            fd.write_all(&blob).await?;
+                        if last_chunk {
+                            let checksum = std::mem::take(&mut snapshot_hasher).finalize();
+                            let (snapshot_header, mut spool) = pending_snapshot.take().unwrap();
+                            if snapshot_checksum.is_null(0) || snapshot_checksum.value(0) != checksum {
+                                spool.remove().await?;
+                                Err(format!("checksum mismatch for snapshot {snapshot_id}"))?
+                            }
+                            let mut db_update = match self.database_path.as_deref() {
+                                Some(path) => Some(DbUpdate::begin(path).await?),
+                                None => None,
+                            };
+                            journal.add_snapshot(&snapshot_header).await?;
+                            let mut blobs = spool.reader().await?;
+                            while let Some((blob_h, blob)) = Spool::next(&mut blobs).await? {
+                                journal.add_blob(&blob_h, &blob).await?;
+                                if let Some(update) = db_update.as_mut() {
+                                    update.save(blob_h.offset, blob.len()).await?;
+                                }
+                            }
+                            journal.commit().await?;
+                            if let Some(mut update) = db_update {
+                                update.sync().await?;
+                                let mut blobs = spool.reader().await?;
+                                while let Some((blob_h, blob)) = Spool::next(&mut blobs).await? {
+                                    update.write(blob_h.offset, &blob).await?;
+                                }
+                                update.commit(self.integrity_check).await?;
+                                state.set("snapshot_id", snapshot_id)?;
+                                section_chan.store_state(state.clone()).await?;
+                            }
+                            spool.remove().await?;
+                            if let (false, Some((point, path))) = (restored, self.restore.as_ref()) {
+                                restored = materialize(
+                                    pin!(journal.stream()), path, Some(*point), self.integrity_check
+                                ).await?;
+                            }
                         }
-                        fd.flush().await?;
                     }
+                    msg.ack().await;
                 }
             }
         }
     }
 }
 
+/// Spool of pending snapshot, next to the journal
+fn spool_path(journal_path: &str) -> String {
+    format!("{journal_path}.mycelial-pending")
+}
+
+/// Blobs of pending snapshot, which are kept aside until snapshot checksum is verified
+///
+/// Each blob is stored as `(offset, blob_num, blob_size, blob)` record.
+struct Spool {
+    path: String,
+    fd: BufWriter<File>,
+}
+
+impl Spool {
+    async fn create(path: &str) -> Result<Self, StdError> {
+        Ok(Self {
+            path: path.into(),
+            fd: BufWriter::new(File::create(path).await?),
+        })
+    }
+
+    async fn push(&mut self, blob_h: &BlobHeader, blob: &[u8]) -> Result<(), StdError> {
+        self.fd.write_u64(blob_h.offset).await?;
+        self.fd.write_u32(blob_h.blob_num).await?;
+        self.fd.write_u32(blob_h.blob_size).await?;
+        self.fd.write_all(blob).await?;
+        Ok(())
+    }
+
+    /// Open spool for reading from the start
+    async fn reader(&mut self) -> Result<BufReader<File>, StdError> {
+        self.fd.flush().await?;
+        Ok(BufReader::new(File::open(&self.path).await?))
+    }
+
+    async fn next(reader: &mut BufReader<File>) -> Result<Option<(BlobHeader, Vec<u8>)>, StdError> {
+        let offset = match reader.read_u64().await {
+            Ok(offset) => offset,
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => Err(e)?,
+        };
+        let blob_num = reader.read_u32().await?;
+        let blob_size = reader.read_u32().await?;
+        let mut blob = vec![0; blob_size as usize];
+        reader.read_exact(&mut blob).await?;
+        Ok(Some((BlobHeader::new(offset, blob_num, blob_size), blob)))
+    }
+
+    async fn remove(self) -> Result<(), StdError> {
+        drop(self.fd);
+        tokio::fs::remove_file(&self.path).await?;
+        Ok(())
+    }
+}
+
+/// Temporary file, which replaces target file once written
+fn tmp_path(path: &str) -> String {
+    format!("{path}.mycelial-tmp")
+}
+
+/// Open empty temporary file for writing
+async fn begin(path: &str) -> Result<File, StdError> {
+    let tmp_path = tmp_path(path);
+    File::create(&tmp_path).await?;
+    let fd = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(Path::new(&tmp_path))
+        .await?;
+    Ok(fd)
+}
+
+/// Flush temporary file and atomically replace target file
+async fn finish(mut fd: File, path: &str, integrity_check: bool) -> Result<(), StdError> {
+    fd.flush().await?;
+    fd.sync_all().await?;
+    drop(fd);
+    let tmp_path = tmp_path(path);
+    if integrity_check {
+        check_integrity(&tmp_path).await?;
+    }
+    tokio::fs::rename(tmp_path, path).await?;
+    Ok(())
+}
+
+/// Rollback file of database, contains original length of database followed by
+/// `(offset, length, original content)` records of overwritten ranges
+fn rollback_path(path: &str) -> String {
+    format!("{path}.mycelial-rollback")
+}
+
+/// Exclusive lock on database, taken through SQLite so SQLite readers wait until lock is released
+///
+/// Lock should be released before any other file descriptor of database is closed, since closing descriptor
+/// drops all POSIX locks of process on the file.
+struct DbLock {
+    connection: SqliteConnection,
+}
+
+impl DbLock {
+    async fn acquire(path: &str) -> Result<Self, StdError> {
+        // exclusive lock blocks readers only in rollback journal mode
+        let mut connection = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Delete)
+            .connect()
+            .await?;
+        sqlx::query("BEGIN EXCLUSIVE")
+            .execute(&mut connection)
+            .await?;
+        Ok(Self { connection })
+    }
+
+    async fn release(mut self) -> Result<(), StdError> {
+        // transaction has no changes, commit only releases lock
+        sqlx::query("COMMIT").execute(&mut self.connection).await?;
+        self.connection.close().await?;
+        Ok(())
+    }
+}
+
+/// In-place update of database file
+///
+/// Database is locked for the whole update. Original content of page is saved with [`DbUpdate::save`], saved
+/// content is flushed with [`DbUpdate::sync`] before page is overwritten with [`DbUpdate::write`].
+struct DbUpdate {
+    path: String,
+    lock: DbLock,
+    db: File,
+    rollback: File,
+    /// length of database before update
+    len: u64,
+}
+
+impl DbUpdate {
+    async fn begin(path: &str) -> Result<Self, StdError> {
+        let lock = DbLock::acquire(path).await?;
+        let db = tokio::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(path)
+            .await?;
+        let len = db.metadata().await?.len();
+        let mut rollback = File::create(rollback_path(path)).await?;
+        rollback.write_u64(len).await?;
+        Ok(Self {
+            path: path.into(),
+            lock,
+            db,
+            rollback,
+            len,
+        })
+    }
+
+    /// Save original content of range, which is going to be overwritten
+    async fn save(&mut self, offset: u64, len: usize) -> Result<(), StdError> {
+        if offset >= self.len {
+            // range past original end of database is dropped on rollback
+            return Ok(());
+        }
+        let len = (len as u64).min(self.len - offset) as usize;
+        let mut original = vec![0; len];
+        self.db.seek(SeekFrom::Start(offset)).await?;
+        self.db.read_exact(&mut original).await?;
+        self.rollback.write_u64(offset).await?;
+        self.rollback.write_u32(len as u32).await?;
+        self.rollback.write_all(&original).await?;
+        Ok(())
+    }
+
+    /// Flush saved content to disk
+    async fn sync(&mut self) -> Result<(), StdError> {
+        self.rollback.flush().await?;
+        self.rollback.sync_data().await?;
+        Ok(())
+    }
+
+    async fn write(&mut self, offset: u64, page: &[u8]) -> Result<(), StdError> {
+        self.db.seek(SeekFrom::Start(offset)).await?;
+        self.db.write_all(page).await?;
+        Ok(())
+    }
+
+    /// Flush database and drop rollback file, snapshot is rolled back if integrity check fails
+    ///
+    /// Integrity check runs once lock is released, since locking connection caches pages of database.
+    async fn commit(mut self, integrity_check: bool) -> Result<(), StdError> {
+        self.db.flush().await?;
+        self.db.sync_all().await?;
+        self.lock.release().await?;
+        drop(self.db);
+        drop(self.rollback);
+        if integrity_check {
+            if let Err(e) = check_integrity(&self.path).await {
+                rollback(&self.path).await?;
+                return Err(e);
+            }
+        }
+        tokio::fs::remove_file(rollback_path(&self.path)).await?;
+        Ok(())
+    }
+}
+
+/// Undo incomplete update of database, if rollback file exists
+async fn rollback(path: &str) -> Result<(), StdError> {
+    let rollback_path = rollback_path(path);
+    let data = match tokio::fs::read(&rollback_path).await {
+        Ok(data) => data,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => Err(e)?,
+    };
+    if data.len() >= 8 {
+        let len = u64::from_be_bytes(data[..8].try_into()?);
+        // records are restored in reverse order, so range saved more than once ends up with original content;
+        // incomplete trailing record means database wasn't written yet
+        let mut records = vec![];
+        let mut pos = 8;
+        while pos + 12 <= data.len() {
+            let offset = u64::from_be_bytes(data[pos..pos + 8].try_into()?);
+            let size = u32::from_be_bytes(data[pos + 8..pos + 12].try_into()?) as usize;
+            if pos + 12 + size > data.len() {
+                break;
+            }
+            records.push((offset, &data[pos + 12..pos + 12 + size]));
+            pos += 12 + size;
+        }
+        let lock = DbLock::acquire(path).await?;
+        let mut db = tokio::fs::OpenOptions::new().write(true).open(path).await?;
+        for (offset, original) in records.into_iter().rev() {
+            db.seek(SeekFrom::Start(offset)).await?;
+            db.write_all(original).await?;
+        }
+        db.set_len(len).await?;
+        db.sync_all().await?;
+        lock.release().await?;
+    }
+    tokio::fs::remove_file(&rollback_path).await?;
+    Ok(())
+}
+
+/// Apply pages of journal snapshots, which follow `applied` snapshot, to database in place
+async fn catch_up<S, E>(
+    mut journal_stream: Pin<&mut S>,
+    path: &str,
+    applied: u64,
+    integrity_check: bool,
+) -> Result<(), StdError>
+where
+    S: Stream<Item = Result<(SnapshotHeader, BlobHeader, Vec<u8>), E>>,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    let mut update: Option<DbUpdate> = None;
+    while let Some(data) = journal_stream.next().await {
+        let (snapshot_h, blob_h, blob) = data?;
+        if snapshot_h.id <= applied {
+            continue;
+        }
+        if update.is_none() {
+            update = Some(DbUpdate::begin(path).await?);
+        }
+        // snapshots are already in journal, so database interrupted during catch up is fixed by next catch up,
+        // rollback file is only used to undo snapshots, which fail integrity check
+        let update = update.as_mut().unwrap();
+        update.save(blob_h.offset, blob.len()).await?;
+        update.write(blob_h.offset, &blob).await?;
+    }
+    if let Some(update) = update {
+        update.commit(integrity_check).await?;
+    }
+    Ok(())
+}
+
+/// Run `PRAGMA integrity_check` on database
+async fn check_integrity(path: &str) -> Result<(), StdError> {
+    let mut connection = SqliteConnectOptions::new()
+        .filename(path)
+        .read_only(true)
+        .connect()
+        .await?;
+    let result: Vec<String> = sqlx::query_scalar("PRAGMA integrity_check")
+        .fetch_all(&mut connection)
+        .await?;
+    connection.close().await?;
+    if result != ["ok"] {
+        Err(format!(
+            "integrity check failed for {path}: {}",
+            result.join("; ")
+        ))?
+    }
+    Ok(())
+}
+
+/// Write pages of journal snapshots into file at given path, replaying journal from scratch
+///
+/// If restore point is provided - only snapshots included in restore point are applied.
+/// Returns true if journal contains all snapshots required to reach restore point.
+async fn materialize<S, E>(
+    mut journal_stream: Pin<&mut S>,
+    path: &str,
+    point: Option<RestorePoint>,
+    integrity_check: bool,
+) -> Result<bool, StdError>
+where
+    S: Stream<Item = Result<(SnapshotHeader, BlobHeader, Vec<u8>), E>>,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    let mut fd = begin(path).await?;
+    let mut reached = point.is_none();
+    while let Some(data) = journal_stream.next().await {
+        let (snapshot_h, blob_h, blob) = data?;
+        match point {
+            Some(point) if !point.includes(&snapshot_h) => {
+                // journal contains snapshot past restore point
+                reached = true;
+                break;
+            }
+            Some(RestorePoint::SnapshotId(id)) if snapshot_h.id == id => reached = true,
+            _ => (),
+        };
+        fd.seek(SeekFrom::Start(blob_h.offset)).await?;
+        fd.write_all(&blob).await?;
+    }
+    finish(fd, path, integrity_check).await?;
+    Ok(reached)
+}
+
 impl<Input, Output, SectionChan> Section<Input, Output, SectionChan> for Destination
 where
     Input: Stream<Item = Message> + Send + 'static,
@@ -153,3 +614,91 @@ where
         Box::pin(async move { self.enter_loop(input, output, section_chan).await })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    /// Create sqlite database with a single table, returns database content
+    async fn create_database(path: &str) -> Result<Vec<u8>, StdError> {
+        let mut connection = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Delete)
+            .connect()
+            .await?;
+        sqlx::query("CREATE TABLE test (id INTEGER PRIMARY KEY)")
+            .execute(&mut connection)
+            .await?;
+        connection.close().await?;
+        Ok(tokio::fs::read(path).await?)
+    }
+
+    #[tokio::test]
+    async fn test_rollback() -> Result<(), StdError> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("db").to_string_lossy().to_string();
+        let original = create_database(&path).await?;
+        let len = original.len() as u64;
+
+        // update which extends database and overwrites the same range twice
+        let mut update = DbUpdate::begin(&path).await?;
+        for (offset, page) in [(len - 16, [3; 16]), (len, [4; 16]), (len - 16, [5; 16])] {
+            update.save(offset, page.len()).await?;
+            update.sync().await?;
+            update.write(offset, &page).await?;
+        }
+        drop(update);
+        assert_eq!(
+            tokio::fs::read(&path).await?,
+            [&original[..len as usize - 16], &[5; 16], &[4; 16]].concat()
+        );
+
+        rollback(&path).await?;
+        assert_eq!(tokio::fs::read(&path).await?, original);
+        assert!(!tokio::fs::try_exists(rollback_path(&path)).await?);
+
+        // committed update leaves no rollback file
+        let mut update = DbUpdate::begin(&path).await?;
+        update.save(len - 16, 16).await?;
+        update.sync().await?;
+        update.write(len - 16, &[6; 16]).await?;
+        update.commit(false).await?;
+        rollback(&path).await?;
+        assert_eq!(
+            tokio::fs::read(&path).await?,
+            [&original[..len as usize - 16], &[6; 16]].concat()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_update_blocks_readers() -> Result<(), StdError> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("db").to_string_lossy().to_string();
+        create_database(&path).await?;
+
+        let read = || async {
+            let mut connection = SqliteConnectOptions::new()
+                .filename(&path)
+                .read_only(true)
+                .busy_timeout(Duration::from_millis(0))
+                .connect()
+                .await?;
+            let count: i64 = sqlx::query_scalar("SELECT count(*) FROM test")
+                .fetch_one(&mut connection)
+                .await?;
+            connection.close().await?;
+            Ok::<_, StdError>(count)
+        };
+        assert_eq!(read().await?, 0);
+
+        // database can't be read until update is committed
+        let update = DbUpdate::begin(&path).await?;
+        assert!(read().await.is_err());
+        update.commit(false).await?;
+        assert_eq!(read().await?, 0);
+        Ok(())
+    }
+}
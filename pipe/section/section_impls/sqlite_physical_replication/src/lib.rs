@@ -1,8 +1,28 @@
 pub mod destination;
 pub mod source;
 
+use arrow::datatypes::{DataType, Field, Schema};
 use arrow::record_batch::RecordBatch;
 use section::Message as _Message;
 
 pub type StdError = Box<dyn std::error::Error + Send + Sync + 'static>;
 pub type Message = _Message<RecordBatch>;
+
+/// Schema of journal messages, each row represents single blob of snapshot
+pub fn schema() -> Schema {
+    Schema::new(vec![
+        Field::new("snapshot_id", DataType::UInt64, false),
+        Field::new("timestamp", DataType::Int64, false),
+        Field::new("page_size", DataType::UInt32, true),
+        Field::new("offset", DataType::UInt64, false),
+        Field::new("blob_num", DataType::UInt32, false),
+        Field::new("blob_size", DataType::UInt32, false),
+        Field::new("blob", DataType::Binary, false),
+        // set if message contains final chunk of snapshot
+        Field::new("last_chunk", DataType::Boolean, false),
+        // crc32 of blob
+        Field::new("blob_checksum", DataType::UInt32, false),
+        // crc32 of all blobs of snapshot, set only for final chunk of snapshot
+        Field::new("snapshot_checksum", DataType::UInt32, true),
+    ])
+}
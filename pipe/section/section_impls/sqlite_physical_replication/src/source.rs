@@ -1,24 +1,35 @@
 //! Source journal source
+//!
+//! Snapshots are streamed in chunks of at most `max_chunk_size` bytes of blobs (snapshot with a single blob larger than
+//! `max_chunk_size` will be sent as a single chunk).
+//! Each row carries `last_chunk` flag, only the final chunk of snapshot is acked, after which snapshot is considered delivered.
+//! Each blob carries crc32 checksum, final chunk also carries crc32 checksum of all blobs of snapshot.
+//!
+//! Journal is read directly from file: byte offset past the last acked snapshot is stored in section state, so
+//! reading resumes from it instead of scanning journal from the start. Stale offset (e.g. journal was recreated)
+//! falls back to skipping snapshot headers from the start of journal.
 
-use arrow::array::{Array, BinaryArray, Int64Array, UInt32Array, UInt64Array};
-use arrow::datatypes::{DataType, Field, Schema};
+use arrow::array::{Array, BinaryArray, BooleanArray, Int64Array, UInt32Array, UInt64Array};
+use arrow::datatypes::Schema;
 use arrow::record_batch::RecordBatch;
 use futures::{FutureExt, Sink, SinkExt, Stream, StreamExt};
 use notify::{Event, RecursiveMode, Watcher};
 use section::{Command, Section, SectionChannel, State, WeakSectionChannel};
 use std::future::Future;
+use std::io::{ErrorKind, SeekFrom};
 use std::path::Path;
 use std::pin::{pin, Pin};
 use std::sync::Arc;
-use tokio::sync::mpsc::Sender;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, BufReader};
+use tokio::sync::mpsc::{Receiver, Sender};
 
 #[derive(Debug)]
 pub struct Source {
     journal_path: String,
-    cur_snapshot: Option<u64>,
-    buf: Vec<(SnapshotHeader, BlobHeader, Vec<u8>)>,
     schema: Arc<Schema>,
     origin: Option<String>,
+    max_chunk_size: usize,
 }
 
 use journal::{AsyncJournal, BlobHeader, SnapshotHeader};
@@ -26,29 +37,133 @@ use tokio_stream::wrappers::ReceiverStream;
 
 use crate::{Message, StdError};
 
+/// Part of snapshot
 #[derive(Debug)]
-enum Buf {
-    Ok(Message),
-    More,
+struct Chunk {
+    snapshot_id: u64,
+    /// journal offset past snapshot, set only for the final chunk of snapshot
+    end_offset: Option<u64>,
+    batch: RecordBatch,
+}
+
+/// Size of journal header block
+const HEADER_SIZE: u64 = 128;
+
+/// Size of snapshot header block
+const SNAPSHOT_HEADER_SIZE: usize = 32;
+
+/// Size of blob header block
+const BLOB_HEADER_SIZE: usize = 16;
+
+/// Sequential reader of journal file, starting at given byte offset
+///
+/// Journal header block is followed by snapshots, each snapshot is snapshot header block followed by blob
+/// header blocks with blobs and terminated by zeroed blob header block.
+/// Integers are stored in big endian.
+struct JournalReader {
+    fd: BufReader<File>,
+    /// byte offset of reader in journal
+    pos: u64,
+}
+
+impl JournalReader {
+    async fn open(path: &str, pos: u64) -> Result<Self, StdError> {
+        let mut fd = File::open(path).await?;
+        fd.seek(SeekFrom::Start(pos)).await?;
+        Ok(Self {
+            fd: BufReader::new(fd),
+            pos,
+        })
+    }
+
+    /// Id of snapshot at reader position, reader position is not changed
+    async fn peek_snapshot_id(&mut self) -> Result<Option<u64>, StdError> {
+        let mut buf = [0; 8];
+        let id = match self.fd.read_exact(&mut buf).await {
+            Ok(_) => Some(u64::from_be_bytes(buf)),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => None,
+            Err(e) => Err(e)?,
+        };
+        self.fd.seek(SeekFrom::Start(self.pos)).await?;
+        Ok(id)
+    }
+
+    async fn snapshot_header(&mut self) -> Result<SnapshotHeader, StdError> {
+        let mut buf = [0; SNAPSHOT_HEADER_SIZE];
+        self.fd.read_exact(&mut buf).await?;
+        self.pos += SNAPSHOT_HEADER_SIZE as u64;
+        let id = u64::from_be_bytes(buf[0..8].try_into()?);
+        let timestamp = i64::from_be_bytes(buf[8..16].try_into()?);
+        let page_size = u32::from_be_bytes(buf[16..20].try_into()?);
+        Ok(SnapshotHeader::new(
+            id,
+            timestamp,
+            Some(page_size).filter(|size| *size != 0),
+        ))
+    }
+
+    /// Next blob header of snapshot, None at the end of snapshot
+    async fn blob_header(&mut self) -> Result<Option<BlobHeader>, StdError> {
+        let mut buf = [0; BLOB_HEADER_SIZE];
+        self.fd.read_exact(&mut buf).await?;
+        self.pos += BLOB_HEADER_SIZE as u64;
+        if buf.iter().all(|byte| *byte == 0) {
+            return Ok(None);
+        }
+        let offset = u64::from_be_bytes(buf[0..8].try_into()?);
+        let blob_num = u32::from_be_bytes(buf[8..12].try_into()?);
+        let blob_size = u32::from_be_bytes(buf[12..16].try_into()?);
+        Ok(Some(BlobHeader::new(offset, blob_num, blob_size)))
+    }
+
+    /// Next blob of snapshot, None at the end of snapshot
+    async fn blob(&mut self, snapshot_id: u64) -> Result<Option<(BlobHeader, Vec<u8>)>, StdError> {
+        let blob_header = match self.blob_header().await? {
+            Some(blob_header) => blob_header,
+            None => return Ok(None),
+        };
+        let mut blob = vec![0; blob_header.blob_size as usize];
+        let read = read_full(&mut self.fd, &mut blob).await?;
+        if read != blob.len() {
+            Err(format!(
+                "truncated blob {} in snapshot {}: expected {} bytes, got {}",
+                blob_header.blob_num, snapshot_id, blob_header.blob_size, read
+            ))?
+        }
+        self.pos += blob.len() as u64;
+        Ok(Some((blob_header, blob)))
+    }
+
+    /// Skip snapshot at reader position without reading its blobs
+    async fn skip_snapshot(&mut self) -> Result<(), StdError> {
+        self.snapshot_header().await?;
+        while let Some(blob_header) = self.blob_header().await? {
+            self.pos += blob_header.blob_size as u64;
+            self.fd.seek(SeekFrom::Start(self.pos)).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Read until buffer is full or reader is exhausted, returns number of read bytes
+async fn read_full(fd: &mut BufReader<File>, buf: &mut [u8]) -> Result<usize, StdError> {
+    let mut read = 0;
+    while read < buf.len() {
+        match fd.read(&mut buf[read..]).await? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    Ok(read)
 }
 
 impl Source {
-    pub fn new(journal_path: impl Into<String>) -> Self {
-        let schema = Schema::new(vec![
-            Field::new("snapshot_id", DataType::UInt64, false),
-            Field::new("timestamp", DataType::Int64, false),
-            Field::new("page_size", DataType::UInt32, true),
-            Field::new("offset", DataType::UInt64, false),
-            Field::new("blob_num", DataType::UInt32, false),
-            Field::new("blob_size", DataType::UInt32, false),
-            Field::new("blob", DataType::Binary, false),
-        ]);
+    pub fn new(journal_path: impl Into<String>, max_chunk_size: usize) -> Self {
         Self {
             journal_path: journal_path.into(),
-            buf: vec![],
-            schema: Arc::new(schema),
-            cur_snapshot: None,
+            schema: Arc::new(crate::schema()),
             origin: None,
+            max_chunk_size,
         }
     }
 
@@ -66,9 +181,6 @@ impl Source {
         // section doesn't consume any input
         let _input = input;
 
-        // open async journal
-        let mut journal = AsyncJournal::try_from(self.journal_path.as_str()).await?;
-
         // better than empty string, but might be just configurable
         self.origin = Path::new(self.journal_path.as_str())
             .file_name()
@@ -78,7 +190,6 @@ impl Source {
 
         // initalize fs watcher to journal
         let (tx, watcher_rx) = tokio::sync::mpsc::channel::<()>(2);
-        let mut watcher_rx = pin!(ReceiverStream::new(watcher_rx).fuse());
         tx.send(()).await?;
         let _watcher = self.watch(self.journal_path.as_str(), tx).await?;
 
@@ -86,7 +197,15 @@ impl Source {
             .retrieve_state()
             .await?
             .unwrap_or(<SectionChan as SectionChannel>::State::new());
-        self.cur_snapshot = state.get::<u64>("snapshot_id")?;
+        let last_snapshot = state.get::<u64>("snapshot_id")?;
+        let journal_offset = state.get::<u64>("journal_offset")?;
+
+        // journal is read concurrently with command processing, so acks are not blocked by snapshot streaming
+        let (chunk_tx, chunk_rx) = tokio::sync::mpsc::channel::<Chunk>(1);
+        let mut chunk_rx = pin!(ReceiverStream::new(chunk_rx).fuse());
+        let mut reader = pin!(self
+            .read_journal(last_snapshot, journal_offset, watcher_rx, chunk_tx)
+            .fuse());
 
         let mut output = pin!(output);
         loop {
@@ -95,142 +214,197 @@ impl Source {
                     match cmd? {
                         Command::Stop => return Ok(()),
                         Command::Ack(ack) => {
-                            match ack.downcast::<u64>() {
-                                Ok(snapshot_id) => {
-                                    state.set("snapshot_id", *snapshot_id)?;
+                            match ack.downcast::<(u64, u64)>() {
+                                Ok(ack) => {
+                                    let (snapshot_id, journal_offset) = *ack;
+                                    state.set("snapshot_id", snapshot_id)?;
+                                    state.set("journal_offset", journal_offset)?;
                                     section_chan.store_state(state.clone()).await?;
                                 },
-                                Err(_) => Err("failed to downcast ack to u64")?
+                                Err(_) => Err("failed to downcast ack to (u64, u64)")?
                             }
                         },
                         _ => (),
                     }
                 },
-                msg = watcher_rx.next() => {
-                    if msg.is_none() {
-                        Err("watcher down")?;
-                    }
-                    // FIXME: journal has no file locks
-                    journal.update_header().await?;
-
-                    // FIXME: acks are blocked until stream consumed till the end
-                    // i.e. if N snapshots streamed - N messages will be waiting in section_chan Queue
-                    // to be acked
-                    // FIXME: journal scanned from the start every time
-                    // FIXME: snapshots can be big, for now snapshot is sent as a whole message
-                    let mut journal_stream = pin!(journal.stream());
-                    let cur_snapshot = self.cur_snapshot;
-                    while let Some(data) = journal_stream.next().await {
-                        let (snapshot_header, blob_header, blob) = data?;
-                        if Some(snapshot_header.id) <= cur_snapshot {
-                            continue
-                        }
-                        match self.bufferize(snapshot_header, blob_header, blob, &section_chan)? {
-                            Buf::Ok(msg) => output.send(msg).await?,
-                            Buf::More => (),
+                res = reader => {
+                    res?;
+                    Err("journal reader stopped")?
+                },
+                chunk = chunk_rx.next() => {
+                    let Chunk { snapshot_id, end_offset, batch } = match chunk {
+                        Some(chunk) => chunk,
+                        None => Err("journal reader stopped")?,
+                    };
+                    let origin = self.origin.as_deref().unwrap_or("");
+                    let msg = match end_offset {
+                        Some(end_offset) => {
+                            let weak_chan = section_chan.weak_chan();
+                            let ack = Box::pin(async move {
+                                weak_chan.ack(Box::new((snapshot_id, end_offset))).await
+                            });
+                            Message::new(origin, batch, Some(ack))
                         }
-                    }
-
-                    // flush last message
-                    if let Some(msg) = self.build_message(&section_chan)? {
-                        output.send(msg).await?
-                    }
+                        None => Message::new(origin, batch, None),
+                    };
+                    output.send(msg).await?;
                 },
             }
         }
     }
 
-    fn bufferize<SectionChan: SectionChannel>(
-        &mut self,
-        snapshot_header: SnapshotHeader,
-        blob_header: BlobHeader,
-        blob: Vec<u8>,
-        section_chan: &SectionChan,
-    ) -> Result<Buf, StdError> {
-        match self.cur_snapshot != Some(snapshot_header.id) {
-            true => {
-                self.cur_snapshot = Some(snapshot_header.id);
-                let res = self
-                    .build_message(section_chan)?
-                    .map(Buf::Ok)
-                    .unwrap_or(Buf::More);
-                self.buf.push((snapshot_header, blob_header, blob));
-                Ok(res)
+    /// Tail journal, emitting chunks of snapshots which follow `last_snapshot`
+    ///
+    /// `journal_offset` is byte offset past `last_snapshot`, if known.
+    async fn read_journal(
+        &self,
+        mut last_snapshot: Option<u64>,
+        mut journal_offset: Option<u64>,
+        mut watcher_rx: Receiver<()>,
+        chunk_tx: Sender<Chunk>,
+    ) -> Result<(), StdError> {
+        // open async journal
+        let mut journal = AsyncJournal::try_from(self.journal_path.as_str()).await?;
+        while watcher_rx.recv().await.is_some() {
+            // FIXME: journal has no file locks
+            journal.update_header().await?;
+
+            // snapshot ids are sequential, header snapshot counter tells if there is anything new
+            let snapshot_counter = journal.get_header().snapshot_counter;
+            let mut next_snapshot = last_snapshot.map(|id| id + 1).unwrap_or(0);
+            if next_snapshot >= snapshot_counter {
+                continue;
             }
-            false => {
-                self.buf.push((snapshot_header, blob_header, blob));
-                Ok(Buf::More)
+
+            // only committed snapshots are read
+            let mut reader = self.seek_snapshot(journal_offset, next_snapshot).await?;
+            while next_snapshot < snapshot_counter {
+                let snapshot_header = reader.snapshot_header().await?;
+                if snapshot_header.id != next_snapshot {
+                    Err(format!(
+                        "journal is missing snapshots: expected snapshot {next_snapshot}, got {}",
+                        snapshot_header.id
+                    ))?
+                }
+                let mut buf: Vec<(SnapshotHeader, BlobHeader, Vec<u8>)> = vec![];
+                let mut buf_size = 0;
+                // checksum of all blobs of snapshot
+                let mut snapshot_hasher = crc32fast::Hasher::new();
+                while let Some((blob_header, blob)) = reader.blob(snapshot_header.id).await? {
+                    if !buf.is_empty() && buf_size + blob.len() > self.max_chunk_size {
+                        chunk_tx.send(self.build_chunk(&mut buf, None)?).await?;
+                        buf_size = 0;
+                    }
+                    snapshot_hasher.update(&blob);
+                    buf_size += blob.len();
+                    buf.push((snapshot_header, blob_header, blob));
+                }
+                // snapshot without blobs has nothing to deliver
+                if !buf.is_empty() {
+                    let last = (snapshot_hasher.finalize(), reader.pos);
+                    chunk_tx
+                        .send(self.build_chunk(&mut buf, Some(last))?)
+                        .await?;
+                }
+                last_snapshot = Some(next_snapshot);
+                journal_offset = Some(reader.pos);
+                next_snapshot += 1;
             }
         }
+        Ok(())
     }
 
-    fn build_message<SectionChan: SectionChannel>(
-        &mut self,
-        section_chan: &SectionChan,
-    ) -> Result<Option<Message>, StdError> {
-        if self.buf.is_empty() {
-            return Ok(None);
+    /// Open journal reader at the start of given snapshot
+    ///
+    /// Known offset is used if snapshot starts at it, otherwise preceding snapshots are skipped from the
+    /// start of journal.
+    async fn seek_snapshot(
+        &self,
+        journal_offset: Option<u64>,
+        snapshot_id: u64,
+    ) -> Result<JournalReader, StdError> {
+        if let Some(offset) = journal_offset {
+            let mut reader = JournalReader::open(&self.journal_path, offset).await?;
+            if reader.peek_snapshot_id().await? == Some(snapshot_id) {
+                return Ok(reader);
+            }
+        }
+        let mut reader = JournalReader::open(&self.journal_path, HEADER_SIZE).await?;
+        loop {
+            match reader.peek_snapshot_id().await? {
+                Some(id) if id < snapshot_id => reader.skip_snapshot().await?,
+                Some(id) if id == snapshot_id => return Ok(reader),
+                Some(id) => Err(format!(
+                    "journal is missing snapshots: expected snapshot {snapshot_id}, got {id}"
+                ))?,
+                None => Err(format!("journal has no snapshot {snapshot_id}"))?,
+            }
         }
+    }
+
+    /// Build chunk from buffered blobs
+    ///
+    /// Snapshot checksum and journal offset past snapshot are set only for the final chunk of snapshot.
+    fn build_chunk(
+        &self,
+        buf: &mut Vec<(SnapshotHeader, BlobHeader, Vec<u8>)>,
+        last: Option<(u32, u64)>,
+    ) -> Result<Chunk, StdError> {
+        let snapshot_checksum = last.map(|(checksum, _)| checksum);
+        let end_offset = last.map(|(_, offset)| offset);
+        let last = last.is_some();
         let columns: Vec<Arc<dyn Array>> = vec![
+            Arc::new(buf.iter().map(|(s, _, _)| s.id).collect::<UInt64Array>()),
             Arc::new(
-                self.buf
-                    .iter()
-                    .map(|(s, _, _)| s.id)
-                    .collect::<UInt64Array>(),
-            ),
-            Arc::new(
-                self.buf
-                    .iter()
+                buf.iter()
                     .map(|(s, _, _)| s.timestamp)
                     .collect::<Int64Array>(),
             ),
             Arc::new(
-                self.buf
-                    .iter()
+                buf.iter()
                     .map(|(s, _, _)| s.page_size)
                     .collect::<UInt32Array>(),
             ),
             Arc::new(
-                self.buf
-                    .iter()
+                buf.iter()
                     .map(|(_, b, _)| b.offset)
                     .collect::<UInt64Array>(),
             ),
             Arc::new(
-                self.buf
-                    .iter()
+                buf.iter()
                     .map(|(_, b, _)| b.blob_num)
                     .collect::<UInt32Array>(),
             ),
             Arc::new(
-                self.buf
-                    .iter()
+                buf.iter()
                     .map(|(_, b, _)| b.blob_size)
                     .collect::<UInt32Array>(),
             ),
             Arc::new(
-                self.buf
-                    .iter()
+                buf.iter()
                     .map(|(_, _, d)| Some(d.as_slice()))
                     .collect::<BinaryArray>(),
             ),
+            Arc::new(buf.iter().map(|_| Some(last)).collect::<BooleanArray>()),
+            Arc::new(
+                buf.iter()
+                    .map(|(_, _, d)| crc32fast::hash(d))
+                    .collect::<UInt32Array>(),
+            ),
+            Arc::new(
+                buf.iter()
+                    .map(|_| snapshot_checksum)
+                    .collect::<UInt32Array>(),
+            ),
         ];
-        let snapshot_id = self
-            .buf
-            .iter()
-            .take(1)
-            .map(|(s, _, _)| s.id)
-            .next()
-            .unwrap();
-        self.buf = vec![];
-        let record_batch = RecordBatch::try_new(Arc::clone(&self.schema), columns)?;
-        let weak_chan = section_chan.weak_chan();
-        let ack = Box::pin(async move { weak_chan.ack(Box::new(snapshot_id)).await });
-        Ok(Some(Message::new(
-            self.origin.as_deref().unwrap_or(""),
-            record_batch,
-            Some(ack),
-        )))
+        let snapshot_id = buf.first().map(|(s, _, _)| s.id).ok_or("empty chunk")?;
+        buf.clear();
+        let batch = RecordBatch::try_new(Arc::clone(&self.schema), columns)?;
+        Ok(Chunk {
+            snapshot_id,
+            end_offset,
+            batch,
+        })
     }
 
     // initiate first check on startup
@@ -261,3 +435,112 @@ where
         Box::pin(async move { self.enter_loop(input, output, section_chan).await })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    async fn write_journal(path: &str, snapshots: &[Vec<(u64, Vec<u8>)>]) -> Result<(), StdError> {
+        let mut journal = AsyncJournal::create(path).await?;
+        for (id, pages) in snapshots.iter().enumerate() {
+            journal
+                .add_snapshot(&SnapshotHeader::new(id as u64, id as i64, Some(16)))
+                .await?;
+            for (num, (offset, page)) in pages.iter().enumerate() {
+                let blob_header = BlobHeader::new(*offset, num as u32, page.len() as u32);
+                journal.add_blob(&blob_header, page).await?;
+            }
+            journal.commit().await?;
+        }
+        Ok(())
+    }
+
+    /// (snapshot id, timestamp, page size, offset, blob num, blob)
+    type Row = (u64, i64, Option<u32>, u64, u32, Vec<u8>);
+
+    fn row(snapshot_header: &SnapshotHeader, blob_header: &BlobHeader, blob: Vec<u8>) -> Row {
+        (
+            snapshot_header.id,
+            snapshot_header.timestamp,
+            snapshot_header.page_size,
+            blob_header.offset,
+            blob_header.blob_num,
+            blob,
+        )
+    }
+
+    /// Read all snapshots with journal reader
+    async fn read_all(path: &str, snapshot_counter: u64) -> Result<Vec<Row>, StdError> {
+        let mut reader = JournalReader::open(path, HEADER_SIZE).await?;
+        let mut blobs = vec![];
+        for _ in 0..snapshot_counter {
+            let snapshot_header = reader.snapshot_header().await?;
+            while let Some((blob_header, blob)) = reader.blob(snapshot_header.id).await? {
+                blobs.push(row(&snapshot_header, &blob_header, blob));
+            }
+        }
+        Ok(blobs)
+    }
+
+    #[tokio::test]
+    async fn test_journal_reader() -> Result<(), StdError> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("journal").to_string_lossy().to_string();
+        write_journal(
+            &path,
+            &[
+                vec![(0, vec![1; 16]), (16, vec![2; 16]), (32, vec![3; 16])],
+                vec![(16, vec![4; 16])],
+                vec![(0, vec![5; 16]), (48, vec![6; 16])],
+            ],
+        )
+        .await?;
+
+        // reader agrees with journal stream
+        let mut journal = AsyncJournal::try_from(path.as_str()).await?;
+        let mut expected = vec![];
+        let mut journal_stream = pin!(journal.stream());
+        while let Some(data) = journal_stream.next().await {
+            let (snapshot_header, blob_header, blob) = data?;
+            expected.push(row(&snapshot_header, &blob_header, blob));
+        }
+        assert_eq!(read_all(&path, 3).await?, expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_seek_snapshot() -> Result<(), StdError> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("journal").to_string_lossy().to_string();
+        write_journal(
+            &path,
+            &[
+                vec![(0, vec![1; 16]), (16, vec![2; 16])],
+                vec![(16, vec![3; 16])],
+                vec![(32, vec![4; 16])],
+            ],
+        )
+        .await?;
+        let source = Source::new(path.as_str(), 1024);
+
+        // offset past snapshot 0
+        let mut reader = source.seek_snapshot(None, 1).await?;
+        let offset = reader.pos;
+        assert_eq!(reader.snapshot_header().await?.id, 1);
+
+        // known offset is used as is
+        let reader = source.seek_snapshot(Some(offset), 1).await?;
+        assert_eq!(reader.pos, offset);
+
+        // stale offset falls back to skipping snapshots from the start
+        let mut reader = source.seek_snapshot(Some(offset), 2).await?;
+        assert!(reader.pos > offset);
+        assert_eq!(reader.snapshot_header().await?.id, 2);
+        let (blob_header, blob) = reader.blob(2).await?.unwrap();
+        assert_eq!((blob_header.offset, blob), (32, vec![4; 16]));
+        assert!(reader.blob(2).await?.is_none());
+
+        assert!(source.seek_snapshot(None, 3).await.is_err());
+        Ok(())
+    }
+}
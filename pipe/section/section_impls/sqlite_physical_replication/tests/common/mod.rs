@@ -0,0 +1,30 @@
+//! Helpers shared by journal source and destination tests
+
+use journal::{AsyncJournal, BlobHeader, SnapshotHeader};
+use sqlite_physical_replication::StdError;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::PollSender;
+
+pub fn channel<T>(buf_size: usize) -> (PollSender<T>, ReceiverStream<T>)
+where
+    T: Send + 'static,
+{
+    let (tx, rx): (Sender<T>, Receiver<T>) = tokio::sync::mpsc::channel(buf_size);
+    (PollSender::new(tx), ReceiverStream::new(rx))
+}
+
+/// Write snapshots of (offset, page) pairs into new journal
+pub async fn write_journal(path: &str, snapshots: &[Vec<(u64, Vec<u8>)>]) -> Result<(), StdError> {
+    let mut journal = AsyncJournal::create(path).await?;
+    for (id, pages) in snapshots.iter().enumerate() {
+        let snapshot_header = SnapshotHeader::new(id as u64, id as i64, Some(16));
+        journal.add_snapshot(&snapshot_header).await?;
+        for (num, (offset, page)) in pages.iter().enumerate() {
+            let blob_header = BlobHeader::new(*offset, num as u32, page.len() as u32);
+            journal.add_blob(&blob_header, page).await?;
+        }
+        journal.commit().await?;
+    }
+    Ok(())
+}
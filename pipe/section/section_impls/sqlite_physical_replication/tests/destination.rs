@@ -0,0 +1,259 @@
+mod common;
+
+use arrow::array::{Array, BinaryArray, BooleanArray, Int64Array, UInt32Array, UInt64Array};
+use arrow::record_batch::RecordBatch;
+use common::{channel, write_journal};
+use futures::{SinkExt, StreamExt};
+use journal::AsyncJournal;
+use section::dummy::*;
+use section::Section as _;
+use sqlite_physical_replication::{
+    destination::{Destination, RestorePoint},
+    source::Source,
+    Message, StdError,
+};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode};
+use sqlx::{ConnectOptions, Connection};
+use std::sync::Arc;
+use std::time::Duration;
+use stub::Stub;
+use tokio_stream::wrappers::ReceiverStream;
+
+/// Wait until journal has given number of snapshots
+async fn wait_for(path: &str, snapshots: u64) -> Result<AsyncJournal, StdError> {
+    for _ in 0..50 {
+        if let Ok(mut journal) = AsyncJournal::try_from(path).await {
+            journal.update_header().await?;
+            if journal.get_header().snapshot_counter == snapshots {
+                return Ok(journal);
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    Err(format!("journal {path} doesn't have {snapshots} snapshots"))?
+}
+
+/// Pages of sqlite database before and after insert, second snapshot contains only changed pages
+async fn database_snapshots(path: &str) -> Result<(Vec<Vec<(u64, Vec<u8>)>>, Vec<u8>), StdError> {
+    const PAGE_SIZE: usize = 4096;
+    let mut connection = SqliteConnectOptions::new()
+        .filename(path)
+        .create_if_missing(true)
+        .page_size(PAGE_SIZE as u32)
+        .journal_mode(SqliteJournalMode::Delete)
+        .connect()
+        .await?;
+    sqlx::query("CREATE TABLE test (id INTEGER PRIMARY KEY)")
+        .execute(&mut connection)
+        .await?;
+    let before = tokio::fs::read(path).await?;
+    sqlx::query("INSERT INTO test VALUES (1)")
+        .execute(&mut connection)
+        .await?;
+    connection.close().await?;
+    let after = tokio::fs::read(path).await?;
+
+    let pages = |data: &[u8]| {
+        data.chunks(PAGE_SIZE)
+            .enumerate()
+            .map(|(num, page)| ((num * PAGE_SIZE) as u64, page.to_vec()))
+            .collect::<Vec<_>>()
+    };
+    let changed = pages(&after)
+        .into_iter()
+        .filter(|(offset, page)| {
+            before.get(*offset as usize..*offset as usize + page.len()) != Some(page.as_slice())
+        })
+        .collect::<Vec<_>>();
+    Ok((vec![pages(&before), changed], after))
+}
+
+#[tokio::test]
+async fn destination_applies_snapshots() -> Result<(), StdError> {
+    let dir = tempfile::tempdir()?;
+    let path = |name: &str| dir.path().join(name).to_string_lossy().to_string();
+    let (source_journal, journal, database) = (path("source"), path("journal"), path("db"));
+    let (snapshots, expected) = database_snapshots(&path("source_db")).await?;
+    write_journal(&source_journal, &snapshots).await?;
+
+    // chunk per blob
+    let source = Source::new(source_journal.as_str(), 16);
+    let destination = Destination::new(
+        journal.as_str(),
+        Some(database.as_str()),
+        None::<(RestorePoint, String)>,
+        false,
+    );
+    let (output, rx) = channel::<Message>(1);
+    let output = output.sink_map_err(|_| "chan closed".into());
+    let source = tokio::spawn(source.start(
+        Stub::<Message, StdError>::new(),
+        output,
+        DummySectionChannel::new(),
+    ));
+    let destination = tokio::spawn(destination.start(
+        rx,
+        Stub::<Message, StdError>::new(),
+        DummySectionChannel::new(),
+    ));
+
+    let mut journal = wait_for(&journal, 2).await?;
+    // database is updated once snapshot is committed to journal
+    for _ in 0..50 {
+        if tokio::fs::read(&database).await? == expected {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    source.abort();
+    destination.abort();
+    assert_eq!(tokio::fs::read(&database).await?, expected);
+
+    // replicated database is readable
+    let mut connection = SqliteConnectOptions::new()
+        .filename(&database)
+        .read_only(true)
+        .connect()
+        .await?;
+    let ids: Vec<i64> = sqlx::query_scalar("SELECT id FROM test")
+        .fetch_all(&mut connection)
+        .await?;
+    connection.close().await?;
+    assert_eq!(ids, vec![1]);
+
+    let blobs = journal
+        .stream()
+        .map(|data| data.map(|(s, b, blob)| (s.id, b.offset, blob)))
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    let expected_blobs = snapshots
+        .iter()
+        .enumerate()
+        .flat_map(|(id, pages)| {
+            pages
+                .iter()
+                .map(move |(offset, page)| (id as u64, *offset, page.clone()))
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(blobs, expected_blobs);
+    Ok(())
+}
+
+/// Chunk of snapshot with given pages, final chunk carries snapshot checksum
+fn chunk(snapshot_id: u64, pages: &[(u64, Vec<u8>)], snapshot_checksum: Option<u32>) -> Message {
+    let rows = pages.len();
+    let columns: Vec<Arc<dyn Array>> = vec![
+        Arc::new(UInt64Array::from(vec![snapshot_id; rows])),
+        Arc::new(Int64Array::from(vec![snapshot_id as i64; rows])),
+        Arc::new(UInt32Array::from(vec![16; rows])),
+        Arc::new(pages.iter().map(|(o, _)| *o).collect::<UInt64Array>()),
+        Arc::new((0..rows as u32).collect::<UInt32Array>()),
+        Arc::new(
+            pages
+                .iter()
+                .map(|(_, p)| p.len() as u32)
+                .collect::<UInt32Array>(),
+        ),
+        Arc::new(
+            pages
+                .iter()
+                .map(|(_, p)| Some(p.as_slice()))
+                .collect::<BinaryArray>(),
+        ),
+        Arc::new(BooleanArray::from(vec![snapshot_checksum.is_some(); rows])),
+        Arc::new(
+            pages
+                .iter()
+                .map(|(_, p)| crc32fast::hash(p))
+                .collect::<UInt32Array>(),
+        ),
+        Arc::new(UInt32Array::from(vec![snapshot_checksum; rows])),
+    ];
+    let batch =
+        RecordBatch::try_new(Arc::new(sqlite_physical_replication::schema()), columns).unwrap();
+    Message::new("journal", batch, None)
+}
+
+fn checksum(pages: &[(u64, Vec<u8>)]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    pages.iter().for_each(|(_, page)| hasher.update(page));
+    hasher.finalize()
+}
+
+#[tokio::test]
+async fn destination_verifies_snapshot_checksum() -> Result<(), StdError> {
+    let dir = tempfile::tempdir()?;
+    let path = |name: &str| dir.path().join(name).to_string_lossy().to_string();
+    let (journal, database) = (path("journal"), path("db"));
+    let destination = Destination::new(
+        journal.as_str(),
+        Some(database.as_str()),
+        None::<(RestorePoint, String)>,
+        false,
+    );
+    let (tx, rx) = tokio::sync::mpsc::channel::<Message>(1);
+    let rx = ReceiverStream::new(rx);
+    let handle = tokio::spawn(destination.start(
+        rx,
+        Stub::<Message, StdError>::new(),
+        DummySectionChannel::new(),
+    ));
+
+    let first = vec![(0, vec![1; 16])];
+    let last = vec![(16, vec![2; 16])];
+    tx.send(chunk(0, &first, None))
+        .await
+        .map_err(|_| "chan closed")?;
+    tx.send(chunk(
+        0,
+        &last,
+        Some(checksum(&[first.clone(), last.clone()].concat()) ^ 1),
+    ))
+    .await
+    .map_err(|_| "chan closed")?;
+    let err = handle.await?.unwrap_err();
+    assert_eq!(err.to_string(), "checksum mismatch for snapshot 0");
+
+    // nothing from broken snapshot reached journal and database
+    let mut journal = AsyncJournal::try_from(journal.as_str()).await?;
+    journal.update_header().await?;
+    assert_eq!(journal.get_header().snapshot_counter, 0);
+    assert_eq!(tokio::fs::read(&database).await?, Vec::<u8>::new());
+    assert!(!tokio::fs::try_exists(format!("{}.mycelial-pending", path("journal"))).await?);
+    Ok(())
+}
+
+#[tokio::test]
+async fn destination_requests_resync_on_gap() -> Result<(), StdError> {
+    let dir = tempfile::tempdir()?;
+    let journal = dir.path().join("journal").to_string_lossy().to_string();
+    let destination = Destination::new(
+        journal.as_str(),
+        None::<String>,
+        None::<(RestorePoint, String)>,
+        false,
+    );
+    let (tx, rx) = tokio::sync::mpsc::channel::<Message>(1);
+    let rx = ReceiverStream::new(rx);
+    let handle = tokio::spawn(destination.start(
+        rx,
+        Stub::<Message, StdError>::new(),
+        DummySectionChannel::new(),
+    ));
+
+    let pages = vec![(0, vec![1; 16])];
+    tx.send(chunk(0, &pages, Some(checksum(&pages))))
+        .await
+        .map_err(|_| "chan closed")?;
+    tx.send(chunk(2, &pages, Some(checksum(&pages))))
+        .await
+        .map_err(|_| "chan closed")?;
+    let err = handle.await?.unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "snapshot gap: expected snapshot 1, got snapshot 2"
+    );
+    Ok(())
+}
@@ -0,0 +1,126 @@
+mod common;
+
+use arrow::array::{BinaryArray, BooleanArray, UInt32Array, UInt64Array};
+use common::{channel, write_journal};
+use futures::{SinkExt, StreamExt};
+use journal::{AsyncJournal, BlobHeader, SnapshotHeader};
+use section::dummy::*;
+use section::Section as _;
+use sqlite_physical_replication::{source::Source, Message, StdError};
+use stub::Stub;
+
+#[tokio::test]
+async fn source_chunks() -> Result<(), StdError> {
+    let dir = tempfile::tempdir()?;
+    let journal_path = dir.path().join("journal").to_string_lossy().to_string();
+    let snapshots = vec![
+        vec![(0, vec![1; 16]), (16, vec![2; 16]), (32, vec![3; 16])],
+        vec![(16, vec![4; 16])],
+    ];
+    write_journal(&journal_path, &snapshots).await?;
+
+    // two blobs per chunk
+    let source = Source::new(journal_path.as_str(), 32);
+    let (output, mut rx) = channel::<Message>(1);
+    let output = output.sink_map_err(|_| "chan closed".into());
+    let input = Stub::<Message, StdError>::new();
+    let handle = tokio::spawn(source.start(input, output, DummySectionChannel::new()));
+
+    // (snapshot id, blob count, last chunk)
+    let mut chunks = vec![];
+    for _ in 0..3 {
+        let message = rx.next().await.unwrap();
+        assert_eq!(message.origin, "journal");
+        let payload = &message.payload;
+        let snapshot_id: UInt64Array = payload["snapshot_id"].to_data().into();
+        let last_chunk: BooleanArray = payload["last_chunk"].to_data().into();
+        let last = last_chunk.value(0);
+        assert_eq!(message.ack.is_some(), last);
+        chunks.push((snapshot_id.value(0), payload.num_rows(), last));
+    }
+    assert_eq!(chunks, vec![(0, 2, false), (0, 1, true), (1, 1, true)]);
+
+    // nothing else to stream
+    let next = tokio::time::timeout(std::time::Duration::from_millis(100), rx.next()).await;
+    assert!(next.is_err());
+    handle.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn source_checksums() -> Result<(), StdError> {
+    let dir = tempfile::tempdir()?;
+    let journal_path = dir.path().join("journal").to_string_lossy().to_string();
+    let snapshots = vec![
+        vec![(0, vec![1; 16]), (16, vec![2; 16]), (32, vec![3; 16])],
+        vec![(16, vec![4; 16])],
+    ];
+    write_journal(&journal_path, &snapshots).await?;
+
+    let source = Source::new(journal_path.as_str(), 32);
+    let (output, mut rx) = channel::<Message>(1);
+    let output = output.sink_map_err(|_| "chan closed".into());
+    let input = Stub::<Message, StdError>::new();
+    let handle = tokio::spawn(source.start(input, output, DummySectionChannel::new()));
+
+    for _ in 0..3 {
+        let message = rx.next().await.unwrap();
+        let payload = &message.payload;
+        let snapshot_id: UInt64Array = payload["snapshot_id"].to_data().into();
+        let blob: BinaryArray = payload["blob"].to_data().into();
+        let last_chunk: BooleanArray = payload["last_chunk"].to_data().into();
+        let blob_checksum: UInt32Array = payload["blob_checksum"].to_data().into();
+        let snapshot_checksum: UInt32Array = payload["snapshot_checksum"].to_data().into();
+        for pos in 0..payload.num_rows() {
+            assert_eq!(blob_checksum.value(pos), crc32fast::hash(blob.value(pos)));
+        }
+        // snapshot checksum is set only for the final chunk
+        let last = last_chunk.value(0);
+        assert_eq!(snapshot_checksum.is_null(0), !last);
+        if last {
+            let snapshot = &snapshots[snapshot_id.value(0) as usize];
+            let mut hasher = crc32fast::Hasher::new();
+            snapshot.iter().for_each(|(_, page)| hasher.update(page));
+            assert_eq!(snapshot_checksum.value(0), hasher.finalize());
+        }
+    }
+    handle.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn source_tails_journal() -> Result<(), StdError> {
+    let dir = tempfile::tempdir()?;
+    let journal_path = dir.path().join("journal").to_string_lossy().to_string();
+    write_journal(&journal_path, &[vec![(0, vec![1; 16])]]).await?;
+
+    let source = Source::new(journal_path.as_str(), 1024);
+    let (output, mut rx) = channel::<Message>(1);
+    let output = output.sink_map_err(|_| "chan closed".into());
+    let input = Stub::<Message, StdError>::new();
+    let handle = tokio::spawn(source.start(input, output, DummySectionChannel::new()));
+
+    let message = rx.next().await.unwrap();
+    let snapshot_id: UInt64Array = message.payload["snapshot_id"].to_data().into();
+    assert_eq!(snapshot_id.value(0), 0);
+
+    // snapshot appended to journal is picked up by watcher
+    let mut journal = AsyncJournal::try_from(journal_path.as_str()).await?;
+    journal
+        .add_snapshot(&SnapshotHeader::new(1, 1, Some(16)))
+        .await?;
+    journal
+        .add_blob(&BlobHeader::new(0, 0, 16), &[2; 16])
+        .await?;
+    journal.commit().await?;
+
+    let message = tokio::time::timeout(std::time::Duration::from_secs(5), rx.next())
+        .await?
+        .unwrap();
+    let snapshot_id: UInt64Array = message.payload["snapshot_id"].to_data().into();
+    let blob: BinaryArray = message.payload["blob"].to_data().into();
+    assert_eq!(snapshot_id.value(0), 1);
+    assert_eq!(blob.value(0), &[2; 16]);
+    handle.abort();
+    Ok(())
+}
@@ -51,6 +51,9 @@ pub trait SectionChannel: Send + Sync + 'static {
     // ask runtime to log message
     async fn log<T: Into<String> + Send>(&mut self, log: T) -> Result<(), Self::Error>;
 
+    // ask runtime to replay pipe from the start: state of preceding sections is reset and pipe is restarted
+    async fn resync(&mut self) -> Result<(), Self::Error>;
+
     // receive command from runtime
     async fn recv(&mut self) -> Result<Command, Self::Error>;
 
@@ -78,6 +81,7 @@ pub enum SectionRequest<S: State, Rs: ReplyTo<With = Option<S>>, Ss: ReplyTo<Wit
     RetrieveState { id: u64, reply_to: Rs },
     StoreState { id: u64, state: S, reply_to: Ss },
     Log { id: u64, message: String },
+    Resync { id: u64 },
     Stopped { id: u64 },
 }
 
@@ -100,6 +104,10 @@ impl<S: State, Rs: ReplyTo<With = Option<S>>, Ss: ReplyTo<With = ()>> std::fmt::
                 .field("id", id)
                 .field("message", message)
                 .finish(),
+            Self::Resync { id } => f
+                .debug_struct("SectionRequest::Resync")
+                .field("id", id)
+                .finish(),
             Self::Stopped { id } => f
                 .debug_struct("SectionRequest::Stopped")
                 .field("id", id)
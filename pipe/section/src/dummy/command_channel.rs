@@ -101,6 +101,10 @@ impl SectionChannel for DummySectionChannel {
         ready(Ok(())).await
     }
 
+    async fn resync(&mut self) -> Result<(), Self::Error> {
+        ready(Ok(())).await
+    }
+
     async fn recv(&mut self) -> Result<Command, Self::Error> {
         pending::<Result<Command, Self::Error>>().await
     }
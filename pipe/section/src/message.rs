@@ -9,6 +9,8 @@ pub struct Message<Payload> {
     pub origin: String,
     pub payload: Payload,
     pub ack: Option<Ack>,
+    /// Position of message in its origin, assigned by source, stays the same if message is redelivered
+    pub offset: Option<String>,
 }
 
 impl<P: std::fmt::Debug> std::fmt::Debug for Message<P> {
@@ -16,6 +18,7 @@ impl<P: std::fmt::Debug> std::fmt::Debug for Message<P> {
         f.debug_struct("Message")
             .field("origin", &self.origin)
             .field("payload", &self.payload)
+            .field("offset", &self.offset)
             .finish()
     }
 }
@@ -26,9 +29,15 @@ impl<P> Message<P> {
             origin: origin.into(),
             payload: payload.into(),
             ack,
+            offset: None,
         }
     }
 
+    pub fn with_offset(mut self, offset: Option<String>) -> Self {
+        self.offset = offset;
+        self
+    }
+
     pub async fn ack(&mut self) {
         if let Some(ack) = self.ack.take() {
             ack.await;
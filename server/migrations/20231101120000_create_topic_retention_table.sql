@@ -0,0 +1,19 @@
+CREATE TABLE IF NOT EXISTS topic_retention (
+    topic TEXT PRIMARY KEY NOT NULL,
+    -- max age of records in seconds
+    max_age INTEGER,
+    -- max total size of topic records in bytes
+    max_bytes INTEGER,
+    -- delete records once all known consumers passed them
+    consumed BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE TABLE IF NOT EXISTS consumers (
+    topic TEXT NOT NULL,
+    consumer_id TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (topic, consumer_id)
+);
+
+CREATE INDEX IF NOT EXISTS records_topic_id ON records (topic, id);
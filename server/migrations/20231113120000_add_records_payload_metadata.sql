@@ -0,0 +1,4 @@
+ALTER TABLE records ADD COLUMN size INTEGER;
+-- key of payload object, if payload is offloaded to payload store
+ALTER TABLE records ADD COLUMN object_key TEXT;
+UPDATE records SET size = length(data);
@@ -0,0 +1,23 @@
+CREATE TABLE IF NOT EXISTS pipe_revisions (
+    pipe_id INTEGER NOT NULL,
+    revision INTEGER NOT NULL,
+    raw_config TEXT NOT NULL,
+    author TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (pipe_id, revision)
+);
+
+ALTER TABLE pipes ADD COLUMN revision INTEGER NOT NULL DEFAULT 1;
+
+-- existing pipe configs become first revision
+INSERT INTO pipe_revisions (pipe_id, revision, raw_config, author, created_at)
+SELECT id, 1, raw_config, 'unknown', created_at FROM pipes;
+
+-- pipe config revisions reported by clients
+CREATE TABLE IF NOT EXISTS client_pipes (
+    client_id TEXT NOT NULL,
+    pipe_id INTEGER NOT NULL,
+    revision INTEGER NOT NULL,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (client_id, pipe_id)
+);
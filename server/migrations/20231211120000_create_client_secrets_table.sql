@@ -0,0 +1,8 @@
+-- secrets of clients, values are encrypted with server secrets key
+CREATE TABLE IF NOT EXISTS client_secrets (
+    client_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    value BLOB NOT NULL,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (client_id, name)
+);
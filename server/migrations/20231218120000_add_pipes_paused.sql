@@ -0,0 +1,2 @@
+-- paused pipes are stopped on clients, config and state are kept
+ALTER TABLE pipes ADD COLUMN paused BOOLEAN NOT NULL DEFAULT 0;
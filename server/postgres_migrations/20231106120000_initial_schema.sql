@@ -0,0 +1,65 @@
+-- Postgres counterpart of sqlite migrations in `migrations` directory, schema of both should be kept in sync
+CREATE TABLE IF NOT EXISTS clients (
+    id TEXT PRIMARY KEY,
+    display_name TEXT,
+    sources TEXT,
+    destinations TEXT
+);
+
+CREATE TABLE IF NOT EXISTS tokens (
+    id TEXT PRIMARY KEY,
+    client_id TEXT NOT NULL REFERENCES clients (id),
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS configs (
+    id BIGINT NOT NULL,
+    raw_config TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS ui_metadata (
+    id BIGSERIAL PRIMARY KEY,
+    raw_config TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS records (
+    id BIGSERIAL PRIMARY KEY,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    topic TEXT NOT NULL,
+    origin TEXT,
+    data BYTEA NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS records_topic_id ON records (topic, id);
+
+CREATE TABLE IF NOT EXISTS workspaces (
+    id BIGSERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+INSERT INTO workspaces (id, name) VALUES (1, 'Default');
+SELECT setval(pg_get_serial_sequence('workspaces', 'id'), 1);
+
+CREATE TABLE IF NOT EXISTS pipes (
+    id BIGSERIAL PRIMARY KEY,
+    raw_config JSONB NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    workspace_id BIGINT REFERENCES workspaces (id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS topic_retention (
+    topic TEXT PRIMARY KEY NOT NULL,
+    max_age BIGINT,
+    max_bytes BIGINT,
+    consumed BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE TABLE IF NOT EXISTS consumers (
+    topic TEXT NOT NULL,
+    consumer_id TEXT NOT NULL,
+    position BIGINT NOT NULL,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (topic, consumer_id)
+);
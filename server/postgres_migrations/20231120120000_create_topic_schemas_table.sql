@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS topic_schemas (
+    topic TEXT NOT NULL,
+    version BIGINT NOT NULL,
+    -- arrow ipc stream without record batches
+    schema BYTEA NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (topic, version)
+);
+
+CREATE TABLE IF NOT EXISTS topic_compatibility (
+    topic TEXT PRIMARY KEY NOT NULL,
+    mode TEXT NOT NULL
+);
+
+ALTER TABLE records ADD COLUMN schema_version BIGINT;
@@ -0,0 +1,9 @@
+-- producer message ids of ingested requests, used to deduplicate retried requests
+CREATE TABLE IF NOT EXISTS ingested_messages (
+    topic TEXT NOT NULL,
+    message_id TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (topic, message_id)
+);
+
+CREATE INDEX IF NOT EXISTS ingested_messages_created_at ON ingested_messages (created_at);
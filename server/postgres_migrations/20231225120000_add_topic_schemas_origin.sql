@@ -0,0 +1,20 @@
+-- schemas are versioned per topic origin, existing topic schemas are copied to every origin of topic
+CREATE TABLE IF NOT EXISTS origin_schemas (
+    topic TEXT NOT NULL,
+    origin TEXT NOT NULL,
+    version BIGINT NOT NULL,
+    -- arrow ipc stream without record batches
+    schema BYTEA NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (topic, origin, version)
+);
+
+INSERT INTO origin_schemas (topic, origin, version, schema, created_at)
+SELECT topic_schemas.topic, origins.origin, topic_schemas.version, topic_schemas.schema, topic_schemas.created_at
+FROM topic_schemas
+JOIN (SELECT DISTINCT topic, COALESCE(origin, '') AS origin FROM records) AS origins
+ON origins.topic = topic_schemas.topic;
+
+DROP TABLE topic_schemas;
+
+ALTER TABLE origin_schemas RENAME TO topic_schemas;
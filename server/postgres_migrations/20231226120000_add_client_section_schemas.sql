@@ -0,0 +1,2 @@
+-- config schemas of sections, which client can run, json array
+ALTER TABLE clients ADD COLUMN section_schemas TEXT;
@@ -0,0 +1,2 @@
+-- hash of credential, generated by client on first provisioning, token is issued only to holder of credential
+ALTER TABLE clients ADD COLUMN credential_hash TEXT;
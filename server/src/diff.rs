@@ -0,0 +1,191 @@
+//! Structural diff of pipe configs
+//!
+//! Configs are compared as json values, changes are addressed by json pointer paths.
+//! Arrays are compared element-wise by index.
+
+use serde::Serialize;
+use serde_json::Value;
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Change {
+    Add {
+        path: String,
+        value: Value,
+    },
+    Remove {
+        path: String,
+        value: Value,
+    },
+    Replace {
+        path: String,
+        old: Value,
+        new: Value,
+    },
+}
+
+/// Changes, which turn `old` value into `new` value
+pub fn diff(old: &Value, new: &Value) -> Vec<Change> {
+    let mut changes = vec![];
+    diff_at(String::new(), old, new, &mut changes);
+    changes
+}
+
+fn diff_at(path: String, old: &Value, new: &Value, changes: &mut Vec<Change>) {
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            for (key, old_value) in old.iter() {
+                let path = format!("{path}/{}", escape(key));
+                match new.get(key) {
+                    Some(new_value) => diff_at(path, old_value, new_value, changes),
+                    None => changes.push(Change::Remove {
+                        path,
+                        value: old_value.clone(),
+                    }),
+                }
+            }
+            for (key, new_value) in new.iter().filter(|(key, _)| !old.contains_key(*key)) {
+                changes.push(Change::Add {
+                    path: format!("{path}/{}", escape(key)),
+                    value: new_value.clone(),
+                })
+            }
+        }
+        (Value::Array(old), Value::Array(new)) => {
+            for (index, old_value) in old.iter().enumerate() {
+                let path = format!("{path}/{index}");
+                match new.get(index) {
+                    Some(new_value) => diff_at(path, old_value, new_value, changes),
+                    None => changes.push(Change::Remove {
+                        path,
+                        value: old_value.clone(),
+                    }),
+                }
+            }
+            for (index, new_value) in new.iter().enumerate().skip(old.len()) {
+                changes.push(Change::Add {
+                    path: format!("{path}/{index}"),
+                    value: new_value.clone(),
+                })
+            }
+        }
+        (old, new) if old != new => changes.push(Change::Replace {
+            path,
+            old: old.clone(),
+            new: new.clone(),
+        }),
+        _ => (),
+    }
+}
+
+/// Escape json pointer reference token
+fn escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_diff_equal() {
+        let config = json!({"section": [{"name": "sqlite", "path": "/tmp/db"}]});
+        assert_eq!(diff(&config, &config), vec![]);
+    }
+
+    #[test]
+    fn test_diff_object() {
+        let old = json!({"a": 1, "b": {"c": true}, "d": "removed"});
+        let new = json!({"a": 2, "b": {"c": true, "e": null}, "f": "added"});
+        assert_eq!(
+            diff(&old, &new),
+            vec![
+                Change::Replace {
+                    path: "/a".into(),
+                    old: json!(1),
+                    new: json!(2)
+                },
+                Change::Add {
+                    path: "/b/e".into(),
+                    value: json!(null)
+                },
+                Change::Remove {
+                    path: "/d".into(),
+                    value: json!("removed")
+                },
+                Change::Add {
+                    path: "/f".into(),
+                    value: json!("added")
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_array() {
+        let old = json!([1, 2, 3]);
+        assert_eq!(
+            diff(&old, &json!([1, 5])),
+            vec![
+                Change::Replace {
+                    path: "/1".into(),
+                    old: json!(2),
+                    new: json!(5)
+                },
+                Change::Remove {
+                    path: "/2".into(),
+                    value: json!(3)
+                },
+            ]
+        );
+        assert_eq!(
+            diff(&old, &json!([1, 2, 3, 4])),
+            vec![Change::Add {
+                path: "/3".into(),
+                value: json!(4)
+            }]
+        );
+    }
+
+    #[test]
+    fn test_diff_type_change() {
+        // values of different types are replaced as a whole
+        assert_eq!(
+            diff(&json!({"a": [1]}), &json!({"a": {"b": 1}})),
+            vec![Change::Replace {
+                path: "/a".into(),
+                old: json!([1]),
+                new: json!({"b": 1})
+            }]
+        );
+        assert_eq!(
+            diff(&json!(1), &json!("1")),
+            vec![Change::Replace {
+                path: "".into(),
+                old: json!(1),
+                new: json!("1")
+            }]
+        );
+    }
+
+    #[test]
+    fn test_diff_escape() {
+        assert_eq!(
+            diff(&json!({}), &json!({"a/b~c": 1})),
+            vec![Change::Add {
+                path: "/a~1b~0c".into(),
+                value: json!(1)
+            }]
+        );
+    }
+
+    #[test]
+    fn test_diff_serialize() {
+        let changes = diff(&json!({"a": 1}), &json!({"a": 2}));
+        assert_eq!(
+            serde_json::to_value(changes).unwrap(),
+            json!([{"op": "replace", "path": "/a", "old": 1, "new": 2}])
+        );
+    }
+}
@@ -1,7 +1,10 @@
 use axum::{
     http::StatusCode,
     response::{IntoResponse, Response},
+    Json,
 };
+use pipe_config::config_schema::FieldError;
+use serde_json::json;
 use sqlx::migrate::MigrateError;
 
 // TODO: figure out this error stuff, I just copied and pasted this for now.
@@ -10,6 +13,12 @@ pub enum Error {
     // status code wrap, probably not needed
     StatusCode(StatusCode),
 
+    // client error with explanation, returned as response body
+    ClientError(StatusCode, String),
+
+    // pipe config validation errors, returned as json response body
+    Validation(Vec<FieldError>),
+
     // sqlx migration error
     SqlxMigration(MigrateError),
 
@@ -31,6 +40,12 @@ pub enum Error {
     // serde error wrap
     SerdeJson(serde_json::Error),
 
+    // arrow error wrap
+    Arrow(arrow::error::ArrowError),
+
+    // object store error wrap
+    ObjectStore(object_store::Error),
+
     // &'static str error
     Str(&'static str),
 }
@@ -48,6 +63,8 @@ impl std::error::Error for Error {
             Error::Sqlx(e) => Some(e),
             Error::Io(e) => Some(e),
             Error::Axum(e) => Some(e),
+            Error::Arrow(e) => Some(e),
+            Error::ObjectStore(e) => Some(e),
             _ => None,
         }
     }
@@ -101,6 +118,18 @@ impl From<serde_json::Error> for Error {
     }
 }
 
+impl From<arrow::error::ArrowError> for Error {
+    fn from(e: arrow::error::ArrowError) -> Self {
+        Self::Arrow(e)
+    }
+}
+
+impl From<object_store::Error> for Error {
+    fn from(e: object_store::Error) -> Self {
+        Self::ObjectStore(e)
+    }
+}
+
 impl From<&'static str> for Error {
     fn from(e: &'static str) -> Self {
         Self::Str(e)
@@ -109,11 +138,16 @@ impl From<&'static str> for Error {
 
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
-        let mut response: Response = match self {
-            Self::StatusCode(s) => s,
-            _ => StatusCode::INTERNAL_SERVER_ERROR,
-        }
-        .into_response();
+        let mut response: Response = match &self {
+            Self::StatusCode(s) => s.into_response(),
+            Self::ClientError(s, msg) => (*s, msg.clone()).into_response(),
+            Self::Validation(errors) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(json!({ "errors": errors })),
+            )
+                .into_response(),
+            _ => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        };
         response.extensions_mut().insert(self);
         response
     }
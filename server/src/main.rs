@@ -1,36 +1,53 @@
 use arrow::{
+    datatypes::Schema,
+    error::ArrowError,
     ipc::{reader::StreamReader, writer::StreamWriter},
     record_batch::RecordBatch,
 };
 use axum::{
-    extract::{BodyStream, State},
-    headers::{authorization::Basic, Authorization},
+    body::StreamBody,
+    extract::{BodyStream, Query, State},
     http::{self, header, Method, Request, StatusCode, Uri},
     middleware::{self, Next},
     response::{IntoResponse, Response},
-    routing::{get, post},
-    Json, Router, Server, TypedHeader,
+    routing::{delete, get, post, put},
+    Json, Router, Server,
 };
 use base64::engine::{general_purpose::STANDARD as BASE64, Engine};
 use chrono::{DateTime, Utc};
 use clap::Parser;
 use common::{
-    Destination, IssueTokenRequest, IssueTokenResponse, PipeConfig, PipeConfigs,
-    ProvisionClientRequest, ProvisionClientResponse, Source,
+    cipher::Cipher, ClientSecrets, Destination, IssueTokenRequest, IssueTokenResponse, PipeConfig,
+    PipeConfigs, ProvisionClientRequest, ProvisionClientResponse, RunningPipe, RunningPipes,
+    Source,
 };
 use futures::StreamExt;
+use payload_store::PayloadStore;
+use pipe_config::{
+    config::{Config as DynamicPipeConfig, Value as DynamicPipeValue},
+    config_schema::{self, SectionSchema},
+};
 use rust_embed::RustEmbed;
+use schema::{Compatibility, SchemaInfo};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use sqlx::{
-    sqlite::SqliteConnectOptions, sqlite::SqliteRow, ConnectOptions, FromRow, Row, SqliteConnection,
-};
-use std::{net::SocketAddr, path::Path};
-use std::{str::FromStr, sync::Arc};
-use tokio::sync::Mutex;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::{io, net::SocketAddr};
+use std::{sync::Arc, time::Duration};
+use storage::{Ingestion, NewRecord, Storage, StoredRecord, StoredSecret};
+use tokio::sync::watch;
+use tokio::time::Instant;
+use tokio_util::io::SyncIoBridge;
 use uuid::Uuid;
 
+mod diff;
 mod error;
+mod payload_store;
+mod query;
+mod schema;
+mod storage;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Workspace {
@@ -43,18 +60,6 @@ pub struct Workspace {
     pub name: String,
 }
 
-impl FromRow<'_, SqliteRow> for Workspace {
-    fn from_row(row: &SqliteRow) -> sqlx::Result<Self> {
-        Ok(Self {
-            id: row.get("id"),
-            name: row.get("name"),
-            created_at: Utc::now(),
-            // created_at: row.get("created_at"),
-            pipe_configs: Vec::new(),
-        })
-    }
-}
-
 #[derive(Parser)]
 struct Cli {
     #[clap(short, long, env = "LISTEN_ADDR", default_value = "0.0.0.0:7777")]
@@ -67,57 +72,652 @@ struct Cli {
     /// Database path
     #[clap(short, long, env = "DATABASE_PATH", default_value = "mycelial.db")]
     database_path: String,
+
+    /// Database url, `sqlite://<path>` or `postgres://<user>:<password>@<host>/<database>`, overrides database path
+    #[clap(long, env = "DATABASE_URL")]
+    database_url: Option<String>,
+
+    /// Max number of database connections
+    #[clap(long, env = "DATABASE_MAX_CONNECTIONS", default_value = "10")]
+    database_max_connections: u32,
+
+    /// Max size of ingestion request body in bytes
+    #[clap(long, env = "MAX_INGESTION_SIZE", default_value = "67108864")]
+    max_ingestion_size: u64,
+
+    /// Payload store url, `file://<dir>` or `s3://<bucket>/<prefix>`
+    ///
+    /// If set, payloads of ingested records are offloaded to the store instead of database.
+    #[clap(long, env = "PAYLOAD_STORE")]
+    payload_store: Option<String>,
+
+    /// Schema compatibility mode of topics without explicitly configured mode: none, backward, forward or full
+    #[clap(long, env = "DEFAULT_COMPATIBILITY", default_value = "none")]
+    default_compatibility: Compatibility,
+
+    /// Interval between topic retention runs in seconds
+    #[clap(long, env = "RETENTION_INTERVAL", default_value = "60")]
+    retention_interval: u64,
+
+    /// Window in seconds during which repeated producer message ids are treated as duplicates
+    #[clap(long, env = "DEDUP_WINDOW", default_value = "86400")]
+    dedup_window: u64,
+
+    /// Base64 encoded 32 byte key, client secrets are encrypted with it at rest
+    #[clap(long, env = "SECRETS_KEY", hide_env_values = true)]
+    secrets_key: Option<String>,
+
+    /// Convert existing sqlite database to incremental vacuum mode and exit
+    ///
+    /// Conversion rewrites whole database and blocks database access until done.
+    #[clap(long)]
+    enable_incremental_vacuum: bool,
 }
 
-// FIXME: full body accumulation
+/// Ingest arrow ipc stream, each record batch is stored as a separate record
+///
+/// Stream is decoded incrementally, each decoded record batch is inserted as it arrives and all records are
+/// committed in single transaction once stream is fully read, so failed request doesn't store any records or
+/// schema versions.
+/// Compressed (LZ4/ZSTD) ipc streams are supported.
+///
+/// If request carries `x-producer-message-id` header - ingestion is idempotent: message id is registered in the
+/// same transaction with records before stream is decoded, and request with message id, which was already
+/// ingested within dedup window, is acknowledged without storing records and response carries `x-message-duplicate: true` header.
+/// Failed request doesn't register message id, so it can be retried with the same message id.
 async fn ingestion(
     State(app): State<Arc<App>>,
     axum::extract::Path(topic): axum::extract::Path<String>,
     headers: axum::http::header::HeaderMap,
-    mut body: BodyStream,
-) -> Result<impl IntoResponse, error::Error> {
+    body: BodyStream,
+) -> Result<Response, error::Error> {
     let origin = match headers.get("x-message-origin") {
         Some(origin) => origin
             .to_str()
-            .map_err(|_| "bad x-message-origin header value")?,
+            .map_err(|_| "bad x-message-origin header value")?
+            .to_string(),
         None => Err(StatusCode::BAD_REQUEST)?,
     };
+    let message_id = match headers.get("x-producer-message-id") {
+        Some(message_id) => message_id
+            .to_str()
+            .map_err(|_| "bad x-producer-message-id header value")?
+            .to_string(),
+        None => {
+            ingest_stream(&app, &topic, &origin, body, None).await?;
+            return Ok(Json("ok").into_response());
+        }
+    };
+    if !ingest_stream(&app, &topic, &origin, body, Some(&message_id)).await? {
+        log::debug!("ingestion: duplicate message {message_id} for topic {topic}");
+        return Ok(([("x-message-duplicate", "true")], Json("duplicate")).into_response());
+    }
+    Ok(([("x-message-duplicate", "false")], Json("ok")).into_response())
+}
 
-    let mut buf = vec![];
-    while let Some(chunk) = body.next().await {
-        buf.append(&mut chunk.unwrap().to_vec());
+/// Decode and store ipc stream, returns false if message id is duplicate and records were not stored
+async fn ingest_stream(
+    app: &App,
+    topic: &str,
+    origin: &str,
+    body: BodyStream,
+    message_id: Option<&str>,
+) -> Result<bool, error::Error> {
+    // message id is claimed first, so duplicate is rejected before anything is decoded or offloaded
+    let mut ingestion = app.database.begin_ingestion(topic, origin).await?;
+    if let Some(message_id) = message_id {
+        if !ingestion
+            .claim_message_id(message_id, app.dedup_window)
+            .await?
+        {
+            return Ok(false);
+        }
     }
-    let reader = StreamReader::try_new(buf.as_slice(), None).unwrap();
-    for record_batch in reader.flatten() {
-        app.database
-            .store_record(&topic, origin, &record_batch)
-            .await
-            .unwrap()
+
+    // enforce body size limit
+    let max_size = app.max_ingestion_size;
+    let too_large = Arc::new(AtomicBool::new(false));
+    let too_large_flag = Arc::clone(&too_large);
+    let mut size = 0;
+    let body = body.map(move |chunk| {
+        let chunk = chunk.map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        size += chunk.len() as u64;
+        if size > max_size {
+            too_large_flag.store(true, Ordering::Relaxed);
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "payload too large",
+            ));
+        }
+        Ok(chunk)
+    });
+
+    // arrow stream reader is sync, so decoding happens on blocking thread
+    let reader = SyncIoBridge::new(tokio_util::io::StreamReader::new(body));
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Result<RecordBatch, ArrowError>>(1);
+    let decoder = tokio::task::spawn_blocking(move || {
+        let reader = match StreamReader::try_new(reader, None) {
+            Ok(reader) => reader,
+            Err(e) => {
+                tx.blocking_send(Err(e)).ok();
+                return;
+            }
+        };
+        for record_batch in reader {
+            if tx.blocking_send(record_batch).is_err() {
+                return;
+            }
+        }
+    });
+
+    // records are inserted as they are decoded and committed at once at the end of stream,
+    // so failed ingestion doesn't leave partially stored stream behind
+    let mut offloaded = vec![];
+    let res = async {
+        while let Some(record_batch) = rx.recv().await {
+            let record_batch =
+                record_batch.map_err(|e| match too_large.load(Ordering::Relaxed) {
+                    true => error::Error::ClientError(
+                        StatusCode::PAYLOAD_TOO_LARGE,
+                        format!("payload exceeds {max_size} bytes"),
+                    ),
+                    false => error::Error::ClientError(
+                        StatusCode::BAD_REQUEST,
+                        format!("malformed arrow ipc stream: {e}"),
+                    ),
+                })?;
+            let record = app
+                .stage_record(ingestion.as_mut(), topic, origin, &record_batch)
+                .await?;
+            offloaded.extend(record.object_key.clone());
+            ingestion.insert_record(&record).await?;
+        }
+        decoder.await.map_err(|_| "ipc decoder failed")?;
+        Ok::<_, error::Error>(())
+    }
+    .await;
+    let res = match res {
+        Ok(()) => ingestion.commit().await,
+        Err(e) => Err(e),
+    };
+    match res {
+        Ok(()) => {
+            // wake up long polling consumers
+            app.new_records.send_replace(());
+            Ok(true)
+        }
+        Err(e) => {
+            app.discard_payloads(offloaded).await;
+            Err(e)
+        }
     }
-    Ok(Json("ok"))
 }
 
-async fn get_record(
+/// Max number of records returned by single `get_records` call
+const MAX_RECORDS_LIMIT: u64 = 1000;
+
+/// Max time `get_records` can wait for new records
+const MAX_WAIT: Duration = Duration::from_secs(60);
+
+#[derive(Deserialize, Debug, Default)]
+struct GetRecordsParams {
+    /// max number of records to return, defaults to 1
+    limit: Option<u64>,
+
+    /// max total size of returned records in bytes, at least one record is returned regardless of size
+    max_bytes: Option<u64>,
+
+    /// seconds to wait for new records if topic has nothing past given offset
+    wait: Option<u64>,
+}
+
+/// Get records past given offset
+///
+/// Records are returned as single arrow ipc stream, each record batch of which is a separate record.
+/// Response contains only records which share origin and schema with the first record.
+/// Response headers:
+/// - `x-message-id` - id of last record in response, or requested offset if there are no new records
+/// - `x-message-ids` - comma separated list of ids of returned records, in order of batches in stream
+/// - `x-message-origin` - origin of returned records
+/// - `x-message-schema-version` - version of topic schema of returned records, empty if unknown
+async fn get_records(
     State(app): State<Arc<App>>,
     axum::extract::Path((topic, offset)): axum::extract::Path<(String, u64)>,
-) -> Result<impl IntoResponse, error::Error> {
-    let response = match app.database.get_record(&topic, offset).await? {
-        Some((id, origin, data)) => (
-            [
-                ("x-message-id", id.to_string()),
-                ("x-message-origin", origin),
-            ],
-            data,
+    Query(params): Query<GetRecordsParams>,
+) -> Result<Response, error::Error> {
+    let limit = params.limit.unwrap_or(1).clamp(1, MAX_RECORDS_LIMIT);
+    let max_bytes = params.max_bytes.unwrap_or(u64::MAX);
+    let deadline = Instant::now() + Duration::from_secs(params.wait.unwrap_or(0)).min(MAX_WAIT);
+
+    // long poll: wait for ingestion notification until deadline
+    let records = loop {
+        let mut new_records = app.new_records.subscribe();
+        let records = app
+            .database
+            .get_records(&topic, offset, limit, max_bytes)
+            .await?;
+        if !records.is_empty() || Instant::now() >= deadline {
+            break records;
+        }
+        tokio::time::timeout_at(deadline, new_records.changed())
+            .await
+            .ok();
+    };
+
+    // single offloaded record is streamed from payload store as is
+    if let [StoredRecord {
+        id,
+        origin,
+        object_key: Some(object_key),
+        ..
+    }] = records.as_slice()
+    {
+        let stream = app.payload_store()?.get_stream(object_key).await?;
+        let headers = record_headers(&[*id], offset, origin.clone(), records[0].schema_version);
+        return Ok((headers, StreamBody::new(stream)).into_response());
+    }
+
+    // records in response share schema with the first record
+    let schema_version = records.first().and_then(|record| record.schema_version);
+    let records = app.load_payloads(records).await?;
+    let records = &records[..batch_count(&records).max(1).min(records.len())];
+    let (origin, data) = match records {
+        [] => (String::new(), vec![]),
+        [(_, origin, data)] => (origin.clone(), data.clone()),
+        [(_, origin, _), ..] => (origin.clone(), merge_records(records)?),
+    };
+    let ids = records.iter().map(|(id, _, _)| *id).collect::<Vec<_>>();
+    Ok((record_headers(&ids, offset, origin, schema_version), data).into_response())
+}
+
+fn record_headers(
+    ids: &[u64],
+    offset: u64,
+    origin: String,
+    schema_version: Option<u64>,
+) -> [(&'static str, String); 4] {
+    let last_id = ids.last().copied().unwrap_or(offset);
+    [
+        ("x-message-id", last_id.to_string()),
+        (
+            "x-message-ids",
+            ids.iter()
+                .map(|id| id.to_string())
+                .collect::<Vec<_>>()
+                .join(","),
         ),
-        None => (
-            [
-                ("x-message-id", offset.to_string()),
-                ("x-message-origin", String::new()),
-            ],
-            vec![],
+        ("x-message-origin", origin),
+        (
+            "x-message-schema-version",
+            schema_version.map(|v| v.to_string()).unwrap_or_default(),
         ),
+    ]
+}
+
+/// Number of leading records which share origin and schema with the first record
+fn batch_count(records: &[(u64, String, Vec<u8>)]) -> usize {
+    let origin = match records.first() {
+        Some((_, origin, _)) => origin,
+        None => return 0,
+    };
+    let mut schema = None;
+    records
+        .iter()
+        .take_while(|(_, o, data)| {
+            if o != origin {
+                return false;
+            }
+            let record_schema = match StreamReader::try_new(data.as_slice(), None) {
+                Ok(reader) => reader.schema(),
+                Err(_) => return false,
+            };
+            match schema.as_ref() {
+                None => {
+                    schema = Some(record_schema);
+                    true
+                }
+                Some(schema) => schema == &record_schema,
+            }
+        })
+        .count()
+}
+
+/// Encode record batch into arrow ipc stream
+fn encode_record(record_batch: &RecordBatch) -> Result<Vec<u8>, error::Error> {
+    let mut stream_writer: StreamWriter<_> =
+        StreamWriter::try_new(vec![], record_batch.schema().as_ref())?;
+    stream_writer.write(record_batch)?;
+    stream_writer.finish()?;
+    Ok(stream_writer.into_inner()?)
+}
+
+/// Merge records into single ipc stream, records are expected to share schema
+fn merge_records(records: &[(u64, String, Vec<u8>)]) -> Result<Vec<u8>, error::Error> {
+    let mut writer: Option<StreamWriter<Vec<u8>>> = None;
+    for (_, _, data) in records {
+        let reader = StreamReader::try_new(data.as_slice(), None)?;
+        let writer = match writer.as_mut() {
+            Some(writer) => writer,
+            None => writer.insert(StreamWriter::try_new(vec![], reader.schema().as_ref())?),
+        };
+        for batch in reader {
+            writer.write(&batch?)?;
+        }
+    }
+    let mut writer = writer.ok_or("no records to merge")?;
+    writer.finish()?;
+    Ok(writer.into_inner()?)
+}
+
+/// Max number of topic records loaded by single query
+const MAX_QUERY_RECORDS: u64 = 10_000;
+
+/// Max total size of topic records loaded by single query
+const MAX_QUERY_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Max number of rows returned by single query
+const MAX_QUERY_ROWS: usize = 100_000;
+
+/// Max query execution time
+const MAX_QUERY_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Deserialize, Debug)]
+struct QueryRequest {
+    /// SQL query, topic records are available as `topic` table
+    sql: String,
+
+    /// only records with ids greater than start offset are queried, defaults to 0
+    start_offset: Option<u64>,
+
+    /// only records with ids less or equal to end offset are queried
+    end_offset: Option<u64>,
+
+    /// max number of returned rows, defaults to 1000
+    limit: Option<usize>,
+
+    /// query timeout in seconds, defaults to 30
+    timeout: Option<u64>,
+
+    /// result format, `json` (default) or `arrow`
+    format: Option<String>,
+}
+
+/// Run SQL query over records of topic
+///
+/// Result is returned either as JSON array of rows or as arrow ipc stream.
+///
+/// Single query loads at most `MAX_QUERY_RECORDS` records of total size `MAX_QUERY_BYTES`, response headers
+/// report queried range:
+/// - `x-query-end-offset` - id of last queried record, equals to start offset if no records were queried
+/// - `x-query-truncated` - `true` if range contains more records, which were not queried, rest of range
+///   can be queried with start offset set to `x-query-end-offset`
+async fn query_topic(
+    State(app): State<Arc<App>>,
+    axum::extract::Path(topic): axum::extract::Path<String>,
+    Json(request): Json<QueryRequest>,
+) -> Result<Response, error::Error> {
+    let start_offset = request.start_offset.unwrap_or(0);
+    let limit = request.limit.unwrap_or(1000).clamp(1, MAX_QUERY_ROWS);
+    let timeout = Duration::from_secs(request.timeout.unwrap_or(30)).min(MAX_QUERY_TIMEOUT);
+    let in_range = |id: u64| request.end_offset.map_or(true, |end| id <= end);
+
+    let records: Vec<StoredRecord> = app
+        .database
+        .get_records(&topic, start_offset, MAX_QUERY_RECORDS, MAX_QUERY_BYTES)
+        .await?
+        .into_iter()
+        .take_while(|record| in_range(record.id))
+        .collect();
+    let end_offset = records.last().map_or(start_offset, |record| record.id);
+    let truncated = match records.len() {
+        0 => false,
+        _ => app
+            .database
+            .get_records(&topic, end_offset, 1, 0)
+            .await?
+            .first()
+            .map_or(false, |record| in_range(record.id)),
     };
-    Ok(response)
+    let mut batches = vec![];
+    for (_, _, data) in app.load_payloads(records).await? {
+        for batch in StreamReader::try_new(data.as_slice(), None)? {
+            batches.push(batch?);
+        }
+    }
+    let range_headers = [
+        ("x-query-end-offset", end_offset.to_string()),
+        ("x-query-truncated", truncated.to_string()),
+    ];
+
+    let result = query::run(batches, &request.sql, limit, timeout).await?;
+    match request.format.as_deref().unwrap_or("json") {
+        "json" => {
+            let mut writer = arrow::json::ArrayWriter::new(vec![]);
+            writer.write_batches(&result.iter().collect::<Vec<_>>())?;
+            writer.finish()?;
+            Ok((
+                [(header::CONTENT_TYPE, "application/json")],
+                range_headers,
+                writer.into_inner(),
+            )
+                .into_response())
+        }
+        "arrow" => {
+            let data = match result.first() {
+                Some(batch) => {
+                    let mut writer = StreamWriter::try_new(vec![], batch.schema().as_ref())?;
+                    for batch in result.iter() {
+                        writer.write(batch)?;
+                    }
+                    writer.finish()?;
+                    writer.into_inner()?
+                }
+                None => vec![],
+            };
+            Ok((
+                [(header::CONTENT_TYPE, "application/vnd.apache.arrow.stream")],
+                range_headers,
+                data,
+            )
+                .into_response())
+        }
+        _ => Err(error::Error::ClientError(
+            StatusCode::BAD_REQUEST,
+            "format should be either json or arrow".into(),
+        )),
+    }
+}
+
+async fn get_topic_schemas(
+    State(app): State<Arc<App>>,
+    axum::extract::Path(topic): axum::extract::Path<String>,
+) -> Result<impl IntoResponse, error::Error> {
+    let mut schemas = vec![];
+    for stored in app.database.get_schemas(&topic).await? {
+        let schema = schema::decode(&stored.schema)?;
+        schemas.push(SchemaInfo::new(
+            stored.origin,
+            stored.version,
+            &schema,
+            stored.created_at,
+        ));
+    }
+    Ok(Json(schemas))
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TopicCompatibility {
+    mode: Compatibility,
+}
+
+async fn get_topic_compatibility(
+    State(app): State<Arc<App>>,
+    axum::extract::Path(topic): axum::extract::Path<String>,
+) -> Result<impl IntoResponse, error::Error> {
+    let mode = app.get_compatibility(&topic).await?;
+    Ok(Json(TopicCompatibility { mode }))
+}
+
+async fn put_topic_compatibility(
+    State(app): State<Arc<App>>,
+    axum::extract::Path(topic): axum::extract::Path<String>,
+    Json(compatibility): Json<TopicCompatibility>,
+) -> Result<impl IntoResponse, error::Error> {
+    app.database
+        .set_compatibility(&topic, &compatibility.mode.to_string())
+        .await?;
+    Ok(Json(compatibility))
+}
+
+/// Topic retention policy
+///
+/// Records are deleted once any of configured limits is exceeded.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+struct RetentionPolicy {
+    /// max age of records in seconds
+    #[serde(default)]
+    max_age: Option<u64>,
+
+    /// max total size of topic records in bytes, oldest records are deleted first
+    #[serde(default)]
+    max_bytes: Option<u64>,
+
+    /// delete records which all known consumers of topic have committed
+    #[serde(default)]
+    consumed: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Topic {
+    topic: String,
+    records: u64,
+    size: u64,
+    min_offset: Option<u64>,
+    max_offset: Option<u64>,
+    retention: Option<RetentionPolicy>,
+}
+
+async fn get_topics(State(app): State<Arc<App>>) -> Result<impl IntoResponse, error::Error> {
+    app.database.get_topics().await.map(Json)
+}
+
+/// Delete all records of topic
+async fn purge_topic(
+    State(app): State<Arc<App>>,
+    axum::extract::Path(topic): axum::extract::Path<String>,
+) -> Result<impl IntoResponse, error::Error> {
+    let object_keys = app.database.purge_topic(&topic).await?;
+    app.delete_payloads(&object_keys).await?;
+    Ok(Json("ok"))
+}
+
+async fn get_topic_retention(
+    State(app): State<Arc<App>>,
+    axum::extract::Path(topic): axum::extract::Path<String>,
+) -> Result<impl IntoResponse, error::Error> {
+    match app.database.get_retention_policy(&topic).await? {
+        Some(policy) => Ok(Json(policy)),
+        None => Err(StatusCode::NOT_FOUND)?,
+    }
+}
+
+async fn put_topic_retention(
+    State(app): State<Arc<App>>,
+    axum::extract::Path(topic): axum::extract::Path<String>,
+    Json(policy): Json<RetentionPolicy>,
+) -> Result<impl IntoResponse, error::Error> {
+    app.database.set_retention_policy(&topic, &policy).await?;
+    Ok(Json(policy))
+}
+
+async fn delete_topic_retention(
+    State(app): State<Arc<App>>,
+    axum::extract::Path(topic): axum::extract::Path<String>,
+) -> Result<impl IntoResponse, error::Error> {
+    app.database.delete_retention_policy(&topic).await?;
+    Ok(Json("ok"))
+}
+
+/// Offset committed by consumer of topic
+#[derive(Serialize, Deserialize, Debug)]
+struct ConsumerOffset {
+    offset: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Consumer {
+    consumer_id: String,
+    offset: u64,
+    /// number of topic records past committed offset
+    lag: u64,
+    updated_at: String,
+}
+
+async fn get_consumer_offset(
+    State(app): State<Arc<App>>,
+    axum::extract::Path((topic, consumer_id)): axum::extract::Path<(String, String)>,
+) -> Result<impl IntoResponse, error::Error> {
+    match app
+        .database
+        .get_consumer_offset(&topic, &consumer_id)
+        .await?
+    {
+        Some(offset) => Ok(Json(ConsumerOffset { offset })),
+        None => Err(StatusCode::NOT_FOUND)?,
+    }
+}
+
+async fn commit_consumer_offset(
+    State(app): State<Arc<App>>,
+    axum::extract::Path((topic, consumer_id)): axum::extract::Path<(String, String)>,
+    Json(offset): Json<ConsumerOffset>,
+) -> Result<impl IntoResponse, error::Error> {
+    app.database
+        .commit_consumer_offset(&topic, &consumer_id, offset.offset)
+        .await?;
+    Ok(Json(offset))
+}
+
+async fn delete_consumer(
+    State(app): State<Arc<App>>,
+    axum::extract::Path((topic, consumer_id)): axum::extract::Path<(String, String)>,
+) -> Result<impl IntoResponse, error::Error> {
+    app.database.delete_consumer(&topic, &consumer_id).await?;
+    Ok(Json("ok"))
+}
+
+async fn get_consumers(
+    State(app): State<Arc<App>>,
+    axum::extract::Path(topic): axum::extract::Path<String>,
+) -> Result<impl IntoResponse, error::Error> {
+    app.database.get_consumers(&topic).await.map(Json)
+}
+
+/// Periodically enforce topic retention policies
+async fn retention_loop(app: Arc<App>, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        match app.database.prune_message_ids(app.dedup_window).await {
+            Ok(0) => (),
+            Ok(pruned) => log::debug!("retention: pruned {pruned} producer message ids"),
+            Err(e) => log::error!("retention: failed to prune producer message ids: {e}"),
+        }
+        let object_keys = match app.database.enforce_retention().await {
+            Ok((0, _)) => continue,
+            Ok((deleted, object_keys)) => {
+                log::info!("retention: deleted {deleted} records");
+                object_keys
+            }
+            Err(e) => {
+                log::error!("retention: failed to enforce policies: {e}");
+                continue;
+            }
+        };
+        if let Err(e) = app.delete_payloads(&object_keys).await {
+            log::error!("retention: failed to delete payloads: {e}");
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -186,12 +786,21 @@ async fn delete_workspace(
     app.delete_workspace(id).await
 }
 
+/// Author of pipe config change, taken from `x-author` request header
+fn author(headers: &axum::http::header::HeaderMap) -> &str {
+    headers
+        .get("x-author")
+        .and_then(|author| author.to_str().ok())
+        .unwrap_or("unknown")
+}
+
 async fn post_pipe_config(
     State(app): State<Arc<App>>,
+    headers: axum::http::header::HeaderMap,
     Json(configs): Json<PipeConfigs>,
 ) -> Result<impl IntoResponse, error::Error> {
     log::trace!("Configs in: {:?}", &configs);
-    let ids = app.set_configs(&configs).await?;
+    let ids = app.set_configs(&configs, author(&headers)).await?;
     Ok(Json(
         ids.iter()
             .zip(configs.configs)
@@ -199,6 +808,8 @@ async fn post_pipe_config(
                 id: *id,
                 pipe: conf.pipe,
                 workspace_id: conf.workspace_id,
+                revision: 1,
+                paused: false,
             })
             .collect::<Vec<PipeConfig>>(),
     )
@@ -207,18 +818,244 @@ async fn post_pipe_config(
 
 async fn put_pipe_configs(
     State(app): State<Arc<App>>,
+    headers: axum::http::header::HeaderMap,
     Json(configs): Json<PipeConfigs>,
 ) -> Result<impl IntoResponse, error::Error> {
-    app.update_configs(configs).await.map(Json)
+    app.update_configs(configs, author(&headers))
+        .await
+        .map(Json)
 }
 
 async fn put_pipe_config(
     State(app): State<Arc<App>>,
     axum::extract::Path(id): axum::extract::Path<u64>,
+    headers: axum::http::header::HeaderMap,
     Json(mut config): Json<PipeConfig>,
 ) -> Result<impl IntoResponse, error::Error> {
     config.id = id;
-    app.update_config(config).await.map(Json)
+    app.update_config(config, author(&headers)).await.map(Json)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct PipeRevision {
+    revision: u64,
+    pipe: serde_json::Value,
+    author: String,
+    created_at: String,
+}
+
+async fn get_pipe_revisions(
+    State(app): State<Arc<App>>,
+    axum::extract::Path(id): axum::extract::Path<u64>,
+) -> Result<impl IntoResponse, error::Error> {
+    app.database.get_config_revisions(id).await.map(Json)
+}
+
+async fn get_pipe_revision(
+    State(app): State<Arc<App>>,
+    axum::extract::Path((id, revision)): axum::extract::Path<(u64, u64)>,
+) -> Result<impl IntoResponse, error::Error> {
+    app.get_config_revision(id, revision).await.map(Json)
+}
+
+#[derive(Deserialize, Debug)]
+struct DiffParams {
+    from: u64,
+    /// defaults to current revision
+    to: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+struct PipeDiff {
+    from: u64,
+    to: u64,
+    changes: Vec<diff::Change>,
+}
+
+/// Diff two revisions of pipe config
+async fn get_pipe_diff(
+    State(app): State<Arc<App>>,
+    axum::extract::Path(id): axum::extract::Path<u64>,
+    Query(params): Query<DiffParams>,
+) -> Result<impl IntoResponse, error::Error> {
+    let to = match params.to {
+        Some(to) => to,
+        None => app.get_config(id).await?.revision,
+    };
+    let old = app.get_config_revision(id, params.from).await?;
+    let new = app.get_config_revision(id, to).await?;
+    Ok(Json(PipeDiff {
+        from: old.revision,
+        to: new.revision,
+        changes: diff::diff(&old.pipe, &new.pipe),
+    }))
+}
+
+/// Roll pipe config back to given revision
+///
+/// Rollback doesn't rewrite history, config of given revision is stored as new revision.
+async fn rollback_pipe_config(
+    State(app): State<Arc<App>>,
+    axum::extract::Path((id, revision)): axum::extract::Path<(u64, u64)>,
+    headers: axum::http::header::HeaderMap,
+) -> Result<impl IntoResponse, error::Error> {
+    let target = app.get_config_revision(id, revision).await?;
+    let config = app.get_config(id).await?;
+    let config = PipeConfig {
+        pipe: target.pipe,
+        ..config
+    };
+    app.update_config(config, author(&headers)).await.map(Json)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct PipeClient {
+    client_id: String,
+    revision: u64,
+    updated_at: String,
+}
+
+/// Paused flag of pipe, paused pipes are stopped on clients
+#[derive(Serialize, Deserialize, Debug)]
+struct PipePaused {
+    paused: bool,
+}
+
+/// Pause or resume pipe on clients, pipe config is not changed
+async fn put_pipe_paused(
+    State(app): State<Arc<App>>,
+    axum::extract::Path(id): axum::extract::Path<u64>,
+    Json(paused): Json<PipePaused>,
+) -> Result<impl IntoResponse, error::Error> {
+    match app.database.set_pipe_paused(id, paused.paused).await? {
+        true => Ok(Json(paused)),
+        false => Err(StatusCode::NOT_FOUND)?,
+    }
+}
+
+/// Clients running pipe and revisions they run
+async fn get_pipe_clients(
+    State(app): State<Arc<App>>,
+    axum::extract::Path(id): axum::extract::Path<u64>,
+) -> Result<impl IntoResponse, error::Error> {
+    app.database.get_pipe_clients(id).await.map(Json)
+}
+
+/// Client reports pipe config revisions it runs
+///
+/// Client can report only own pipes, request is authorized by client token.
+async fn put_client_pipes(
+    State(app): State<Arc<App>>,
+    axum::extract::Path(client_id): axum::extract::Path<String>,
+    headers: axum::http::header::HeaderMap,
+    Json(running): Json<RunningPipes>,
+) -> Result<impl IntoResponse, error::Error> {
+    if token_client(&app, &headers).await? != client_id {
+        Err(StatusCode::FORBIDDEN)?
+    }
+    app.database
+        .set_client_pipes(&client_id, &running.pipes)
+        .await?;
+    Ok(Json("ok"))
+}
+
+#[derive(Serialize, Debug)]
+struct ClientSecretInfo {
+    name: String,
+    updated_at: String,
+}
+
+/// Secret value, intentionally not Debug
+#[derive(Deserialize)]
+struct SecretValue {
+    value: String,
+}
+
+/// Secret names are referenced as `${secret:<name>}` in pipe configs
+fn is_valid_secret_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+/// Names of client secrets, secret values are never listed
+async fn get_client_secrets(
+    State(app): State<Arc<App>>,
+    axum::extract::Path(client_id): axum::extract::Path<String>,
+) -> Result<impl IntoResponse, error::Error> {
+    let secrets = app.database.get_client_secrets(&client_id).await?;
+    Ok(Json(
+        secrets
+            .into_iter()
+            .map(|secret| ClientSecretInfo {
+                name: secret.name,
+                updated_at: secret.updated_at,
+            })
+            .collect::<Vec<_>>(),
+    ))
+}
+
+async fn put_client_secret(
+    State(app): State<Arc<App>>,
+    axum::extract::Path((client_id, name)): axum::extract::Path<(String, String)>,
+    Json(secret): Json<SecretValue>,
+) -> Result<impl IntoResponse, error::Error> {
+    if !is_valid_secret_name(&name) {
+        Err(error::Error::ClientError(
+            StatusCode::BAD_REQUEST,
+            "secret name should consist of alphanumeric characters, '_', '-' or '.'".into(),
+        ))?
+    }
+    let value = app.encrypt_secret(&client_id, &name, &secret.value)?;
+    app.database
+        .set_client_secret(&client_id, &name, &value)
+        .await?;
+    Ok(Json("ok"))
+}
+
+async fn delete_client_secret(
+    State(app): State<Arc<App>>,
+    axum::extract::Path((client_id, name)): axum::extract::Path<(String, String)>,
+) -> Result<impl IntoResponse, error::Error> {
+    match app.database.delete_client_secret(&client_id, &name).await? {
+        true => Ok(Json("ok")),
+        false => Err(StatusCode::NOT_FOUND)?,
+    }
+}
+
+/// Id of client, authorized by client token in `x-authorization` header
+async fn token_client(
+    app: &App,
+    headers: &axum::http::header::HeaderMap,
+) -> Result<String, error::Error> {
+    let token = headers
+        .get("x-authorization")
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.strip_prefix("Bearer "))
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+    Ok(app
+        .database
+        .get_token_client(token)
+        .await?
+        .ok_or(StatusCode::UNAUTHORIZED)?)
+}
+
+/// Secrets of client, which issued request
+///
+/// Client is identified by token in `X-Authorization` header, so secrets are delivered only to client they
+/// belong to.
+async fn get_own_secrets(
+    State(app): State<Arc<App>>,
+    headers: axum::http::header::HeaderMap,
+) -> Result<impl IntoResponse, error::Error> {
+    let client_id = token_client(&app, &headers).await?;
+    let mut secrets = HashMap::new();
+    for secret in app.database.get_client_secrets(&client_id).await? {
+        let value = app.decrypt_secret(&client_id, &secret)?;
+        secrets.insert(secret.name, value);
+    }
+    Ok(Json(ClientSecrets { secrets }))
 }
 
 async fn delete_pipe_config(
@@ -228,6 +1065,11 @@ async fn delete_pipe_config(
     app.delete_config(id).await
 }
 
+/// Config schemas of sections, which can be used in pipe configs, schemas are registered by clients
+async fn get_sections(State(app): State<Arc<App>>) -> Result<impl IntoResponse, error::Error> {
+    app.section_schemas().await.map(Json)
+}
+
 async fn get_clients(State(app): State<Arc<App>>) -> Result<impl IntoResponse, error::Error> {
     app.database.get_clients().await.map(Json)
 }
@@ -273,7 +1115,6 @@ async fn basic_auth<B>(
 async fn log_middleware<B>(
     method: Method,
     uri: Uri,
-    maybe_auth: Option<TypedHeader<Authorization<Basic>>>,
     request: Request<B>,
     next: Next<B>,
 ) -> Response {
@@ -283,15 +1124,9 @@ async fn log_middleware<B>(
         .signed_duration_since(timestamp)
         .num_milliseconds();
 
-    let token = match maybe_auth.as_ref() {
-        None => "",
-        Some(TypedHeader(Authorization(basic))) => basic.username(),
-    };
-
     let error: Option<&error::Error> = response.extensions().get();
-    // FIXME: do not log token
+    // credentials are never logged
     let log = json!({
-        "token": token,
         "timestamp": timestamp,
         "request_time_ms": request_time_ms,
         "method": method.as_str(),
@@ -303,33 +1138,53 @@ async fn log_middleware<B>(
     response
 }
 
+/// Hash of client credential, credentials are never stored in plain text
+fn credential_hash(credential: &str) -> String {
+    BASE64.encode(Sha256::digest(credential.as_bytes()))
+}
+
+/// Provision client
+///
+/// First provisioning binds client id to credential, client can't be re-provisioned with other credential.
 async fn provision_client(
     State(state): State<Arc<App>>,
     Json(payload): Json<ProvisionClientRequest>,
 ) -> Result<impl IntoResponse, error::Error> {
     let client_id = payload.client_config.node.unique_id;
+    if payload.credential.is_empty() {
+        Err(error::Error::ClientError(
+            StatusCode::BAD_REQUEST,
+            "client credential is required".into(),
+        ))?
+    }
 
-    state
+    let provisioned = state
         .database
         .insert_client(
             &client_id,
             &payload.client_config.node.display_name,
             &payload.client_config.sources,
             &payload.client_config.destinations,
+            &payload.sections,
+            &credential_hash(&payload.credential),
         )
-        .await
-        .map(|_| {
-            Json(ProvisionClientResponse {
-                id: client_id.clone(),
-            })
-        })
+        .await?;
+    match provisioned {
+        true => Ok(Json(ProvisionClientResponse { id: client_id })),
+        false => Err(StatusCode::FORBIDDEN)?,
+    }
 }
 
+/// Issue token to client, which presents credential it was provisioned with
 async fn issue_token(
     State(state): State<Arc<App>>,
     Json(payload): Json<IssueTokenRequest>,
 ) -> Result<impl IntoResponse, error::Error> {
     let client_id = payload.client_id.clone();
+    match state.database.get_client_credential(&client_id).await? {
+        Some(hash) if hash == credential_hash(&payload.credential) => (),
+        _ => Err(StatusCode::UNAUTHORIZED)?,
+    }
 
     // todo: It'd be smarter/safer to store the salted & hashed token in the database
     let token = Uuid::new_v4().to_string();
@@ -348,260 +1203,27 @@ async fn issue_token(
 }
 
 #[derive(Debug)]
-#[allow(unused)]
-pub struct Database {
-    connection: Arc<Mutex<SqliteConnection>>,
-    database_path: String,
-}
-
-impl Database {
-    async fn new(database_path: &str) -> Result<Self, error::Error> {
-        let database_url = format!("sqlite://{database_path}");
-        let mut connection = SqliteConnectOptions::from_str(database_url.as_str())?
-            .create_if_missing(true)
-            .connect()
-            .await?;
-        sqlx::migrate!().run(&mut connection).await?;
-        Ok(Self {
-            connection: Arc::new(Mutex::new(connection)),
-            database_path: database_path.into(),
-        })
-    }
-
-    async fn insert_client(
-        &self,
-        client_id: &str,
-        display_name: &str,
-        sources: &[Source],
-        destinations: &[Destination],
-    ) -> Result<(), error::Error> {
-        let sources = serde_json::to_string(sources)?;
-        let destinations = serde_json::to_string(destinations)?;
-
-        let mut connection = self.connection.lock().await;
-        let _ = sqlx::query("INSERT OR REPLACE INTO clients (id, display_name, sources, destinations) VALUES (?, ?, ?, ?)")
-            .bind(client_id)
-            .bind(display_name)
-            .bind(sources)
-            .bind(destinations)
-            .execute(&mut *connection)
-            .await?;
-        Ok(())
-    }
-
-    async fn insert_token(&self, client_id: &str, token: &str) -> Result<(), error::Error> {
-        let mut connection = self.connection.lock().await;
-        let _ = sqlx::query(
-            "INSERT INTO tokens (client_id, id) VALUES (?,?) ON CONFLICT (id) DO NOTHING",
-        )
-        .bind(client_id)
-        .bind(token)
-        .execute(&mut *connection)
-        .await?;
-        Ok(())
-    }
-
-    async fn insert_config(
-        &self,
-        config: &serde_json::Value,
-        workspace_id: i64,
-    ) -> Result<u64, error::Error> {
-        let mut connection = self.connection.lock().await;
-        // FIXME: unwrap
-        let config: String = serde_json::to_string(config)?;
-        let id = sqlx::query("INSERT INTO pipes (raw_config, workspace_id) VALUES (?, ?)")
-            .bind(config)
-            .bind(workspace_id)
-            .execute(&mut *connection)
-            .await?
-            .last_insert_rowid();
-        Ok(id.try_into().unwrap())
-    }
-
-    async fn update_config(&self, id: u64, config: &serde_json::Value) -> Result<(), error::Error> {
-        let mut connection = self.connection.lock().await;
-        let config: String = serde_json::to_string(config)?;
-        let id: i64 = id.try_into().unwrap();
-        let _ = sqlx::query("update pipes set raw_config = ? WHERE id = ?")
-            .bind(config)
-            .bind(id)
-            .execute(&mut *connection)
-            .await?;
-        Ok(())
-    }
-
-    async fn delete_config(&self, id: u64) -> Result<(), error::Error> {
-        let mut connection = self.connection.lock().await;
-        let id: i64 = id.try_into().unwrap();
-        let _ = sqlx::query("DELETE FROM pipes WHERE id = ?")
-            .bind(id) // fixme
-            .execute(&mut *connection)
-            .await?;
-        Ok(())
-    }
-
-    async fn store_record(
-        &self,
-        topic: &str,
-        origin: &str,
-        record_batch: &RecordBatch,
-    ) -> Result<(), error::Error> {
-        let mut stream_writer: StreamWriter<_> =
-            StreamWriter::try_new(vec![], record_batch.schema().as_ref()).unwrap();
-        // FIXME: unwrap
-        stream_writer.write(record_batch).unwrap();
-        stream_writer.finish().unwrap();
-
-        let bytes: Vec<u8> = stream_writer.into_inner().unwrap();
-
-        let mut connection = self.connection.lock().await;
-        sqlx::query("INSERT INTO records (topic, origin, data) VALUES (?, ?, ?)")
-            .bind(topic)
-            .bind(origin)
-            .bind(bytes)
-            .execute(&mut *connection)
-            .await?;
-        Ok(())
-    }
-
-    async fn get_record(
-        &self,
-        topic: &str,
-        offset: u64,
-    ) -> Result<Option<(u64, String, Vec<u8>)>, error::Error> {
-        let mut connection = self.connection.lock().await;
-        let offset: i64 = offset.try_into().unwrap();
-        let row = sqlx::query(
-            "SELECT id, origin, data FROM records WHERE topic = ? AND id > ? ORDER BY id ASC LIMIT 1",
-        )
-            .bind(topic)
-            .bind(offset)
-            .fetch_optional(&mut *connection)
-            .await?;
-        Ok(row.map(|row| {
-            (
-                row.get::<i64, &str>("id").try_into().unwrap(),
-                row.get("origin"),
-                row.get("data"),
-            )
-        }))
-    }
-
-    async fn get_clients(&self) -> Result<Clients, error::Error> {
-        let mut connection = self.connection.lock().await;
-        // todo: should we return ui as client?
-        let rows = sqlx::query("SELECT id, display_name, sources, destinations FROM clients")
-            .fetch_all(&mut *connection)
-            .await?;
-
-        let mut clients: Vec<Client> = Vec::new();
-        for row in rows.iter() {
-            let id = row.get("id");
-            let display_name = row.get("display_name");
-            let sources: Option<String> = row.get("sources");
-            let sources = serde_json::from_str(&sources.unwrap_or("[]".to_string()))?;
-            let destinations: Option<String> = row.get("destinations");
-            let destinations = serde_json::from_str(&destinations.unwrap_or("[]".to_string()))?;
-            clients.push(Client {
-                id,
-                display_name,
-                sources,
-                destinations,
-            });
-        }
-        Ok(Clients { clients })
-    }
-
-    async fn get_workspaces(&self) -> Result<Vec<Workspace>, error::Error> {
-        let mut connection = self.connection.lock().await;
-        let records: Vec<Workspace> =
-            sqlx::query_as(r"SELECT id, name, created_at FROM workspaces")
-                .fetch_all(&mut *connection)
-                .await
-                .unwrap();
-
-        Ok(records)
-    }
-
-    async fn create_workspace(&self, mut workspace: Workspace) -> Result<Workspace, error::Error> {
-        let mut connection = self.connection.lock().await;
-        workspace.id = sqlx::query("INSERT INTO workspaces (name) VALUES (?)")
-            .bind(workspace.name.clone())
-            .execute(&mut *connection)
-            .await?
-            .last_insert_rowid();
-        Ok(workspace)
-    }
-
-    async fn get_workspace(&self, id: u64) -> Result<Workspace, error::Error> {
-        let mut connection = self.connection.lock().await;
-        let id: i64 = id.try_into().unwrap();
-        let mut record: Workspace =
-            sqlx::query_as(r"SELECT id, name, created_at FROM workspaces WHERE id = ?")
-                .bind(id)
-                .fetch_one(&mut *connection)
-                .await
-                .unwrap();
-
-        let pipes: Vec<PipeConfig> =
-            sqlx::query_as("SELECT id, raw_config, workspace_id from pipes where workspace_id = ?")
-                .bind(id)
-                .fetch_all(&mut *connection)
-                .await?;
-
-        record.pipe_configs = pipes;
-
-        Ok(record)
-    }
+pub struct App {
+    database: Box<dyn Storage>,
+    token: String,
 
-    async fn update_workspace(&self, workspace: Workspace) -> Result<Workspace, error::Error> {
-        let mut connection = self.connection.lock().await;
-        let _ = sqlx::query("UPDATE workspaces SET name = ? where id = ?")
-            .bind(workspace.name.clone())
-            .bind(workspace.id)
-            .execute(&mut *connection)
-            .await
-            .unwrap();
-        Ok(workspace)
-    }
+    /// max size of ingestion request body in bytes
+    max_ingestion_size: u64,
 
-    async fn delete_workspace(&self, id: u64) -> Result<(), error::Error> {
-        let mut connection = self.connection.lock().await;
-        let id: i64 = id.try_into().unwrap();
-        let _ = sqlx::query("DELETE FROM pipes WHERE id = ?")
-            .bind(id) // fixme
-            .execute(&mut *connection)
-            .await?;
-        Ok(())
-    }
+    /// notifies waiting consumers about newly ingested records
+    new_records: watch::Sender<()>,
 
-    async fn get_config(&self, id: u64) -> Result<PipeConfig, error::Error> {
-        let mut connection = self.connection.lock().await;
-        let id: i64 = id.try_into().unwrap();
-        let pipe: PipeConfig =
-            sqlx::query_as("SELECT id, workspace_id, raw_config from pipes WHERE id = ?")
-                .bind(id)
-                .fetch_one(&mut *connection)
-                .await?;
-        Ok(pipe)
-    }
+    /// store of record payloads, if not set - payloads are stored in database
+    payload_store: Option<PayloadStore>,
 
-    async fn get_configs(&self) -> Result<PipeConfigs, error::Error> {
-        let mut connection = self.connection.lock().await;
-        let rows: Vec<PipeConfig> =
-            sqlx::query_as("SELECT id, raw_config, workspace_id from pipes")
-                .fetch_all(&mut *connection)
-                .await?;
+    /// schema compatibility mode of topics without explicitly configured mode
+    default_compatibility: Compatibility,
 
-        let configs: PipeConfigs = PipeConfigs { configs: rows };
-        Ok(configs)
-    }
-}
+    /// window in seconds during which repeated producer message ids are treated as duplicates
+    dedup_window: u64,
 
-#[derive(Debug)]
-pub struct App {
-    database: Database,
-    token: String,
+    /// cipher of client secrets, if not set - client secrets can't be stored
+    secrets_cipher: Option<Cipher>,
 }
 
 #[derive(RustEmbed)]
@@ -614,28 +1236,91 @@ impl App {
         Ok(())
     }
 
+    /// Config schemas of sections, registered by clients
+    ///
+    /// If section is registered by multiple clients - schema registered by first client is used.
+    async fn section_schemas(&self) -> Result<Vec<SectionSchema>, error::Error> {
+        let mut schemas: Vec<SectionSchema> = vec![];
+        for schema in self.database.get_section_schemas().await? {
+            if !schemas.iter().any(|known| known.name == schema.name) {
+                schemas.push(schema);
+            }
+        }
+        Ok(schemas)
+    }
+
+    /// Validate pipe config against section config schemas
+    fn validate_config(
+        pipe: &serde_json::Value,
+        schemas: &[SectionSchema],
+    ) -> Result<(), error::Error> {
+        let config = DynamicPipeValue::try_from(pipe.clone())
+            .and_then(DynamicPipeConfig::try_from)
+            .map_err(|e| {
+                error::Error::ClientError(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    format!("malformed pipe config: {e}"),
+                )
+            })?;
+        let errors = config_schema::validate(&config, schemas);
+        if !errors.is_empty() {
+            Err(error::Error::Validation(errors))?
+        }
+        Ok(())
+    }
+
     /// Set pipe configs
-    async fn set_configs(&self, new_configs: &PipeConfigs) -> Result<Vec<u64>, error::Error> {
+    async fn set_configs(
+        &self,
+        new_configs: &PipeConfigs,
+        author: &str,
+    ) -> Result<Vec<u64>, error::Error> {
+        let schemas = self.section_schemas().await?;
+        for config in new_configs.configs.iter() {
+            Self::validate_config(&config.pipe, &schemas)?;
+        }
         let mut inserted_ids = Vec::new();
         for config in new_configs.configs.iter() {
             let id = self
                 .database
-                .insert_config(&config.pipe, config.workspace_id.try_into().unwrap())
+                .insert_config(
+                    &config.pipe,
+                    config.workspace_id.try_into().unwrap(),
+                    author,
+                )
                 .await?;
             inserted_ids.push(id);
         }
         Ok(inserted_ids)
     }
 
-    async fn update_configs(&self, configs: PipeConfigs) -> Result<(), error::Error> {
+    async fn update_configs(&self, configs: PipeConfigs, author: &str) -> Result<(), error::Error> {
+        // all configs are validated before any of them is updated
+        let schemas = self.section_schemas().await?;
+        for config in configs.configs.iter() {
+            Self::validate_config(&config.pipe, &schemas)?;
+        }
         for config in configs.configs {
-            self.database.update_config(config.id, &config.pipe).await?
+            self.database
+                .update_config(config.id, &config.pipe, author)
+                .await?
+                .ok_or(StatusCode::NOT_FOUND)?;
         }
         Ok(())
     }
 
-    async fn update_config(&self, config: PipeConfig) -> Result<PipeConfig, error::Error> {
-        self.database.update_config(config.id, &config.pipe).await?;
+    /// Update pipe config, returned config carries current revision
+    async fn update_config(
+        &self,
+        mut config: PipeConfig,
+        author: &str,
+    ) -> Result<PipeConfig, error::Error> {
+        Self::validate_config(&config.pipe, &self.section_schemas().await?)?;
+        config.revision = self
+            .database
+            .update_config(config.id, &config.pipe, author)
+            .await?
+            .ok_or(StatusCode::NOT_FOUND)?;
         Ok(config)
     }
 
@@ -643,6 +1328,20 @@ impl App {
         self.database.get_config(id).await
     }
 
+    async fn get_config_revision(
+        &self,
+        id: u64,
+        revision: u64,
+    ) -> Result<PipeRevision, error::Error> {
+        match self.database.get_config_revision(id, revision).await? {
+            Some(revision) => Ok(revision),
+            None => Err(error::Error::ClientError(
+                StatusCode::NOT_FOUND,
+                format!("pipe {id} has no revision {revision}"),
+            )),
+        }
+    }
+
     async fn get_workspaces(&self) -> Result<Vec<Workspace>, error::Error> {
         self.database.get_workspaces().await
     }
@@ -669,18 +1368,188 @@ impl App {
 }
 
 impl App {
-    pub async fn new(db_path: impl AsRef<str>, token: impl Into<String>) -> anyhow::Result<Self> {
-        let db_path: &str = db_path.as_ref();
-        if let Some(parent) = Path::new(db_path).parent() {
-            tokio::fs::create_dir_all(parent).await?;
+    pub async fn new(
+        database_url: impl AsRef<str>,
+        max_connections: u32,
+        token: impl Into<String>,
+        max_ingestion_size: u64,
+        payload_store: Option<&str>,
+        default_compatibility: Compatibility,
+        dedup_window: u64,
+        secrets_key: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        let database = storage::connect(database_url.as_ref(), max_connections).await?;
+        let payload_store = match payload_store {
+            Some(url) => Some(PayloadStore::new(url).await?),
+            None => None,
         };
-        let database = Database::new(db_path).await?;
         Ok(Self {
             database,
             token: token.into(),
+            max_ingestion_size,
+            new_records: watch::channel(()).0,
+            payload_store,
+            default_compatibility,
+            dedup_window,
+            secrets_cipher: secrets_key.map(Cipher::new).transpose()?,
+        })
+    }
+
+    async fn get_compatibility(&self, topic: &str) -> Result<Compatibility, error::Error> {
+        match self.database.get_compatibility(topic).await? {
+            Some(mode) => Ok(mode.parse()?),
+            None => Ok(self.default_compatibility),
+        }
+    }
+
+    /// Register schema of incoming record batch, returns schema version of topic origin
+    ///
+    /// Schemas are versioned per origin of topic, since origins of the same topic can carry different schemas.
+    /// New version is registered only if schema differs from latest schema of origin and is compatible with it.
+    /// Schema version is registered in transaction of ingestion, so it's dropped if ingestion fails.
+    /// Concurrent registration of the same version is detected by primary key, and registration is retried
+    /// against newly registered schema.
+    async fn register_schema(
+        &self,
+        ingestion: &mut dyn Ingestion,
+        topic: &str,
+        origin: &str,
+        schema: &Schema,
+    ) -> Result<u64, error::Error> {
+        loop {
+            let latest = ingestion.get_latest_schema().await?;
+            let version = latest.as_ref().map_or(1, |latest| latest.version + 1);
+            if let Some(latest) = latest {
+                let previous = schema::decode(&latest.schema)?;
+                if &previous == schema {
+                    return Ok(latest.version);
+                }
+                let compatibility = self.get_compatibility(topic).await?;
+                if let Err(e) = compatibility.check(&previous, schema) {
+                    let message = format!(
+                        "schema is not {compatibility} compatible with schema version {} of origin '{origin}': {e}",
+                        latest.version
+                    );
+                    Err(error::Error::ClientError(StatusCode::CONFLICT, message))?
+                }
+            }
+            if ingestion
+                .insert_schema(version, &schema::encode(schema)?)
+                .await?
+            {
+                return Ok(version);
+            }
+        }
+    }
+
+    fn secrets_cipher(&self) -> Result<&Cipher, error::Error> {
+        self.secrets_cipher.as_ref().ok_or_else(|| {
+            error::Error::ClientError(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "secrets key is not configured".into(),
+            )
         })
     }
 
+    /// Encrypt client secret, ciphertext is bound to client and secret name
+    fn encrypt_secret(
+        &self,
+        client_id: &str,
+        name: &str,
+        value: &str,
+    ) -> Result<Vec<u8>, error::Error> {
+        Ok(self
+            .secrets_cipher()?
+            .encrypt(&format!("{client_id}/{name}"), value)
+            .map_err(|_| "failed to encrypt client secret")?)
+    }
+
+    fn decrypt_secret(
+        &self,
+        client_id: &str,
+        secret: &StoredSecret,
+    ) -> Result<String, error::Error> {
+        Ok(self
+            .secrets_cipher()?
+            .decrypt(&format!("{client_id}/{}", secret.name), &secret.value)
+            .map_err(|e| {
+                log::error!(
+                    "failed to decrypt secret '{}' of client '{client_id}': {e}",
+                    secret.name
+                );
+                "failed to decrypt client secret"
+            })?)
+    }
+
+    fn payload_store(&self) -> Result<&PayloadStore, error::Error> {
+        Ok(self
+            .payload_store
+            .as_ref()
+            .ok_or("record payload is offloaded, but payload store is not configured")?)
+    }
+
+    /// Prepare record for storing, payload is offloaded to payload store if configured
+    async fn stage_record(
+        &self,
+        ingestion: &mut dyn Ingestion,
+        topic: &str,
+        origin: &str,
+        record_batch: &RecordBatch,
+    ) -> Result<NewRecord, error::Error> {
+        let schema_version = self
+            .register_schema(ingestion, topic, origin, record_batch.schema().as_ref())
+            .await?;
+        let data = encode_record(record_batch)?;
+        let size = data.len() as u64;
+        let record = match self.payload_store.as_ref() {
+            Some(payload_store) => NewRecord {
+                data: vec![],
+                size,
+                object_key: Some(payload_store.put(topic, data).await?),
+                schema_version: Some(schema_version),
+            },
+            None => NewRecord {
+                data,
+                size,
+                object_key: None,
+                schema_version: Some(schema_version),
+            },
+        };
+        Ok(record)
+    }
+
+    /// Delete offloaded payloads of records, which were not stored
+    async fn discard_payloads(&self, object_keys: Vec<String>) {
+        if let (false, Some(payload_store)) = (object_keys.is_empty(), &self.payload_store) {
+            if let Err(e) = payload_store.delete(&object_keys).await {
+                log::error!("failed to delete offloaded payloads of discarded records: {e}");
+            }
+        }
+    }
+
+    /// Load offloaded payloads of records
+    async fn load_payloads(
+        &self,
+        records: Vec<StoredRecord>,
+    ) -> Result<Vec<(u64, String, Vec<u8>)>, error::Error> {
+        let mut loaded = Vec::with_capacity(records.len());
+        for record in records {
+            let data = match record.object_key.as_deref() {
+                Some(object_key) => self.payload_store()?.get(object_key).await?,
+                None => record.data,
+            };
+            loaded.push((record.id, record.origin, data));
+        }
+        Ok(loaded)
+    }
+
+    async fn delete_payloads(&self, object_keys: &[String]) -> Result<(), error::Error> {
+        if object_keys.is_empty() {
+            return Ok(());
+        }
+        self.payload_store()?.delete(object_keys).await
+    }
+
     fn basic_auth(&self, token: &str) -> bool {
         token == self.basic_auth_token()
     }
@@ -710,15 +1579,44 @@ async fn main() -> anyhow::Result<()> {
     pretty_env_logger::init();
 
     let cli = Cli::try_parse()?;
-    let app = App::new(cli.database_path, cli.token).await?;
+    let database_url = cli
+        .database_url
+        .unwrap_or_else(|| format!("sqlite://{}", cli.database_path));
+    let app = App::new(
+        database_url,
+        cli.database_max_connections,
+        cli.token,
+        cli.max_ingestion_size,
+        cli.payload_store.as_deref(),
+        cli.default_compatibility,
+        cli.dedup_window,
+        cli.secrets_key.as_deref(),
+    )
+    .await?;
+    if cli.enable_incremental_vacuum {
+        log::info!("converting database to incremental vacuum mode");
+        app.database.enable_incremental_vacuum().await?;
+        log::info!("database converted");
+        return Ok(());
+    }
     let state = Arc::new(app);
 
+    tokio::spawn(retention_loop(
+        Arc::clone(&state),
+        Duration::from_secs(cli.retention_interval.max(1)),
+    ));
+
     // FIXME: consistent endpoint namings
     let api = Router::new()
         .route("/api/client", post(provision_client)) // no client auth needed
         .route("/api/tokens", post(issue_token)) // no client auth needed
+        .route("/api/client/secrets", get(get_own_secrets))
         .route("/ingestion/:topic", post(ingestion))
-        .route("/ingestion/:topic/:offset", get(get_record))
+        .route("/ingestion/:topic/:offset", get(get_records))
+        .route(
+            "/ingestion/:topic/consumers/:consumer_id",
+            get(get_consumer_offset).put(commit_consumer_offset),
+        )
         .merge(
             Router::new()
                 .route(
@@ -733,6 +1631,21 @@ async fn main() -> anyhow::Result<()> {
                         .delete(delete_pipe_config)
                         .put(put_pipe_config),
                 )
+                .route("/api/pipe/:id/revisions", get(get_pipe_revisions))
+                .route("/api/pipe/:id/revisions/:revision", get(get_pipe_revision))
+                .route(
+                    "/api/pipe/:id/revisions/:revision/rollback",
+                    post(rollback_pipe_config),
+                )
+                .route("/api/pipe/:id/diff", get(get_pipe_diff))
+                .route("/api/pipe/:id/clients", get(get_pipe_clients))
+                .route("/api/pipe/:id/paused", put(put_pipe_paused))
+                .route("/api/clients/:client_id/pipes", put(put_client_pipes))
+                .route("/api/clients/:client_id/secrets", get(get_client_secrets))
+                .route(
+                    "/api/clients/:client_id/secrets/:name",
+                    put(put_client_secret).delete(delete_client_secret),
+                )
                 .route(
                     "/api/workspaces/:id",
                     get(get_workspace)
@@ -743,7 +1656,27 @@ async fn main() -> anyhow::Result<()> {
                     "/api/workspaces",
                     get(get_workspaces).post(create_workspace),
                 )
-                .route("/api/clients", get(get_clients)),
+                .route("/api/clients", get(get_clients))
+                .route("/api/sections", get(get_sections))
+                .route("/api/topics", get(get_topics))
+                .route("/api/topics/:topic", delete(purge_topic))
+                .route("/api/topics/:topic/consumers", get(get_consumers))
+                .route("/api/topics/:topic/query", post(query_topic))
+                .route("/api/topics/:topic/schemas", get(get_topic_schemas))
+                .route(
+                    "/api/topics/:topic/compatibility",
+                    get(get_topic_compatibility).put(put_topic_compatibility),
+                )
+                .route(
+                    "/api/topics/:topic/consumers/:consumer_id",
+                    delete(delete_consumer),
+                )
+                .route(
+                    "/api/topics/:topic/retention",
+                    get(get_topic_retention)
+                        .put(put_topic_retention)
+                        .delete(delete_topic_retention),
+                ),
         )
         .with_state(state.clone());
 
@@ -761,3 +1694,431 @@ async fn main() -> anyhow::Result<()> {
         .await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use arrow::array::Int64Array;
+    use arrow::datatypes::{DataType, Field};
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    async fn app(dir: &tempfile::TempDir, max_ingestion_size: u64) -> Arc<App> {
+        let database_url = format!("sqlite://{}", dir.path().join("db.sqlite").display());
+        let app = App::new(
+            database_url,
+            2,
+            "token",
+            max_ingestion_size,
+            None,
+            Compatibility::Backward,
+            60,
+            None,
+        )
+        .await
+        .unwrap();
+        Arc::new(app)
+    }
+
+    fn ipc_stream(batches: usize) -> Vec<u8> {
+        let schema = Arc::new(Schema::new(vec![Field::new("id", DataType::Int64, false)]));
+        let mut writer = StreamWriter::try_new(vec![], &schema).unwrap();
+        for i in 0..batches as i64 {
+            let batch = RecordBatch::try_new(
+                Arc::clone(&schema),
+                vec![Arc::new(Int64Array::from(vec![i, i + 1, i + 2]))],
+            )
+            .unwrap();
+            writer.write(&batch).unwrap();
+        }
+        writer.finish().unwrap();
+        writer.into_inner().unwrap()
+    }
+
+    async fn ingest(app: &Arc<App>, topic: &str, body: Body) -> StatusCode {
+        let router = Router::new()
+            .route("/ingestion/:topic", post(ingestion))
+            .with_state(Arc::clone(app));
+        let request = Request::post(format!("/ingestion/{topic}"))
+            .header("x-message-origin", "test")
+            .body(body)
+            .unwrap();
+        router.oneshot(request).await.unwrap().status()
+    }
+
+    /// Ingest message with producer message id, returns status and value of `x-message-duplicate` header
+    async fn ingest_message(
+        app: &Arc<App>,
+        topic: &str,
+        message_id: &str,
+        body: Body,
+    ) -> (StatusCode, Option<String>) {
+        let router = Router::new()
+            .route("/ingestion/:topic", post(ingestion))
+            .with_state(Arc::clone(app));
+        let request = Request::post(format!("/ingestion/{topic}"))
+            .header("x-message-origin", "test")
+            .header("x-producer-message-id", message_id)
+            .body(body)
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        let duplicate = response
+            .headers()
+            .get("x-message-duplicate")
+            .map(|value| value.to_str().unwrap().to_string());
+        (response.status(), duplicate)
+    }
+
+    async fn stored(app: &App, topic: &str) -> usize {
+        app.database
+            .get_records(topic, 0, 100, u64::MAX)
+            .await
+            .unwrap()
+            .len()
+    }
+
+    async fn query(app: &Arc<App>, topic: &str, start_offset: u64) -> (String, String, String) {
+        let router = Router::new()
+            .route("/api/topics/:topic/query", post(query_topic))
+            .with_state(Arc::clone(app));
+        let body =
+            json!({"sql": "SELECT count(*) AS count FROM topic", "start_offset": start_offset});
+        let request = Request::post(format!("/api/topics/{topic}/query"))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let get_header = |name| response.headers()[name].to_str().unwrap().to_string();
+        let end_offset = get_header("x-query-end-offset");
+        let truncated = get_header("x-query-truncated");
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (
+            end_offset,
+            truncated,
+            String::from_utf8(body.to_vec()).unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_query_truncated() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = app(&dir, 1 << 20).await;
+
+        let data = ipc_stream(1);
+        let mut ingestion = app.database.begin_ingestion("topic", "test").await.unwrap();
+        for _ in 0..=MAX_QUERY_RECORDS {
+            let record = NewRecord {
+                data: data.clone(),
+                size: data.len() as u64,
+                object_key: None,
+                schema_version: None,
+            };
+            ingestion.insert_record(&record).await.unwrap();
+        }
+        ingestion.commit().await.unwrap();
+
+        let (end_offset, truncated, body) = query(&app, "topic", 0).await;
+        assert_eq!(end_offset, MAX_QUERY_RECORDS.to_string());
+        assert_eq!(truncated, "true");
+        assert_eq!(body, format!(r#"[{{"count":{}}}]"#, MAX_QUERY_RECORDS * 3));
+
+        // rest of range
+        let (end_offset, truncated, body) = query(&app, "topic", MAX_QUERY_RECORDS).await;
+        assert_eq!(end_offset, (MAX_QUERY_RECORDS + 1).to_string());
+        assert_eq!(truncated, "false");
+        assert_eq!(body, r#"[{"count":3}]"#);
+    }
+
+    /// Register schema of topic origin in its own ingestion
+    async fn register_schema(
+        app: &App,
+        origin: &str,
+        schema: &Schema,
+    ) -> Result<u64, error::Error> {
+        let mut ingestion = app.database.begin_ingestion("topic", origin).await?;
+        let version = app
+            .register_schema(ingestion.as_mut(), "topic", origin, schema)
+            .await?;
+        ingestion.commit().await?;
+        Ok(version)
+    }
+
+    #[tokio::test]
+    async fn test_register_schema_per_origin() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = app(&dir, 1 << 20).await;
+
+        let ids = Schema::new(vec![Field::new("id", DataType::Int64, false)]);
+        let names = Schema::new(vec![Field::new("name", DataType::Utf8, false)]);
+        assert_eq!(register_schema(&app, "a", &ids).await.unwrap(), 1);
+        // other origin of the same topic has its own schema history
+        assert_eq!(register_schema(&app, "b", &names).await.unwrap(), 1);
+        assert_eq!(register_schema(&app, "a", &ids).await.unwrap(), 1);
+        // incompatible change of origin schema is rejected under backward compatibility
+        assert!(register_schema(&app, "a", &names).await.is_err());
+
+        let versions = app
+            .database
+            .get_schemas("topic")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|schema| (schema.origin, schema.version))
+            .collect::<Vec<_>>();
+        assert_eq!(versions, vec![("a".into(), 1), ("b".into(), 1)]);
+    }
+
+    #[tokio::test]
+    async fn test_ingest_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = app(&dir, 1 << 20).await;
+
+        // stream is delivered in small chunks to exercise incremental decoding
+        let chunks = ipc_stream(3)
+            .chunks(7)
+            .map(|chunk| Ok::<_, io::Error>(chunk.to_vec()))
+            .collect::<Vec<_>>();
+        let body = Body::wrap_stream(futures::stream::iter(chunks));
+        assert_eq!(ingest(&app, "topic", body).await, StatusCode::OK);
+        assert_eq!(stored(&app, "topic").await, 3);
+    }
+
+    #[tokio::test]
+    async fn test_ingest_too_large() {
+        let dir = tempfile::tempdir().unwrap();
+        let stream = ipc_stream(3);
+        let app = app(&dir, stream.len() as u64 - 1).await;
+
+        let status = ingest(&app, "topic", Body::from(stream)).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(stored(&app, "topic").await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_ingest_malformed() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = app(&dir, 1 << 20).await;
+
+        let status = ingest(&app, "topic", Body::from("not an arrow stream")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        // stream truncated in the middle of last batch, previous batches are not stored
+        let mut stream = ipc_stream(3);
+        stream.truncate(stream.len() - 20);
+        let status = ingest(&app, "topic", Body::from(stream)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(stored(&app, "topic").await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_ingest_duplicate() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = app(&dir, 1 << 20).await;
+
+        let res = ingest_message(&app, "topic", "p:1", Body::from(ipc_stream(2))).await;
+        assert_eq!(res, (StatusCode::OK, Some("false".into())));
+        let res = ingest_message(&app, "topic", "p:1", Body::from(ipc_stream(2))).await;
+        assert_eq!(res, (StatusCode::OK, Some("true".into())));
+        assert_eq!(stored(&app, "topic").await, 2);
+
+        // message ids are scoped by topic
+        let res = ingest_message(&app, "other", "p:1", Body::from(ipc_stream(2))).await;
+        assert_eq!(res, (StatusCode::OK, Some("false".into())));
+    }
+
+    #[tokio::test]
+    async fn test_ingest_duplicate_in_flight() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = app(&dir, 1 << 20).await;
+
+        // first request is still streaming, while retry with the same message id arrives
+        let (mut sender, body) = Body::channel();
+        let first = tokio::spawn({
+            let app = Arc::clone(&app);
+            async move { ingest_message(&app, "topic", "p:1", body).await }
+        });
+        let stream = ipc_stream(2);
+        let (head, tail) = stream.split_at(stream.len() / 2);
+        sender.send_data(head.to_vec().into()).await.unwrap();
+
+        // message id is claimed in transaction of ingestion, so retry waits until the other request is done
+        let retry = tokio::spawn({
+            let app = Arc::clone(&app);
+            let stream = stream.clone();
+            async move { ingest_message(&app, "topic", "p:1", Body::from(stream)).await }
+        });
+        sender.send_data(tail.to_vec().into()).await.unwrap();
+        drop(sender);
+
+        // either request can claim message id first, the other one is duplicate
+        let mut duplicates = vec![first.await.unwrap(), retry.await.unwrap()]
+            .into_iter()
+            .map(|(status, duplicate)| {
+                assert_eq!(status, StatusCode::OK);
+                duplicate.unwrap()
+            })
+            .collect::<Vec<_>>();
+        duplicates.sort();
+        assert_eq!(duplicates, vec!["false", "true"]);
+        assert_eq!(stored(&app, "topic").await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_ingest_failed_midway() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = app(&dir, 1 << 20).await;
+
+        // failed request doesn't register message id, so retry is stored
+        let mut stream = ipc_stream(3);
+        stream.truncate(stream.len() - 20);
+        let res = ingest_message(&app, "topic", "p:1", Body::from(stream)).await;
+        assert_eq!(res, (StatusCode::BAD_REQUEST, None));
+        assert_eq!(stored(&app, "topic").await, 0);
+        // schema of failed ingestion is not registered either
+        assert!(app.database.get_schemas("topic").await.unwrap().is_empty());
+
+        let res = ingest_message(&app, "topic", "p:1", Body::from(ipc_stream(3))).await;
+        assert_eq!(res, (StatusCode::OK, Some("false".into())));
+        assert_eq!(stored(&app, "topic").await, 3);
+    }
+
+    /// Post json to client provisioning route, returns response status
+    async fn post_client_json(app: &Arc<App>, path: &str, body: serde_json::Value) -> StatusCode {
+        let router = Router::new()
+            .route("/api/client", post(provision_client))
+            .route("/api/tokens", post(issue_token))
+            .with_state(Arc::clone(app));
+        let request = Request::post(path)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        router.oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_client_credential() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = app(&dir, 1 << 20).await;
+
+        let provision = |credential: &str| {
+            json!({
+                "client_config": {
+                    "node": {"display_name": "client", "unique_id": "client", "storage_path": ""}
+                },
+                "credential": credential,
+            })
+        };
+        let issue = |credential: &str| json!({"client_id": "client", "credential": credential});
+
+        // token is not issued to unknown client
+        assert_eq!(
+            post_client_json(&app, "/api/tokens", issue("secret")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            post_client_json(&app, "/api/client", provision("")).await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            post_client_json(&app, "/api/client", provision("secret")).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            post_client_json(&app, "/api/client", provision("secret")).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            post_client_json(&app, "/api/client", provision("other")).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            post_client_json(&app, "/api/tokens", issue("other")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            post_client_json(&app, "/api/tokens", issue("secret")).await,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pipe_paused() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = app(&dir, 1 << 20).await;
+
+        let workspace = serde_json::from_value(json!({"name": "workspace"})).unwrap();
+        let workspace = app.database.create_workspace(workspace).await.unwrap();
+        let pipe = json!([{"name": "source"}, {"name": "destination"}]);
+        let id = app
+            .database
+            .insert_config(&pipe, workspace.id, "test")
+            .await
+            .unwrap();
+
+        let put_paused = |id: u64, paused: bool| {
+            let router = Router::new()
+                .route("/api/pipe/:id/paused", put(put_pipe_paused))
+                .with_state(Arc::clone(&app));
+            let request = Request::put(format!("/api/pipe/{id}/paused"))
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(json!({ "paused": paused }).to_string()))
+                .unwrap();
+            async move { router.oneshot(request).await.unwrap().status() }
+        };
+        let paused = |app: Arc<App>| async move {
+            let configs = app.get_configs().await.unwrap().configs;
+            assert_eq!(configs.len(), 1);
+            // pause doesn't create new revision of pipe config
+            assert_eq!(configs[0].revision, 1);
+            configs[0].paused
+        };
+
+        assert!(!paused(Arc::clone(&app)).await);
+        assert_eq!(put_paused(id, true).await, StatusCode::OK);
+        assert!(paused(Arc::clone(&app)).await);
+        assert_eq!(put_paused(id, false).await, StatusCode::OK);
+        assert!(!paused(Arc::clone(&app)).await);
+        assert_eq!(put_paused(id + 1, true).await, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_registered_section_schemas() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = app(&dir, 1 << 20).await;
+
+        let schema = |description| -> SectionSchema {
+            serde_json::from_value(json!({
+                "name": "source",
+                "description": description,
+                "fields": [{"name": "path", "type": "string", "required": true}]
+            }))
+            .unwrap()
+        };
+        for (client_id, description) in [("b", "second"), ("a", "first")] {
+            app.database
+                .insert_client(
+                    client_id,
+                    client_id,
+                    &[],
+                    &[],
+                    &[schema(description)],
+                    "hash",
+                )
+                .await
+                .unwrap();
+        }
+        let schemas = app.section_schemas().await.unwrap();
+        assert_eq!(schemas.len(), 1);
+        assert_eq!(schemas[0].description, "first");
+
+        let valid = json!([{"name": "source", "path": "/tmp/path"}]);
+        assert!(App::validate_config(&valid, &schemas).is_ok());
+        for invalid in [json!([{"name": "source"}]), json!([{"name": "unknown"}])] {
+            assert!(matches!(
+                App::validate_config(&invalid, &schemas),
+                Err(error::Error::Validation(_))
+            ));
+        }
+    }
+}
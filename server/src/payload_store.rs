@@ -0,0 +1,80 @@
+//! Record payload store
+//!
+//! If configured, payloads of ingested records are written to filesystem directory or S3 compatible bucket,
+//! while database keeps only record metadata and object key.
+//!
+//! Store is configured by url:
+//! - `file:///path/to/dir`
+//! - `s3://bucket/optional/prefix`, credentials, region and endpoint are picked up from `AWS_*` environment
+//!   variables, e.g. `AWS_ENDPOINT=http://localhost:9000` and `AWS_ALLOW_HTTP=true` for local MinIO.
+
+use crate::error;
+use axum::body::Bytes;
+use futures::stream::BoxStream;
+use object_store::{aws::AmazonS3Builder, local::LocalFileSystem, path::Path, ObjectStore};
+use std::sync::Arc;
+use uuid::Uuid;
+
+#[derive(Debug)]
+pub struct PayloadStore {
+    store: Arc<dyn ObjectStore>,
+    prefix: String,
+}
+
+impl PayloadStore {
+    pub async fn new(url: &str) -> Result<Self, error::Error> {
+        let (store, prefix): (Arc<dyn ObjectStore>, &str) = match url.split_once("://") {
+            Some(("file", path)) => {
+                tokio::fs::create_dir_all(path).await?;
+                (Arc::new(LocalFileSystem::new_with_prefix(path)?), "")
+            }
+            Some(("s3", path)) => {
+                let (bucket, prefix) = path.split_once('/').unwrap_or((path, ""));
+                let store = AmazonS3Builder::from_env()
+                    .with_bucket_name(bucket)
+                    .build()?;
+                (Arc::new(store), prefix)
+            }
+            _ => Err("unsupported payload store url, expected file:// or s3://")?,
+        };
+        Ok(Self {
+            store,
+            prefix: prefix.trim_matches('/').to_string(),
+        })
+    }
+
+    /// Write payload of topic record, returns object key
+    pub async fn put(&self, topic: &str, payload: Vec<u8>) -> Result<String, error::Error> {
+        let key = match self.prefix.as_str() {
+            "" => format!("{topic}/{}.arrow", Uuid::new_v4()),
+            prefix => format!("{prefix}/{topic}/{}.arrow", Uuid::new_v4()),
+        };
+        self.store
+            .put(&Path::from(key.as_str()), payload.into())
+            .await?;
+        Ok(key)
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Vec<u8>, error::Error> {
+        let bytes = self.store.get(&Path::from(key)).await?.bytes().await?;
+        Ok(bytes.to_vec())
+    }
+
+    pub async fn get_stream(
+        &self,
+        key: &str,
+    ) -> Result<BoxStream<'static, object_store::Result<Bytes>>, error::Error> {
+        Ok(self.store.get(&Path::from(key)).await?.into_stream())
+    }
+
+    /// Delete payloads, missing objects are ignored
+    pub async fn delete(&self, keys: &[String]) -> Result<(), error::Error> {
+        for key in keys {
+            match self.store.delete(&Path::from(key.as_str())).await {
+                Ok(_) | Err(object_store::Error::NotFound { .. }) => (),
+                Err(e) => Err(e)?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+//! SQL queries over topic records
+//!
+//! Record batches of topic are registered as in-memory `topic` table and queried with DataFusion.
+//! Only queries are allowed, DDL, DML and statements are rejected.
+
+use crate::error;
+use arrow::record_batch::RecordBatch;
+use axum::http::StatusCode;
+use datafusion::datasource::MemTable;
+use datafusion::error::DataFusionError;
+use datafusion::logical_expr::LogicalPlan;
+use datafusion::prelude::SessionContext;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Name of table, under which topic records are available to queries
+pub const TABLE_NAME: &str = "topic";
+
+/// Run SQL query over record batches, which are expected to share schema
+///
+/// Returns at most `limit` rows.
+pub async fn run(
+    batches: Vec<RecordBatch>,
+    sql: &str,
+    limit: usize,
+    timeout: Duration,
+) -> Result<Vec<RecordBatch>, error::Error> {
+    let schema = match batches.first() {
+        Some(batch) => batch.schema(),
+        None => Err(client_error("topic has no records in given offset range"))?,
+    };
+    if batches.iter().any(|batch| batch.schema() != schema) {
+        Err(client_error(
+            "records in given offset range have different schemas, narrow offset range",
+        ))?
+    }
+
+    let ctx = SessionContext::new();
+    let table = MemTable::try_new(schema, vec![batches]).map_err(query_error)?;
+    ctx.register_table(TABLE_NAME, Arc::new(table))
+        .map_err(query_error)?;
+
+    let plan = ctx
+        .state()
+        .create_logical_plan(sql)
+        .await
+        .map_err(query_error)?;
+    if matches!(
+        plan,
+        LogicalPlan::Ddl(_) | LogicalPlan::Dml(_) | LogicalPlan::Statement(_)
+    ) {
+        Err(client_error("only queries are allowed"))?
+    }
+
+    let query = async {
+        ctx.execute_logical_plan(plan)
+            .await?
+            .limit(0, Some(limit))?
+            .collect()
+            .await
+    };
+    match tokio::time::timeout(timeout, query).await {
+        Ok(res) => res.map_err(query_error),
+        Err(_) => Err(error::Error::ClientError(
+            StatusCode::REQUEST_TIMEOUT,
+            format!("query timed out after {} seconds", timeout.as_secs()),
+        )),
+    }
+}
+
+fn client_error(msg: &str) -> error::Error {
+    error::Error::ClientError(StatusCode::BAD_REQUEST, msg.into())
+}
+
+fn query_error(e: DataFusionError) -> error::Error {
+    error::Error::ClientError(StatusCode::BAD_REQUEST, format!("query failed: {e}"))
+}
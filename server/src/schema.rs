@@ -0,0 +1,234 @@
+//! Topic schema registry
+//!
+//! Server keeps history of schemas per topic origin. Schema of incoming record batch is checked against latest schema
+//! of its origin, new schema version is registered if schema changed and is compatible according to compatibility
+//! mode of topic:
+//! - `none` - any schema change is accepted
+//! - `backward` - consumers using new schema can read records written with previous schema: fields can be removed,
+//!   added fields should be nullable
+//! - `forward` - consumers using previous schema can read records written with new schema: fields can be added,
+//!   removed fields should be nullable
+//! - `full` - both backward and forward
+//!
+//! In all modes but `none` types of existing fields can't change.
+
+use crate::error;
+use arrow::datatypes::Schema;
+use arrow::ipc::{reader::StreamReader, writer::StreamWriter};
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::str::FromStr;
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compatibility {
+    #[default]
+    None,
+    Backward,
+    Forward,
+    Full,
+}
+
+impl FromStr for Compatibility {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "backward" => Ok(Self::Backward),
+            "forward" => Ok(Self::Forward),
+            "full" => Ok(Self::Full),
+            _ => Err("compatibility should be one of: none, backward, forward, full"),
+        }
+    }
+}
+
+impl Display for Compatibility {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mode = match self {
+            Self::None => "none",
+            Self::Backward => "backward",
+            Self::Forward => "forward",
+            Self::Full => "full",
+        };
+        write!(f, "{mode}")
+    }
+}
+
+impl Compatibility {
+    /// Check if new schema is compatible with previous schema
+    pub fn check(&self, previous: &Schema, new: &Schema) -> Result<(), String> {
+        match self {
+            Self::None => Ok(()),
+            Self::Backward => can_read(new, previous),
+            Self::Forward => can_read(previous, new),
+            Self::Full => can_read(new, previous).and_then(|_| can_read(previous, new)),
+        }
+    }
+}
+
+/// Check if data, written with `writer` schema, can be read with `reader` schema
+fn can_read(reader: &Schema, writer: &Schema) -> Result<(), String> {
+    for field in reader.fields().iter() {
+        match writer.field_with_name(field.name()) {
+            Ok(written) if written.data_type() != field.data_type() => Err(format!(
+                "type of field '{}' changed from {} to {}",
+                field.name(),
+                written.data_type(),
+                field.data_type()
+            ))?,
+            Ok(written) if written.is_nullable() && !field.is_nullable() => Err(format!(
+                "field '{}' is nullable, but expected to be non nullable",
+                field.name()
+            ))?,
+            Ok(_) => (),
+            Err(_) if field.is_nullable() => (),
+            Err(_) => Err(format!("non nullable field '{}' is missing", field.name()))?,
+        }
+    }
+    Ok(())
+}
+
+/// Encode schema as arrow ipc stream without record batches
+pub fn encode(schema: &Schema) -> Result<Vec<u8>, error::Error> {
+    let mut writer = StreamWriter::try_new(vec![], schema)?;
+    writer.finish()?;
+    Ok(writer.into_inner()?)
+}
+
+pub fn decode(data: &[u8]) -> Result<Schema, error::Error> {
+    let reader = StreamReader::try_new(data, None)?;
+    Ok(reader.schema().as_ref().clone())
+}
+
+/// JSON representation of schema field
+#[derive(Serialize, Debug)]
+pub struct FieldInfo {
+    name: String,
+    data_type: String,
+    nullable: bool,
+}
+
+/// JSON representation of schema version
+#[derive(Serialize, Debug)]
+pub struct SchemaInfo {
+    origin: String,
+    version: u64,
+    fields: Vec<FieldInfo>,
+    created_at: String,
+}
+
+impl SchemaInfo {
+    pub fn new(origin: String, version: u64, schema: &Schema, created_at: String) -> Self {
+        Self {
+            origin,
+            version,
+            fields: schema
+                .fields()
+                .iter()
+                .map(|field| FieldInfo {
+                    name: field.name().clone(),
+                    data_type: field.data_type().to_string(),
+                    nullable: field.is_nullable(),
+                })
+                .collect(),
+            created_at,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use arrow::datatypes::{DataType, Field};
+
+    fn schema(fields: &[(&str, DataType, bool)]) -> Schema {
+        Schema::new(
+            fields
+                .iter()
+                .map(|(name, data_type, nullable)| Field::new(*name, data_type.clone(), *nullable))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn test_can_read_same_schema() {
+        let schema = schema(&[
+            ("id", DataType::Int64, false),
+            ("name", DataType::Utf8, true),
+        ]);
+        assert!(can_read(&schema, &schema).is_ok());
+    }
+
+    #[test]
+    fn test_can_read_missing_field() {
+        let writer = schema(&[("id", DataType::Int64, false)]);
+        let nullable = schema(&[
+            ("id", DataType::Int64, false),
+            ("name", DataType::Utf8, true),
+        ]);
+        let non_nullable = schema(&[
+            ("id", DataType::Int64, false),
+            ("name", DataType::Utf8, false),
+        ]);
+        assert!(can_read(&nullable, &writer).is_ok());
+        assert_eq!(
+            can_read(&non_nullable, &writer),
+            Err("non nullable field 'name' is missing".into())
+        );
+    }
+
+    #[test]
+    fn test_can_read_extra_field() {
+        // fields, which reader doesn't know about, are ignored
+        let reader = schema(&[("id", DataType::Int64, false)]);
+        let writer = schema(&[
+            ("id", DataType::Int64, false),
+            ("name", DataType::Utf8, false),
+        ]);
+        assert!(can_read(&reader, &writer).is_ok());
+    }
+
+    #[test]
+    fn test_can_read_type_change() {
+        let reader = schema(&[("id", DataType::Utf8, false)]);
+        let writer = schema(&[("id", DataType::Int64, false)]);
+        assert_eq!(
+            can_read(&reader, &writer),
+            Err("type of field 'id' changed from Int64 to Utf8".into())
+        );
+    }
+
+    #[test]
+    fn test_can_read_nullability() {
+        let nullable = schema(&[("id", DataType::Int64, true)]);
+        let non_nullable = schema(&[("id", DataType::Int64, false)]);
+        assert!(can_read(&nullable, &non_nullable).is_ok());
+        assert_eq!(
+            can_read(&non_nullable, &nullable),
+            Err("field 'id' is nullable, but expected to be non nullable".into())
+        );
+    }
+
+    #[test]
+    fn test_compatibility_check() {
+        let previous = schema(&[("id", DataType::Int64, false)]);
+        let added = schema(&[
+            ("id", DataType::Int64, false),
+            ("name", DataType::Utf8, true),
+        ]);
+        let removed = schema(&[]);
+        for mode in [
+            Compatibility::None,
+            Compatibility::Backward,
+            Compatibility::Forward,
+            Compatibility::Full,
+        ] {
+            assert!(mode.check(&previous, &added).is_ok(), "{mode}");
+        }
+        assert!(Compatibility::None.check(&previous, &removed).is_ok());
+        assert!(Compatibility::Backward.check(&previous, &removed).is_ok());
+        assert!(Compatibility::Forward.check(&previous, &removed).is_err());
+        assert!(Compatibility::Full.check(&previous, &removed).is_err());
+    }
+}
@@ -0,0 +1,388 @@
+//! Server storage
+//!
+//! Control plane (clients, tokens, pipes, workspaces) and topic records are stored behind [`Storage`] trait.
+//! Backend is picked by scheme of database url: `sqlite://` or `postgres://`.
+
+pub mod postgres;
+pub mod sqlite;
+
+use crate::{
+    error, Clients, Consumer, Destination, PipeClient, PipeConfig, PipeConfigs, PipeRevision,
+    RetentionPolicy, RunningPipe, Source, Topic, Workspace,
+};
+use pipe_config::config_schema::SectionSchema;
+
+/// Record stored in topic
+///
+/// Payload of record is either stored inline in `data`, or offloaded to payload store under `object_key`.
+#[derive(Debug)]
+pub(crate) struct StoredRecord {
+    pub id: u64,
+    pub origin: String,
+    pub data: Vec<u8>,
+    pub object_key: Option<String>,
+    pub schema_version: Option<u64>,
+}
+
+/// Record to be stored in topic
+///
+/// If payload is offloaded - `data` is empty and `object_key` points to payload.
+#[derive(Debug)]
+pub(crate) struct NewRecord {
+    pub data: Vec<u8>,
+    pub size: u64,
+    pub object_key: Option<String>,
+    pub schema_version: Option<u64>,
+}
+
+/// Schema version of topic origin, schema is encoded as arrow ipc stream without record batches
+#[derive(Debug)]
+pub(crate) struct StoredSchema {
+    pub origin: String,
+    pub version: u64,
+    pub schema: Vec<u8>,
+    pub created_at: String,
+}
+
+/// Client secret, value is encrypted
+#[derive(Debug)]
+pub(crate) struct StoredSecret {
+    pub name: String,
+    pub value: Vec<u8>,
+    pub updated_at: String,
+}
+
+/// Ingestion of records from origin into topic
+///
+/// Message id, schema versions and records of ingestion are stored in single transaction: either all of them
+/// are stored on [`Ingestion::commit`] or none, dropped ingestion is rolled back.
+#[async_trait::async_trait]
+pub(crate) trait Ingestion: Send {
+    /// Register message id, returns false if it was already registered within last `window` seconds
+    ///
+    /// Stale message id is re-registered. Concurrent ingestion with the same message id waits for this one, so
+    /// message id is never observed as registered, while its records are not committed yet.
+    async fn claim_message_id(
+        &mut self,
+        message_id: &str,
+        window: u64,
+    ) -> Result<bool, error::Error>;
+
+    /// Latest schema of origin
+    async fn get_latest_schema(&mut self) -> Result<Option<StoredSchema>, error::Error>;
+
+    /// Insert schema version of origin, returns false if version is already registered
+    async fn insert_schema(&mut self, version: u64, schema: &[u8]) -> Result<bool, error::Error>;
+
+    async fn insert_record(&mut self, record: &NewRecord) -> Result<(), error::Error>;
+
+    async fn commit(self: Box<Self>) -> Result<(), error::Error>;
+}
+
+#[async_trait::async_trait]
+pub(crate) trait Storage: std::fmt::Debug + Send + Sync {
+    /// Insert or update client, returns false if client is already provisioned with different credential
+    async fn insert_client(
+        &self,
+        client_id: &str,
+        display_name: &str,
+        sources: &[Source],
+        destinations: &[Destination],
+        section_schemas: &[SectionSchema],
+        credential_hash: &str,
+    ) -> Result<bool, error::Error>;
+
+    /// Hash of credential of client, clients provisioned before credentials were introduced have none
+    async fn get_client_credential(&self, client_id: &str) -> Result<Option<String>, error::Error>;
+
+    /// Section schemas registered by all clients, the same section can be registered by multiple clients
+    async fn get_section_schemas(&self) -> Result<Vec<SectionSchema>, error::Error>;
+
+    async fn insert_token(&self, client_id: &str, token: &str) -> Result<(), error::Error>;
+
+    /// Id of client, which was issued token
+    async fn get_token_client(&self, token: &str) -> Result<Option<String>, error::Error>;
+
+    async fn get_client_secrets(&self, client_id: &str) -> Result<Vec<StoredSecret>, error::Error>;
+
+    async fn set_client_secret(
+        &self,
+        client_id: &str,
+        name: &str,
+        value: &[u8],
+    ) -> Result<(), error::Error>;
+
+    /// Delete client secret, returns false if secret doesn't exist
+    async fn delete_client_secret(&self, client_id: &str, name: &str)
+        -> Result<bool, error::Error>;
+
+    /// Pause or resume pipe, returns false if pipe doesn't exist
+    async fn set_pipe_paused(&self, id: u64, paused: bool) -> Result<bool, error::Error>;
+
+    /// Insert pipe config, config is stored as first revision
+    async fn insert_config(
+        &self,
+        config: &serde_json::Value,
+        workspace_id: i64,
+        author: &str,
+    ) -> Result<u64, error::Error>;
+
+    /// Store pipe config as new revision, returns current revision or None if pipe doesn't exist
+    ///
+    /// Revision is not bumped if config didn't change.
+    async fn update_config(
+        &self,
+        id: u64,
+        config: &serde_json::Value,
+        author: &str,
+    ) -> Result<Option<u64>, error::Error>;
+
+    async fn get_config_revisions(&self, id: u64) -> Result<Vec<PipeRevision>, error::Error>;
+
+    async fn get_config_revision(
+        &self,
+        id: u64,
+        revision: u64,
+    ) -> Result<Option<PipeRevision>, error::Error>;
+
+    /// Replace pipe config revisions reported by client
+    async fn set_client_pipes(
+        &self,
+        client_id: &str,
+        pipes: &[RunningPipe],
+    ) -> Result<(), error::Error>;
+
+    /// Clients running pipe and revisions they run
+    async fn get_pipe_clients(&self, id: u64) -> Result<Vec<PipeClient>, error::Error>;
+
+    async fn delete_config(&self, id: u64) -> Result<(), error::Error>;
+
+    async fn get_config(&self, id: u64) -> Result<PipeConfig, error::Error>;
+
+    async fn get_configs(&self) -> Result<PipeConfigs, error::Error>;
+
+    async fn get_clients(&self) -> Result<Clients, error::Error>;
+
+    async fn get_workspaces(&self) -> Result<Vec<Workspace>, error::Error>;
+
+    async fn create_workspace(&self, workspace: Workspace) -> Result<Workspace, error::Error>;
+
+    async fn get_workspace(&self, id: u64) -> Result<Workspace, error::Error>;
+
+    async fn update_workspace(&self, workspace: Workspace) -> Result<Workspace, error::Error>;
+
+    async fn delete_workspace(&self, id: u64) -> Result<(), error::Error>;
+
+    /// Begin ingestion of records from origin into topic
+    async fn begin_ingestion(
+        &self,
+        topic: &str,
+        origin: &str,
+    ) -> Result<Box<dyn Ingestion>, error::Error>;
+
+    /// Get up to `limit` records past offset, with total size of at most `max_bytes`
+    ///
+    /// First record is always returned, regardless of its size.
+    async fn get_records(
+        &self,
+        topic: &str,
+        offset: u64,
+        limit: u64,
+        max_bytes: u64,
+    ) -> Result<Vec<StoredRecord>, error::Error>;
+
+    async fn get_consumer_offset(
+        &self,
+        topic: &str,
+        consumer_id: &str,
+    ) -> Result<Option<u64>, error::Error>;
+
+    async fn commit_consumer_offset(
+        &self,
+        topic: &str,
+        consumer_id: &str,
+        offset: u64,
+    ) -> Result<(), error::Error>;
+
+    async fn delete_consumer(&self, topic: &str, consumer_id: &str) -> Result<(), error::Error>;
+
+    /// Consumers of topic with their committed offsets and lag
+    async fn get_consumers(&self, topic: &str) -> Result<Vec<Consumer>, error::Error>;
+
+    async fn get_topics(&self) -> Result<Vec<Topic>, error::Error>;
+
+    /// Delete all records of topic, returns object keys of deleted records
+    async fn purge_topic(&self, topic: &str) -> Result<Vec<String>, error::Error>;
+
+    async fn get_retention_policy(
+        &self,
+        topic: &str,
+    ) -> Result<Option<RetentionPolicy>, error::Error>;
+
+    async fn set_retention_policy(
+        &self,
+        topic: &str,
+        policy: &RetentionPolicy,
+    ) -> Result<(), error::Error>;
+
+    async fn delete_retention_policy(&self, topic: &str) -> Result<(), error::Error>;
+
+    /// Schemas of all origins of topic
+    async fn get_schemas(&self, topic: &str) -> Result<Vec<StoredSchema>, error::Error>;
+
+    async fn get_compatibility(&self, topic: &str) -> Result<Option<String>, error::Error>;
+
+    async fn set_compatibility(&self, topic: &str, mode: &str) -> Result<(), error::Error>;
+
+    /// Delete message ids registered more than `window` seconds ago
+    async fn prune_message_ids(&self, window: u64) -> Result<u64, error::Error>;
+
+    /// Delete records which fall out of topic retention policies
+    ///
+    /// Returns number of deleted records and object keys of deleted records.
+    async fn enforce_retention(&self) -> Result<(u64, Vec<String>), error::Error>;
+
+    /// Enable incremental reclaiming of space freed by deleted records
+    ///
+    /// Might require full rewrite of database, which blocks all other database access while running.
+    async fn enable_incremental_vacuum(&self) -> Result<(), error::Error>;
+}
+
+/// Connect to storage by database url
+pub(crate) async fn connect(
+    database_url: &str,
+    max_connections: u32,
+) -> Result<Box<dyn Storage>, error::Error> {
+    let storage: Box<dyn Storage> = match database_url.split_once("://") {
+        Some(("sqlite", path)) => {
+            Box::new(sqlite::SqliteStorage::new(path, max_connections).await?)
+        }
+        Some(("postgres", _)) | Some(("postgresql", _)) => {
+            Box::new(postgres::PostgresStorage::new(database_url, max_connections).await?)
+        }
+        _ => Err("unsupported database url, expected sqlite:// or postgres://")?,
+    };
+    Ok(storage)
+}
+
+#[cfg(test)]
+mod test {
+    use sqlx::migrate::Migrator;
+    use sqlx::{Connection, Row, SqliteConnection};
+    use std::collections::BTreeSet;
+
+    /// Tables, columns and indexes of database, column types and defaults are normalized to sqlite
+    async fn schema(conn: &mut SqliteConnection) -> BTreeSet<String> {
+        let normalize_type = |ty: String| match ty.to_uppercase().as_str() {
+            "BIGINT" | "BIGSERIAL" => "INTEGER".to_string(),
+            "BYTEA" => "BLOB".to_string(),
+            "JSONB" => "TEXT".to_string(),
+            ty => ty.to_string(),
+        };
+        let normalize_default = |default: Option<String>| match default.as_deref() {
+            Some("FALSE") => Some("0".to_string()),
+            Some("TRUE") => Some("1".to_string()),
+            _ => default,
+        };
+        let mut schema = BTreeSet::new();
+        let tables = sqlx::query(
+            "SELECT name FROM sqlite_master WHERE type = 'table' \
+            AND name NOT LIKE 'sqlite_%' AND name != '_sqlx_migrations'",
+        )
+        .fetch_all(&mut *conn)
+        .await
+        .unwrap();
+        for table in tables {
+            let table: String = table.get("name");
+            let columns = sqlx::query(
+                "SELECT name, type, \"notnull\", dflt_value, pk FROM pragma_table_info(?)",
+            )
+            .bind(&table)
+            .fetch_all(&mut *conn)
+            .await
+            .unwrap();
+            for column in columns {
+                let name: String = column.get("name");
+                let pk: i64 = column.get("pk");
+                // primary key columns are implicitly not null in postgres
+                let not_null = column.get::<bool, _>("notnull") || pk > 0;
+                schema.insert(format!(
+                    "column {table}.{name} {} not_null={not_null} default={:?} pk={pk}",
+                    normalize_type(column.get("type")),
+                    normalize_default(column.get("dflt_value")),
+                ));
+            }
+        }
+        let indexes = sqlx::query(
+            "SELECT name, tbl_name FROM sqlite_master WHERE type = 'index' AND sql IS NOT NULL",
+        )
+        .fetch_all(&mut *conn)
+        .await
+        .unwrap();
+        for index in indexes {
+            let name: String = index.get("name");
+            let table: String = index.get("tbl_name");
+            schema.insert(format!("index {name} on {table}"));
+        }
+        schema
+    }
+
+    /// Postgres migrations start from single baseline migration, which mirrors sqlite schema at that point,
+    /// every later sqlite migration should have postgres counterpart with the same version and description.
+    #[test]
+    fn test_migrations_in_sync() {
+        let sqlite: Migrator = sqlx::migrate!();
+        let postgres: Migrator = sqlx::migrate!("./postgres_migrations");
+        let baseline = postgres.iter().next().unwrap().version;
+        let versions = |migrator: &Migrator| {
+            migrator
+                .iter()
+                .filter(|migration| migration.version > baseline)
+                .map(|migration| (migration.version, migration.description.to_string()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(versions(&sqlite), versions(&postgres));
+    }
+
+    /// Postgres migrations are applied to sqlite, since there is no postgres server in tests: postgres column
+    /// types are mapped to sqlite counterparts, and `SELECT` statements, which only adjust postgres sequences,
+    /// are skipped.
+    #[tokio::test]
+    async fn test_migrations_schema_in_sync() {
+        let mut sqlite = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        sqlx::migrate!().run(&mut sqlite).await.unwrap();
+
+        let mut postgres = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        let migrator: Migrator = sqlx::migrate!("./postgres_migrations");
+        for migration in migrator.iter() {
+            let sql = migration
+                .sql
+                .lines()
+                .filter(|line| !line.trim_start().starts_with("--"))
+                .collect::<Vec<_>>()
+                .join("\n");
+            for statement in sql.split(';').map(str::trim) {
+                if statement.is_empty() || statement.to_uppercase().starts_with("SELECT") {
+                    continue;
+                }
+                sqlx::query(statement)
+                    .execute(&mut postgres)
+                    .await
+                    .unwrap_or_else(|e| panic!("{}: {e}", migration.description));
+            }
+        }
+
+        let sqlite = schema(&mut sqlite).await;
+        let postgres = schema(&mut postgres).await;
+        assert_eq!(
+            sqlite.difference(&postgres).collect::<Vec<_>>(),
+            Vec::<&String>::new(),
+            "missing in postgres migrations"
+        );
+        assert_eq!(
+            postgres.difference(&sqlite).collect::<Vec<_>>(),
+            Vec::<&String>::new(),
+            "missing in sqlite migrations"
+        );
+    }
+}
@@ -0,0 +1,902 @@
+//! SQLite storage
+//!
+//! Connections are pooled and database is opened in WAL mode, so readers are not blocked by writers.
+
+use super::{Ingestion, NewRecord, Storage, StoredRecord, StoredSchema, StoredSecret};
+use crate::{
+    error, Client, Clients, Consumer, Destination, PipeClient, PipeConfig, PipeConfigs,
+    PipeRevision, RetentionPolicy, RunningPipe, Source, Topic, Workspace,
+};
+use chrono::Utc;
+use pipe_config::config_schema::SectionSchema;
+use sqlx::sqlite::{
+    SqliteAutoVacuum, SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqlitePoolOptions,
+    SqliteRow,
+};
+use sqlx::{FromRow, Row, Sqlite, Transaction};
+use std::path::Path;
+use std::time::Duration;
+
+/// Value of `PRAGMA auto_vacuum` for incremental mode
+const AUTO_VACUUM_INCREMENTAL: i64 = 2;
+
+#[derive(Debug)]
+pub struct SqliteStorage {
+    pool: SqlitePool,
+}
+
+impl FromRow<'_, SqliteRow> for Workspace {
+    fn from_row(row: &SqliteRow) -> sqlx::Result<Self> {
+        Ok(Self {
+            id: row.get("id"),
+            name: row.get("name"),
+            created_at: Utc::now(),
+            // created_at: row.get("created_at"),
+            pipe_configs: Vec::new(),
+        })
+    }
+}
+
+impl SqliteStorage {
+    pub async fn new(database_path: &str, max_connections: u32) -> Result<Self, error::Error> {
+        if let Some(parent) = Path::new(database_path).parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        };
+        let options = SqliteConnectOptions::new()
+            .filename(database_path)
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            // takes effect only on new database, existing database needs explicit conversion
+            .auto_vacuum(SqliteAutoVacuum::Incremental)
+            .busy_timeout(Duration::from_secs(5));
+        let pool = SqlitePoolOptions::new()
+            .max_connections(max_connections)
+            .connect_with(options)
+            .await?;
+        sqlx::migrate!().run(&pool).await?;
+
+        // incremental vacuum allows to reclaim space freed by retention without full vacuum
+        let auto_vacuum: i64 = sqlx::query_scalar("PRAGMA auto_vacuum")
+            .fetch_one(&pool)
+            .await?;
+        if auto_vacuum != AUTO_VACUUM_INCREMENTAL {
+            log::warn!(
+                "incremental vacuum is not enabled for database '{database_path}', \
+                space freed by deleted records is not reclaimed, run server with --enable-incremental-vacuum to convert database"
+            );
+        }
+        Ok(Self { pool })
+    }
+}
+
+/// Ingestion in single transaction
+///
+/// SQLite has single writer, so concurrent ingestions wait for each other once message id is claimed or
+/// first record is inserted.
+struct SqliteIngestion {
+    transaction: Transaction<'static, Sqlite>,
+    topic: String,
+    origin: String,
+}
+
+#[async_trait::async_trait]
+impl Ingestion for SqliteIngestion {
+    async fn claim_message_id(
+        &mut self,
+        message_id: &str,
+        window: u64,
+    ) -> Result<bool, error::Error> {
+        let claimed = sqlx::query(
+            "INSERT INTO ingested_messages (topic, message_id) VALUES (?, ?) \
+            ON CONFLICT (topic, message_id) DO UPDATE SET created_at = CURRENT_TIMESTAMP \
+            WHERE ingested_messages.created_at < datetime('now', ?)",
+        )
+        .bind(self.topic.as_str())
+        .bind(message_id)
+        .bind(format!("-{window} seconds"))
+        .execute(&mut *self.transaction)
+        .await?
+        .rows_affected();
+        Ok(claimed > 0)
+    }
+
+    async fn get_latest_schema(&mut self) -> Result<Option<StoredSchema>, error::Error> {
+        let row = sqlx::query(
+            "SELECT origin, version, schema, CAST(created_at AS TEXT) AS created_at \
+            FROM topic_schemas WHERE topic = ? AND origin = ? ORDER BY version DESC LIMIT 1",
+        )
+        .bind(self.topic.as_str())
+        .bind(self.origin.as_str())
+        .fetch_optional(&mut *self.transaction)
+        .await?;
+        Ok(row.map(|row| StoredSchema {
+            origin: row.get("origin"),
+            version: row.get::<i64, &str>("version") as u64,
+            schema: row.get("schema"),
+            created_at: row.get("created_at"),
+        }))
+    }
+
+    async fn insert_schema(&mut self, version: u64, schema: &[u8]) -> Result<bool, error::Error> {
+        let inserted = sqlx::query(
+            "INSERT INTO topic_schemas (topic, origin, version, schema) VALUES (?, ?, ?, ?) \
+            ON CONFLICT (topic, origin, version) DO NOTHING",
+        )
+        .bind(self.topic.as_str())
+        .bind(self.origin.as_str())
+        .bind(version as i64)
+        .bind(schema)
+        .execute(&mut *self.transaction)
+        .await?
+        .rows_affected();
+        Ok(inserted > 0)
+    }
+
+    async fn insert_record(&mut self, record: &NewRecord) -> Result<(), error::Error> {
+        let size: i64 = record.size.try_into().unwrap_or(i64::MAX);
+        let schema_version: Option<i64> = record.schema_version.map(|x| x as i64);
+        sqlx::query(
+            "INSERT INTO records (topic, origin, data, size, object_key, schema_version) \
+            VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(self.topic.as_str())
+        .bind(self.origin.as_str())
+        .bind(record.data.as_slice())
+        .bind(size)
+        .bind(record.object_key.as_deref())
+        .bind(schema_version)
+        .execute(&mut *self.transaction)
+        .await?;
+        Ok(())
+    }
+
+    async fn commit(self: Box<Self>) -> Result<(), error::Error> {
+        self.transaction.commit().await?;
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Storage for SqliteStorage {
+    async fn insert_client(
+        &self,
+        client_id: &str,
+        display_name: &str,
+        sources: &[Source],
+        destinations: &[Destination],
+        section_schemas: &[SectionSchema],
+        credential_hash: &str,
+    ) -> Result<bool, error::Error> {
+        let sources = serde_json::to_string(sources)?;
+        let destinations = serde_json::to_string(destinations)?;
+        let section_schemas = serde_json::to_string(section_schemas)?;
+
+        let result = sqlx::query(
+            "INSERT INTO clients (id, display_name, sources, destinations, section_schemas, credential_hash) \
+            VALUES (?, ?, ?, ?, ?, ?) \
+            ON CONFLICT (id) DO UPDATE SET \
+                display_name = excluded.display_name, \
+                sources = excluded.sources, \
+                destinations = excluded.destinations, \
+                section_schemas = excluded.section_schemas, \
+                credential_hash = excluded.credential_hash \
+            WHERE clients.credential_hash IS NULL OR clients.credential_hash = excluded.credential_hash",
+        )
+        .bind(client_id)
+        .bind(display_name)
+        .bind(sources)
+        .bind(destinations)
+        .bind(section_schemas)
+        .bind(credential_hash)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_client_credential(&self, client_id: &str) -> Result<Option<String>, error::Error> {
+        let credential_hash: Option<Option<String>> =
+            sqlx::query_scalar("SELECT credential_hash FROM clients WHERE id = ?")
+                .bind(client_id)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(credential_hash.flatten())
+    }
+
+    async fn get_section_schemas(&self) -> Result<Vec<SectionSchema>, error::Error> {
+        let rows: Vec<String> = sqlx::query_scalar(
+            "SELECT section_schemas FROM clients WHERE section_schemas IS NOT NULL ORDER BY id",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        let mut schemas = vec![];
+        for row in rows {
+            schemas.extend(serde_json::from_str::<Vec<SectionSchema>>(&row)?);
+        }
+        Ok(schemas)
+    }
+
+    async fn insert_token(&self, client_id: &str, token: &str) -> Result<(), error::Error> {
+        let _ = sqlx::query(
+            "INSERT INTO tokens (client_id, id) VALUES (?,?) ON CONFLICT (id) DO NOTHING",
+        )
+        .bind(client_id)
+        .bind(token)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_token_client(&self, token: &str) -> Result<Option<String>, error::Error> {
+        let row = sqlx::query("SELECT client_id FROM tokens WHERE id = ?")
+            .bind(token)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(|row| row.get("client_id")))
+    }
+
+    async fn get_client_secrets(&self, client_id: &str) -> Result<Vec<StoredSecret>, error::Error> {
+        let rows = sqlx::query(
+            "SELECT name, value, CAST(updated_at AS TEXT) AS updated_at \
+            FROM client_secrets WHERE client_id = ? ORDER BY name",
+        )
+        .bind(client_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| StoredSecret {
+                name: row.get("name"),
+                value: row.get("value"),
+                updated_at: row.get("updated_at"),
+            })
+            .collect())
+    }
+
+    async fn set_client_secret(
+        &self,
+        client_id: &str,
+        name: &str,
+        value: &[u8],
+    ) -> Result<(), error::Error> {
+        sqlx::query(
+            "INSERT INTO client_secrets (client_id, name, value) VALUES (?, ?, ?) \
+            ON CONFLICT (client_id, name) DO UPDATE \
+            SET value = excluded.value, updated_at = CURRENT_TIMESTAMP",
+        )
+        .bind(client_id)
+        .bind(name)
+        .bind(value)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_client_secret(
+        &self,
+        client_id: &str,
+        name: &str,
+    ) -> Result<bool, error::Error> {
+        let result = sqlx::query("DELETE FROM client_secrets WHERE client_id = ? AND name = ?")
+            .bind(client_id)
+            .bind(name)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn set_pipe_paused(&self, id: u64, paused: bool) -> Result<bool, error::Error> {
+        let result = sqlx::query("UPDATE pipes SET paused = ? WHERE id = ?")
+            .bind(paused)
+            .bind(id as i64)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn insert_config(
+        &self,
+        config: &serde_json::Value,
+        workspace_id: i64,
+        author: &str,
+    ) -> Result<u64, error::Error> {
+        // FIXME: unwrap
+        let config: String = serde_json::to_string(config)?;
+        let mut transaction = self.pool.begin().await?;
+        let id = sqlx::query("INSERT INTO pipes (raw_config, workspace_id) VALUES (?, ?)")
+            .bind(config.as_str())
+            .bind(workspace_id)
+            .execute(&mut *transaction)
+            .await?
+            .last_insert_rowid();
+        sqlx::query(
+            "INSERT INTO pipe_revisions (pipe_id, revision, raw_config, author) \
+            VALUES (?, 1, ?, ?)",
+        )
+        .bind(id)
+        .bind(config.as_str())
+        .bind(author)
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(id.try_into().unwrap())
+    }
+
+    async fn update_config(
+        &self,
+        id: u64,
+        config: &serde_json::Value,
+        author: &str,
+    ) -> Result<Option<u64>, error::Error> {
+        let id: i64 = id.try_into().unwrap();
+        let mut transaction = self.pool.begin().await?;
+        let row = sqlx::query("SELECT raw_config, revision FROM pipes WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&mut *transaction)
+            .await?;
+        let (current, revision): (serde_json::Value, i64) = match row {
+            Some(row) => (row.get("raw_config"), row.get("revision")),
+            None => return Ok(None),
+        };
+        if &current == config {
+            return Ok(Some(revision as u64));
+        }
+        let revision = revision + 1;
+        let config: String = serde_json::to_string(config)?;
+        sqlx::query("UPDATE pipes SET raw_config = ?, revision = ? WHERE id = ?")
+            .bind(config.as_str())
+            .bind(revision)
+            .bind(id)
+            .execute(&mut *transaction)
+            .await?;
+        sqlx::query(
+            "INSERT INTO pipe_revisions (pipe_id, revision, raw_config, author) \
+            VALUES (?, ?, ?, ?)",
+        )
+        .bind(id)
+        .bind(revision)
+        .bind(config.as_str())
+        .bind(author)
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(Some(revision as u64))
+    }
+
+    async fn get_config_revisions(&self, id: u64) -> Result<Vec<PipeRevision>, error::Error> {
+        let rows = sqlx::query(
+            "SELECT revision, raw_config, author, CAST(created_at AS TEXT) AS created_at \
+            FROM pipe_revisions WHERE pipe_id = ? ORDER BY revision",
+        )
+        .bind(id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| PipeRevision {
+                revision: row.get::<i64, &str>("revision") as u64,
+                pipe: row.get("raw_config"),
+                author: row.get("author"),
+                created_at: row.get("created_at"),
+            })
+            .collect())
+    }
+
+    async fn get_config_revision(
+        &self,
+        id: u64,
+        revision: u64,
+    ) -> Result<Option<PipeRevision>, error::Error> {
+        let row = sqlx::query(
+            "SELECT revision, raw_config, author, CAST(created_at AS TEXT) AS created_at \
+            FROM pipe_revisions WHERE pipe_id = ? AND revision = ?",
+        )
+        .bind(id as i64)
+        .bind(revision as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(|row| PipeRevision {
+            revision: row.get::<i64, &str>("revision") as u64,
+            pipe: row.get("raw_config"),
+            author: row.get("author"),
+            created_at: row.get("created_at"),
+        }))
+    }
+
+    async fn set_client_pipes(
+        &self,
+        client_id: &str,
+        pipes: &[RunningPipe],
+    ) -> Result<(), error::Error> {
+        let mut transaction = self.pool.begin().await?;
+        sqlx::query("DELETE FROM client_pipes WHERE client_id = ?")
+            .bind(client_id)
+            .execute(&mut *transaction)
+            .await?;
+        for pipe in pipes {
+            sqlx::query("INSERT INTO client_pipes (client_id, pipe_id, revision) VALUES (?, ?, ?)")
+                .bind(client_id)
+                .bind(pipe.id as i64)
+                .bind(pipe.revision as i64)
+                .execute(&mut *transaction)
+                .await?;
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn get_pipe_clients(&self, id: u64) -> Result<Vec<PipeClient>, error::Error> {
+        let rows = sqlx::query(
+            "SELECT client_id, revision, CAST(updated_at AS TEXT) AS updated_at \
+            FROM client_pipes WHERE pipe_id = ? ORDER BY client_id",
+        )
+        .bind(id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| PipeClient {
+                client_id: row.get("client_id"),
+                revision: row.get::<i64, &str>("revision") as u64,
+                updated_at: row.get("updated_at"),
+            })
+            .collect())
+    }
+
+    async fn delete_config(&self, id: u64) -> Result<(), error::Error> {
+        let id: i64 = id.try_into().unwrap();
+        // revisions and client reports of deleted pipe are deleted with it
+        let mut transaction = self.pool.begin().await?;
+        for query in [
+            "DELETE FROM pipes WHERE id = ?",
+            "DELETE FROM pipe_revisions WHERE pipe_id = ?",
+            "DELETE FROM client_pipes WHERE pipe_id = ?",
+        ] {
+            sqlx::query(query)
+                .bind(id)
+                .execute(&mut *transaction)
+                .await?;
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn begin_ingestion(
+        &self,
+        topic: &str,
+        origin: &str,
+    ) -> Result<Box<dyn Ingestion>, error::Error> {
+        Ok(Box::new(SqliteIngestion {
+            transaction: self.pool.begin().await?,
+            topic: topic.into(),
+            origin: origin.into(),
+        }))
+    }
+
+    async fn get_records(
+        &self,
+        topic: &str,
+        offset: u64,
+        limit: u64,
+        max_bytes: u64,
+    ) -> Result<Vec<StoredRecord>, error::Error> {
+        let offset: i64 = offset.try_into().unwrap();
+        let limit: i64 = limit.try_into().unwrap_or(i64::MAX);
+        let max_bytes: i64 = max_bytes.try_into().unwrap_or(i64::MAX);
+        let rows = sqlx::query(
+            "SELECT id, origin, data, object_key, schema_version FROM ( \
+                SELECT id, origin, data, size, object_key, schema_version, SUM(size) OVER (ORDER BY id) AS total \
+                FROM records WHERE topic = ? AND id > ? ORDER BY id ASC LIMIT ? \
+            ) WHERE total - size < ? ORDER BY id ASC",
+        )
+        .bind(topic)
+        .bind(offset)
+        .bind(limit)
+        .bind(max_bytes)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| StoredRecord {
+                id: row.get::<i64, &str>("id").try_into().unwrap(),
+                origin: row
+                    .get::<Option<String>, &str>("origin")
+                    .unwrap_or_default(),
+                data: row.get("data"),
+                object_key: row.get("object_key"),
+                schema_version: row
+                    .get::<Option<i64>, &str>("schema_version")
+                    .map(|x| x as u64),
+            })
+            .collect())
+    }
+
+    async fn get_consumer_offset(
+        &self,
+        topic: &str,
+        consumer_id: &str,
+    ) -> Result<Option<u64>, error::Error> {
+        let position: Option<i64> = sqlx::query_scalar(
+            "SELECT position FROM consumers WHERE topic = ? AND consumer_id = ?",
+        )
+        .bind(topic)
+        .bind(consumer_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(position.map(|x| x as u64))
+    }
+
+    async fn commit_consumer_offset(
+        &self,
+        topic: &str,
+        consumer_id: &str,
+        offset: u64,
+    ) -> Result<(), error::Error> {
+        let position: i64 = offset.try_into().unwrap_or(i64::MAX);
+        sqlx::query(
+            "INSERT INTO consumers (topic, consumer_id, position) VALUES (?, ?, ?) \
+            ON CONFLICT (topic, consumer_id) \
+            DO UPDATE SET position = excluded.position, updated_at = CURRENT_TIMESTAMP",
+        )
+        .bind(topic)
+        .bind(consumer_id)
+        .bind(position)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_consumer(&self, topic: &str, consumer_id: &str) -> Result<(), error::Error> {
+        sqlx::query("DELETE FROM consumers WHERE topic = ? AND consumer_id = ?")
+            .bind(topic)
+            .bind(consumer_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_consumers(&self, topic: &str) -> Result<Vec<Consumer>, error::Error> {
+        let rows = sqlx::query(
+            "SELECT consumer_id, position, CAST(updated_at AS TEXT) AS updated_at, ( \
+                SELECT COUNT(*) FROM records \
+                WHERE records.topic = consumers.topic AND records.id > consumers.position \
+            ) AS lag \
+            FROM consumers WHERE topic = ? ORDER BY consumer_id",
+        )
+        .bind(topic)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| Consumer {
+                consumer_id: row.get("consumer_id"),
+                offset: row.get::<i64, &str>("position") as u64,
+                lag: row.get::<i64, &str>("lag") as u64,
+                updated_at: row.get("updated_at"),
+            })
+            .collect())
+    }
+
+    async fn get_topics(&self) -> Result<Vec<Topic>, error::Error> {
+        let rows = sqlx::query(
+            "SELECT topics.topic, \
+                COUNT(records.id) AS records, \
+                COALESCE(SUM(records.size), 0) AS size, \
+                MIN(records.id) AS min_offset, \
+                MAX(records.id) AS max_offset, \
+                topic_retention.max_age, \
+                topic_retention.max_bytes, \
+                topic_retention.consumed \
+            FROM ( \
+                SELECT DISTINCT topic FROM records UNION SELECT topic FROM topic_retention \
+            ) AS topics \
+            LEFT JOIN records ON records.topic = topics.topic \
+            LEFT JOIN topic_retention ON topic_retention.topic = topics.topic \
+            GROUP BY topics.topic \
+            ORDER BY topics.topic",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| Topic {
+                topic: row.get("topic"),
+                records: row.get::<i64, &str>("records") as u64,
+                size: row.get::<i64, &str>("size") as u64,
+                min_offset: row.get::<Option<i64>, &str>("min_offset").map(|x| x as u64),
+                max_offset: row.get::<Option<i64>, &str>("max_offset").map(|x| x as u64),
+                retention: row.get::<Option<bool>, &str>("consumed").map(|consumed| {
+                    RetentionPolicy {
+                        max_age: row.get::<Option<i64>, &str>("max_age").map(|x| x as u64),
+                        max_bytes: row.get::<Option<i64>, &str>("max_bytes").map(|x| x as u64),
+                        consumed,
+                    }
+                }),
+            })
+            .collect())
+    }
+
+    async fn purge_topic(&self, topic: &str) -> Result<Vec<String>, error::Error> {
+        let object_keys: Vec<Option<String>> =
+            sqlx::query_scalar("DELETE FROM records WHERE topic = ? RETURNING object_key")
+                .bind(topic)
+                .fetch_all(&self.pool)
+                .await?;
+        sqlx::query("PRAGMA incremental_vacuum")
+            .execute(&self.pool)
+            .await?;
+        Ok(object_keys.into_iter().flatten().collect())
+    }
+
+    async fn get_retention_policy(
+        &self,
+        topic: &str,
+    ) -> Result<Option<RetentionPolicy>, error::Error> {
+        let row =
+            sqlx::query("SELECT max_age, max_bytes, consumed FROM topic_retention WHERE topic = ?")
+                .bind(topic)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(row.map(|row| RetentionPolicy {
+            max_age: row.get::<Option<i64>, &str>("max_age").map(|x| x as u64),
+            max_bytes: row.get::<Option<i64>, &str>("max_bytes").map(|x| x as u64),
+            consumed: row.get("consumed"),
+        }))
+    }
+
+    async fn set_retention_policy(
+        &self,
+        topic: &str,
+        policy: &RetentionPolicy,
+    ) -> Result<(), error::Error> {
+        let max_age: Option<i64> = policy.max_age.map(|x| x.try_into().unwrap_or(i64::MAX));
+        let max_bytes: Option<i64> = policy.max_bytes.map(|x| x.try_into().unwrap_or(i64::MAX));
+        sqlx::query(
+            "INSERT OR REPLACE INTO topic_retention (topic, max_age, max_bytes, consumed) \
+            VALUES (?, ?, ?, ?)",
+        )
+        .bind(topic)
+        .bind(max_age)
+        .bind(max_bytes)
+        .bind(policy.consumed)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_retention_policy(&self, topic: &str) -> Result<(), error::Error> {
+        sqlx::query("DELETE FROM topic_retention WHERE topic = ?")
+            .bind(topic)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_schemas(&self, topic: &str) -> Result<Vec<StoredSchema>, error::Error> {
+        let rows = sqlx::query(
+            "SELECT origin, version, schema, CAST(created_at AS TEXT) AS created_at \
+            FROM topic_schemas WHERE topic = ? ORDER BY origin, version",
+        )
+        .bind(topic)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| StoredSchema {
+                origin: row.get("origin"),
+                version: row.get::<i64, &str>("version") as u64,
+                schema: row.get("schema"),
+                created_at: row.get("created_at"),
+            })
+            .collect())
+    }
+
+    async fn get_compatibility(&self, topic: &str) -> Result<Option<String>, error::Error> {
+        let mode: Option<String> =
+            sqlx::query_scalar("SELECT mode FROM topic_compatibility WHERE topic = ?")
+                .bind(topic)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(mode)
+    }
+
+    async fn set_compatibility(&self, topic: &str, mode: &str) -> Result<(), error::Error> {
+        sqlx::query(
+            "INSERT INTO topic_compatibility (topic, mode) VALUES (?, ?) \
+            ON CONFLICT (topic) DO UPDATE SET mode = excluded.mode",
+        )
+        .bind(topic)
+        .bind(mode)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn prune_message_ids(&self, window: u64) -> Result<u64, error::Error> {
+        let pruned =
+            sqlx::query("DELETE FROM ingested_messages WHERE created_at < datetime('now', ?)")
+                .bind(format!("-{window} seconds"))
+                .execute(&self.pool)
+                .await?
+                .rows_affected();
+        Ok(pruned)
+    }
+
+    async fn enforce_retention(&self) -> Result<(u64, Vec<String>), error::Error> {
+        let policies =
+            sqlx::query("SELECT topic, max_age, max_bytes, consumed FROM topic_retention")
+                .fetch_all(&self.pool)
+                .await?;
+        let mut deleted: Vec<Option<String>> = vec![];
+        for row in policies {
+            let topic: String = row.get("topic");
+            if let Some(max_age) = row.get::<Option<i64>, &str>("max_age") {
+                deleted.extend(
+                    sqlx::query_scalar::<_, Option<String>>(
+                        "DELETE FROM records WHERE topic = ? AND created_at < datetime('now', ?) \
+                        RETURNING object_key",
+                    )
+                    .bind(&topic)
+                    .bind(format!("-{max_age} seconds"))
+                    .fetch_all(&self.pool)
+                    .await?,
+                );
+            }
+            if let Some(max_bytes) = row.get::<Option<i64>, &str>("max_bytes") {
+                deleted.extend(
+                    sqlx::query_scalar::<_, Option<String>>(
+                        "DELETE FROM records WHERE id IN ( \
+                            SELECT id FROM ( \
+                                SELECT id, SUM(size) OVER (ORDER BY id DESC) AS total \
+                                FROM records WHERE topic = ? \
+                            ) WHERE total > ? \
+                        ) RETURNING object_key",
+                    )
+                    .bind(&topic)
+                    .bind(max_bytes)
+                    .fetch_all(&self.pool)
+                    .await?,
+                );
+            }
+            if row.get::<bool, &str>("consumed") {
+                // topic without known consumers is left untouched
+                deleted.extend(
+                    sqlx::query_scalar::<_, Option<String>>(
+                        "DELETE FROM records WHERE topic = ? \
+                        AND id <= (SELECT MIN(position) FROM consumers WHERE topic = ?) \
+                        RETURNING object_key",
+                    )
+                    .bind(&topic)
+                    .bind(&topic)
+                    .fetch_all(&self.pool)
+                    .await?,
+                );
+            }
+        }
+        if !deleted.is_empty() {
+            sqlx::query("PRAGMA incremental_vacuum")
+                .execute(&self.pool)
+                .await?;
+        }
+        let count = deleted.len() as u64;
+        Ok((count, deleted.into_iter().flatten().collect()))
+    }
+
+    async fn get_clients(&self) -> Result<Clients, error::Error> {
+        // todo: should we return ui as client?
+        let rows = sqlx::query("SELECT id, display_name, sources, destinations FROM clients")
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut clients: Vec<Client> = Vec::new();
+        for row in rows.iter() {
+            let id = row.get("id");
+            let display_name = row.get("display_name");
+            let sources: Option<String> = row.get("sources");
+            let sources = serde_json::from_str(&sources.unwrap_or("[]".to_string()))?;
+            let destinations: Option<String> = row.get("destinations");
+            let destinations = serde_json::from_str(&destinations.unwrap_or("[]".to_string()))?;
+            clients.push(Client {
+                id,
+                display_name,
+                sources,
+                destinations,
+            });
+        }
+        Ok(Clients { clients })
+    }
+
+    async fn get_workspaces(&self) -> Result<Vec<Workspace>, error::Error> {
+        let records: Vec<Workspace> =
+            sqlx::query_as(r"SELECT id, name, created_at FROM workspaces")
+                .fetch_all(&self.pool)
+                .await
+                .unwrap();
+
+        Ok(records)
+    }
+
+    async fn create_workspace(&self, mut workspace: Workspace) -> Result<Workspace, error::Error> {
+        workspace.id = sqlx::query("INSERT INTO workspaces (name) VALUES (?)")
+            .bind(workspace.name.clone())
+            .execute(&self.pool)
+            .await?
+            .last_insert_rowid();
+        Ok(workspace)
+    }
+
+    async fn get_workspace(&self, id: u64) -> Result<Workspace, error::Error> {
+        let id: i64 = id.try_into().unwrap();
+        let mut record: Workspace =
+            sqlx::query_as(r"SELECT id, name, created_at FROM workspaces WHERE id = ?")
+                .bind(id)
+                .fetch_one(&self.pool)
+                .await
+                .unwrap();
+
+        let pipes: Vec<PipeConfig> = sqlx::query_as(
+            "SELECT id, raw_config, workspace_id, revision, paused from pipes where workspace_id = ?",
+        )
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        record.pipe_configs = pipes;
+
+        Ok(record)
+    }
+
+    async fn update_workspace(&self, workspace: Workspace) -> Result<Workspace, error::Error> {
+        let _ = sqlx::query("UPDATE workspaces SET name = ? where id = ?")
+            .bind(workspace.name.clone())
+            .bind(workspace.id)
+            .execute(&self.pool)
+            .await
+            .unwrap();
+        Ok(workspace)
+    }
+
+    async fn delete_workspace(&self, id: u64) -> Result<(), error::Error> {
+        let id: i64 = id.try_into().unwrap();
+        let _ = sqlx::query("DELETE FROM pipes WHERE id = ?")
+            .bind(id) // fixme
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_config(&self, id: u64) -> Result<PipeConfig, error::Error> {
+        let id: i64 = id.try_into().unwrap();
+        let pipe: PipeConfig = sqlx::query_as(
+            "SELECT id, workspace_id, raw_config, revision, paused from pipes WHERE id = ?",
+        )
+        .bind(id)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(pipe)
+    }
+
+    async fn get_configs(&self) -> Result<PipeConfigs, error::Error> {
+        let rows: Vec<PipeConfig> =
+            sqlx::query_as("SELECT id, raw_config, workspace_id, revision, paused from pipes")
+                .fetch_all(&self.pool)
+                .await?;
+
+        let configs: PipeConfigs = PipeConfigs { configs: rows };
+        Ok(configs)
+    }
+
+    async fn enable_incremental_vacuum(&self) -> Result<(), error::Error> {
+        // changing auto vacuum mode of existing database requires full vacuum on the same connection
+        let mut connection = self.pool.acquire().await?;
+        let auto_vacuum: i64 = sqlx::query_scalar("PRAGMA auto_vacuum")
+            .fetch_one(&mut *connection)
+            .await?;
+        if auto_vacuum != AUTO_VACUUM_INCREMENTAL {
+            sqlx::query("PRAGMA auto_vacuum = INCREMENTAL")
+                .execute(&mut *connection)
+                .await?;
+            sqlx::query("VACUUM").execute(&mut *connection).await?;
+        }
+        Ok(())
+    }
+}